@@ -0,0 +1,252 @@
+//! Expansion of `diff_fn!` into straight-line code computing a value and its gradient.
+
+use proc_macro2::{Span, TokenStream, TokenTree};
+use quote::{quote, ToTokens};
+use syn::{BinOp, Error, Expr, ExprClosure, Ident, Index, Lit, Pat, UnOp};
+
+pub fn expand(closure: ExprClosure) -> Result<TokenStream, Error> {
+    let params = closure
+        .inputs
+        .iter()
+        .map(|input| match input {
+            Pat::Ident(pat) => Ok(pat.ident.clone()),
+            Pat::Type(pat) => match &*pat.pat {
+                Pat::Ident(pat) => Ok(pat.ident.clone()),
+                pat => Err(Error::new_spanned(pat, "expected a parameter name")),
+            },
+            pat => Err(Error::new_spanned(pat, "expected a parameter name")),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let n = params.len();
+    let body = &closure.body;
+    let forward = Forward { params: &params };
+    let code = match forward.gen(body) {
+        Some(code) => code,
+        None => {
+            // record the body on a tape, which supports everything `Var` does
+            let tape = internal("tape");
+            let res = internal("res");
+            let grads = internal("grads");
+            quote! {
+                let #tape = ::reverse::Tape::new();
+                #(let #params = #tape.add_var(#params);)*
+                let #res: ::reverse::Var = #body;
+                let #grads = #res.grad();
+                (#res.val, [#(::reverse::Gradient::wrt(&#grads, &#params)),*])
+            }
+        }
+    };
+    Ok(quote! {
+        move |#(#params: f64),*| -> (f64, [f64; #n]) {
+            #code
+        }
+    })
+}
+
+/// Gets an identifier for a local variable of the generated code, which cannot clash with the
+/// names of the parameters.
+fn internal(name: &str) -> Ident {
+    Ident::new(name, Span::mixed_site())
+}
+
+/// Generator of forward-mode code, where each subexpression evaluates to its value and its
+/// gradient with respect to the parameters, as `(f64, [f64; N])`.
+struct Forward<'a> {
+    params: &'a [Ident],
+}
+
+impl Forward<'_> {
+    /// Checks whether `tokens` mention a parameter.
+    fn uses_params(&self, tokens: TokenStream) -> bool {
+        tokens.into_iter().any(|tree| match tree {
+            TokenTree::Ident(ident) => self.params.contains(&ident),
+            TokenTree::Group(group) => self.uses_params(group.stream()),
+            _ => false,
+        })
+    }
+
+    /// Write a gradient with one entry for each parameter, from the entry at index `i`.
+    fn gradient(&self, entry: impl Fn(Index) -> TokenStream) -> TokenStream {
+        let entries = (0..self.params.len()).map(|i| entry(Index::from(i)));
+        quote!([#(#entries),*])
+    }
+
+    /// Generate code for `expr`, or `None` if it uses something other than arithmetic and the
+    /// supported methods of `f64`.
+    fn gen(&self, expr: &Expr) -> Option<TokenStream> {
+        let (a, da, b, db, v, d) = (
+            internal("a"),
+            internal("da"),
+            internal("b"),
+            internal("db"),
+            internal("v"),
+            internal("d"),
+        );
+        if !self.uses_params(expr.to_token_stream()) {
+            let value = match expr {
+                Expr::Lit(lit) if matches!(lit.lit, Lit::Int(_)) => quote!((#expr as f64)),
+                _ => quote!(#expr),
+            };
+            let zeros = self.gradient(|_| quote!(0.0));
+            return Some(quote!({ let #v: f64 = #value; (#v, #zeros) }));
+        }
+        let code = match expr {
+            Expr::Paren(expr) => self.gen(&expr.expr)?,
+            Expr::Group(expr) => self.gen(&expr.expr)?,
+            Expr::Path(path) => {
+                let ident = path.path.get_ident()?;
+                let k = self.params.iter().position(|param| param == ident)?;
+                let one_hot = self.gradient(|i| {
+                    if i.index as usize == k {
+                        quote!(1.0)
+                    } else {
+                        quote!(0.0)
+                    }
+                });
+                quote!((#ident, #one_hot))
+            }
+            Expr::Unary(unary) if matches!(unary.op, UnOp::Neg(_)) => {
+                let x = self.gen(&unary.expr)?;
+                let grad = self.gradient(|i| quote!(-#da[#i]));
+                quote!({ let (#a, #da) = #x; (-#a, #grad) })
+            }
+            Expr::Binary(binary) => {
+                let (lhs, rhs) = (self.gen(&binary.left)?, self.gen(&binary.right)?);
+                let (val, grad) = match binary.op {
+                    BinOp::Add(_) => (
+                        quote!(#a + #b),
+                        self.gradient(|i| quote!(#da[#i] + #db[#i])),
+                    ),
+                    BinOp::Sub(_) => (
+                        quote!(#a - #b),
+                        self.gradient(|i| quote!(#da[#i] - #db[#i])),
+                    ),
+                    BinOp::Mul(_) => (
+                        quote!(#a * #b),
+                        self.gradient(|i| quote!(#da[#i] * #b + #a * #db[#i])),
+                    ),
+                    BinOp::Div(_) => (
+                        quote!(#a / #b),
+                        self.gradient(|i| quote!((#da[#i] * #b - #a * #db[#i]) / (#b * #b))),
+                    ),
+                    _ => return None,
+                };
+                quote!({ let (#a, #da) = #lhs; let (#b, #db) = #rhs; (#val, #grad) })
+            }
+            Expr::MethodCall(call) => {
+                let x = self.gen(&call.receiver)?;
+                let method = call.method.to_string();
+                let args = call.args.iter().collect::<Vec<_>>();
+                match args.as_slice() {
+                    [] => {
+                        let (val, deriv) = unary(&method, &a, &v)?;
+                        let grad = self.gradient(|i| quote!(#d * #da[#i]));
+                        quote!({
+                            let (#a, #da) = #x;
+                            let #v: f64 = #val;
+                            let #d: f64 = #deriv;
+                            (#v, #grad)
+                        })
+                    }
+                    // a constant argument that is not differentiated
+                    [arg]
+                        if matches!(method.as_str(), "powi" | "powf" | "log")
+                            && !self.uses_params(arg.to_token_stream()) =>
+                    {
+                        let (val, deriv) = match method.as_str() {
+                            "powi" => (quote!(#a.powi(#b)), quote!(#b as f64 * #a.powi(#b - 1))),
+                            "powf" => (quote!(#a.powf(#b)), quote!(#b * #a.powf(#b - 1.0))),
+                            _ => (quote!(#a.log(#b)), quote!(1.0 / (#a * #b.ln()))),
+                        };
+                        let grad = self.gradient(|i| quote!(#d * #da[#i]));
+                        quote!({
+                            let (#a, #da) = #x;
+                            let #b = #arg;
+                            let #v: f64 = #val;
+                            let #d: f64 = #deriv;
+                            (#v, #grad)
+                        })
+                    }
+                    [arg] => {
+                        let y = self.gen(arg)?;
+                        let (val, d_a, d_b) = binary(&method, &a, &b, &v)?;
+                        let grad = self.gradient(|i| quote!(#d[0] * #da[#i] + #d[1] * #db[#i]));
+                        quote!({
+                            let (#a, #da) = #x;
+                            let (#b, #db) = #y;
+                            let #v: f64 = #val;
+                            let #d: [f64; 2] = [#d_a, #d_b];
+                            (#v, #grad)
+                        })
+                    }
+                    _ => return None,
+                }
+            }
+            _ => return None,
+        };
+        Some(code)
+    }
+}
+
+/// Value and derivative of the method `name` of `f64` without arguments, applied to `a`, where
+/// the derivative may use the value `v`.
+fn unary(name: &str, a: &Ident, v: &Ident) -> Option<(TokenStream, TokenStream)> {
+    let deriv = match name {
+        "sin" => quote!(#a.cos()),
+        "cos" => quote!(-#a.sin()),
+        "tan" => quote!(1.0 / (#a.cos() * #a.cos())),
+        "exp" => quote!(#v),
+        "exp2" => quote!(#v * ::std::f64::consts::LN_2),
+        "exp_m1" => quote!(#a.exp()),
+        "ln" => quote!(1.0 / #a),
+        "log10" => quote!(1.0 / (#a * ::std::f64::consts::LN_10)),
+        "log2" => quote!(1.0 / (#a * ::std::f64::consts::LN_2)),
+        "ln_1p" => quote!(1.0 / (1.0 + #a)),
+        "sqrt" => quote!(0.5 / #v),
+        "cbrt" => quote!(1.0 / (3.0 * #v * #v)),
+        // undefined at zero, as for `Var::abs`
+        "abs" => quote!(#a / #v),
+        "recip" => quote!(-1.0 / (#a * #a)),
+        "sinh" => quote!(#a.cosh()),
+        "cosh" => quote!(#a.sinh()),
+        "tanh" => quote!(1.0 - #v * #v),
+        "asin" => quote!(1.0 / (1.0 - #a * #a).sqrt()),
+        "acos" => quote!(-1.0 / (1.0 - #a * #a).sqrt()),
+        "atan" => quote!(1.0 / (1.0 + #a * #a)),
+        "asinh" => quote!(1.0 / (1.0 + #a * #a).sqrt()),
+        "acosh" => quote!(1.0 / (#a * #a - 1.0).sqrt()),
+        "atanh" => quote!(1.0 / (1.0 - #a * #a)),
+        _ => return None,
+    };
+    let method = Ident::new(name, Span::call_site());
+    Some((quote!(#a.#method()), deriv))
+}
+
+/// Value and partial derivatives of the method `name` of `f64` applied to `a` with the argument
+/// `b`, where the derivatives may use the value `v`.
+fn binary(
+    name: &str,
+    a: &Ident,
+    b: &Ident,
+    v: &Ident,
+) -> Option<(TokenStream, TokenStream, TokenStream)> {
+    Some(match name {
+        "powf" => (
+            quote!(#a.powf(#b)),
+            quote!(#b * #a.powf(#b - 1.0)),
+            quote!(#v * #a.ln()),
+        ),
+        "atan2" => (
+            quote!(#a.atan2(#b)),
+            quote!(#b / (#a * #a + #b * #b)),
+            quote!(-#a / (#a * #a + #b * #b)),
+        ),
+        // zero at the origin, as for `Var::hypot`
+        "hypot" => (
+            quote!(#a.hypot(#b)),
+            quote!(if #v == 0.0 { 0.0 } else { #a / #v }),
+            quote!(if #v == 0.0 { 0.0 } else { #b / #v }),
+        ),
+        _ => return None,
+    })
+}
@@ -0,0 +1,146 @@
+//! Derive macros for the `reverse` crate. Enable the `derive` feature of `reverse` rather than
+//! depending on this crate directly.
+
+use proc_macro::TokenStream;
+use proc_macro2::Span;
+use quote::{format_ident, quote};
+use syn::{
+    parse_macro_input, visit_mut::VisitMut, Data, DeriveInput, Error, ExprClosure, Fields,
+    GenericParam, Lifetime,
+};
+
+mod diff_fn;
+
+/// Derive `reverse::Differentiable` for a struct with named fields and a single lifetime
+/// parameter, whose fields are all `Differentiable` (variables, `Vec`s and arrays of them, or
+/// other derived structs).
+///
+/// This also defines a struct named after the original with a `Grad` suffix, which has the same
+/// fields with an `f64` in place of each variable, and implements `Gradient` so that
+/// `grads.wrt(&params)` returns it.
+#[proc_macro_derive(Differentiable)]
+pub fn derive_differentiable(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    expand(input)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+/// Turn a closure over named `f64` parameters into one returning its value along with the
+/// gradient with respect to the parameters, as `(f64, [f64; N])`.
+///
+/// Bodies built from arithmetic and the elementary methods of `f64` are differentiated into
+/// straight-line code, with no tape involved. Anything else is recorded on a tape as
+/// `reverse::Var`s, so the body must then be valid for variables.
+///
+/// ```ignore
+/// use reverse::diff_fn;
+///
+/// let f = diff_fn!(|x, y| x * y.sin() + x.powi(2));
+/// let (val, [dx, dy]) = f(2., 1.);
+/// assert_eq!(val, 2. * 1f64.sin() + 4.);
+/// assert_eq!(dx, 1f64.sin() + 4.);
+/// assert_eq!(dy, 2. * 1f64.cos());
+/// ```
+#[proc_macro]
+pub fn diff_fn(input: TokenStream) -> TokenStream {
+    let closure = parse_macro_input!(input as ExprClosure);
+    diff_fn::expand(closure)
+        .unwrap_or_else(Error::into_compile_error)
+        .into()
+}
+
+fn expand(input: DeriveInput) -> Result<proc_macro2::TokenStream, Error> {
+    let name = &input.ident;
+    let vis = &input.vis;
+    let grad_name = format_ident!("{}Grad", name);
+
+    let mut lifetimes = input.generics.params.iter().map(|param| match param {
+        GenericParam::Lifetime(def) => Ok(&def.lifetime),
+        _ => Err(Error::new_spanned(
+            param,
+            "`Differentiable` can only be derived for structs without type or const parameters",
+        )),
+    });
+    let lifetime = match (lifetimes.next(), lifetimes.next()) {
+        (Some(lifetime), None) => lifetime?,
+        _ => {
+            return Err(Error::new_spanned(
+                &input.generics,
+                "`Differentiable` can only be derived for structs with exactly one lifetime",
+            ))
+        }
+    };
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(Error::new_spanned(
+                    &data.fields,
+                    "`Differentiable` can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                name,
+                "`Differentiable` can only be derived for structs",
+            ))
+        }
+    };
+    let idents = fields.iter().map(|f| &f.ident).collect::<Vec<_>>();
+    let grad_fields = fields.iter().map(|f| {
+        let (fvis, ident) = (&f.vis, &f.ident);
+        // the gradient struct has no lifetime, and the shape does not depend on it
+        let mut ty = f.ty.clone();
+        StaticLifetimes.visit_type_mut(&mut ty);
+        quote! {
+            #fvis #ident: <#ty as ::reverse::Differentiable<'static>>::Values
+        }
+    });
+    let doc = format!("Gradient with respect to a `{}`, of the same shape.", name);
+
+    Ok(quote! {
+        #[doc = #doc]
+        #[derive(Debug, Clone, PartialEq)]
+        #vis struct #grad_name {
+            #(#grad_fields,)*
+        }
+
+        impl<#lifetime> ::reverse::Differentiable<#lifetime> for #name<#lifetime> {
+            type Values = #grad_name;
+
+            fn push_vars(&self, vars: &mut ::std::vec::Vec<::reverse::Var<#lifetime>>) {
+                #(::reverse::Differentiable::push_vars(&self.#idents, vars);)*
+            }
+
+            fn take_values(&self, vals: &mut dyn ::std::iter::Iterator<Item = f64>) -> #grad_name {
+                #grad_name {
+                    #(#idents: ::reverse::Differentiable::take_values(&self.#idents, vals),)*
+                }
+            }
+        }
+
+        impl<#lifetime> ::reverse::Gradient<&#name<#lifetime>, #grad_name> for ::std::vec::Vec<f64> {
+            fn wrt(&self, v: &#name<#lifetime>) -> #grad_name {
+                ::reverse::Differentiable::grad_wrt(v, self)
+            }
+        }
+
+        impl<#lifetime> ::reverse::Gradient<&#name<#lifetime>, #grad_name> for ::reverse::Grad<#lifetime> {
+            fn wrt(&self, v: &#name<#lifetime>) -> #grad_name {
+                ::reverse::Differentiable::grad_wrt(v, self)
+            }
+        }
+    })
+}
+
+/// Replaces every lifetime in a type with `'static`.
+struct StaticLifetimes;
+
+impl VisitMut for StaticLifetimes {
+    fn visit_lifetime_mut(&mut self, lifetime: &mut Lifetime) {
+        *lifetime = Lifetime::new("'static", Span::call_site());
+    }
+}
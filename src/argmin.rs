@@ -0,0 +1,104 @@
+//! Objectives for the solvers of the `argmin` crate, enabled with the `argmin` feature.
+//!
+//! `Problem` wraps an objective written with variables, like those of `optim::minimize`, and
+//! implements argmin's `CostFunction` and `Gradient` with `Vec<f64>` parameters, so it can be
+//! handed to any argmin solver that works on vectors. The gradient comes from a backward pass over
+//! a tape that the problem owns and clears before every evaluation.
+//!
+//! ```rust
+//! use argmin::core::{CostFunction, Gradient};
+//! use reverse::argmin::Problem;
+//!
+//! let problem = Problem::new(|x| (x[0] - 1.).powi(2) + x[0] * x[1]);
+//! assert_eq!(problem.cost(&vec![2., 3.]).unwrap(), 7.);
+//! assert_eq!(problem.gradient(&vec![2., 3.]).unwrap(), vec![5., 2.]);
+//! ```
+
+use crate::{error::assert_same_tape, Tape, Var};
+use ::argmin::core::{CostFunction, Error, Gradient};
+
+/// Objective `f` over `Vec<f64>` parameters, for argmin solvers.
+pub struct Problem<F> {
+    f: F,
+    tape: Tape,
+}
+
+impl<F> Problem<F>
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    pub fn new(f: F) -> Self {
+        Self {
+            f,
+            tape: Tape::new(),
+        }
+    }
+
+    /// Evaluate the objective at `x`, returning its value and gradient.
+    pub fn eval(&self, x: &[f64]) -> (f64, Vec<f64>) {
+        self.tape.clear();
+        let params = self.tape.add_vars(x);
+        let res = (self.f)(&params);
+        assert_same_tape("Problem::eval", &self.tape, res.tape);
+        let grad = res.grad().iter().take(x.len()).copied().collect();
+        (res.val, grad)
+    }
+}
+
+impl<F> CostFunction for Problem<F>
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    type Param = Vec<f64>;
+    type Output = f64;
+
+    fn cost(&self, x: &Vec<f64>) -> Result<f64, Error> {
+        self.tape.clear();
+        let params = self.tape.add_vars(x);
+        Ok((self.f)(&params).val)
+    }
+}
+
+impl<F> Gradient for Problem<F>
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    type Param = Vec<f64>;
+    type Gradient = Vec<f64>;
+
+    fn gradient(&self, x: &Vec<f64>) -> Result<Vec<f64>, Error> {
+        Ok(self.eval(x).1)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ::argmin::{
+        core::Executor,
+        solver::{linesearch::MoreThuenteLineSearch, quasinewton::LBFGS},
+    };
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_problem() {
+        let problem = Problem::new(|x| (1. - x[0]).powi(2) + 100. * (x[1] - x[0].powi(2)).powi(2));
+        let (val, grad) = problem.eval(&[0., 1.]);
+        assert_eq!(val, 101.);
+        assert_eq!(grad, vec![-2., 200.]);
+        let len = problem.tape.len();
+        assert_eq!(problem.gradient(&vec![0., 1.]).unwrap(), grad);
+        // the tape is cleared for every evaluation
+        assert_eq!(problem.tape.len(), len);
+        assert_eq!(problem.cost(&vec![0., 1.]).unwrap(), val);
+
+        let solver = LBFGS::new(MoreThuenteLineSearch::new(), 5);
+        let res = Executor::new(problem, solver)
+            .configure(|state| state.param(vec![-1.2, 1.]).max_iters(100))
+            .run()
+            .unwrap();
+        let best = res.state.best_param.unwrap();
+        assert_approx_eq!(best[0], 1., 1e-6);
+        assert_approx_eq!(best[1], 1., 1e-6);
+    }
+}
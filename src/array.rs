@@ -0,0 +1,140 @@
+//! Interoperability with [`ndarray`](https://docs.rs/ndarray), enabled with the `ndarray` feature.
+//!
+//! Arrays of `Var<'a>` support the usual elementwise arithmetic from `ndarray` (`&a + &b`,
+//! `a * 2.`, `a.mapv(|x| x.sin())`, ...), since `Var<'a>` behaves like a number. This module adds
+//! the pieces that need to know about the tape: creating arrays of variables, reductions and
+//! extracting gradients in the shape of the original array.
+//!
+//! ```rust
+//! use ndarray::array;
+//! use reverse::*;
+//! use reverse::array::VarArray;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_array(&array![[1., 2.], [3., 4.]]);
+//! let y = (&x * &x).mapv(|v| v.sin()).sum_vars();
+//! let gradients = y.grad().wrt(&x);
+//! assert_eq!(gradients.shape(), &[2, 2]);
+//! ```
+
+use crate::{Grad, Gradient, Mat, Tape, Var};
+use ndarray::{Array, Array2, ArrayBase, Data, Dimension};
+
+impl Tape {
+    /// Add every element of `vals` to the tape, returning an array of variables with the same
+    /// shape.
+    pub fn add_array<'a, S, D>(&'a self, vals: &ArrayBase<S, D>) -> Array<Var<'a>, D>
+    where
+        S: Data<Elem = f64>,
+        D: Dimension,
+    {
+        vals.map(|&x| self.add_var(x))
+    }
+}
+
+/// Operations on arrays of variables.
+pub trait VarArray<'a, D> {
+    /// Gets the values of the variables, in the same shape.
+    fn vals(&self) -> Array<f64, D>;
+
+    /// Calculate the sum of all elements, recorded as a single node.
+    fn sum_vars(&self) -> Var<'a>;
+
+    /// Multiply every element by the variable `v`.
+    fn scale(&self, v: Var<'a>) -> Array<Var<'a>, D>;
+}
+
+impl<'a, S, D> VarArray<'a, D> for ArrayBase<S, D>
+where
+    S: Data<Elem = Var<'a>>,
+    D: Dimension,
+{
+    fn vals(&self) -> Array<f64, D> {
+        self.map(|x| x.val)
+    }
+
+    fn sum_vars(&self) -> Var<'a> {
+        crate::sum(&self.iter().copied().collect::<Vec<_>>())
+    }
+
+    fn scale(&self, v: Var<'a>) -> Array<Var<'a>, D> {
+        self.map(|&x| x * v)
+    }
+}
+
+/// Calculate the gradient with respect to every element of `v`, returned in the same shape.
+impl<'a, S, D> Gradient<&ArrayBase<S, D>, Array<f64, D>> for Vec<f64>
+where
+    S: Data<Elem = Var<'a>>,
+    D: Dimension,
+{
+    fn wrt(&self, v: &ArrayBase<S, D>) -> Array<f64, D> {
+        v.map(|x| self.wrt(x))
+    }
+}
+
+/// Calculate the gradient with respect to every element of `v`, returned in the same shape.
+impl<'a, S, D> Gradient<&ArrayBase<S, D>, Array<f64, D>> for Grad<'a>
+where
+    S: Data<Elem = Var<'a>>,
+    D: Dimension,
+{
+    fn wrt(&self, v: &ArrayBase<S, D>) -> Array<f64, D> {
+        v.map(|x| self.wrt(x))
+    }
+}
+
+impl<'a> Mat<'a> {
+    /// Create a matrix from a two-dimensional array of variables.
+    pub fn from_array<S>(array: &ArrayBase<S, ndarray::Ix2>) -> Self
+    where
+        S: Data<Elem = Var<'a>>,
+    {
+        let (rows, cols) = array.dim();
+        Self::new(rows, cols, array.iter().copied().collect())
+    }
+
+    /// Convert the matrix into a two-dimensional array of variables.
+    pub fn to_array(&self) -> Array2<Var<'a>> {
+        Array2::from_shape_vec(self.shape(), self.as_slice().to_vec())
+            .expect("matrix data matches its shape")
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+    use ndarray::array;
+
+    #[test]
+    fn test_array() {
+        let g = Tape::new();
+        let x = g.add_array(&array![[1., 2.], [3., 4.]]);
+        let w = g.add_var(0.5);
+        let y = (&x * &x).scale(w).sum_vars();
+        assert_approx_eq!(y.val(), 15.);
+
+        let grads = y.grad();
+        assert_eq!(grads.wrt(&x), array![[1., 2.], [3., 4.]]);
+        assert_approx_eq!(grads.wrt(&w), 30.);
+
+        // elementwise arithmetic with arrays and numbers
+        let v = g.add_array(&array![1., 2., 3.]);
+        let w = (&v * 2. + 1.).mapv(|x| x.ln()) / &v;
+        let grads = w.sum_vars().grad().wrt(&v);
+        let expected = v
+            .vals()
+            .mapv(|v| (2. / (2. * v + 1.) - (2. * v + 1.).ln() / v) / v);
+        assert_eq!(grads.shape(), &[3]);
+        for (x, y) in grads.iter().zip(&expected) {
+            assert_approx_eq!(*x, *y);
+        }
+
+        let m = Mat::from_array(&x);
+        assert_eq!(
+            m.matmul(&m).to_array().vals(),
+            array![[7., 10.], [15., 22.]]
+        );
+    }
+}
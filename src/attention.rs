@@ -0,0 +1,134 @@
+use crate::{
+    error::assert_same_tape,
+    linalg::{matmul, transpose},
+    Mat, Op, Tape, Var,
+};
+
+/// Softmax of every row of the row-major `rows x cols` matrix `s`, in place. Entries for which
+/// `masked` holds are excluded, getting zero weight.
+fn softmax_rows(s: &mut [f64], cols: usize, masked: impl Fn(usize, usize) -> bool) {
+    for (i, row) in s.chunks_mut(cols).enumerate() {
+        let max = (0..cols)
+            .filter(|&j| !masked(i, j))
+            .map(|j| row[j])
+            .fold(f64::NEG_INFINITY, f64::max);
+        for (j, x) in row.iter_mut().enumerate() {
+            *x = if masked(i, j) { 0. } else { (*x - max).exp() };
+        }
+        let total = row.iter().sum::<f64>();
+        row.iter_mut().for_each(|x| *x /= total);
+    }
+}
+
+/// Scaled dot-product attention, `softmax(Q K^T / sqrt(d)) V` with the softmax over every row,
+/// for queries `q` of shape `n x d`, keys `k` of shape `m x d` and values `v` of shape `m x p`.
+/// With `causal`, query `i` only attends to keys `j <= i`, as in autoregressive models.
+///
+/// The whole computation is recorded as a single block, with the softmax evaluated stably. Given
+/// the adjoint `Ō` of the `n x p` output, the backward pass computes `V̄ = P^T Ō` and
+/// `S̄ = P ⊙ (Ō V^T - rowsum(P ⊙ Ō V^T))` for the attention weights `P`, then maps `S̄` back to
+/// the queries and the keys.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let q = Mat::from_vals(&tape, 1, 2, &[10., 0.]);
+/// let k = Mat::from_vals(&tape, 2, 2, &[1., 0., 0., 1.]);
+/// let v = Mat::from_vals(&tape, 2, 1, &[3., -1.]);
+/// let out = attention(&q, &k, &v, false);
+/// // the query is aligned with the first key, so it mostly reads the first value
+/// assert!((out[(0, 0)].val() - 3.).abs() < 1e-2);
+/// ```
+///
+/// # Panics
+///
+/// Panics if the shapes do not match, any of the matrices is empty, or the variables are on
+/// different tapes.
+pub fn attention<'a>(q: &Mat<'a>, k: &Mat<'a>, v: &Mat<'a>, causal: bool) -> Mat<'a> {
+    let ((n, d), (m, p)) = (q.shape(), v.shape());
+    assert_eq!(
+        k.shape(),
+        (m, d),
+        "keys do not match the queries and values"
+    );
+    assert!(n * d * p > 0, "cannot attend over empty matrices");
+    let tape: &'a Tape = q.as_slice()[0].tape;
+    let inputs = [q, k, v]
+        .iter()
+        .flat_map(|mat| mat.as_slice())
+        .map(|x| {
+            assert_same_tape("attention", tape, x.tape);
+            x.location
+        })
+        .collect();
+    let (q, k, v) = (q.vals(), k.vals(), v.vals());
+    let scale = 1. / (d as f64).sqrt();
+    let masked = move |i: usize, j: usize| causal && j > i;
+    let mut weights = matmul(&q, &transpose(&k, m, d), n, d, m);
+    weights.iter_mut().for_each(|s| *s *= scale);
+    softmax_rows(&mut weights, m, masked);
+    let out = matmul(&weights, &v, n, m, p);
+
+    let start = tape.add_block(Op::Attention, &out, inputs, move |out_bar, in_bars| {
+        let (q_bar, rest) = in_bars.split_at_mut(n * d);
+        let (k_bar, v_bar) = rest.split_at_mut(m * d);
+        v_bar.copy_from_slice(&matmul(&transpose(&weights, n, m), out_bar, m, n, p));
+        // adjoints of the scaled scores, through the softmax of each row
+        let mut s_bar = matmul(out_bar, &transpose(&v, m, p), n, p, m);
+        for (row, w) in s_bar.chunks_mut(m).zip(weights.chunks(m)) {
+            let mean = row.iter().zip(w).map(|(g, w)| g * w).sum::<f64>();
+            for (g, w) in row.iter_mut().zip(w) {
+                *g = w * (*g - mean) * scale;
+            }
+        }
+        q_bar.copy_from_slice(&matmul(&s_bar, &k, n, m, d));
+        k_bar.copy_from_slice(&matmul(&transpose(&s_bar, n, m), &q, m, n, d));
+    });
+    let data = (0..n * p)
+        .map(|i| Var {
+            val: out[i],
+            location: start + i,
+            tape,
+        })
+        .collect();
+    Mat::new(n, p, data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{gradcheck, Gradient};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_attention() {
+        let g = Tape::new();
+        let q = Mat::from_vals(&g, 2, 2, &[0.5, -1., 2., 0.3]);
+        let k = Mat::from_vals(&g, 3, 2, &[1., 0., -0.4, 0.7, 0.2, 0.2]);
+        let v = Mat::from_vals(&g, 3, 1, &[1., 2., 4.]);
+        let len = g.len();
+        let out = attention(&q, &k, &v, true);
+        assert_eq!(g.len(), len + 2);
+        // with the causal mask the first query only sees the first key
+        assert_approx_eq!(out[(0, 0)].val(), 1.);
+        assert_eq!(out[(0, 0)].grad().wrt(&q[(0, 0)]), 0.);
+
+        for causal in [false, true] {
+            let check = gradcheck(
+                |x| {
+                    let q = Mat::new(2, 2, x[..4].to_vec());
+                    let k = Mat::new(3, 2, x[4..10].to_vec());
+                    let v = Mat::new(3, 2, x[10..].to_vec());
+                    let out = attention(&q, &k, &v, causal);
+                    out[(0, 1)] * 2. + out[(1, 0)] * out[(1, 1)]
+                },
+                &[
+                    0.5, -1., 2., 0.3, 1., 0., -0.4, 0.7, 0.2, 0.2, 1., -1., 2., 0.5, 4., 3.,
+                ],
+                1e-6,
+            );
+            assert!(check.passed(), "{}", check);
+        }
+    }
+}
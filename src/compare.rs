@@ -0,0 +1,101 @@
+//! Approximate comparisons of variables with the `approx` crate, enabled with the `approx` feature.
+//!
+//! Variables compare by their values, as with `PartialEq`, against other variables or `f64`s. The
+//! tolerances are `f64`s, since a variable cannot be created without a tape.
+//!
+//! ```rust
+//! use approx::assert_relative_eq;
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_var(0.1);
+//! assert_relative_eq!(x * 3., 0.3);
+//! assert_relative_eq!(x * 3., x + x + x);
+//! ```
+
+use crate::Var;
+use approx::{AbsDiffEq, RelativeEq, UlpsEq};
+
+impl<'a> AbsDiffEq for Var<'a> {
+    type Epsilon = f64;
+
+    fn default_epsilon() -> f64 {
+        f64::default_epsilon()
+    }
+
+    fn abs_diff_eq(&self, other: &Self, epsilon: f64) -> bool {
+        self.val.abs_diff_eq(&other.val, epsilon)
+    }
+}
+
+impl<'a> AbsDiffEq<f64> for Var<'a> {
+    type Epsilon = f64;
+
+    fn default_epsilon() -> f64 {
+        f64::default_epsilon()
+    }
+
+    fn abs_diff_eq(&self, other: &f64, epsilon: f64) -> bool {
+        self.val.abs_diff_eq(other, epsilon)
+    }
+}
+
+impl<'a> RelativeEq for Var<'a> {
+    fn default_max_relative() -> f64 {
+        f64::default_max_relative()
+    }
+
+    fn relative_eq(&self, other: &Self, epsilon: f64, max_relative: f64) -> bool {
+        self.val.relative_eq(&other.val, epsilon, max_relative)
+    }
+}
+
+impl<'a> RelativeEq<f64> for Var<'a> {
+    fn default_max_relative() -> f64 {
+        f64::default_max_relative()
+    }
+
+    fn relative_eq(&self, other: &f64, epsilon: f64, max_relative: f64) -> bool {
+        self.val.relative_eq(other, epsilon, max_relative)
+    }
+}
+
+impl<'a> UlpsEq for Var<'a> {
+    fn default_max_ulps() -> u32 {
+        f64::default_max_ulps()
+    }
+
+    fn ulps_eq(&self, other: &Self, epsilon: f64, max_ulps: u32) -> bool {
+        self.val.ulps_eq(&other.val, epsilon, max_ulps)
+    }
+}
+
+impl<'a> UlpsEq<f64> for Var<'a> {
+    fn default_max_ulps() -> u32 {
+        f64::default_max_ulps()
+    }
+
+    fn ulps_eq(&self, other: &f64, epsilon: f64, max_ulps: u32) -> bool {
+        self.val.ulps_eq(other, epsilon, max_ulps)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Tape;
+    use approx::{abs_diff_eq, assert_abs_diff_eq, assert_ulps_eq, relative_ne};
+
+    #[test]
+    fn test_approx() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.1, 0.2, 1e6]);
+        let y = x[0] + x[1];
+        assert_ulps_eq!(y, 0.3);
+        assert_abs_diff_eq!(y, x[1] * 1.5, epsilon = 1e-15);
+        assert!(abs_diff_eq!(x[0], 0.11, epsilon = 0.02));
+        assert!(!abs_diff_eq!(x[0], 0.11, epsilon = 0.005));
+        assert!(x[2].relative_eq(&(x[2] + 0.5), 0., 1e-6));
+        assert!(relative_ne!(x[2], x[2] + 0.5, max_relative = 1e-8));
+    }
+}
@@ -0,0 +1,296 @@
+//! Flattening a recorded tape into a program that can be evaluated repeatedly at new inputs.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2.]);
+//! let y = (x[0] * x[1]).ln() + x[1].powi(2);
+//! let program = tape.compile().unwrap();
+//!
+//! for point in [[1., 2.], [0.5, 3.]] {
+//!     let vals = program.eval(&point);
+//!     assert_eq!(vals.wrt(&y), (point[0] * point[1]).ln() + point[1].powi(2));
+//!     let grads = program.grad(&point, &y);
+//!     assert_eq!(grads.wrt(&x), vec![1. / point[0], 1. / point[1] + 2. * point[1]]);
+//! }
+//! ```
+//!
+//! Programs are interpreted. With the `jit` feature, `Program::jit` translates a program into
+//! native code with Cranelift instead.
+
+use crate::{
+    replay::{check_replayable, ReplayError},
+    Op, Tape, Var,
+};
+use std::ops::{Add, Mul};
+
+/// Single step of a `Program`, writing the value of the node at the same position.
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Instr {
+    /// Reads the input with this index.
+    Input(usize),
+    Const(f64),
+    /// Scalar operation on the values at two locations, which are equal for unary operations.
+    Scalar(Op, usize, usize),
+    /// Operation on the values at the locations in a range of `Program::operands`.
+    Nary(Op, usize, usize),
+}
+
+/// Tape flattened into a list of instructions, created with `Tape::compile`. Unlike the tape, a
+/// program does not borrow anything, can be shared between threads, and evaluates without
+/// updating any recorded state.
+#[derive(Debug, Clone)]
+pub struct Program {
+    pub(crate) instrs: Vec<Instr>,
+    pub(crate) operands: Vec<usize>,
+    /// Coefficients of the operands of `Op::Linear` instructions, and zero for the others.
+    pub(crate) coeffs: Vec<f64>,
+    pub(crate) inputs: usize,
+}
+
+impl Tape {
+    /// Compile the operations recorded so far into a `Program`. The inputs of the program are the
+    /// variables added with `add_var`, in the order they were added.
+    ///
+    /// Returns an error if the tape holds an operation that cannot be re-evaluated.
+    pub fn compile(&self) -> Result<Program, ReplayError> {
+        let ops = self.ops.borrow();
+        check_replayable(&ops)?;
+        let spans = self.spans.borrow();
+        let mut spans = spans.iter().peekable();
+        let all_operands = self.operands.borrow();
+        let mut operands = vec![];
+        let mut coeffs = vec![];
+        let mut inputs = 0;
+        let instrs = ops
+            .iter()
+            .zip(self.nodes.borrow().iter())
+            .enumerate()
+            .map(|(idx, (&op, node))| {
+                if let Some(span) = spans.next_if(|span| span.node == idx) {
+                    let start = operands.len();
+                    for &(dep, weight) in &all_operands[span.start..span.end] {
+                        operands.push(dep);
+                        coeffs.push(if op == Op::Linear { weight } else { 0. });
+                    }
+                    return Instr::Nary(op, start, operands.len());
+                }
+                match op {
+                    Op::Input => {
+                        inputs += 1;
+                        Instr::Input(inputs - 1)
+                    }
+                    Op::Const(val) => Instr::Const(val),
+                    op => Instr::Scalar(op, node.dependencies()[0], node.dependencies()[1]),
+                }
+            })
+            .collect();
+        Ok(Program {
+            instrs,
+            operands,
+            coeffs,
+            inputs,
+        })
+    }
+}
+
+impl Program {
+    /// Gets the number of inputs the program expects.
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Gets the number of instructions, one for each node of the compiled tape.
+    pub fn len(&self) -> usize {
+        self.instrs.len()
+    }
+
+    /// Checks whether the program is empty.
+    pub fn is_empty(&self) -> bool {
+        self.instrs.is_empty()
+    }
+
+    /// Evaluate every node at `inputs`. The value of a variable from the compiled tape is read
+    /// with `Gradient::wrt`, as for gradients.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of inputs is wrong.
+    pub fn eval(&self, inputs: &[f64]) -> Vec<f64> {
+        // every operation in a program can be evaluated at real values
+        self.forward(inputs, false).unwrap().0
+    }
+
+    /// Calculate the gradient of `output` at `inputs` with respect to every node, like
+    /// `Var::grad`. `output` must be a variable from the compiled tape.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of inputs is wrong or `output` was recorded after compiling.
+    pub fn grad(&self, inputs: &[f64], output: &Var) -> Vec<f64> {
+        self.gradient(inputs, output).unwrap()
+    }
+
+    /// Calculate the gradient of `output` at `inputs` over any `Domain`.
+    pub(crate) fn gradient<T: Domain>(
+        &self,
+        inputs: &[T],
+        output: &Var,
+    ) -> Result<Vec<T>, ReplayError> {
+        let (_, scalar, nary) = self.forward(inputs, true)?;
+        let mut derivs = vec![T::constant(0.); self.instrs.len()];
+        derivs[output.location] = T::constant(1.);
+        for (idx, instr) in self.instrs.iter().enumerate().rev() {
+            let deriv = derivs[idx];
+            match *instr {
+                Instr::Scalar(_, x, y) => {
+                    derivs[x] = derivs[x] + scalar[idx][0] * deriv;
+                    derivs[y] = derivs[y] + scalar[idx][1] * deriv;
+                }
+                Instr::Nary(_, start, end) => {
+                    for (&dep, &weight) in self.operands[start..end].iter().zip(&nary[start..end]) {
+                        derivs[dep] = derivs[dep] + weight * deriv;
+                    }
+                }
+                Instr::Input(_) | Instr::Const(_) => {}
+            }
+        }
+        Ok(derivs)
+    }
+
+    /// Evaluate every node at `inputs`. If `partials` is set, also returns the partial derivatives
+    /// of the scalar nodes by location, and the weights of the operands of the other nodes.
+    #[allow(clippy::type_complexity)]
+    pub(crate) fn forward<T: Domain>(
+        &self,
+        inputs: &[T],
+        partials: bool,
+    ) -> Result<(Vec<T>, Vec<[T; 2]>, Vec<T>), ReplayError> {
+        assert_eq!(
+            inputs.len(),
+            self.inputs,
+            "wrong number of inputs for the program"
+        );
+        let zero = T::constant(0.);
+        let (mut scalar, mut nary) = if partials {
+            (
+                vec![[zero; 2]; self.instrs.len()],
+                vec![zero; self.operands.len()],
+            )
+        } else {
+            (vec![], vec![])
+        };
+        let mut vals = Vec::with_capacity(self.instrs.len());
+        for (idx, instr) in self.instrs.iter().enumerate() {
+            let unsupported = |op: Op| ReplayError::Unsupported {
+                location: idx,
+                op: format!("{:?}", op),
+            };
+            let val = match *instr {
+                Instr::Input(i) => inputs[i],
+                Instr::Const(val) => T::constant(val),
+                Instr::Scalar(op, x, y) => {
+                    let (val, d_x, d_y) =
+                        T::eval(op, vals[x], vals[y]).ok_or_else(|| unsupported(op))?;
+                    if partials {
+                        scalar[idx] = [d_x, d_y];
+                    }
+                    val
+                }
+                Instr::Nary(Op::Linear, start, end) => {
+                    let coeffs = self.coeffs[start..end].iter().map(|&c| T::constant(c));
+                    if partials {
+                        nary[start..end]
+                            .iter_mut()
+                            .zip(coeffs.clone())
+                            .for_each(|(weight, c)| *weight = c);
+                    }
+                    self.operands[start..end]
+                        .iter()
+                        .zip(coeffs)
+                        .fold(zero, |acc, (&dep, c)| acc + c * vals[dep])
+                }
+                Instr::Nary(op, start, end) => {
+                    let xs = self.operands[start..end]
+                        .iter()
+                        .map(|&dep| vals[dep])
+                        .collect::<Vec<_>>();
+                    let (val, weights) = T::eval_nary(op, &xs).ok_or_else(|| unsupported(op))?;
+                    if partials {
+                        nary[start..end].copy_from_slice(&weights);
+                    }
+                    val
+                }
+            };
+            vals.push(val);
+        }
+        Ok((vals, scalar, nary))
+    }
+}
+
+/// Numbers that a `Program` can be evaluated over.
+pub(crate) trait Domain: Copy + Add<Output = Self> + Mul<Output = Self> {
+    fn constant(val: f64) -> Self;
+
+    /// Value and partial derivatives of the scalar operation `op`, like `Op::eval`, or `None` if
+    /// it cannot be evaluated in this domain.
+    fn eval(op: Op, x: Self, y: Self) -> Option<(Self, Self, Self)>;
+
+    /// Value and partial derivatives of the operation `op` on several values, like
+    /// `Op::eval_nary`, or `None` if it cannot be evaluated in this domain.
+    fn eval_nary(op: Op, xs: &[Self]) -> Option<(Self, Vec<Self>)>;
+}
+
+impl Domain for f64 {
+    fn constant(val: f64) -> Self {
+        val
+    }
+
+    fn eval(op: Op, x: Self, y: Self) -> Option<(Self, Self, Self)> {
+        Some(op.eval(x, y))
+    }
+
+    fn eval_nary(op: Op, xs: &[Self]) -> Option<(Self, Vec<Self>)> {
+        Some(op.eval_nary(xs))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{det, sum, Gradient, Mat};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_compile() {
+        fn f<'a>(x: &[Var<'a>]) -> Var<'a> {
+            let m = Mat::new(2, 2, vec![x[0], x[1], x[2], x[0] * x[1]]);
+            det(&m) * x[2].tanh() + sum(x).sqrt() - 3. / x[1]
+        }
+
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2., 3.]);
+        let y = f(&x);
+        let before = y.grad();
+        let program = g.compile().unwrap();
+        assert_eq!(program.len(), g.len());
+        assert_eq!(program.inputs(), 3);
+
+        for point in [[1., 2., 3.], [0.3, -1.5, 4.]] {
+            let fresh = Tape::new();
+            let x2 = fresh.add_vars(&point);
+            let y2 = f(&x2);
+            assert_approx_eq!(program.eval(&point).wrt(&y), y2.val());
+            let grads = program.grad(&point, &y).wrt(&x);
+            for (grad, expected) in grads.iter().zip(y2.grad().wrt(&x2)) {
+                assert_approx_eq!(*grad, expected);
+            }
+        }
+        // evaluating the program leaves the tape as it was
+        assert_eq!(y.grad(), before);
+
+        let _ = crate::inv(&Mat::from_vals(&g, 1, 1, &[2.]));
+        assert!(g.compile().is_err());
+    }
+}
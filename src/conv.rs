@@ -0,0 +1,110 @@
+use crate::{reduce::fused_dot, Mat, Var};
+
+/// Number of outputs of a convolution along a dimension of length `len`.
+fn output_len(len: usize, kernel: usize, stride: usize, padding: usize) -> usize {
+    assert!(stride > 0, "stride must be positive");
+    assert!(kernel > 0, "kernel must not be empty");
+    let padded = len + 2 * padding;
+    assert!(padded >= kernel, "kernel is larger than the padded input");
+    (padded - kernel) / stride + 1
+}
+
+/// Index into the unpadded input for kernel offset `k` of output `i`, if it is not padding.
+fn input_index(i: usize, k: usize, stride: usize, padding: usize, len: usize) -> Option<usize> {
+    (i * stride + k)
+        .checked_sub(padding)
+        .filter(|&idx| idx < len)
+}
+
+/// Calculate the 1D convolution (cross-correlation, as in most neural network libraries) of
+/// `input` with `kernel`, moving the kernel by `stride` and implicitly zero padding `padding`
+/// entries on each side. Each output is recorded on the tape as a single fused node.
+pub fn conv1d<'a>(
+    input: &[Var<'a>],
+    kernel: &[Var<'a>],
+    stride: usize,
+    padding: usize,
+) -> Vec<Var<'a>> {
+    let n = input.len();
+    let tape = kernel.first().expect("kernel must not be empty").tape;
+    (0..output_len(n, kernel.len(), stride, padding))
+        .map(|i| {
+            let pairs = kernel.iter().enumerate().filter_map(|(k, &w)| {
+                input_index(i, k, stride, padding, n).map(|idx| (input[idx], w))
+            });
+            fused_dot("conv1d", tape, pairs)
+        })
+        .collect()
+}
+
+/// Calculate the 2D convolution (cross-correlation) of `input` with `kernel`, using `stride` and
+/// zero `padding` given as `(rows, cols)`. Each output is recorded on the tape as a single fused
+/// node.
+pub fn conv2d<'a>(
+    input: &Mat<'a>,
+    kernel: &Mat<'a>,
+    stride: (usize, usize),
+    padding: (usize, usize),
+) -> Mat<'a> {
+    let (rows, cols) = input.shape();
+    let (k_rows, k_cols) = kernel.shape();
+    let out_rows = output_len(rows, k_rows, stride.0, padding.0);
+    let out_cols = output_len(cols, k_cols, stride.1, padding.1);
+    let tape = kernel.as_slice()[0].tape;
+    let data = (0..out_rows)
+        .flat_map(|i| (0..out_cols).map(move |j| (i, j)))
+        .map(|(i, j)| {
+            let pairs = (0..k_rows)
+                .flat_map(|ki| (0..k_cols).map(move |kj| (ki, kj)))
+                .filter_map(|(ki, kj)| {
+                    let r = input_index(i, ki, stride.0, padding.0, rows)?;
+                    let c = input_index(j, kj, stride.1, padding.1, cols)?;
+                    Some((input[(r, c)], kernel[(ki, kj)]))
+                });
+            fused_dot("conv2d", tape, pairs)
+        })
+        .collect();
+    Mat::new(out_rows, out_cols, data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Gradient, Tape};
+
+    #[test]
+    fn test_conv1d() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[1., 2., 3., 4., 5.]);
+        let w = g.add_vars(&[1., 0., -1.]);
+        let res = conv1d(&xs, &w, 1, 0);
+        assert_eq!(
+            res.iter().map(|v| v.val()).collect::<Vec<_>>(),
+            vec![-2.; 3]
+        );
+
+        let res = conv1d(&xs, &w, 2, 1);
+        let vals = res.iter().map(|v| v.val()).collect::<Vec<_>>();
+        assert_eq!(vals, vec![-2., -2., 4.]);
+        let grads = res[2].grad();
+        assert_eq!(grads.wrt(&xs), vec![0., 0., 0., 1., 0.]);
+        assert_eq!(grads.wrt(&w), vec![4., 5., 0.]);
+    }
+
+    #[test]
+    fn test_conv2d() {
+        let g = Tape::new();
+        let x = Mat::from_vals(&g, 3, 3, &[1., 2., 3., 4., 5., 6., 7., 8., 9.]);
+        let w = Mat::from_vals(&g, 2, 2, &[1., 0., 0., -1.]);
+        let res = conv2d(&x, &w, (1, 1), (0, 0));
+        assert_eq!(res.shape(), (2, 2));
+        assert_eq!(res.vals(), vec![-4.; 4]);
+
+        let res = conv2d(&x, &w, (2, 2), (1, 1));
+        assert_eq!(res.shape(), (2, 2));
+        assert_eq!(res.vals(), vec![-1., -3., -7., -4.]);
+        let grads = res[(1, 1)].grad();
+        assert_eq!(grads.wrt(&x), vec![0., 0., 0., 0., 1., 0., 0., 0., -1.]);
+        assert_eq!(grads.wrt(&w), vec![5., 6., 8., 9.]);
+    }
+}
@@ -0,0 +1,196 @@
+//! Structured collections of variables, which can be flattened into a list of variables to
+//! differentiate with respect to and rebuilt from a list of values of the same length.
+//!
+//! With the `derive` feature, `Differentiable` can be derived for structs of variables:
+//!
+//! ```rust
+//! # #[cfg(feature = "derive")]
+//! # fn main() {
+//! use reverse::*;
+//!
+//! #[derive(Differentiable)]
+//! struct Params<'a> {
+//!     weights: Vec<Var<'a>>,
+//!     bias: Var<'a>,
+//! }
+//!
+//! let tape = Tape::new();
+//! let params = Params {
+//!     weights: tape.add_vars(&[1., 2.]),
+//!     bias: tape.add_var(0.5),
+//! };
+//! let res = params.weights[0] * params.weights[1] + params.bias;
+//! let grads: ParamsGrad = res.grad().wrt(&params);
+//! assert_eq!(grads.weights, vec![2., 1.]);
+//! assert_eq!(grads.bias, 1.);
+//! # }
+//! # #[cfg(not(feature = "derive"))]
+//! # fn main() {}
+//! ```
+
+use crate::{Gradient, Var};
+
+/// Collection of variables with a fixed shape. `Values` has the same shape with an `f64` in place
+/// of each variable, and is used for both values and gradients.
+pub trait Differentiable<'a> {
+    type Values;
+
+    /// Append the variables in `self` to `vars`, in order.
+    fn push_vars(&self, vars: &mut Vec<Var<'a>>);
+
+    /// Build values of the same shape as `self`, taking one value for each variable from `vals`.
+    fn take_values(&self, vals: &mut dyn Iterator<Item = f64>) -> Self::Values;
+
+    /// Gets every variable, in the order used by `unflatten`.
+    fn flatten(&self) -> Vec<Var<'a>> {
+        let mut vars = vec![];
+        self.push_vars(&mut vars);
+        vars
+    }
+
+    /// Arrange `vals`, with one value for each variable in the order of `flatten`, into the shape
+    /// of `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of values is wrong.
+    fn unflatten(&self, vals: &[f64]) -> Self::Values {
+        let mut iter = vals.iter().copied();
+        let res = self.take_values(&mut iter);
+        assert!(iter.next().is_none(), "too many values to unflatten");
+        res
+    }
+
+    /// Gets the values of the variables, in the shape of `self`.
+    fn vals(&self) -> Self::Values {
+        let vals = self.flatten().iter().map(Var::val).collect::<Vec<_>>();
+        self.unflatten(&vals)
+    }
+
+    /// Gets the gradients `grads` with respect to the variables, in the shape of `self`.
+    fn grad_wrt<G>(&self, grads: &G) -> Self::Values
+    where
+        G: for<'v> Gradient<&'v [Var<'a>], Vec<f64>>,
+    {
+        self.unflatten(&grads.wrt(&self.flatten()[..]))
+    }
+}
+
+impl<'a> Differentiable<'a> for Var<'a> {
+    type Values = f64;
+
+    fn push_vars(&self, vars: &mut Vec<Var<'a>>) {
+        vars.push(*self);
+    }
+
+    fn take_values(&self, vals: &mut dyn Iterator<Item = f64>) -> f64 {
+        vals.next().expect("not enough values to unflatten")
+    }
+}
+
+impl<'a, T: Differentiable<'a>> Differentiable<'a> for Vec<T> {
+    type Values = Vec<T::Values>;
+
+    fn push_vars(&self, vars: &mut Vec<Var<'a>>) {
+        self.iter().for_each(|x| x.push_vars(vars));
+    }
+
+    fn take_values(&self, vals: &mut dyn Iterator<Item = f64>) -> Self::Values {
+        self.iter().map(|x| x.take_values(vals)).collect()
+    }
+}
+
+impl<'a, T: Differentiable<'a>, const N: usize> Differentiable<'a> for [T; N] {
+    type Values = [T::Values; N];
+
+    fn push_vars(&self, vars: &mut Vec<Var<'a>>) {
+        self.iter().for_each(|x| x.push_vars(vars));
+    }
+
+    fn take_values(&self, vals: &mut dyn Iterator<Item = f64>) -> Self::Values {
+        let mut items = self.iter();
+        [(); N].map(|_| items.next().unwrap().take_values(vals))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Tape;
+
+    #[test]
+    fn test_flatten() {
+        let g = Tape::new();
+        let nested = vec![g.add_vars(&[1., 2.]), vec![], g.add_vars(&[3.])];
+        let pair = [g.add_var(4.), g.add_var(5.)];
+        assert_eq!(nested.flatten().len(), 3);
+        assert_eq!(nested.vals(), vec![vec![1., 2.], vec![], vec![3.]]);
+        let res = nested[0][1] * nested[2][0] + pair[0] * 2.;
+        let grads = res.grad();
+        assert_eq!(
+            nested.grad_wrt(&grads),
+            vec![vec![0., 3.], vec![], vec![2.]]
+        );
+        assert_eq!(pair.grad_wrt(&grads), [2., 0.]);
+    }
+
+    #[test]
+    #[should_panic(expected = "not enough values")]
+    fn test_unflatten_length() {
+        let g = Tape::new();
+        let _ = g.add_vars(&[1., 2.]).unflatten(&[1.]);
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_derive() {
+        use crate::Differentiable;
+
+        #[derive(Differentiable)]
+        struct Layer<'a> {
+            weights: Vec<Var<'a>>,
+            bias: Var<'a>,
+        }
+
+        #[derive(Differentiable)]
+        struct Model<'p> {
+            layers: Vec<Layer<'p>>,
+            scale: [Var<'p>; 2],
+        }
+
+        let g = Tape::new();
+        let model = Model {
+            layers: vec![
+                Layer {
+                    weights: g.add_vars(&[1., 2.]),
+                    bias: g.add_var(3.),
+                },
+                Layer {
+                    weights: g.add_vars(&[4.]),
+                    bias: g.add_var(5.),
+                },
+            ],
+            scale: [g.add_var(6.), g.add_var(7.)],
+        };
+        assert_eq!(model.flatten().len(), 7);
+        let res = model.layers[0].weights[1] * model.layers[1].bias + model.scale[1];
+        let grads: ModelGrad = res.grad().wrt(&model);
+        assert_eq!(
+            grads,
+            ModelGrad {
+                layers: vec![
+                    LayerGrad {
+                        weights: vec![0., 5.],
+                        bias: 0.,
+                    },
+                    LayerGrad {
+                        weights: vec![0.],
+                        bias: 2.,
+                    },
+                ],
+                scale: [0., 1.],
+            }
+        );
+        assert_eq!(model.vals().scale, [6., 7.]);
+    }
+}
@@ -0,0 +1,355 @@
+//! Log-densities of common probability distributions, parameterized by differentiable variables.
+//!
+//! The point at which a density is evaluated can either be an `f64` (for observed data) or a
+//! `Var<'a>` on the same tape as the parameters.
+//!
+//! ```rust
+//! use reverse::*;
+//! use reverse::distributions::{LogDensity, Normal};
+//!
+//! let tape = Tape::new();
+//! let params = tape.add_vars(&[0.5, 2.]);
+//! let model = Normal::new(params[0], params[1]);
+//! let ll = [1.2, -0.3, 0.8].iter().map(|&x| model.ln_pdf(x)).sum::<Var>();
+//! let gradients = ll.grad().wrt(&params);
+//! ```
+//!
+//! The `*_rsample` functions draw from a distribution by reparameterization: the noise is an
+//! `f64` drawn by the caller from a fixed distribution, and the sample is a differentiable
+//! function of the parameters. Averaging over samples then gives stochastic gradients of an
+//! expectation with respect to the parameters, as in variational inference.
+//!
+//! ```rust
+//! use reverse::*;
+//! use reverse::distributions::normal_rsample;
+//!
+//! let tape = Tape::new();
+//! let (mu, sigma) = (tape.add_var(1.), tape.add_var(0.5));
+//! // E[x^2] = mu^2 + sigma^2, estimated with antithetic standard normal draws
+//! let noise = [0.3, -0.3, 1.2, -1.2];
+//! let estimate = noise
+//!     .iter()
+//!     .map(|&eps| normal_rsample(mu, sigma, eps).powi(2))
+//!     .sum::<Var>()
+//!     * 0.25;
+//! let grads = estimate.grad();
+//! assert!((grads.wrt(&mu) - 2.).abs() < 1e-12);
+//! assert!((grads.wrt(&sigma) - 0.5 * 1.53).abs() < 1e-12);
+//! ```
+
+use crate::{logsumexp, Var};
+use std::f64::consts::PI;
+use std::ops::{Mul, Sub};
+
+/// Distributions with a differentiable log-density.
+pub trait LogDensity<'a, X = f64> {
+    /// Natural logarithm of the probability density at `x`.
+    fn ln_pdf(&self, x: X) -> Var<'a>;
+}
+
+/// Numerically stable `ln(1 + exp(x))`.
+fn softplus(x: Var) -> Var {
+    if x.val > 0. {
+        x + (-x).exp().ln_1p()
+    } else {
+        x.exp().ln_1p()
+    }
+}
+
+/// Normal distribution with mean `mu` and standard deviation `sigma`.
+#[derive(Debug, Clone, Copy)]
+pub struct Normal<'a> {
+    pub mu: Var<'a>,
+    pub sigma: Var<'a>,
+}
+
+impl<'a> Normal<'a> {
+    pub fn new(mu: Var<'a>, sigma: Var<'a>) -> Self {
+        Self { mu, sigma }
+    }
+}
+
+impl<'a, X> LogDensity<'a, X> for Normal<'a>
+where
+    X: Sub<Var<'a>, Output = Var<'a>>,
+{
+    fn ln_pdf(&self, x: X) -> Var<'a> {
+        let z = (x - self.mu) / self.sigma;
+        -0.5 * z.powi(2) - self.sigma.ln() - 0.5 * (2. * PI).ln()
+    }
+}
+
+/// Exponential distribution with rate `rate`.
+#[derive(Debug, Clone, Copy)]
+pub struct Exponential<'a> {
+    pub rate: Var<'a>,
+}
+
+impl<'a> Exponential<'a> {
+    pub fn new(rate: Var<'a>) -> Self {
+        Self { rate }
+    }
+}
+
+impl<'a, X> LogDensity<'a, X> for Exponential<'a>
+where
+    X: PartialOrd<f64> + Mul<Var<'a>, Output = Var<'a>>,
+{
+    fn ln_pdf(&self, x: X) -> Var<'a> {
+        if x < 0. {
+            self.rate.tape.constant(f64::NEG_INFINITY)
+        } else {
+            self.rate.ln() - x * self.rate
+        }
+    }
+}
+
+/// Logistic distribution with location `mu` and scale `s`.
+#[derive(Debug, Clone, Copy)]
+pub struct Logistic<'a> {
+    pub mu: Var<'a>,
+    pub s: Var<'a>,
+}
+
+impl<'a> Logistic<'a> {
+    pub fn new(mu: Var<'a>, s: Var<'a>) -> Self {
+        Self { mu, s }
+    }
+}
+
+impl<'a, X> LogDensity<'a, X> for Logistic<'a>
+where
+    X: Sub<Var<'a>, Output = Var<'a>>,
+{
+    fn ln_pdf(&self, x: X) -> Var<'a> {
+        let z = (x - self.mu) / self.s;
+        -z - self.s.ln() - 2. * softplus(-z)
+    }
+}
+
+/// Continuous uniform distribution on `[low, high]`.
+#[derive(Debug, Clone, Copy)]
+pub struct Uniform<'a> {
+    pub low: Var<'a>,
+    pub high: Var<'a>,
+}
+
+impl<'a> Uniform<'a> {
+    pub fn new(low: Var<'a>, high: Var<'a>) -> Self {
+        Self { low, high }
+    }
+}
+
+impl<'a, X> LogDensity<'a, X> for Uniform<'a>
+where
+    X: PartialOrd<f64>,
+{
+    fn ln_pdf(&self, x: X) -> Var<'a> {
+        if x < self.low.val || x > self.high.val {
+            self.low.tape.constant(f64::NEG_INFINITY)
+        } else {
+            -(self.high - self.low).ln()
+        }
+    }
+}
+
+/// Sample from `Normal::new(mu, sigma)` as `mu + sigma * eps`, given standard normal noise `eps`.
+pub fn normal_rsample<'a>(mu: Var<'a>, sigma: Var<'a>, eps: f64) -> Var<'a> {
+    mu + sigma * eps
+}
+
+/// Sample from the log-normal distribution whose logarithm is normal with mean `mu` and standard
+/// deviation `sigma`, as `exp(mu + sigma * eps)` given standard normal noise `eps`.
+pub fn lognormal_rsample<'a>(mu: Var<'a>, sigma: Var<'a>, eps: f64) -> Var<'a> {
+    normal_rsample(mu, sigma, eps).exp()
+}
+
+/// Assert that `u` is valid uniform noise, in `[0, 1)` or, if `open`, in `(0, 1)`.
+fn check_uniform(u: f64, open: bool) {
+    assert!(
+        u < 1. && (u > 0. || !open && u == 0.),
+        "uniform noise {} is out of range",
+        u
+    );
+}
+
+/// Sample from `Logistic::new(mu, s)` by inverting its CDF, as `mu + s * ln(u / (1 - u))` given
+/// uniform noise `u`.
+///
+/// # Panics
+///
+/// Panics if `u` is not in `(0, 1)`.
+pub fn logistic_rsample<'a>(mu: Var<'a>, s: Var<'a>, u: f64) -> Var<'a> {
+    check_uniform(u, true);
+    mu + s * (u / (1. - u)).ln()
+}
+
+/// Sample from `Exponential::new(rate)` by inverting its CDF, as `-ln(1 - u) / rate` given
+/// uniform noise `u`.
+///
+/// # Panics
+///
+/// Panics if `u` is not in `[0, 1)`.
+pub fn exponential_rsample(rate: Var<'_>, u: f64) -> Var<'_> {
+    check_uniform(u, false);
+    rate.recip() * -(-u).ln_1p()
+}
+
+/// Sample from `Uniform::new(low, high)` as `low + (high - low) * u`, given uniform noise `u`.
+///
+/// # Panics
+///
+/// Panics if `u` is not in `[0, 1)`.
+pub fn uniform_rsample<'a>(low: Var<'a>, high: Var<'a>, u: f64) -> Var<'a> {
+    check_uniform(u, false);
+    low + (high - low) * u
+}
+
+/// Draw a relaxed one-hot sample from the categorical distribution with unnormalized log
+/// probabilities `logits`, by the Gumbel-softmax (or concrete) relaxation. Given uniform noise `u`,
+/// one entry per logit, the sample is `softmax((logits + g) / temperature)` with the Gumbel noise
+/// `g = -ln(-ln(u))`. As the temperature goes to zero the sample approaches the one-hot encoding
+/// of `argmax(logits + g)`, which is an exact sample from the distribution.
+///
+/// The softmax is evaluated in log space, subtracting a single fused `logsumexp` node, so large
+/// logits or small temperatures do not overflow.
+///
+/// ```rust
+/// use reverse::*;
+/// use reverse::distributions::gumbel_softmax;
+///
+/// let tape = Tape::new();
+/// let logits = tape.add_vars(&[1., 2., 0.5]);
+/// let sample = gumbel_softmax(&logits, &[0.3, 0.6, 0.95], 0.01);
+/// // the third category has the largest perturbed logit
+/// assert!(sample[2].val() > 0.999);
+/// assert!((sample.iter().map(|s| s.val()).sum::<f64>() - 1.).abs() < 1e-12);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `logits` is empty, the lengths differ, the temperature is not positive, any `u` is
+/// not in `(0, 1)`, or the logits are on different tapes.
+pub fn gumbel_softmax<'a>(logits: &[Var<'a>], u: &[f64], temperature: f64) -> Vec<Var<'a>> {
+    assert_eq!(logits.len(), u.len(), "need one noise value for each logit");
+    assert!(temperature > 0., "temperature must be positive");
+    let z = logits
+        .iter()
+        .zip(u)
+        .map(|(&logit, &u)| {
+            check_uniform(u, true);
+            (logit - (-u.ln()).ln()) * temperature.recip()
+        })
+        .collect::<Vec<_>>();
+    let total = logsumexp(&z);
+    z.into_iter().map(|z| (z - total).exp()).collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Gradient, Tape};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_ln_pdf() {
+        let g = Tape::new();
+        let mu = g.add_var(0.5);
+        let sigma = g.add_var(2.);
+        let res = Normal::new(mu, sigma).ln_pdf(1.3);
+        assert_approx_eq!(res.val(), -1.692085713764618);
+        let grads = res.grad();
+        assert_approx_eq!(grads.wrt(&mu), 0.8 / 4.);
+        assert_approx_eq!(grads.wrt(&sigma), 0.64 / 8. - 0.5);
+
+        let x = g.add_var(1.3);
+        let res = Normal::new(mu, sigma).ln_pdf(x);
+        assert_approx_eq!(res.grad().wrt(&x), -0.8 / 4.);
+
+        let res = Exponential::new(sigma).ln_pdf(0.3);
+        assert_approx_eq!(res.val(), 2_f64.ln() - 0.6);
+        assert_approx_eq!(res.grad().wrt(&sigma), 0.5 - 0.3);
+        assert_eq!(Exponential::new(sigma).ln_pdf(-1.).val(), f64::NEG_INFINITY);
+
+        let res = Logistic::new(mu, sigma).ln_pdf(-800.);
+        assert_approx_eq!(res.val(), -400.25 - 2_f64.ln(), 1e-9);
+        let res = Logistic::new(mu, sigma).ln_pdf(1.3);
+        assert_approx_eq!(res.val(), -2.1191776853598503);
+
+        let res = Uniform::new(mu, sigma).ln_pdf(x);
+        assert_approx_eq!(res.val(), -1.5_f64.ln());
+        let grads = res.grad();
+        assert_approx_eq!(grads.wrt(&mu), 1. / 1.5);
+        assert_approx_eq!(grads.wrt(&sigma), -1. / 1.5);
+        assert_eq!(Uniform::new(mu, sigma).ln_pdf(2.5).val(), f64::NEG_INFINITY);
+    }
+
+    #[test]
+    fn test_rsample() {
+        let g = Tape::new();
+        let mu = g.add_var(0.5);
+        let sigma = g.add_var(2.);
+        let x = normal_rsample(mu, sigma, -0.4);
+        assert_approx_eq!(x.val(), -0.3);
+        let grads = x.grad();
+        assert_eq!((grads.wrt(&mu), grads.wrt(&sigma)), (1., -0.4));
+
+        let x = lognormal_rsample(mu, sigma, 0.25);
+        assert_approx_eq!(x.val(), 1_f64.exp());
+        assert_approx_eq!(x.grad().wrt(&sigma), 0.25 * 1_f64.exp());
+
+        // the logistic quantile at 1/2 is the location, where the derivative in the scale is zero
+        let x = logistic_rsample(mu, sigma, 0.5);
+        assert_eq!(x.val(), 0.5);
+        assert_eq!(x.grad().wrt(&sigma), 0.);
+        let x = logistic_rsample(mu, sigma, 0.75);
+        assert_approx_eq!(x.grad().wrt(&sigma), 3_f64.ln());
+
+        let x = exponential_rsample(sigma, 0.75);
+        assert_approx_eq!(x.val(), 4_f64.ln() / 2.);
+        assert_approx_eq!(x.grad().wrt(&sigma), -4_f64.ln() / 4.);
+        assert_eq!(exponential_rsample(sigma, 0.).val(), 0.);
+
+        let x = uniform_rsample(mu, sigma, 0.2);
+        assert_approx_eq!(x.val(), 0.8);
+        let grads = x.grad();
+        assert_approx_eq!(grads.wrt(&mu), 0.8);
+        assert_approx_eq!(grads.wrt(&sigma), 0.2);
+    }
+
+    #[test]
+    #[should_panic(expected = "out of range")]
+    fn test_rsample_noise_range() {
+        let g = Tape::new();
+        logistic_rsample(g.add_var(0.), g.add_var(1.), 0.);
+    }
+
+    #[test]
+    fn test_gumbel_softmax() {
+        let g = Tape::new();
+        let logits = g.add_vars(&[1., -0.5, 3.]);
+        let u = [0.2, 0.9, 0.4];
+        let sample = gumbel_softmax(&logits, &u, 0.5);
+        let perturbed = [1., -0.5, 3.]
+            .iter()
+            .zip(u)
+            .map(|(l, u): (&f64, f64)| ((l - (-u.ln()).ln()) / 0.5).exp())
+            .collect::<Vec<_>>();
+        let total = perturbed.iter().sum::<f64>();
+        for (s, p) in sample.iter().zip(&perturbed) {
+            assert_approx_eq!(s.val(), p / total);
+        }
+        // d y_0 / d logit_j = (y_0 (delta_0j - y_j)) / temperature
+        let y = sample.iter().map(|s| s.val()).collect::<Vec<_>>();
+        let grads = sample[0].grad().wrt(&logits);
+        assert_approx_eq!(grads[0], y[0] * (1. - y[0]) * 2.);
+        assert_approx_eq!(grads[2], -y[0] * y[2] * 2.);
+
+        // huge logits and a tiny temperature stay finite
+        let logits = g.add_vars(&[1e4, 0.]);
+        let sample = gumbel_softmax(&logits, &[0.5, 0.5], 1e-3);
+        assert_eq!(sample[0].val(), 1.);
+        assert_eq!(sample[1].val(), 0.);
+        assert!(sample[0].grad().wrt(&logits).iter().all(|g| g.is_finite()));
+    }
+}
@@ -0,0 +1,90 @@
+//! Conversions between `Mat` and the dense matrices of `nalgebra`, enabled with the `nalgebra`
+//! feature.
+//!
+//! `Var<'a>` borrows its tape, so it cannot be the scalar of a nalgebra matrix, which needs
+//! `'static` scalars and constants such as zero and one that exist without a tape. Instead, values
+//! cross over as `DMatrix<f64>` and `DVector<f64>`: the inputs are added to a tape as a `Mat` or as
+//! variables, the computation is written with `Mat` and the other functions of this crate, and the
+//! value and the gradients are converted back.
+//!
+//! ```rust
+//! use nalgebra::{dmatrix, dvector};
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let a = Mat::from_dmatrix(&tape, &dmatrix![2., 1.; 1., 3.]);
+//! let b = tape.add_vars(dvector![1., 2.].as_slice());
+//! let x = solve(&a, &b);
+//! let grad = (x[0] + x[1]).grad();
+//! // the gradient with respect to b solves the transposed system
+//! let lambda = grad.wrt_dvector(&b);
+//! assert!((a.to_dmatrix().transpose() * lambda - dvector![1., 1.]).norm() < 1e-12);
+//! assert_eq!(grad.wrt_dmatrix(&a).shape(), (2, 2));
+//! ```
+
+use crate::{Grad, Gradient, Mat, Tape, Var};
+use ::nalgebra::{DMatrix, DVector};
+
+impl<'a> Mat<'a> {
+    /// Add the values of `m` to `tape` as a matrix of variables of the same shape.
+    pub fn from_dmatrix(tape: &'a Tape, m: &DMatrix<f64>) -> Self {
+        let (rows, cols) = m.shape();
+        // nalgebra stores matrices in column-major order
+        Self::from_vals(tape, rows, cols, m.transpose().as_slice())
+    }
+
+    /// Gets the values of the entries as a nalgebra matrix.
+    pub fn to_dmatrix(&self) -> DMatrix<f64> {
+        DMatrix::from_row_slice(self.rows(), self.cols(), &self.vals())
+    }
+}
+
+impl<'a> Grad<'a> {
+    /// Calculate the gradient with respect to all entries of `m`, as a matrix of the same shape.
+    pub fn wrt_dmatrix(&self, m: &Mat<'a>) -> DMatrix<f64> {
+        DMatrix::from_row_slice(m.rows(), m.cols(), &self.wrt(m))
+    }
+
+    /// Calculate the gradient with respect to all variables in `v`, as a column vector.
+    pub fn wrt_dvector(&self, v: &[Var<'a>]) -> DVector<f64> {
+        DVector::from_vec(self.wrt(v))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{det, inv};
+    use ::nalgebra::dmatrix;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_dmatrix() {
+        let g = Tape::new();
+        let m = dmatrix![1., 2., 3.; 4., 5., 6.];
+        let a = Mat::from_dmatrix(&g, &m);
+        assert_eq!(a.shape(), (2, 3));
+        assert_eq!(a[(0, 2)].val(), 3.);
+        assert_eq!(a.to_dmatrix(), m);
+
+        // d det(A) / dA = det(A) A^-T
+        let m = dmatrix![2., -1., 0.; 1., 3., 2.; 0.5, 0., 1.];
+        let a = Mat::from_dmatrix(&g, &m);
+        let d = det(&a);
+        assert_approx_eq!(d.val(), m.determinant());
+        let expected = m.determinant() * m.clone().try_inverse().unwrap().transpose();
+        let grad = d.grad().wrt_dmatrix(&a);
+        for (x, y) in grad.iter().zip(expected.iter()) {
+            assert_approx_eq!(*x, *y);
+        }
+        let a_inv = inv(&a).to_dmatrix();
+        assert!((a_inv * &m - DMatrix::identity(3, 3)).norm() < 1e-12);
+
+        let x = g.add_vars(&[1., 2.]);
+        let y = x[0] * x[1].exp();
+        assert_eq!(
+            y.grad().wrt_dvector(&x),
+            DVector::from_vec(vec![2_f64.exp(), 2_f64.exp()])
+        );
+    }
+}
@@ -0,0 +1,113 @@
+//! Elementwise arithmetic between slices of variables, and between variables and constants.
+//!
+//! Operands of equal length are combined pairwise. An operand with a single entry is broadcast,
+//! that is applied to every entry of the other one.
+//!
+//! ```rust
+//! use reverse::*;
+//! use reverse::elementwise::{add, mul};
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2., 3.]);
+//! let scale = tape.add_var(2.);
+//! let y = add(&mul(&x, &[scale]), &[0.5, 0., -0.5]);
+//! assert_eq!(y.iter().map(|y| y.val).collect::<Vec<_>>(), vec![2.5, 4., 5.5]);
+//! assert_eq!(sum(&y).grad().wrt(&scale), 6.);
+//! ```
+
+use crate::Var;
+use std::ops::{Add, Div, Mul, Sub};
+
+/// Combine `xs` and `ys` pairwise with `f`, broadcasting an operand with a single entry.
+///
+/// # Panics
+///
+/// Panics if the lengths differ and neither is one.
+pub fn broadcast<'a, T, F>(xs: &[Var<'a>], ys: &[T], mut f: F) -> Vec<Var<'a>>
+where
+    T: Copy,
+    F: FnMut(Var<'a>, T) -> Var<'a>,
+{
+    match (xs.len(), ys.len()) {
+        (n, m) if n == m => xs.iter().zip(ys).map(|(&x, &y)| f(x, y)).collect(),
+        (1, _) => ys.iter().map(|&y| f(xs[0], y)).collect(),
+        (_, 1) => xs.iter().map(|&x| f(x, ys[0])).collect(),
+        (n, m) => panic!(
+            "cannot broadcast slices of lengths {} and {} together",
+            n, m
+        ),
+    }
+}
+
+/// Calculate `xs + ys` elementwise, where `ys` holds variables or `f64` constants.
+pub fn add<'a, T>(xs: &[Var<'a>], ys: &[T]) -> Vec<Var<'a>>
+where
+    T: Copy,
+    Var<'a>: Add<T, Output = Var<'a>>,
+{
+    broadcast(xs, ys, |x, y| x + y)
+}
+
+/// Calculate `xs - ys` elementwise, where `ys` holds variables or `f64` constants.
+pub fn sub<'a, T>(xs: &[Var<'a>], ys: &[T]) -> Vec<Var<'a>>
+where
+    T: Copy,
+    Var<'a>: Sub<T, Output = Var<'a>>,
+{
+    broadcast(xs, ys, |x, y| x - y)
+}
+
+/// Calculate `xs * ys` elementwise, where `ys` holds variables or `f64` constants.
+pub fn mul<'a, T>(xs: &[Var<'a>], ys: &[T]) -> Vec<Var<'a>>
+where
+    T: Copy,
+    Var<'a>: Mul<T, Output = Var<'a>>,
+{
+    broadcast(xs, ys, |x, y| x * y)
+}
+
+/// Calculate `xs / ys` elementwise, where `ys` holds variables or `f64` constants.
+pub fn div<'a, T>(xs: &[Var<'a>], ys: &[T]) -> Vec<Var<'a>>
+where
+    T: Copy,
+    Var<'a>: Div<T, Output = Var<'a>>,
+{
+    broadcast(xs, ys, |x, y| x / y)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Gradient, Tape};
+
+    fn vals(xs: &[Var]) -> Vec<f64> {
+        xs.iter().map(|x| x.val).collect()
+    }
+
+    #[test]
+    fn test_elementwise() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2., 4.]);
+        let y = g.add_vars(&[2., -1., 0.5]);
+        assert_eq!(vals(&add(&x, &y)), vec![3., 1., 4.5]);
+        assert_eq!(vals(&sub(&x, &[1., 1., 1.])), vec![0., 1., 3.]);
+        assert_eq!(vals(&mul(&x, &[y[0]])), vec![2., 4., 8.]);
+        assert_eq!(vals(&div(&x[..1], &[2., 4.])), vec![0.5, 0.25]);
+
+        let z = div(&mul(&x, &y), &[y[2]]);
+        let grads = z[1].grad();
+        assert_eq!(grads.wrt(&x), vec![0., -2., 0.]);
+        assert_eq!(grads.wrt(&y), vec![0., 4., 8.]);
+        // constants do not add nodes of their own
+        let len = g.len();
+        let _ = add(&x, &[1.]);
+        assert_eq!(g.len(), len + 3);
+    }
+
+    #[test]
+    #[should_panic(expected = "cannot broadcast")]
+    fn test_length_mismatch() {
+        let g = Tape::new();
+        let _ = add(&g.add_vars(&[1., 2.]), &[1., 2., 3.]);
+    }
+}
@@ -0,0 +1,156 @@
+use crate::{Atan2, Copysign, Hypot, LogAddExp, Powf, Tape, Var};
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+/// Error returned by the `try_` operations when their operands are recorded on different tapes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapeMismatchError {
+    /// Name of the operation that was given the variables.
+    pub op: &'static str,
+    /// Address of the tape that the operation is recorded on.
+    pub expected: usize,
+    /// Address of the tape of the variable that does not belong to it.
+    pub found: usize,
+}
+
+impl Display for TapeMismatchError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "variables from different tapes cannot be combined in {} (tapes at {:#x} and {:#x})",
+            self.op, self.expected, self.found
+        )
+    }
+}
+
+impl Error for TapeMismatchError {}
+
+impl TapeMismatchError {
+    /// Checks that `a` and `b` are the same tape, for the operation `op`.
+    pub(crate) fn check(op: &'static str, a: &Tape, b: &Tape) -> Result<(), Self> {
+        if std::ptr::eq(a, b) {
+            Ok(())
+        } else {
+            Err(Self {
+                op,
+                expected: a as *const Tape as usize,
+                found: b as *const Tape as usize,
+            })
+        }
+    }
+
+    /// Checks that all of `vars` are on the tape of the first one, for the operation `op`.
+    pub(crate) fn check_all<'v, 'a: 'v>(
+        op: &'static str,
+        vars: impl IntoIterator<Item = &'v Var<'a>>,
+    ) -> Result<(), Self> {
+        let mut vars = vars.into_iter();
+        match vars.next() {
+            Some(first) => vars.try_for_each(|v| Self::check(op, first.tape, v.tape)),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Panics with a `TapeMismatchError` unless `a` and `b` are the same tape.
+pub(crate) fn assert_same_tape(op: &'static str, a: &Tape, b: &Tape) {
+    if let Err(e) = TapeMismatchError::check(op, a, b) {
+        panic!("{}", e);
+    }
+}
+
+impl<'a> Var<'a> {
+    /// Checks whether `self` and `other` are recorded on the same tape, so that they can be
+    /// combined.
+    pub fn same_tape(&self, other: &Var) -> bool {
+        std::ptr::eq(self.tape, other.tape)
+    }
+
+    pub fn try_add(self, rhs: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("add", self.tape, rhs.tape).map(|_| self + rhs)
+    }
+
+    pub fn try_sub(self, rhs: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("sub", self.tape, rhs.tape).map(|_| self - rhs)
+    }
+
+    pub fn try_mul(self, rhs: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("mul", self.tape, rhs.tape).map(|_| self * rhs)
+    }
+
+    pub fn try_div(self, rhs: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("div", self.tape, rhs.tape).map(|_| self / rhs)
+    }
+
+    pub fn try_powf(self, rhs: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("powf", self.tape, rhs.tape).map(|_| self.powf(rhs))
+    }
+
+    pub fn try_atan2(self, rhs: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("atan2", self.tape, rhs.tape).map(|_| self.atan2(rhs))
+    }
+
+    pub fn try_hypot(self, rhs: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("hypot", self.tape, rhs.tape).map(|_| self.hypot(rhs))
+    }
+
+    pub fn try_logaddexp(self, rhs: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("logaddexp", self.tape, rhs.tape).map(|_| self.logaddexp(rhs))
+    }
+
+    pub fn try_copysign(self, sign: Self) -> Result<Self, TapeMismatchError> {
+        TapeMismatchError::check("copysign", self.tape, sign.tape).map(|_| self.copysign(sign))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_try_ops() {
+        let g1 = Tape::new();
+        let g2 = Tape::new();
+        let x = g1.add_var(2.);
+        let y = g1.add_var(3.);
+        let z = g2.add_var(4.);
+        assert!(x.same_tape(&y));
+        assert!(!x.same_tape(&z));
+        assert_eq!(x.try_mul(y).unwrap().val(), 6.);
+        assert_eq!(x.try_powf(y).unwrap().val(), 8.);
+        let addr = |tape: &Tape| tape as *const Tape as usize;
+        let e = x.try_add(z).unwrap_err();
+        assert_eq!(
+            e,
+            TapeMismatchError {
+                op: "add",
+                expected: addr(&g1),
+                found: addr(&g2),
+            }
+        );
+        assert!(e.to_string().contains("combined in add"));
+        assert_eq!(z.try_div(x).unwrap_err().op, "div");
+        assert_eq!(g2.len(), 1);
+
+        assert_eq!(crate::try_sum(&[x, y]).unwrap().val(), 5.);
+        assert_eq!(crate::try_sum(&[x, z, y]).unwrap_err().op, "sum");
+        assert_eq!(crate::try_dot(&[x], &[z]).unwrap_err().found, addr(&g2));
+        assert_eq!(crate::try_polyval(&[x, y], z).unwrap_err().op, "polyval");
+        let a = crate::Mat::new(1, 1, vec![x]);
+        assert_eq!(crate::try_solve(&a, &[y]).unwrap()[0].val(), 1.5);
+        assert_eq!(crate::try_solve(&a, &[z]).unwrap_err().op, "solve");
+        let loss = crate::losses::try_mse(&[x], &[z]);
+        assert_eq!(loss.unwrap_err().op, "mse");
+        assert!(crate::losses::try_mse(&[x], &[1.]).is_ok());
+    }
+
+    #[test]
+    #[should_panic(expected = "variables from different tapes cannot be combined in sub")]
+    fn test_mismatch_panics() {
+        let g1 = Tape::new();
+        let g2 = Tape::new();
+        let _ = g1.add_var(1.) - g2.add_var(1.);
+    }
+}
@@ -0,0 +1,694 @@
+//! Printing what was recorded on a tape, for checking that a computation was recorded as
+//! intended when its gradients look wrong.
+//!
+//! `Tape::dump` lists every node, `Var::expr_string` gives a readable expression for a variable,
+//! and `Var::to_symbolic` exports an exact one for a computer algebra system.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2.]);
+//! let y = (x[0] * x[1]).sin() - x[1] / 2.;
+//! assert_eq!(y.expr_string(), "sin(x0 * x1) - 0.5 * x1");
+//! assert_eq!(tape.dump().lines().nth(2), Some("v2 = Mul(v0, v1)  d [2, 1]"));
+//! ```
+
+use crate::{Op, Tape, Var};
+use std::{
+    error::Error,
+    fmt::{self, Display, Write},
+};
+
+/// Dependencies of a node, with their partial derivatives unless the node is part of a block.
+struct Args {
+    locations: Vec<usize>,
+    weights: Option<Vec<f64>>,
+    /// Position among the outputs of the block that the node is part of.
+    output: Option<usize>,
+}
+
+/// Checks whether the scalar operation `op` depends on two values rather than one.
+fn is_binary(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Add
+            | Op::Mul
+            | Op::Powf
+            | Op::Atan2
+            | Op::Hypot
+            | Op::LogAddExp
+            | Op::Copysign
+            | Op::LnBeta
+    )
+}
+
+/// Gets the name of `op` in snake case and its constant parameters, if any, from its `Debug`
+/// representation, such as `("log", Some("2.0"))` for `Log(2.0)`.
+fn name_and_params(op: Op) -> (String, Option<String>) {
+    let debug = format!("{:?}", op);
+    let (name, params) = match debug.find('(') {
+        Some(i) => (&debug[..i], Some(debug[i + 1..debug.len() - 1].to_string())),
+        None => (&debug[..], None),
+    };
+    let mut snake = String::new();
+    for (i, c) in name.chars().enumerate() {
+        if c.is_uppercase() && i > 0 {
+            snake.push('_');
+        }
+        snake.push(c.to_ascii_lowercase());
+    }
+    (snake, params)
+}
+
+/// Binding strength of an expression, to decide where parentheses are needed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Precedence {
+    Sum,
+    Product,
+    Power,
+    Atom,
+}
+
+/// Expression for a node, with the strength of its outermost operation.
+#[derive(Debug, Clone)]
+struct Expr {
+    text: String,
+    precedence: Precedence,
+}
+
+impl Expr {
+    fn new(text: String, precedence: Precedence) -> Self {
+        Self { text, precedence }
+    }
+
+    /// Gets the expression for use where at least `precedence` is needed, in parentheses if it
+    /// binds less strongly.
+    fn at(&self, precedence: Precedence) -> String {
+        if self.precedence < precedence {
+            format!("({})", self.text)
+        } else {
+            self.text.clone()
+        }
+    }
+}
+
+/// Gets a constant as an expression, treating negative numbers as sums so they are parenthesized.
+fn number(c: f64) -> Expr {
+    let precedence = if c < 0. {
+        Precedence::Sum
+    } else {
+        Precedence::Atom
+    };
+    Expr::new(format!("{}", c), precedence)
+}
+
+impl Tape {
+    /// Gets the dependencies of the node at `location`.
+    fn args(&self, location: usize) -> Args {
+        let blocks = self.blocks.borrow();
+        let block = blocks.partition_point(|block| block.end <= location);
+        if let Some(block) = blocks.get(block).filter(|block| block.start <= location) {
+            return Args {
+                locations: block.inputs.clone(),
+                weights: None,
+                output: Some(location - block.start),
+            };
+        }
+        let spans = self.spans.borrow();
+        let span = spans.partition_point(|span| span.node < location);
+        if let Some(span) = spans.get(span).filter(|span| span.node == location) {
+            let operands = &self.operands.borrow()[span.start..span.end];
+            return Args {
+                locations: operands.iter().map(|&(dep, _)| dep).collect(),
+                weights: Some(operands.iter().map(|&(_, weight)| weight).collect()),
+                output: None,
+            };
+        }
+        let node = self.nodes.borrow()[location];
+        let arity = match self.ops.borrow()[location] {
+            Op::Input | Op::Const(_) => 0,
+            op if is_binary(op) => 2,
+            _ => 1,
+        };
+        Args {
+            locations: node.dependencies()[..arity].to_vec(),
+            weights: Some(node.weights[..arity].to_vec()),
+            output: None,
+        }
+    }
+
+    /// List every node on the tape, one per line, with the operation that produced it, its
+    /// dependencies, and the partial derivatives with respect to them. Nodes are named `v` followed
+    /// by their location, and tapes created with `Tape::with_provenance` also list their values.
+    /// The outputs of operations recorded as blocks are numbered, and their partial derivatives
+    /// are not stored.
+    pub fn dump(&self) -> String {
+        let ops = self.ops.borrow().to_vec();
+        let vals = self.provenance.as_ref().map(|vals| vals.borrow().clone());
+        let mut out = String::new();
+        for (location, op) in ops.into_iter().enumerate() {
+            let args = self.args(location);
+            let names = args
+                .locations
+                .iter()
+                .map(|dep| format!("v{}", dep))
+                .collect::<Vec<_>>();
+            write!(out, "v{} = {:?}", location, op).unwrap();
+            if let Some(output) = args.output {
+                write!(out, "[{}]", output).unwrap();
+            }
+            if !names.is_empty() {
+                write!(out, "({})", names.join(", ")).unwrap();
+            }
+            if let Some(vals) = &vals {
+                write!(out, "  val {}", vals[location]).unwrap();
+            }
+            if let Some(weights) = args.weights.filter(|weights| !weights.is_empty()) {
+                let weights = weights.iter().map(f64::to_string).collect::<Vec<_>>();
+                write!(out, "  d [{}]", weights.join(", ")).unwrap();
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
+
+impl<'a> Var<'a> {
+    /// Reconstruct an infix expression for `self` from the operations recorded for it and its
+    /// ancestors. The inputs of the tape are named `x0`, `x1` and so on, in the order they were
+    /// added, and operations without an infix form are written like function calls with their
+    /// constant parameters last.
+    ///
+    /// The expression is only approximate: constants are folded into the recorded operations, so
+    /// `x / 2` prints as `0.5 * x`, and a value used several times is written out in full at every
+    /// use. Use `Tape::dump` for large or heavily shared computations.
+    pub fn expr_string(&self) -> String {
+        // every operation can be written as a function call
+        self.render(false).unwrap()
+    }
+
+    /// Export the operations recorded for `self` and its ancestors as an exact symbolic
+    /// expression in the syntax of SymPy, for example to check derivatives with a computer
+    /// algebra system. The inputs of the tape are written as the symbols `x0`, `x1` and so on, in
+    /// the order they were added, so the expression can be parsed with `sympify`.
+    ///
+    /// Unlike `expr_string`, operations are written in terms of functions SymPy knows, such as
+    /// `log(1 + x0)` for `ln_1p`. As with `expr_string`, a value used several times is written
+    /// out in full at every use.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let tape = Tape::new();
+    /// let x = tape.add_vars(&[1., 2.]);
+    /// let y = x[0].powi(2) * x[1].ln_1p() - x[1].hypot(x[0]) / 2.;
+    /// assert_eq!(y.to_symbolic().unwrap(), "x0**2 * log(1 + x1) - 0.5 * sqrt(x1**2 + x0**2)");
+    /// ```
+    ///
+    /// Returns an error for the first ancestor whose operation has no symbolic form, such as the
+    /// operations recorded as blocks by `solve` or `ode::rk4_adjoint`.
+    pub fn to_symbolic(&self) -> Result<String, SymbolicError> {
+        self.render(true)
+    }
+
+    /// Write the expression for `self`, either approximately or in the syntax of SymPy.
+    fn render(&self, symbolic: bool) -> Result<String, SymbolicError> {
+        let tape = self.tape;
+        let ops = tape.ops.borrow().to_vec();
+        let len = self.location + 1;
+        let args = (0..len).map(|i| tape.args(i)).collect::<Vec<_>>();
+        let mut needed = vec![false; len];
+        needed[self.location] = true;
+        for i in (0..len).rev() {
+            if needed[i] {
+                args[i].locations.iter().for_each(|&dep| needed[dep] = true);
+            }
+        }
+
+        let mut exprs: Vec<Option<Expr>> = vec![None; len];
+        let mut inputs = 0;
+        for i in 0..len {
+            if ops[i] == Op::Input {
+                inputs += 1;
+            }
+            if !needed[i] {
+                continue;
+            }
+            let arg = |k: usize| exprs[args[i].locations[k]].as_ref().unwrap();
+            let expr = match ops[i] {
+                Op::Input => Expr::new(format!("x{}", inputs - 1), Precedence::Atom),
+                Op::Const(c) if symbolic => symbolic_number(c),
+                Op::Const(c) => number(c),
+                _ if args[i].output.is_some() && !symbolic => call(ops[i], &args[i], &exprs),
+                // `a - b` is recorded as `a + (-1 * b)`
+                Op::Add if ops[args[i].locations[1]] == Op::MulConst(-1.) => {
+                    let negated = args[args[i].locations[1]].locations[0];
+                    let rhs = exprs[negated].as_ref().unwrap();
+                    binary(arg(0), " - ", rhs, Precedence::Sum)
+                }
+                Op::Add => binary(arg(0), " + ", arg(1), Precedence::Sum),
+                Op::AddConst(c) if c < 0. => infix(arg(0).at(Precedence::Sum), " - ", -c),
+                Op::AddConst(c) => infix(arg(0).at(Precedence::Sum), " + ", c),
+                Op::ConstSub(c) => Expr::new(
+                    format!(
+                        "{} - {}",
+                        number(c).at(Precedence::Sum),
+                        arg(0).at(Precedence::Product)
+                    ),
+                    Precedence::Sum,
+                ),
+                // `a / b` is recorded as `a * (1 / b)`
+                Op::Mul if ops[args[i].locations[1]] == Op::Recip => {
+                    let inverted = args[args[i].locations[1]].locations[0];
+                    let rhs = exprs[inverted].as_ref().unwrap();
+                    binary(arg(0), " / ", rhs, Precedence::Product)
+                }
+                Op::Mul => binary(arg(0), " * ", arg(1), Precedence::Product),
+                Op::MulConst(-1.) => Expr::new(
+                    format!("-{}", arg(0).at(Precedence::Power)),
+                    Precedence::Sum,
+                ),
+                Op::MulConst(c) => Expr::new(
+                    format!(
+                        "{} * {}",
+                        number(c).at(Precedence::Product),
+                        arg(0).at(Precedence::Power)
+                    ),
+                    Precedence::Product,
+                ),
+                Op::ConstDiv(c) => Expr::new(
+                    format!(
+                        "{} / {}",
+                        number(c).at(Precedence::Product),
+                        arg(0).at(Precedence::Power)
+                    ),
+                    Precedence::Product,
+                ),
+                Op::Recip => Expr::new(
+                    format!("1 / {}", arg(0).at(Precedence::Power)),
+                    Precedence::Product,
+                ),
+                Op::Powi(n) => power(arg(0), &number(n as f64), symbolic),
+                Op::PowfConst(c) => power(arg(0), &number(c), symbolic),
+                Op::ConstPowf(c) => power(&number(c), arg(0), symbolic),
+                Op::Powf => power(arg(0), arg(1), symbolic),
+                Op::Sum | Op::CompensatedSum => {
+                    let terms = args[i]
+                        .locations
+                        .iter()
+                        .map(|&dep| exprs[dep].as_ref().unwrap().at(Precedence::Sum))
+                        .collect::<Vec<_>>();
+                    Expr::new(terms.join(" + "), Precedence::Sum)
+                }
+                Op::Linear => {
+                    let weights = args[i].weights.as_ref().unwrap();
+                    let terms = args[i]
+                        .locations
+                        .iter()
+                        .zip(weights)
+                        .map(|(&dep, &weight)| {
+                            format!(
+                                "{} * {}",
+                                number(weight).at(Precedence::Product),
+                                exprs[dep].as_ref().unwrap().at(Precedence::Power)
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    Expr::new(terms.join(" + "), Precedence::Sum)
+                }
+                op if symbolic => {
+                    let xs = args[i]
+                        .locations
+                        .iter()
+                        .map(|&dep| exprs[dep].as_ref().unwrap())
+                        .collect::<Vec<_>>();
+                    symbolic_call(op, &xs).ok_or_else(|| SymbolicError {
+                        location: i,
+                        op: format!("{:?}", op),
+                    })?
+                }
+                op => call(op, &args[i], &exprs),
+            };
+            exprs[i] = Some(expr);
+        }
+        Ok(exprs[self.location].take().unwrap().text)
+    }
+}
+
+/// Write `lhs` and `rhs` joined by the left-associative operator `symbol` of strength
+/// `precedence`.
+fn binary(lhs: &Expr, symbol: &str, rhs: &Expr, precedence: Precedence) -> Expr {
+    // the right operand of `-` and `/` needs parentheses at equal strength
+    let right = match symbol {
+        " - " => Precedence::Product,
+        " / " => Precedence::Power,
+        _ => precedence,
+    };
+    Expr::new(
+        format!("{}{}{}", lhs.at(precedence), symbol, rhs.at(right)),
+        precedence,
+    )
+}
+
+/// Write a sum or difference with a constant `c`.
+fn infix(lhs: String, symbol: &str, c: f64) -> Expr {
+    Expr::new(format!("{}{}{}", lhs, symbol, c), Precedence::Sum)
+}
+
+/// Write `base^exponent`, or `base**exponent` in the syntax of SymPy.
+fn power(base: &Expr, exponent: &Expr, symbolic: bool) -> Expr {
+    Expr::new(
+        format!(
+            "{}{}{}",
+            base.at(Precedence::Atom),
+            if symbolic { "**" } else { "^" },
+            exponent.at(Precedence::Atom)
+        ),
+        Precedence::Power,
+    )
+}
+
+/// Write `op` as a function call on its dependencies, followed by its constant parameters or,
+/// for an output of a block, preceded by the position of the output.
+fn call(op: Op, args: &Args, exprs: &[Option<Expr>]) -> Expr {
+    let (mut name, params) = name_and_params(op);
+    if let Some(output) = args.output {
+        write!(name, "[{}]", output).unwrap();
+    }
+    let mut items = args
+        .locations
+        .iter()
+        .map(|&dep| exprs[dep].as_ref().unwrap().text.clone())
+        .collect::<Vec<_>>();
+    items.extend(params);
+    Expr::new(format!("{}({})", name, items.join(", ")), Precedence::Atom)
+}
+
+/// Error returned by `Var::to_symbolic` for an operation without a symbolic form.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SymbolicError {
+    /// Location of the node on the tape.
+    pub location: usize,
+    /// Name of the operation that produced the node.
+    pub op: String,
+}
+
+impl Display for SymbolicError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node {} ({}) has no symbolic form",
+            self.location, self.op
+        )
+    }
+}
+
+impl Error for SymbolicError {}
+
+/// Gets a constant in the syntax of SymPy, which spells infinity `oo`.
+fn symbolic_number(c: f64) -> Expr {
+    match c {
+        _ if c.is_nan() => Expr::new("nan".to_string(), Precedence::Atom),
+        f64::INFINITY => Expr::new("oo".to_string(), Precedence::Atom),
+        f64::NEG_INFINITY => Expr::new("-oo".to_string(), Precedence::Sum),
+        c => number(c),
+    }
+}
+
+/// Write the operation `op` on `xs` in the syntax of SymPy, or `None` if it has no symbolic form.
+fn symbolic_call(op: Op, xs: &[&Expr]) -> Option<Expr> {
+    use Precedence::*;
+    // arguments inside the parentheses of a call, and as operands of anything else
+    let p = |k: usize| xs[k].text.clone();
+    let a = |k: usize| xs[k].at(Atom);
+    let n = number;
+    let func =
+        |name: &str, args: &[String]| Expr::new(format!("{}({})", name, args.join(", ")), Atom);
+    let mean = |terms: Vec<String>| {
+        Expr::new(
+            format!("({}) / {}", terms.join(" + "), terms.len()),
+            Product,
+        )
+    };
+    let pairs = || (0..xs.len() / 2).map(|k| (a(2 * k), a(2 * k + 1)));
+    let named = match op {
+        Op::Sin => "sin",
+        Op::Cos => "cos",
+        Op::Tan => "tan",
+        Op::Ln => "log",
+        Op::Asin => "asin",
+        Op::Acos => "acos",
+        Op::Atan => "atan",
+        Op::Sinh => "sinh",
+        Op::Cosh => "cosh",
+        Op::Tanh => "tanh",
+        Op::Asinh => "asinh",
+        Op::Acosh => "acosh",
+        Op::Atanh => "atanh",
+        Op::Exp => "exp",
+        Op::Sqrt => "sqrt",
+        Op::Abs => "Abs",
+        Op::Floor => "floor",
+        Op::Ceil => "ceiling",
+        Op::Signum => "sign",
+        Op::Erf => "erf",
+        Op::Erfc => "erfc",
+        Op::Lgamma => "loggamma",
+        // SymPy's sinc is also unnormalized
+        Op::Sinc => "sinc",
+        Op::Atan2 => "atan2",
+        Op::MaxOf => "Max",
+        Op::MinOf => "Min",
+        _ => "",
+    };
+    if !named.is_empty() {
+        return Some(func(named, &(0..xs.len()).map(p).collect::<Vec<_>>()));
+    }
+    let expr = match op {
+        Op::Log(base) => func("log", &[p(0), n(base).text]),
+        Op::Ln1p => func("log", &[format!("1 + {}", p(0))]),
+        Op::ExpM1 => Expr::new(format!("exp({}) - 1", p(0)), Sum),
+        Op::Exp2 => Expr::new(format!("2**{}", a(0)), Power),
+        Op::Huber(delta) => func(
+            "Piecewise",
+            &[
+                format!("({}**2 / 2, Abs({}) <= {})", a(0), p(0), delta),
+                format!("({} * (Abs({}) - {}), True)", delta, p(0), delta / 2.),
+            ],
+        ),
+        Op::SmoothAbs(eps) => {
+            Expr::new(format!("sqrt({}**2 + {}) - {}", a(0), eps * eps, eps), Sum)
+        }
+        Op::Atan2Const(c) => func("atan2", &[p(0), n(c).text]),
+        Op::ConstAtan2(c) => func("atan2", &[n(c).text, p(0)]),
+        Op::Hypot => func("sqrt", &[format!("{}**2 + {}**2", a(0), a(1))]),
+        Op::HypotConst(c) => func("sqrt", &[format!("{}**2 + {}", a(0), c * c)]),
+        Op::LogAddExp => func("log", &[format!("exp({}) + exp({})", p(0), p(1))]),
+        Op::LogAddExpConst(c) => func("log", &[format!("exp({}) + exp({})", p(0), c)]),
+        Op::Copysign => Expr::new(format!("Abs({}) * sign({})", p(0), p(1)), Product),
+        Op::CopysignConst(c) if c.is_sign_negative() => Expr::new(format!("-Abs({})", p(0)), Sum),
+        Op::CopysignConst(_) => func("Abs", &[p(0)]),
+        // rounding is away from zero at halves
+        Op::Round => Expr::new(
+            format!("sign({}) * floor(Abs({}) + 1/2)", p(0), p(0)),
+            Product,
+        ),
+        Op::Trunc => Expr::new(format!("sign({}) * floor(Abs({}))", p(0), p(0)), Product),
+        Op::Fract => Expr::new(
+            format!("{} - sign({}) * floor(Abs({}))", xs[0].at(Sum), p(0), p(0)),
+            Sum,
+        ),
+        Op::Polygamma(order) => func("polygamma", &[order.to_string(), p(0)]),
+        Op::BesselJ(order) => func("besselj", &[order.to_string(), p(0)]),
+        Op::BesselI0 => func("besseli", &["0".to_string(), p(0)]),
+        Op::BesselI1 => func("besseli", &["1".to_string(), p(0)]),
+        Op::NormCdf => Expr::new(format!("(1 + erf({} / sqrt(2))) / 2", a(0)), Product),
+        Op::NormCdfInv => Expr::new(format!("sqrt(2) * erfinv(2 * {} - 1)", a(0)), Product),
+        Op::LnBeta => Expr::new(
+            format!(
+                "loggamma({}) + loggamma({}) - loggamma({} + {})",
+                p(0),
+                p(1),
+                p(0),
+                p(1)
+            ),
+            Sum,
+        ),
+        #[cfg(feature = "nn")]
+        Op::Relu => func("Max", &["0".to_string(), p(0)]),
+        #[cfg(feature = "nn")]
+        Op::Sigmoid => Expr::new(format!("1 / (1 + exp(-{}))", a(0)), Product),
+        Op::Mean => mean((0..xs.len()).map(p).collect()),
+        Op::Variance | Op::Std => {
+            let m = mean((0..xs.len()).map(p).collect()).at(Atom);
+            let terms = (0..xs.len()).map(|k| format!("({} - {})**2", p(k), m));
+            let variance = mean(terms.collect());
+            match op {
+                Op::Std => func("sqrt", &[variance.text]),
+                _ => variance,
+            }
+        }
+        Op::Dot => symbolic_call(Op::Einsum(2), xs)?,
+        Op::Einsum(factors) => {
+            let terms = (0..xs.len())
+                .map(a)
+                .collect::<Vec<_>>()
+                .chunks(factors)
+                .map(|term| term.join(" * "))
+                .collect::<Vec<_>>();
+            Expr::new(terms.join(" + "), Sum)
+        }
+        Op::Affine => {
+            let dot = symbolic_call(Op::Dot, &xs[1..])?;
+            Expr::new(format!("{} + {}", p(0), dot.text), Sum)
+        }
+        Op::NormL1 => {
+            let terms = (0..xs.len()).map(|k| format!("Abs({})", p(k)));
+            Expr::new(terms.collect::<Vec<_>>().join(" + "), Sum)
+        }
+        Op::NormL2 => symbolic_call(Op::NormLp(2.), xs)?,
+        Op::NormLp(order) => {
+            let terms = (0..xs.len()).map(|k| format!("Abs({})**{}", p(k), order));
+            Expr::new(
+                format!(
+                    "({})**(1 / {})",
+                    terms.collect::<Vec<_>>().join(" + "),
+                    order
+                ),
+                Power,
+            )
+        }
+        Op::LogSumExp => {
+            let terms = (0..xs.len()).map(|k| format!("exp({})", p(k)));
+            func("log", &[terms.collect::<Vec<_>>().join(" + ")])
+        }
+        Op::Polyval => {
+            let x = a(xs.len() - 1);
+            let horner =
+                (1..xs.len() - 1).fold(p(0), |acc, k| format!("({}) * {} + {}", acc, x, a(k)));
+            Expr::new(horner, Sum)
+        }
+        Op::Mse => mean(
+            pairs()
+                .map(|(y, t)| format!("({} - {})**2", y, t))
+                .collect(),
+        ),
+        Op::Mae => mean(
+            pairs()
+                .map(|(y, t)| format!("Abs({} - {})", y, t))
+                .collect(),
+        ),
+        Op::BinaryCrossEntropy => mean(
+            pairs()
+                .map(|(z, t)| {
+                    format!(
+                        "Max({z}, 0) - {z} * {t} + log(1 + exp(-Abs({z})))",
+                        z = z,
+                        t = t
+                    )
+                })
+                .collect(),
+        ),
+        Op::CrossEntropy => {
+            let logits = pairs()
+                .map(|(z, _)| format!("exp({})", z))
+                .collect::<Vec<_>>();
+            let lse = format!("log({})", logits.join(" + "));
+            let terms = pairs().map(|(z, t)| format!("{} * ({} - {})", t, lse, z));
+            Expr::new(terms.collect::<Vec<_>>().join(" + "), Sum)
+        }
+        Op::Entropy => {
+            let terms = (0..xs.len()).map(|k| format!("{} * log({})", a(k), p(k)));
+            Expr::new(
+                format!("-({})", terms.collect::<Vec<_>>().join(" + ")),
+                Product,
+            )
+        }
+        Op::KlDiv => {
+            let terms = pairs().map(|(p, q)| format!("{} * log({} / {})", p, p, q));
+            Expr::new(terms.collect::<Vec<_>>().join(" + "), Sum)
+        }
+        Op::SelectGt(threshold) => func(
+            "Piecewise",
+            &[
+                format!("({}, {} > {})", p(1), p(0), n(threshold).text),
+                format!("({}, True)", p(2)),
+            ],
+        ),
+        Op::Hinge => mean(
+            pairs()
+                .map(|(y, t)| format!("Max(0, 1 - {} * {})", t, y))
+                .collect(),
+        ),
+        _ => return None,
+    };
+    Some(expr)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{solve, Atan2, Hypot, Mat};
+
+    #[test]
+    fn test_expr_string() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2., 3.]);
+        assert_eq!(x[2].expr_string(), "x2");
+        let y = (x[0] + x[1]) * (x[2] - x[0]) / x[1];
+        assert_eq!(y.expr_string(), "(x0 + x1) * (x2 - x0) / x1");
+        let y = (x[0] * 2. - 1.).powi(2) + x[1].log(10.) + 3. - x[2];
+        assert_eq!(y.expr_string(), "(2 * x0 - 1)^2 + log(x1, 10.0) + 3 - x2");
+        let y = crate::sum(&[x[0], x[1].exp(), -x[2]]);
+        assert_eq!(y.expr_string(), "x0 + exp(x1) + -x2");
+        assert_eq!((1. - x[0]).expr_string(), "1 - x0");
+    }
+
+    #[test]
+    fn test_dump() {
+        let g = Tape::with_provenance();
+        let x = g.add_vars(&[1., 2.]);
+        let y = x[0].hypot(x[1]).ln_1p() * 3.;
+        let a = Mat::new(1, 1, vec![x[0]]);
+        let _ = solve(&a, &[y]);
+        let lines = g.dump().lines().map(str::to_string).collect::<Vec<_>>();
+        assert_eq!(lines[0], "v0 = Input  val 1");
+        assert!(lines[2].starts_with("v2 = Hypot(v0, v1)  val 2.23"));
+        assert!(lines[3].starts_with("v3 = Ln1p(v2)"));
+        assert_eq!(
+            lines[4],
+            format!("v4 = MulConst(3.0)(v3)  val {}  d [3]", y.val())
+        );
+        assert_eq!(lines[5], format!("v5 = Solve[0](v0, v4)  val {}", y.val()));
+    }
+
+    #[test]
+    fn test_to_symbolic() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.5, 2.]);
+        let y = (x[0] - x[1]).powi(2).exp_m1() + x[0].atan2(x[1]) * -2.;
+        assert_eq!(
+            y.to_symbolic().unwrap(),
+            "exp((x0 - x1)**2) - 1 + (-2) * atan2(x0, x1)"
+        );
+        assert_eq!(
+            crate::mean(&[x[0].abs(), x[1].norm_cdf()])
+                .to_symbolic()
+                .unwrap(),
+            "(Abs(x0) + (1 + erf(x1 / sqrt(2))) / 2) / 2"
+        );
+        assert_eq!(g.constant(f64::INFINITY).to_symbolic().unwrap(), "oo");
+        assert_eq!(
+            crate::select_gt(x[0], 1., x[1], -x[1])
+                .to_symbolic()
+                .unwrap(),
+            "Piecewise((x1, x0 > 1), (-x1, True))"
+        );
+
+        let a = Mat::new(1, 1, vec![x[0]]);
+        let z = solve(&a, &[x[1]])[0].sin();
+        let err = z.to_symbolic().unwrap_err();
+        assert_eq!((err.location, err.op.as_str()), (z.location - 1, "Solve"));
+    }
+}
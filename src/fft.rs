@@ -0,0 +1,225 @@
+//! Discrete Fourier transforms of variables, for signals whose length is a power of two.
+//!
+//! Complex values are passed and returned as separate slices of real and imaginary parts. Each
+//! transform is recorded as a single block, whose backward pass applies the conjugate transpose of
+//! the transform with another FFT.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2., 0., -1.]);
+//! let (re, im) = rfft(&x);
+//! assert_eq!((re[1].val(), im[1].val()), (1., -3.));
+//!
+//! // the power at the first frequency
+//! let power = re[1].powi(2) + im[1].powi(2);
+//! assert_eq!(power.grad().wrt(&x), vec![2., 6., -2., -6.]);
+//! ```
+
+use crate::{error::assert_same_tape, Op, Tape, Var};
+use std::f64::consts::PI;
+
+/// Transform `re` and `im` in place with the radix-2 Cooley-Tukey algorithm, computing
+/// `sum_j x_j exp(sign * 2 pi i j k / n)` without normalizing.
+fn transform(re: &mut [f64], im: &mut [f64], sign: f64) {
+    let n = re.len();
+    // bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            re.swap(i, j);
+            im.swap(i, j);
+        }
+    }
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2. * PI / len as f64;
+        for start in (0..n).step_by(len) {
+            for k in 0..len / 2 {
+                // quarter turns are exact, which keeps transforms of short signals exact
+                let (wi, wr) = match k {
+                    0 => (0., 1.),
+                    _ if 4 * k == len => (sign, 0.),
+                    _ => (angle * k as f64).sin_cos(),
+                };
+                let (a, b) = (start + k, start + k + len / 2);
+                let xr = re[b] * wr - im[b] * wi;
+                let xi = re[b] * wi + im[b] * wr;
+                re[b] = re[a] - xr;
+                im[b] = im[a] - xi;
+                re[a] += xr;
+                im[a] += xi;
+            }
+        }
+        len <<= 1;
+    }
+}
+
+/// Record the transform `name` of the signal `re + i im` as a block, treating a missing imaginary
+/// part as zero. The inverse transform is normalized by the length.
+fn record<'a>(
+    name: &'static str,
+    re: &[Var<'a>],
+    im: Option<&[Var<'a>]>,
+    inverse: bool,
+) -> (Vec<Var<'a>>, Vec<Var<'a>>) {
+    let n = re.len();
+    assert!(n.is_power_of_two(), "length must be a power of two");
+    let tape: &'a Tape = re[0].tape;
+    let mut inputs = vec![];
+    for x in re.iter().chain(im.into_iter().flatten()) {
+        assert_same_tape(name, tape, x.tape);
+        inputs.push(x.location);
+    }
+    let has_im = im.is_some();
+    let (sign, scale) = if inverse {
+        (1., 1. / n as f64)
+    } else {
+        (-1., 1.)
+    };
+
+    let mut vals = re.iter().map(|x| x.val).collect::<Vec<_>>();
+    match im {
+        Some(im) => vals.extend(im.iter().map(|x| x.val)),
+        None => vals.resize(2 * n, 0.),
+    }
+    let (vals_re, vals_im) = vals.split_at_mut(n);
+    transform(vals_re, vals_im, sign);
+    vals.iter_mut().for_each(|x| *x *= scale);
+
+    let op = if inverse { Op::Ifft } else { Op::Fft };
+    let start = tape.add_block(op, &vals, inputs, move |out_bars, in_bars| {
+        // the conjugate transpose of the transform is the transform with the opposite sign
+        let mut bars = out_bars.to_vec();
+        let (bars_re, bars_im) = bars.split_at_mut(n);
+        transform(bars_re, bars_im, -sign);
+        let len = if has_im { 2 * n } else { n };
+        for (in_bar, bar) in in_bars.iter_mut().zip(&bars[..len]) {
+            *in_bar = scale * bar;
+        }
+    });
+    let outputs = vals
+        .iter()
+        .enumerate()
+        .map(|(i, &val)| Var {
+            val,
+            location: start + i,
+            tape,
+        })
+        .collect::<Vec<_>>();
+    let (out_re, out_im) = outputs.split_at(n);
+    (out_re.to_vec(), out_im.to_vec())
+}
+
+/// Calculate the discrete Fourier transform `X_k = sum_j x_j exp(-2 pi i j k / n)` of the complex
+/// signal with real parts `re` and imaginary parts `im`. Returns the real and imaginary parts of
+/// the transform.
+///
+/// # Panics
+///
+/// Panics if the lengths differ, are not a power of two, or the variables are on different tapes.
+pub fn fft<'a>(re: &[Var<'a>], im: &[Var<'a>]) -> (Vec<Var<'a>>, Vec<Var<'a>>) {
+    assert_eq!(
+        re.len(),
+        im.len(),
+        "real and imaginary parts differ in length"
+    );
+    record("fft", re, Some(im), false)
+}
+
+/// Calculate the discrete Fourier transform of the real signal `x`, as `fft` with a zero
+/// imaginary part. All `n` frequencies are returned.
+///
+/// # Panics
+///
+/// Panics if the length is not a power of two, or the variables are on different tapes.
+pub fn rfft<'a>(x: &[Var<'a>]) -> (Vec<Var<'a>>, Vec<Var<'a>>) {
+    record("rfft", x, None, false)
+}
+
+/// Calculate the inverse discrete Fourier transform `x_j = 1/n sum_k X_k exp(2 pi i j k / n)`, so
+/// that `ifft` undoes `fft`.
+///
+/// # Panics
+///
+/// Panics if the lengths differ, are not a power of two, or the variables are on different tapes.
+pub fn ifft<'a>(re: &[Var<'a>], im: &[Var<'a>]) -> (Vec<Var<'a>>, Vec<Var<'a>>) {
+    assert_eq!(
+        re.len(),
+        im.len(),
+        "real and imaginary parts differ in length"
+    );
+    record("ifft", re, Some(im), true)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Gradient;
+    use approx_eq::assert_approx_eq;
+
+    /// Discrete Fourier transform computed term by term.
+    fn dft<'a>(re: &[Var<'a>], im: &[Var<'a>]) -> (Vec<Var<'a>>, Vec<Var<'a>>) {
+        let n = re.len();
+        let terms = |k: usize| {
+            (0..n).map(move |j| {
+                let (s, c) = (-2. * PI * (j * k) as f64 / n as f64).sin_cos();
+                (re[j] * c - im[j] * s, re[j] * s + im[j] * c)
+            })
+        };
+        (0..n)
+            .map(|k| {
+                let (res, ims): (Vec<_>, Vec<_>) = terms(k).unzip();
+                (res.into_iter().sum::<Var>(), ims.into_iter().sum::<Var>())
+            })
+            .unzip()
+    }
+
+    fn loss<'a>(re: &[Var<'a>], im: &[Var<'a>]) -> Var<'a> {
+        re.iter()
+            .zip(im)
+            .enumerate()
+            .map(|(k, (r, i))| (r.powi(2) + *i * r.sin()) * (k as f64 + 1.))
+            .sum()
+    }
+
+    #[test]
+    fn test_fft() {
+        let g = Tape::new();
+        let re = g.add_vars(&[0.5, -1., 2., 0., 1.5, 3., -0.5, 1.]);
+        let im = g.add_vars(&[1., 0., -2., 0.5, 0., 1., 0.25, -1.]);
+        let (fr, fi) = fft(&re, &im);
+        let (dr, di) = dft(&re, &im);
+        let (fast, naive) = (loss(&fr, &fi), loss(&dr, &di));
+        assert_approx_eq!(fast.val(), naive.val());
+        let (fast, naive) = (fast.grad(), naive.grad());
+        for x in re.iter().chain(&im) {
+            assert_approx_eq!(fast.wrt(x), naive.wrt(x));
+        }
+
+        let (rf, ri) = rfft(&re);
+        let (zr, zi) = dft(&re, &[g.add_var(0.); 8]);
+        assert_approx_eq!(rf[3].val(), zr[3].val());
+        let fast = (rf[3] * ri[5]).grad().wrt(&re);
+        let naive = (zr[3] * zi[5]).grad().wrt(&re);
+        for (fast, naive) in fast.iter().zip(naive) {
+            assert_approx_eq!(*fast, naive);
+        }
+
+        let (br, bi) = ifft(&fr, &fi);
+        for (x, y) in re.iter().chain(&im).zip(br.iter().chain(&bi)) {
+            assert_approx_eq!(x.val(), y.val());
+        }
+        let grads = (br[2] + bi[5] * 2.).grad();
+        assert_approx_eq!(grads.wrt(&re[2]), 1.);
+        assert_approx_eq!(grads.wrt(&im[5]), 2.);
+        assert!(grads.wrt(&re[5]).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,332 @@
+//! Gradients returned by `Var::grad`, which remember the tape they were computed on.
+
+use crate::{error::assert_same_tape, Gradient, Op, Tape, Var};
+use std::{
+    borrow::Borrow,
+    collections::{BTreeMap, HashMap},
+    fmt::{self, Debug, Display},
+    hash::{BuildHasher, Hash},
+    ops::Deref,
+};
+
+mod capture {
+    /// Lets a returned `impl Iterator` borrow for a lifetime that it does not outlive, which
+    /// editions before 2024 only allow for lifetimes named in its bounds.
+    pub trait Captures<'a> {}
+
+    impl<'a, T: ?Sized> Captures<'a> for T {}
+}
+use capture::Captures;
+
+/// Gradient of a variable with respect to every node of its tape. Reading it with `wrt` checks
+/// that the variables are from the same tape, and panics otherwise. It also dereferences to the
+/// gradients indexed by location, for passing to functions such as `Tape::find_non_finite`.
+#[derive(Clone)]
+pub struct Grad<'a> {
+    tape: &'a Tape,
+    derivs: Vec<f64>,
+}
+
+impl<'a> Grad<'a> {
+    pub(crate) fn new(tape: &'a Tape, derivs: Vec<f64>) -> Self {
+        Self { tape, derivs }
+    }
+
+    /// Gets the tape the gradient was computed on.
+    pub fn tape(&self) -> &'a Tape {
+        self.tape
+    }
+
+    /// Iterate over the gradients with respect to the variables added with `add_var`, in the
+    /// order they were added.
+    pub fn leaves(&self) -> impl Iterator<Item = f64> + '_ {
+        let ops = self.tape.ops.borrow();
+        let leaves = (0..self.derivs.len())
+            .filter(|&i| matches!(ops[i], Op::Input))
+            .collect::<Vec<_>>();
+        leaves.into_iter().map(move |i| self.derivs[i])
+    }
+
+    /// Iterate over the gradients with respect to the variables in `v`, in the same order. Unlike
+    /// `wrt`, this reads the gradients in place without allocating a vector.
+    ///
+    /// # Panics
+    ///
+    /// Panics when reaching a variable from a different tape.
+    pub fn wrt_iter<'b, I>(&'b self, v: I) -> impl Iterator<Item = f64> + Captures<'a> + 'b
+    where
+        I: IntoIterator<Item = &'b Var<'a>>,
+        I::IntoIter: 'b,
+        'a: 'b,
+    {
+        v.into_iter().map(move |v| self.wrt(v))
+    }
+
+    /// Gets the largest absolute value of the gradients with respect to the leaves, or zero if
+    /// there are none.
+    pub fn max_norm(&self) -> f64 {
+        self.leaves().fold(0., |max, grad| max.max(grad.abs()))
+    }
+
+    /// Gets the largest absolute value of the gradients with respect to the variables `v`.
+    pub fn max_norm_wrt(&self, v: &[Var<'a>]) -> f64 {
+        self.wrt_iter(v).fold(0., |max, grad| max.max(grad.abs()))
+    }
+
+    /// Summarize the gradients with respect to named parameters, for finding the ones that drive
+    /// the output. `params` yields pairs of names and variables, such as a map of variables or
+    /// `names.iter().zip(&vars)`; the rows are in the same order until sorted.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let tape = Tape::new();
+    /// let (w, b) = (tape.add_var(2.), tape.add_var(0.5));
+    /// let loss = (w * 3. + b - 1.).powi(2);
+    /// let mut report = loss.grad().report([("w", w), ("b", b)]);
+    /// report.sort_by_magnitude();
+    /// assert_eq!(report.rows[0].name, "w");
+    /// println!("{}", report);
+    /// ```
+    pub fn report<N, V>(&self, params: impl IntoIterator<Item = (N, V)>) -> GradReport
+    where
+        N: ToString,
+        V: Borrow<Var<'a>>,
+    {
+        let rows = params
+            .into_iter()
+            .map(|(name, var)| {
+                let var = var.borrow();
+                let grad = self.wrt(var);
+                GradRow {
+                    name: name.to_string(),
+                    value: var.val,
+                    grad,
+                    magnitude: grad.abs(),
+                }
+            })
+            .collect();
+        GradReport { rows }
+    }
+
+    /// Gets the gradients indexed by location, discarding the tape.
+    pub fn into_vec(self) -> Vec<f64> {
+        self.derivs
+    }
+}
+
+impl<'a> Deref for Grad<'a> {
+    type Target = [f64];
+
+    fn deref(&self) -> &[f64] {
+        &self.derivs
+    }
+}
+
+impl<'a> From<Grad<'a>> for Vec<f64> {
+    fn from(grad: Grad<'a>) -> Self {
+        grad.derivs
+    }
+}
+
+impl<'a> Debug for Grad<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Grad").field(&self.derivs).finish()
+    }
+}
+
+impl<'a> PartialEq for Grad<'a> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.tape, other.tape) && self.derivs == other.derivs
+    }
+}
+
+/// Gradients with respect to named parameters, as returned by `Grad::report`. It prints as a
+/// table with one row per parameter.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradReport {
+    pub rows: Vec<GradRow>,
+}
+
+/// Parameter in a `GradReport`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GradRow {
+    pub name: String,
+    /// Value of the parameter.
+    pub value: f64,
+    /// Gradient with respect to the parameter.
+    pub grad: f64,
+    /// Absolute value of the gradient.
+    pub magnitude: f64,
+}
+
+impl GradReport {
+    /// Sort the rows by decreasing magnitude of the gradient. NaNs come first, since they are
+    /// usually what is being looked for.
+    pub fn sort_by_magnitude(&mut self) {
+        self.rows
+            .sort_by(|a, b| b.magnitude.total_cmp(&a.magnitude));
+    }
+
+    /// Gets the `n` rows with the largest magnitudes, in decreasing order.
+    pub fn top(&self, n: usize) -> Vec<&GradRow> {
+        let mut rows = self.rows.iter().collect::<Vec<_>>();
+        rows.sort_by(|a, b| b.magnitude.total_cmp(&a.magnitude));
+        rows.truncate(n);
+        rows
+    }
+}
+
+impl Display for GradReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let width = self
+            .rows
+            .iter()
+            .map(|row| row.name.len())
+            .max()
+            .unwrap_or(0);
+        for row in &self.rows {
+            writeln!(
+                f,
+                "{:<width$}  value {:>14.6e}  grad {:>14.6e}  |grad| {:.2e}",
+                row.name,
+                row.value,
+                row.grad,
+                row.magnitude,
+                width = width
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Calculate the gradient with respect to variable `v`.
+impl<'a> Gradient<&Var<'a>, f64> for Grad<'a> {
+    fn wrt(&self, v: &Var<'a>) -> f64 {
+        assert_same_tape("wrt", self.tape, v.tape);
+        self.derivs[v.location]
+    }
+}
+
+/// Calculate the gradient with respect to all variables in `v`, in the same order.
+impl<'a> Gradient<&[Var<'a>], Vec<f64>> for Grad<'a> {
+    fn wrt(&self, v: &[Var<'a>]) -> Vec<f64> {
+        v.iter().map(|v| self.wrt(v)).collect()
+    }
+}
+
+/// Calculate the gradient with respect to all variables in `v`, in the same order.
+impl<'a> Gradient<&Vec<Var<'a>>, Vec<f64>> for Grad<'a> {
+    fn wrt(&self, v: &Vec<Var<'a>>) -> Vec<f64> {
+        self.wrt(v.as_slice())
+    }
+}
+
+/// Calculate the gradient with respect to all variables in `v`, in the same order.
+impl<'a, const N: usize> Gradient<[Var<'a>; N], Vec<f64>> for Grad<'a> {
+    fn wrt(&self, v: [Var<'a>; N]) -> Vec<f64> {
+        self.wrt(&v[..])
+    }
+}
+
+/// Calculate the gradient with respect to all variables in `v`, in the same order.
+impl<'a, const N: usize> Gradient<&[Var<'a>; N], Vec<f64>> for Grad<'a> {
+    fn wrt(&self, v: &[Var<'a>; N]) -> Vec<f64> {
+        self.wrt(&v[..])
+    }
+}
+
+/// Calculate the gradient with respect to all variables in the map `v`. Returns a map from the
+/// same keys to the gradients with respect to their variables.
+impl<'a, K, H> Gradient<&HashMap<K, Var<'a>, H>, HashMap<K, f64, H>> for Grad<'a>
+where
+    K: Clone + Eq + Hash,
+    H: BuildHasher + Clone,
+{
+    fn wrt(&self, v: &HashMap<K, Var<'a>, H>) -> HashMap<K, f64, H> {
+        let mut grads = HashMap::with_capacity_and_hasher(v.len(), v.hasher().clone());
+        grads.extend(v.iter().map(|(k, var)| (k.clone(), self.wrt(var))));
+        grads
+    }
+}
+
+/// Calculate the gradient with respect to all variables in the map `v`. Returns a map from the
+/// same keys to the gradients with respect to their variables.
+impl<'a, K: Clone + Ord> Gradient<&BTreeMap<K, Var<'a>>, BTreeMap<K, f64>> for Grad<'a> {
+    fn wrt(&self, v: &BTreeMap<K, Var<'a>>) -> BTreeMap<K, f64> {
+        v.iter()
+            .map(|(k, var)| (k.clone(), self.wrt(var)))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_grad() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., -3.]);
+        let y = (x[0] * 2.).sin();
+        let z = g.add_var(0.5);
+        let res = x[0] * x[1] + y * z;
+        let grads = res.grad();
+        assert!(std::ptr::eq(grads.tape(), &g));
+        assert_eq!(grads.len(), g.len());
+        assert_eq!(
+            grads.leaves().collect::<Vec<_>>(),
+            vec![grads.wrt(&x[0]), grads.wrt(&x[1]), grads.wrt(&z)]
+        );
+        assert_approx_eq!(grads.max_norm(), 3. - 2_f64.cos());
+        assert!(grads.wrt_iter(&x).eq(grads.wrt(&x)));
+        assert_eq!(
+            grads.wrt_iter(&[z, x[0]]).sum::<f64>(),
+            grads.wrt(&z) + grads.wrt(&x[0])
+        );
+        assert_eq!(grads.max_norm_wrt(&[x[1], z]), 1.);
+        assert_eq!(grads.wrt(&y), 0.5);
+        assert_eq!(Vec::from(grads.clone()), grads.into_vec());
+    }
+
+    #[test]
+    fn test_report() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., -3., 0.5]);
+        let y = x[0] * x[1] + x[2].exp();
+        let names = ["a", "b", "c"];
+        let mut report = y.grad().report(names.iter().zip(&x));
+        assert_eq!(report.rows[1].name, "b");
+        assert_eq!(report.rows[1].value, -3.);
+        assert_eq!(report.rows[0].grad, -3.);
+        assert_eq!(report.rows[0].magnitude, 3.);
+        assert_eq!(report.top(1)[0].name, "a");
+
+        report.sort_by_magnitude();
+        let order = report
+            .rows
+            .iter()
+            .map(|row| row.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(order, ["a", "c", "b"]);
+        assert!(report.to_string().starts_with("a  value"));
+        assert_eq!(report.to_string().lines().count(), 3);
+
+        let params = x
+            .iter()
+            .enumerate()
+            .map(|(i, &v)| (i, v))
+            .collect::<BTreeMap<_, _>>();
+        assert_eq!(y.grad().report(&params).rows[2].name, "2");
+    }
+
+    #[test]
+    #[should_panic(expected = "different tapes")]
+    fn test_grad_wrong_tape() {
+        let (g1, g2) = (Tape::new(), Tape::new());
+        let x = g1.add_var(1.);
+        let y = g2.add_var(1.);
+        let _ = (x * 2.).grad().wrt(&y);
+    }
+}
@@ -0,0 +1,142 @@
+use crate::{Gradient, Tape, Var};
+use std::fmt::{self, Display};
+
+/// Comparison of reverse-mode and finite-difference gradients, as returned by `gradcheck`.
+#[derive(Debug, Clone)]
+pub struct GradCheck {
+    /// Gradient computed by reverse-mode differentiation.
+    pub analytic: Vec<f64>,
+    /// Gradient estimated with central finite differences.
+    pub numeric: Vec<f64>,
+    /// Error of each component, relative to the larger of the two estimates (or absolute when
+    /// both are below 1).
+    pub errors: Vec<f64>,
+    /// Tolerance the errors were checked against.
+    pub tol: f64,
+}
+
+impl GradCheck {
+    /// Whether component `i` is within the tolerance (a NaN error never is).
+    fn within(&self, i: usize) -> bool {
+        self.errors[i] <= self.tol
+    }
+
+    /// Whether every component is within the tolerance.
+    pub fn passed(&self) -> bool {
+        (0..self.errors.len()).all(|i| self.within(i))
+    }
+
+    /// Indices of the components that are not within the tolerance.
+    pub fn failures(&self) -> Vec<usize> {
+        (0..self.errors.len())
+            .filter(|&i| !self.within(i))
+            .collect()
+    }
+}
+
+impl Display for GradCheck {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for i in 0..self.errors.len() {
+            writeln!(
+                f,
+                "{:>4}: analytic {:>14.6e}  numeric {:>14.6e}  error {:.2e}{}",
+                i,
+                self.analytic[i],
+                self.numeric[i],
+                self.errors[i],
+                if self.errors[i] <= self.tol {
+                    ""
+                } else {
+                    "  FAIL"
+                }
+            )?;
+        }
+        Ok(())
+    }
+}
+
+/// Compare the gradient of `f` at `x` from reverse-mode differentiation against central finite
+/// differences, reporting the error of each component against `tol`.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let check = gradcheck(|p| p[0].sin() * p[1].exp(), &[0.5, -1.], 1e-6);
+/// assert!(check.passed(), "{}", check);
+/// ```
+pub fn gradcheck<F>(f: F, x: &[f64], tol: f64) -> GradCheck
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    let tape = Tape::new();
+    let params = tape.add_vars(x);
+    let analytic = f(&params).grad().wrt(&params);
+
+    let mut point = x.to_vec();
+    let numeric = (0..x.len())
+        .map(|i| {
+            let h = f64::EPSILON.cbrt() * x[i].abs().max(1.);
+            let mut eval = |xi: f64| {
+                point[i] = xi;
+                tape.clear();
+                f(&tape.add_vars(&point)).val
+            };
+            let diff = (eval(x[i] + h) - eval(x[i] - h)) / (2. * h);
+            point[i] = x[i];
+            diff
+        })
+        .collect::<Vec<_>>();
+    let errors = analytic
+        .iter()
+        .zip(&numeric)
+        .map(|(a, n)| (a - n).abs() / a.abs().max(n.abs()).max(1.))
+        .collect();
+    GradCheck {
+        analytic,
+        numeric,
+        errors,
+        tol,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Op, Powf};
+
+    #[test]
+    fn test_gradcheck() {
+        let check = gradcheck(
+            |p| p[0].powf(p[1]) + (p[2] * 100.).atan() / p[0],
+            &[1.5, 2., 0.01],
+            1e-6,
+        );
+        assert!(check.passed(), "{}", check);
+        assert!(check.failures().is_empty());
+
+        // a wrong derivative (the node records `1` instead of `2 x`) is caught
+        let check = gradcheck(
+            |p| {
+                let sq = p[0] * p[0];
+                let wrong = Var {
+                    val: sq.val,
+                    location: p[0].tape.add_node(
+                        Op::Mul,
+                        sq.val,
+                        p[0].location,
+                        p[0].location,
+                        1.,
+                        0.,
+                    ),
+                    tape: p[0].tape,
+                };
+                wrong + p[1]
+            },
+            &[3., 1.],
+            1e-6,
+        );
+        assert!(!check.passed());
+        assert_eq!(check.failures(), vec![0]);
+        assert!(check.to_string().lines().next().unwrap().ends_with("FAIL"));
+    }
+}
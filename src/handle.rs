@@ -0,0 +1,217 @@
+//! Tape whose variables are referred to by plain `usize` handles rather than borrowing `Var`s,
+//! for use across language boundaries (such as WebAssembly or Python bindings) where lifetimes
+//! cannot be expressed. Operations are chosen by name so that a binding layer can forward calls
+//! without one wrapper per function.
+//!
+//! ```rust
+//! use reverse::handle::HandleTape;
+//!
+//! let mut tape = HandleTape::new();
+//! let x = tape.var(2.);
+//! let y = tape.var(3.);
+//! let xy = tape.binary("mul", x, y).unwrap();
+//! let z = tape.unary("sin", xy).unwrap();
+//! assert_eq!(tape.val(z), 6_f64.sin());
+//! assert_eq!(tape.grad_wrt(z, &[x, y]), vec![3. * 6_f64.cos(), 2. * 6_f64.cos()]);
+//! ```
+
+use crate::{Atan2, Copysign, Hypot, LogAddExp, Powf, Tape, Var};
+
+/// Tape of variables identified by `usize` handles. A handle is only meaningful for the tape that
+/// returned it, and becomes invalid when the tape is cleared.
+#[derive(Debug, Clone)]
+pub struct HandleTape {
+    tape: Tape,
+}
+
+impl HandleTape {
+    /// Create a new, empty tape.
+    pub fn new() -> Self {
+        Self {
+            tape: Tape::with_provenance(),
+        }
+    }
+
+    /// Add an input variable with value `val`.
+    pub fn var(&mut self, val: f64) -> usize {
+        self.tape.add_var(val).location
+    }
+
+    /// Add a constant with value `val`, which unlike `var` is not an input of the tape.
+    pub fn constant(&mut self, val: f64) -> usize {
+        self.tape.constant(val).location
+    }
+
+    /// Gets the value of the variable `handle`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `handle` does not belong to the tape.
+    pub fn val(&self, handle: usize) -> f64 {
+        self.get(handle).val
+    }
+
+    /// Gets the number of nodes on the tape.
+    pub fn len(&self) -> usize {
+        self.tape.len()
+    }
+
+    /// Checks whether the tape is empty.
+    pub fn is_empty(&self) -> bool {
+        self.tape.is_empty()
+    }
+
+    /// Delete every variable, invalidating all handles.
+    pub fn clear(&mut self) {
+        self.tape.clear();
+    }
+
+    /// Apply the unary function `name` to `x`, for `neg` and any method of `Var` that takes no
+    /// arguments, such as `sin`, `exp` or `lgamma`. Returns `None` for an unknown name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` does not belong to the tape.
+    pub fn unary(&mut self, name: &str, x: usize) -> Option<usize> {
+        let x = self.get(x);
+        let res = match name {
+            "neg" => -x,
+            "recip" => x.recip(),
+            "sin" => x.sin(),
+            "cos" => x.cos(),
+            "tan" => x.tan(),
+            "ln" => x.ln(),
+            "log10" => x.log10(),
+            "log2" => x.log2(),
+            "ln_1p" => x.ln_1p(),
+            "asin" => x.asin(),
+            "acos" => x.acos(),
+            "atan" => x.atan(),
+            "sinh" => x.sinh(),
+            "cosh" => x.cosh(),
+            "tanh" => x.tanh(),
+            "asinh" => x.asinh(),
+            "acosh" => x.acosh(),
+            "atanh" => x.atanh(),
+            "exp" => x.exp(),
+            "exp_m1" => x.exp_m1(),
+            "exp2" => x.exp2(),
+            "sqrt" => x.sqrt(),
+            "cbrt" => x.cbrt(),
+            "abs" => x.abs(),
+            "floor" => x.floor(),
+            "ceil" => x.ceil(),
+            "round" => x.round(),
+            "trunc" => x.trunc(),
+            "signum" => x.signum(),
+            "fract" => x.fract(),
+            "erf" => x.erf(),
+            "erfc" => x.erfc(),
+            "lgamma" => x.lgamma(),
+            "digamma" => x.digamma(),
+            "norm_cdf" => x.norm_cdf(),
+            "norm_cdf_inv" => x.norm_cdf_inv(),
+            "sinc" => x.sinc(),
+            _ => return None,
+        };
+        Some(res.location)
+    }
+
+    /// Apply the binary function `name` to `x` and `y`, for `add`, `sub`, `mul`, `div`, `powf`,
+    /// `atan2`, `hypot`, `logaddexp`, `copysign` and `ln_beta`. Returns `None` for an unknown name.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `x` or `y` does not belong to the tape.
+    pub fn binary(&mut self, name: &str, x: usize, y: usize) -> Option<usize> {
+        let (x, y) = (self.get(x), self.get(y));
+        let res = match name {
+            "add" => x + y,
+            "sub" => x - y,
+            "mul" => x * y,
+            "div" => x / y,
+            "powf" => x.powf(y),
+            "atan2" => x.atan2(y),
+            "hypot" => x.hypot(y),
+            "logaddexp" => x.logaddexp(y),
+            "copysign" => x.copysign(y),
+            "ln_beta" => crate::ln_beta(x, y),
+            _ => return None,
+        };
+        Some(res.location)
+    }
+
+    /// Calculate the gradient of `output` with respect to every node, indexed by handle.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `output` does not belong to the tape.
+    pub fn grad(&self, output: usize) -> Vec<f64> {
+        self.get(output).grad().into_vec()
+    }
+
+    /// Calculate the gradient of `output` with respect to each of the variables `wrt`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any handle does not belong to the tape.
+    pub fn grad_wrt(&self, output: usize, wrt: &[usize]) -> Vec<f64> {
+        let grads = self.grad(output);
+        wrt.iter().map(|&handle| grads[handle]).collect()
+    }
+
+    /// Rebuild the variable at `handle` from its recorded value.
+    fn get(&self, handle: usize) -> Var<'_> {
+        let val = self.tape.provenance.as_ref().unwrap().borrow()[handle];
+        Var {
+            val,
+            location: handle,
+            tape: &self.tape,
+        }
+    }
+}
+
+impl Default for HandleTape {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Gradient;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_handles() {
+        let mut h = HandleTape::new();
+        let x = h.var(0.5);
+        let y = h.var(-1.5);
+        let one = h.constant(1.);
+        let a = h.binary("sub", one, x).unwrap();
+        let a2 = h.binary("mul", a, a).unwrap();
+        let x2 = h.binary("mul", x, x).unwrap();
+        let b = h.binary("sub", y, x2).unwrap();
+        let b2 = h.binary("mul", b, b).unwrap();
+        let hundred = h.constant(100.);
+        let b2 = h.binary("mul", hundred, b2).unwrap();
+        let res = h.binary("add", a2, b2).unwrap();
+        assert!(h.unary("nope", res).is_none());
+        assert!(h.binary("nope", res, x).is_none());
+
+        let g = Tape::new();
+        let (vx, vy) = (g.add_var(0.5), g.add_var(-1.5));
+        let expected = (1. - vx).powi(2) + 100. * (vy - vx.powi(2)).powi(2);
+        assert_approx_eq!(h.val(res), expected.val());
+        let grads = h.grad_wrt(res, &[x, y]);
+        for (grad, expected) in grads.iter().zip(expected.grad().wrt(&[vx, vy])) {
+            assert_approx_eq!(*grad, expected);
+        }
+
+        let s = h.unary("exp", x).unwrap();
+        assert_eq!(h.val(s), 0.5_f64.exp());
+        h.clear();
+        assert!(h.is_empty());
+    }
+}
@@ -0,0 +1,609 @@
+//! Gradients recorded as variables on the tape, so that expressions involving them can be
+//! differentiated in turn.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2.]);
+//! let f = x[0].powi(2) * x[1];
+//! let g = f.grad_recorded(&x);
+//! // gradient penalty ‖∇f‖² = (2 x y)² + (x²)²
+//! let penalty = g[0] * g[0] + g[1] * g[1];
+//! assert_eq!(penalty.val, 17.);
+//! let grads = penalty.grad();
+//! assert_eq!(grads.wrt(&x), vec![8. * 2. * 2. + 4., 8. * 2.]);
+//! ```
+
+use crate::{det, inv, linalg::Lu, logsumexp, mean, sum, Mat, Op, Powf, Tape, Var};
+use std::f64::consts::{FRAC_2_SQRT_PI, LN_2, PI};
+
+/// Adjoint or partial derivative, kept as a plain number while it does not depend on any
+/// variable so that constants are not recorded.
+#[derive(Clone, Copy)]
+enum Term<'a> {
+    Const(f64),
+    Var(Var<'a>),
+}
+
+impl<'a> Term<'a> {
+    fn mul(self, other: Self) -> Self {
+        match (self, other) {
+            (Term::Const(a), Term::Const(b)) => Term::Const(a * b),
+            (Term::Const(c), Term::Var(v)) | (Term::Var(v), Term::Const(c)) => Term::Var(v * c),
+            (Term::Var(a), Term::Var(b)) => Term::Var(a * b),
+        }
+    }
+
+    fn add(self, other: Self) -> Self {
+        match (self, other) {
+            (Term::Const(a), Term::Const(b)) => Term::Const(a + b),
+            (Term::Const(c), Term::Var(v)) | (Term::Var(v), Term::Const(c)) => Term::Var(v + c),
+            (Term::Var(a), Term::Var(b)) => Term::Var(a + b),
+        }
+    }
+
+    fn into_var(self, tape: &'a Tape) -> Var<'a> {
+        match self {
+            Term::Const(c) => tape.constant(c),
+            Term::Var(v) => v,
+        }
+    }
+}
+
+impl<'a> Var<'a> {
+    /// Calculate the gradient of this variable with respect to `inputs` like `grad`, but record
+    /// the backward pass on the tape, so that the returned gradients are variables that can be
+    /// used in further expressions and differentiated again.
+    ///
+    /// The values of the nodes are recomputed from `inputs`, which must therefore include every
+    /// variable this one depends on, unless the tape was created with `Tape::with_provenance`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the value of a variable is unknown, or if this variable depends on a hook or on
+    /// an operation recorded as a block other than through `det` and `logdet`, such as `solve`.
+    pub fn grad_recorded(&self, inputs: &[Var<'a>]) -> Vec<Var<'a>> {
+        let tape = self.tape;
+        let len = self.location + 1;
+        // copy what the sweep reads, as recording borrows the tape mutably
+        let ops = tape.ops.borrow()[..len].to_vec();
+        let nodes = tape.nodes.borrow()[..len].to_vec();
+        let spans = {
+            let spans = tape.spans.borrow();
+            spans[..spans.partition_point(|span| span.node < len)].to_vec()
+        };
+        let operands = tape.operands.borrow()[..spans.last().map_or(0, |span| span.end)].to_vec();
+        if let Some(location) = ops.iter().position(|op| {
+            matches!(
+                op,
+                Op::Solve
+                    | Op::Inv
+                    | Op::Eigh
+                    | Op::Fft
+                    | Op::Ifft
+                    | Op::Ode
+                    | Op::Implicit
+                    | Op::SoftSort
+                    | Op::Normalize
+                    | Op::Attention
+            )
+        }) {
+            panic!(
+                "the backward pass of node {} ({:?}) cannot be recorded",
+                location, ops[location]
+            );
+        }
+        assert!(
+            tape.hooks.borrow().iter().all(|hook| hook.location >= len),
+            "the backward pass of hooks cannot be recorded"
+        );
+        let vals = node_values(tape, &ops, &nodes, &spans, &operands, inputs);
+        let var = |location: usize| Var {
+            val: vals[location],
+            location,
+            tape,
+        };
+
+        let mut adjoints = vec![None; len];
+        adjoints[self.location] = Some(Term::Const(1.));
+        let mut spans = spans.iter().rev().peekable();
+        for idx in (0..len).rev() {
+            let span = spans.next_if(|span| span.node == idx);
+            let adjoint = match adjoints[idx] {
+                Some(adjoint) => adjoint,
+                None => continue,
+            };
+            let mut accumulate = |dep: usize, partial: Term<'a>| {
+                let term = adjoint.mul(partial);
+                adjoints[dep] = Some(match adjoints[dep] {
+                    Some(sum) => sum.add(term),
+                    None => term,
+                });
+            };
+            if let Some(span) = span {
+                let deps = operands[span.start..span.end]
+                    .iter()
+                    .map(|&(dep, _)| dep)
+                    .collect::<Vec<_>>();
+                let xs = deps.iter().map(|&dep| var(dep)).collect::<Vec<_>>();
+                let partials = match ops[idx] {
+                    Op::Linear => operands[span.start..span.end]
+                        .iter()
+                        .map(|&(_, weight)| Term::Const(weight))
+                        .collect(),
+                    op => nary_partials(op, &xs, var(idx)),
+                };
+                for (dep, partial) in deps.into_iter().zip(partials) {
+                    accumulate(dep, partial);
+                }
+            } else if !matches!(ops[idx], Op::Input | Op::Const(_)) {
+                let [x, y] = nodes[idx].dependencies();
+                let [d_x, d_y] = partials(ops[idx], var(x), var(y), var(idx));
+                accumulate(x, d_x);
+                if !is_unary(ops[idx]) {
+                    accumulate(y, d_y);
+                }
+            }
+        }
+        inputs
+            .iter()
+            .map(|input| {
+                match adjoints.get(input.location).copied().flatten() {
+                    Some(adjoint) => adjoint,
+                    None => Term::Const(0.),
+                }
+                .into_var(tape)
+            })
+            .collect()
+    }
+}
+
+/// Values of the first `ops.len()` nodes, taken from provenance if the tape records it or else
+/// recomputed from the values of `inputs`.
+fn node_values(
+    tape: &Tape,
+    ops: &[Op],
+    nodes: &[crate::Node],
+    spans: &[crate::Span],
+    operands: &[(usize, f64)],
+    inputs: &[Var],
+) -> Vec<f64> {
+    if let Some(provenance) = &tape.provenance {
+        return provenance.borrow()[..ops.len()].to_vec();
+    }
+    let mut known = vec![None; ops.len()];
+    for input in inputs.iter().filter(|input| input.location < ops.len()) {
+        known[input.location] = Some(input.val);
+    }
+    let mut spans = spans.iter().peekable();
+    let mut vals = Vec::with_capacity(ops.len());
+    for (idx, (&op, node)) in ops.iter().zip(nodes).enumerate() {
+        let val = if let Some(span) = spans.next_if(|span| span.node == idx) {
+            let (xs, weights): (Vec<_>, Vec<_>) = operands[span.start..span.end]
+                .iter()
+                .map(|&(dep, weight)| (vals[dep], weight))
+                .unzip();
+            op.eval_operands(&xs, &weights).0
+        } else {
+            match op {
+                Op::Input => known[idx].unwrap_or_else(|| {
+                    panic!(
+                        "the value of the variable at location {} is unknown, pass it in `inputs`",
+                        idx
+                    )
+                }),
+                Op::Const(val) => val,
+                op => {
+                    let [x, y] = node.dependencies();
+                    op.eval(vals[x], vals[y]).0
+                }
+            }
+        };
+        vals.push(val);
+    }
+    vals
+}
+
+/// Checks whether the scalar operation `op` takes a single value.
+fn is_unary(op: Op) -> bool {
+    !matches!(
+        op,
+        Op::Add
+            | Op::Mul
+            | Op::Powf
+            | Op::Atan2
+            | Op::Hypot
+            | Op::LogAddExp
+            | Op::Copysign
+            | Op::LnBeta
+    )
+}
+
+/// Partial derivatives of the scalar operation `op` applied to `x` (and `y`) with value `val`, as
+/// expressions of the variables. They match the weights computed by `Op::eval`.
+fn partials<'a>(op: Op, x: Var<'a>, y: Var<'a>, val: Var<'a>) -> [Term<'a>; 2] {
+    let (_, d_x, d_y) = op.eval(x.val, y.val);
+    let unary = |deriv: Var<'a>| [Term::Var(deriv), Term::Const(0.)];
+    match op {
+        Op::Mul => [Term::Var(y), Term::Var(x)],
+        Op::ConstDiv(_) => unary(x.recip() * -1.),
+        Op::Recip => unary(x.powi(-2) * -1.),
+        Op::Sin => unary(x.cos()),
+        Op::Cos => unary(x.sin() * -1.),
+        Op::Tan => unary(x.cos().powi(-2)),
+        Op::Ln => unary(x.recip()),
+        Op::Log(base) => unary(x.recip() * base.ln().recip()),
+        Op::Ln1p => unary((x + 1.).recip()),
+        Op::Asin => unary((1. - x.powi(2)).sqrt().recip()),
+        Op::Acos => unary((1. - x.powi(2)).sqrt().recip() * -1.),
+        Op::Atan => unary((x.powi(2) + 1.).recip()),
+        Op::Sinh => unary(x.cosh()),
+        Op::Cosh => unary(x.sinh()),
+        Op::Tanh => unary(x.cosh().powi(-2)),
+        Op::Asinh => unary((x.powi(2) + 1.).sqrt().recip()),
+        Op::Acosh => unary((x.powi(2) - 1.).sqrt().recip()),
+        Op::Atanh => unary((1. - x.powi(2)).recip()),
+        Op::Exp => unary(val),
+        Op::ExpM1 => unary(x.exp()),
+        Op::Exp2 => unary(val * LN_2),
+        Op::Sqrt => unary(val.recip() * 0.5),
+        Op::Abs => unary(x * val.recip()),
+        Op::Huber(delta) if x.val.abs() <= delta => unary(x),
+        Op::SmoothAbs(eps) => unary(x * (val + eps).recip()),
+        Op::Powi(n) => unary(x.powi(n - 1) * n as f64),
+        Op::Powf => [Term::Var(y * x.powf(y - 1.)), Term::Var(val * x.ln())],
+        Op::PowfConst(c) => unary(x.powf(c - 1.) * c),
+        Op::ConstPowf(c) => unary(val * c.ln()),
+        Op::Atan2 => {
+            let denom = (x.powi(2) + y.powi(2)).recip();
+            [Term::Var(y * denom), Term::Var(x * denom * -1.)]
+        }
+        Op::Atan2Const(c) => unary((x.powi(2) + c * c).recip() * c),
+        Op::ConstAtan2(c) => unary((x.powi(2) + c * c).recip() * -c),
+        Op::Hypot if val.val != 0. => [Term::Var(x * val.recip()), Term::Var(y * val.recip())],
+        Op::HypotConst(_) if val.val != 0. => unary(x * val.recip()),
+        Op::LogAddExp if val.val.is_finite() => {
+            [Term::Var((x - val).exp()), Term::Var((y - val).exp())]
+        }
+        Op::LogAddExpConst(_) if val.val.is_finite() => unary((x - val).exp()),
+        Op::Erf => unary((x.powi(2) * -1.).exp() * FRAC_2_SQRT_PI),
+        Op::Erfc => unary((x.powi(2) * -1.).exp() * -FRAC_2_SQRT_PI),
+        Op::Lgamma => unary(x.digamma()),
+        Op::Polygamma(n) => unary(x.polygamma(n + 1)),
+        Op::BesselJ(n) => unary((x.bessel_jn(n - 1) - x.bessel_jn(n + 1)) * 0.5),
+        Op::BesselI0 => unary(x.bessel_i1()),
+        // I₁' = I₀ - I₁ / x away from the origin
+        Op::BesselI1 if x.val != 0. => unary(x.bessel_i0() - val * x.recip()),
+        Op::NormCdf => unary((x.powi(2) * -0.5).exp() * (2. * PI).sqrt().recip()),
+        Op::NormCdfInv => unary((val.powi(2) * 0.5).exp() * (2. * PI).sqrt()),
+        Op::Sinc => unary(sinc_deriv(x)),
+        Op::LnBeta => {
+            let digamma_xy = (x + y).digamma();
+            [
+                Term::Var(x.digamma() - digamma_xy),
+                Term::Var(y.digamma() - digamma_xy),
+            ]
+        }
+        #[cfg(feature = "nn")]
+        Op::Sigmoid => unary(val - val.powi(2)),
+        // the remaining derivatives are constant around `x`
+        _ => [Term::Const(d_x), Term::Const(d_y)],
+    }
+}
+
+/// Derivative of `sinc` as an expression of `x`, switching to its Taylor series near zero as
+/// `special::sinc_deriv` does.
+fn sinc_deriv(x: Var) -> Var {
+    if x.val.abs() < 0.1 {
+        let x2 = x.powi(2);
+        let series = (1. - x2 * (1. / 88.)) * (1. / 54.);
+        let series = (1. - x2 * series) * (1. / 28.);
+        let series = (1. - x2 * series) * (1. / 10.);
+        (1. - x2 * series) * x * (-1. / 3.)
+    } else {
+        (x * x.cos() - x.sin()) * x.powi(-2)
+    }
+}
+
+/// Partial derivatives of the operation `op` on the values `xs` with value `val`, matching the
+/// weights computed by `Op::eval_nary`.
+fn nary_partials<'a>(op: Op, xs: &[Var<'a>], val: Var<'a>) -> Vec<Term<'a>> {
+    let vals = xs.iter().map(|x| x.val).collect::<Vec<_>>();
+    let n = xs.len() as f64;
+    let constant = || {
+        op.eval_nary(&vals)
+            .1
+            .into_iter()
+            .map(Term::Const)
+            .collect::<Vec<_>>()
+    };
+    match op {
+        Op::Variance => {
+            let mean = mean(xs);
+            xs.iter()
+                .map(|&x| Term::Var((x - mean) * (2. / n)))
+                .collect()
+        }
+        Op::Std if val.val != 0. => {
+            let mean = mean(xs);
+            let scale = val.recip() * n.recip();
+            xs.iter().map(|&x| Term::Var((x - mean) * scale)).collect()
+        }
+        Op::NormL2 | Op::NormLp(_) if val.val != 0. && val.val.is_finite() => {
+            let p = match op {
+                Op::NormLp(p) => p,
+                _ => 2.,
+            };
+            xs.iter()
+                .map(|&x| {
+                    let sign = x.val.signum();
+                    if x.val == 0. {
+                        Term::Const(0.)
+                    } else if p == 2. {
+                        Term::Var(x * val.recip())
+                    } else {
+                        Term::Var((x * sign * val.recip()).powf(p - 1.) * sign)
+                    }
+                })
+                .collect()
+        }
+        Op::LogSumExp if val.val.is_finite() => {
+            xs.iter().map(|&x| Term::Var((x - val).exp())).collect()
+        }
+        Op::Polyval => {
+            let (coeffs, x) = xs.split_at(xs.len() - 1);
+            let x = x[0];
+            let mut partials = vec![Term::Const(0.); xs.len()];
+            let mut power = Term::Const(1.);
+            for partial in partials[..coeffs.len()].iter_mut().rev() {
+                *partial = power;
+                power = power.mul(Term::Var(x));
+            }
+            // Horner's scheme for the derivative of the polynomial
+            let (mut val, mut deriv) = (Term::Const(0.), Term::Const(0.));
+            for &c in coeffs {
+                deriv = deriv.mul(Term::Var(x)).add(val);
+                val = val.mul(Term::Var(x)).add(Term::Var(c));
+            }
+            partials[coeffs.len()] = deriv;
+            partials
+        }
+        Op::Dot => nary_partials(Op::Einsum(2), xs, val),
+        Op::Einsum(factors) => xs
+            .chunks(factors)
+            .flat_map(|term| {
+                (0..term.len()).map(move |k| {
+                    term.iter()
+                        .enumerate()
+                        .filter(|&(l, _)| l != k)
+                        .fold(Term::Const(1.), |prod, (_, &x)| prod.mul(Term::Var(x)))
+                })
+            })
+            .collect(),
+        Op::Affine => {
+            let mut partials = nary_partials(Op::Dot, &xs[1..], val);
+            partials.insert(0, Term::Const(1.));
+            partials
+        }
+        Op::LogDet | Op::Det if Lu::new(&vals, n.sqrt() as usize).is_none() => match op {
+            // the cofactors are determinants of the minors, and are constant for a 1 x 1 matrix
+            Op::Det if xs.len() > 1 => {
+                let size = n.sqrt() as usize;
+                (0..xs.len())
+                    .map(|k| {
+                        let (i, j) = (k / size, k % size);
+                        let minor = (0..xs.len())
+                            .filter(|&l| l / size != i && l % size != j)
+                            .map(|l| xs[l])
+                            .collect();
+                        let cofactor = det(&Mat::new(size - 1, size - 1, minor));
+                        Term::Var(cofactor * if (i + j) % 2 == 0 { 1. } else { -1. })
+                    })
+                    .collect()
+            }
+            _ => constant(),
+        },
+        Op::LogDet | Op::Det => {
+            let size = (n.sqrt()) as usize;
+            let inverse = inv(&Mat::new(size, size, xs.to_vec()));
+            let scale = if op == Op::Det {
+                Term::Var(val)
+            } else {
+                Term::Const(1.)
+            };
+            (0..xs.len())
+                .map(|k| scale.mul(Term::Var(inverse[(k % size, k / size)])))
+                .collect()
+        }
+        Op::Mse => xs
+            .chunks(2)
+            .flat_map(|pair| {
+                let weight = (pair[0] - pair[1]) * (2. / (n / 2.));
+                [Term::Var(weight), Term::Var(weight * -1.)]
+            })
+            .collect(),
+        Op::BinaryCrossEntropy => {
+            let n = n / 2.;
+            xs.chunks(2)
+                .flat_map(|pair| {
+                    let (z, t) = (pair[0], pair[1]);
+                    let sigmoid = if z.val >= 0. {
+                        ((z * -1.).exp() + 1.).recip()
+                    } else {
+                        z.exp() * (z.exp() + 1.).recip()
+                    };
+                    [
+                        Term::Var((sigmoid - t) * n.recip()),
+                        Term::Var(z * -n.recip()),
+                    ]
+                })
+                .collect()
+        }
+        Op::CrossEntropy => {
+            let logits = xs.iter().step_by(2).copied().collect::<Vec<_>>();
+            let targets = xs.iter().skip(1).step_by(2).copied().collect::<Vec<_>>();
+            let lse = logsumexp(&logits);
+            let total = sum(&targets);
+            logits
+                .iter()
+                .zip(&targets)
+                .flat_map(|(&z, &t)| [Term::Var((z - lse).exp() * total - t), Term::Var(lse - z)])
+                .collect()
+        }
+        Op::Entropy => xs
+            .iter()
+            .map(|&p| {
+                if p.val == 0. {
+                    Term::Const(0.)
+                } else {
+                    Term::Var((p.ln() + 1.) * -1.)
+                }
+            })
+            .collect(),
+        Op::KlDiv => xs
+            .chunks(2)
+            .flat_map(|pair| {
+                let (p, q) = (pair[0], pair[1]);
+                if p.val == 0. {
+                    [Term::Const(0.), Term::Const(0.)]
+                } else {
+                    [Term::Var(p.ln() - q.ln() + 1.), Term::Var(p / q * -1.)]
+                }
+            })
+            .collect(),
+        Op::KlDivLogits => {
+            let log_softmax = |logits: Vec<Var<'a>>| {
+                let lse = logsumexp(&logits);
+                logits.into_iter().map(|z| z - lse).collect::<Vec<_>>()
+            };
+            let lp = log_softmax(xs.iter().step_by(2).copied().collect());
+            let lq = log_softmax(xs.iter().skip(1).step_by(2).copied().collect());
+            lp.iter()
+                .zip(&lq)
+                .flat_map(|(&a, &b)| {
+                    [
+                        Term::Var(a.exp() * (a - b - val)),
+                        Term::Var(b.exp() - a.exp()),
+                    ]
+                })
+                .collect()
+        }
+        Op::Hinge => {
+            let n = n / 2.;
+            xs.chunks(2)
+                .flat_map(|pair| {
+                    let (y, t) = (pair[0], pair[1]);
+                    if 1. - t.val * y.val > 0. {
+                        [Term::Var(t * -n.recip()), Term::Var(y * -n.recip())]
+                    } else {
+                        [Term::Const(0.), Term::Const(0.)]
+                    }
+                })
+                .collect()
+        }
+        // the remaining derivatives are constant around `xs`
+        _ => constant(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{
+        det, dot, logdet, norm_l1, norm_l2, norm_lp, polyval, std, variance, Atan2, Gradient, Hypot,
+    };
+    use approx_eq::assert_approx_eq;
+
+    /// Checks the recorded gradient of `f` against `grad`, and the gradient of its first
+    /// component against central differences of `grad`.
+    fn check(f: impl for<'a> Fn(&[Var<'a>]) -> Var<'a>, xs: &[f64]) {
+        let tape = Tape::new();
+        let x = tape.add_vars(xs);
+        let y = f(&x);
+        let recorded = y.grad_recorded(&x);
+        let grads = y.grad().wrt(&x);
+        for (recorded, grad) in recorded.iter().zip(&grads) {
+            assert_approx_eq!(recorded.val, *grad);
+        }
+        let second = recorded[0].grad().wrt(&x);
+        for (i, second) in second.iter().enumerate() {
+            let h = 1e-6;
+            let partial = |offset: f64| {
+                let tape = Tape::new();
+                let mut xs = xs.to_vec();
+                xs[i] += offset;
+                let x = tape.add_vars(&xs);
+                f(&x).grad().wrt(&x[0])
+            };
+            assert_approx_eq!(*second, (partial(h) - partial(-h)) / (2. * h), 1e-5);
+        }
+    }
+
+    #[test]
+    fn test_grad_recorded() {
+        check(
+            |x| (x[0] * x[1]).sin() / x[1].hypot(x[0]) + x[0].powf(x[1]) - x[1].atanh(),
+            &[0.7, 0.4],
+        );
+        check(
+            |x| Powf::powf(2., x[0] * x[1]) + Powf::powf(0.5, x[0]).sin(),
+            &[1.3, -0.6],
+        );
+        check(
+            |x| x[0].erf() * x[1].lgamma() + x[0].sinc() + x[1].norm_cdf_inv().tanh(),
+            &[0.05, 0.3],
+        );
+        check(
+            |x| variance(x) * std(x) + dot(x, x).ln() - polyval(&[x[1], x[2], x[1] * 2.], x[0]),
+            &[0.5, -1.2, 2.],
+        );
+        check(
+            |x| {
+                let m = Mat::new(2, 2, vec![x[0], x[1], x[2], x[0] * x[1]]);
+                det(&m) * logdet(&m) + logsumexp(x)
+            },
+            &[1.5, 0.5, -0.8],
+        );
+        check(
+            |x| det(&Mat::new(3, 3, x.to_vec())) + det(&Mat::new(1, 1, vec![x[8]])),
+            &[1., 2., 3., 2., 4., 6., 0., 1., 0.],
+        );
+        check(
+            |x| {
+                let (p, t) = (&x[..2], &x[2..]);
+                crate::losses::mse(p, t) * crate::losses::binary_cross_entropy(p, t)
+                    + crate::losses::cross_entropy(p, t) * x[0].atan2(x[3]).bessel_i1()
+            },
+            &[0.3, -0.6, 0.2, 0.9],
+        );
+        check(
+            |x| {
+                let (p, q) = (&x[..2], &x[2..]);
+                crate::losses::entropy(p) * crate::losses::kl_div(p, q)
+                    + crate::losses::kl_div_logits(q, p).exp()
+            },
+            &[0.3, 0.6, 0.2, 0.9],
+        );
+        check(
+            |x| norm_l2(x) * norm_lp(x, 3.) + norm_lp(x, 1.5).sin() + norm_l1(x),
+            &[0.8, -0.3, 1.1],
+        );
+    }
+
+    #[test]
+    fn test_provenance_values() {
+        let tape = Tape::with_provenance();
+        let x = tape.add_vars(&[2., 3.]);
+        let y = x[0].exp() * x[1];
+        assert_eq!(y.grad_recorded(&x[1..])[0].val, 2f64.exp());
+    }
+
+    #[test]
+    #[should_panic(expected = "the value of the variable at location 0 is unknown")]
+    fn test_missing_input() {
+        let tape = Tape::new();
+        let x = tape.add_vars(&[2., 3.]);
+        let _ = (x[0] * x[1]).grad_recorded(&x[1..]);
+    }
+}
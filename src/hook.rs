@@ -0,0 +1,103 @@
+//! Functions that observe or replace the adjoint of a node during the backward pass.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_var(3.);
+//! let y = x.powi(2);
+//! // stop the gradient from flowing through `y`, and print it on the way
+//! y.register_hook(|adjoint| {
+//!     println!("adjoint of y: {}", adjoint);
+//!     0.
+//! });
+//! let res = y * 2. + x;
+//! assert_eq!(res.grad().wrt(&x), 1.);
+//! ```
+
+use crate::{Tape, Var};
+use std::{fmt::Debug, sync::Arc};
+
+/// Function registered with `Var::register_hook`.
+#[derive(Clone)]
+pub(crate) struct Hook {
+    pub(crate) location: usize,
+    pub(crate) f: Arc<dyn Fn(f64) -> f64 + Send + Sync>,
+}
+
+impl Debug for Hook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Hook")
+            .field("location", &self.location)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<'a> Var<'a> {
+    /// Register a function that is called with the adjoint of this variable during every backward
+    /// pass, once all contributions to it have been accumulated and before it is propagated to the
+    /// variable's dependencies. The adjoint is replaced with the return value, so returning the
+    /// argument only observes it. Hooks on the same variable run in the order they were
+    /// registered, and run even if the adjoint is zero. Compiled programs do not run hooks.
+    pub fn register_hook(&self, hook: impl Fn(f64) -> f64 + Send + Sync + 'static) {
+        let mut hooks = self.tape.hooks.borrow_mut();
+        // later hooks go first, as the backward pass visits them in reverse
+        let at = hooks.partition_point(|h| h.location < self.location);
+        hooks.insert(
+            at,
+            Hook {
+                location: self.location,
+                f: Arc::new(hook),
+            },
+        );
+    }
+}
+
+impl Tape {
+    /// Remove every hook registered with `Var::register_hook`.
+    pub fn clear_hooks(&self) {
+        self.hooks.borrow_mut().clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{solve, Gradient, Mat};
+    use std::sync::Mutex;
+
+    #[test]
+    fn test_hooks() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2.]);
+        let y = x[0] * x[1];
+        let seen = Arc::new(Mutex::new(vec![]));
+        let log = seen.clone();
+        y.register_hook(move |adjoint| {
+            log.lock().unwrap().push(adjoint);
+            adjoint
+        });
+        y.register_hook(|adjoint| adjoint * 10.);
+        y.register_hook(|adjoint| adjoint + 1.);
+        let res = y.sin() + x[0];
+        let grads = res.grad();
+        let adjoint = 2_f64.cos();
+        assert_eq!(*seen.lock().unwrap(), vec![adjoint]);
+        assert_eq!(grads.wrt(&y), adjoint * 10. + 1.);
+        assert_eq!(
+            grads.wrt(&x),
+            vec![(adjoint * 10. + 1.) * 2. + 1., adjoint * 10. + 1.]
+        );
+
+        g.clear_hooks();
+        assert_eq!(res.grad().wrt(&y), adjoint);
+
+        // the outputs of a block see their hooks before the block's own backward pass
+        let m = Mat::new(1, 1, vec![x[1]]);
+        let z = solve(&m, &[x[0]]);
+        assert_eq!(z[0].grad().wrt(&x), vec![0.5, -0.25]);
+        z[0].register_hook(|adjoint| adjoint * 4.);
+        assert_eq!(z[0].grad().wrt(&x), vec![2., -1.]);
+        assert_eq!(z[0].grad_leaves().wrt(&x), vec![2., -1.]);
+    }
+}
@@ -0,0 +1,247 @@
+//! Solutions of equations, differentiated with the implicit function theorem instead of through
+//! the iterations that found them.
+//!
+//! The equations are functions of the unknowns and of parameters, evaluated on a scratch tape
+//! while solving. Only the solution is recorded on the tape of the parameters, so the tape does
+//! not grow with the number of iterations, and the gradient is exact once the iteration has
+//! converged.
+
+use crate::{error::assert_same_tape, vjp, Gradient, Op, Tape, Var};
+
+/// Largest number of iterations taken by the solvers before giving up.
+const MAX_ITERS: usize = 1000;
+
+/// Checks whether an iteration has converged, from the change `step` to each `x`.
+fn converged(x: &[f64], step: impl Iterator<Item = f64>) -> bool {
+    x.iter()
+        .zip(step)
+        .all(|(x, step)| step.abs() <= 1e-12 * x.abs().max(1.))
+}
+
+/// Gets the tape of `params` and their values, panicking if they are empty or on different tapes,
+/// which is reported as coming from `name`.
+fn tape_and_vals<'a>(name: &'static str, params: &[Var<'a>]) -> (&'a Tape, Vec<f64>) {
+    let tape = params.first().expect("need at least one parameter").tape;
+    for p in params {
+        assert_same_tape(name, tape, p.tape);
+    }
+    (tape, params.iter().map(|p| p.val).collect())
+}
+
+/// Find a root of `f(x, params)` with Newton's method starting from `x0`. The derivative of the
+/// root with respect to each parameter is `-(df/dparam) / (df/dx)` at the root, and no gradient
+/// flows through the iterations.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// // the positive root of x^2 - a
+/// fn f<'b>(x: Var<'b>, params: &[Var<'b>]) -> Var<'b> {
+///     x.powi(2) - params[0]
+/// }
+///
+/// let tape = Tape::new();
+/// let a = tape.add_var(2.);
+/// let root = solve_root(f, &[a], 1.);
+/// assert!((root.val() - 2_f64.sqrt()).abs() < 1e-12);
+/// assert!((root.grad().wrt(&a) - 0.5 / 2_f64.sqrt()).abs() < 1e-12);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `params` is empty or on different tapes, if the derivative of `f` vanishes, or if
+/// the iteration does not converge.
+pub fn solve_root<'a, F>(f: F, params: &[Var<'a>], x0: f64) -> Var<'a>
+where
+    F: for<'b> Fn(Var<'b>, &[Var<'b>]) -> Var<'b>,
+{
+    let (tape, vals) = tape_and_vals("solve_root", params);
+    let scratch = Tape::new();
+    let mut x = x0;
+    for _ in 0..MAX_ITERS {
+        let xv = scratch.add_var(x);
+        let p = scratch.add_vars(&vals);
+        let y = f(xv, &p);
+        let grads = y.grad();
+        let slope = grads.wrt(&xv);
+        assert!(slope != 0., "derivative vanishes during root finding");
+        let step = y.val / slope;
+        if step.abs() > 1e-15 * x.abs().max(1.) {
+            x -= step;
+            scratch.clear();
+            continue;
+        }
+        // the root is converged, and the gradients at it give its sensitivities
+        let weights = grads
+            .wrt(&p)
+            .iter()
+            .map(|grad| -grad / slope)
+            .collect::<Vec<_>>();
+        let inputs = params.iter().map(|p| p.location).collect();
+        let location = tape.add_block(Op::Implicit, &[x], inputs, move |x_bar, p_bar| {
+            for (p_bar, weight) in p_bar.iter_mut().zip(&weights) {
+                *p_bar = x_bar[0] * weight;
+            }
+        });
+        return Var {
+            val: x,
+            location,
+            tape,
+        };
+    }
+    panic!("root finding did not converge")
+}
+
+/// Record `f(x, params)` on `scratch`, returning the variables for `x`, `params` and the result.
+#[allow(clippy::type_complexity)]
+fn record_map<'b, F>(
+    f: &F,
+    scratch: &'b Tape,
+    x: &[f64],
+    params: &[f64],
+) -> (Vec<Var<'b>>, Vec<Var<'b>>, Vec<Var<'b>>)
+where
+    F: for<'c> Fn(&[Var<'c>], &[Var<'c>]) -> Vec<Var<'c>>,
+{
+    let (xv, p) = (scratch.add_vars(x), scratch.add_vars(params));
+    let y = f(&xv, &p);
+    assert_eq!(y.len(), x.len(), "fixed point changes the number of values");
+    (xv, p, y)
+}
+
+/// Find a fixed point `x = f(x, params)` by iterating `f` from `x0`, which converges when `f` is a
+/// contraction in `x`. Only the fixed point is recorded on the tape, however many iterations it
+/// takes.
+///
+/// The gradients are those of the fixed point itself. The backward pass solves the adjoint
+/// equation `w = x_bar + (df/dx)^T w` by the same iteration, re-evaluating `f` once at the fixed
+/// point on a scratch tape, and pulls `w` back to the parameters with `(df/dparams)^T w`. As `f`
+/// is kept for the backward pass, it cannot borrow anything and is usually a plain function.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// // x = cos(a x) has a fixed point for small a
+/// fn f<'b>(x: &[Var<'b>], params: &[Var<'b>]) -> Vec<Var<'b>> {
+///     vec![(params[0] * x[0]).cos()]
+/// }
+///
+/// let tape = Tape::new();
+/// let a = tape.add_var(0.5);
+/// let x = fixed_point(f, &[a], &[0.]);
+/// assert!((x[0].val() - (0.5 * x[0].val()).cos()).abs() < 1e-12);
+/// // implicitly, dx/da = -x sin(a x) / (1 + a sin(a x))
+/// let s = (0.5 * x[0].val()).sin();
+/// let expected = -x[0].val() * s / (1. + 0.5 * s);
+/// assert!((x[0].grad().wrt(&a) - expected).abs() < 1e-10);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `params` is empty or on different tapes, if `f` returns a different number of
+/// values than `x0`, or if the iteration does not converge.
+pub fn fixed_point<'a, F>(f: F, params: &[Var<'a>], x0: &[f64]) -> Vec<Var<'a>>
+where
+    F: for<'b> Fn(&[Var<'b>], &[Var<'b>]) -> Vec<Var<'b>> + Send + Sync + 'static,
+{
+    let (tape, param_vals) = tape_and_vals("fixed_point", params);
+    let n = x0.len();
+    let scratch = Tape::new();
+    let mut x = x0.to_vec();
+    for _ in 0..MAX_ITERS {
+        let (.., y) = record_map(&f, &scratch, &x, &param_vals);
+        let next = y.iter().map(|y| y.val).collect::<Vec<_>>();
+        scratch.clear();
+        let done = converged(&next, next.iter().zip(&x).map(|(a, b)| a - b));
+        x = next;
+        if !done {
+            continue;
+        }
+        let inputs = params.iter().map(|p| p.location).collect();
+        let vals = x.clone();
+        let start = tape.add_block(Op::Implicit, &x, inputs, move |x_bar, p_bar| {
+            let scratch = Tape::new();
+            let (xv, p, y) = record_map(&f, &scratch, &vals, &param_vals);
+            let mut w = x_bar.to_vec();
+            for _ in 0..MAX_ITERS {
+                let pulled = vjp(&y, &w).wrt(&xv);
+                let next = x_bar.iter().zip(pulled).map(|(a, b)| a + b);
+                let next = next.collect::<Vec<_>>();
+                let done = converged(&next, next.iter().zip(&w).map(|(a, b)| a - b));
+                w = next;
+                if done {
+                    p_bar.copy_from_slice(&vjp(&y, &w).wrt(&p));
+                    return;
+                }
+            }
+            panic!("adjoint of the fixed point did not converge")
+        });
+        return (0..n)
+            .map(|i| Var {
+                val: x[i],
+                location: start + i,
+                tape,
+            })
+            .collect();
+    }
+    panic!("fixed point iteration did not converge")
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    /// Kepler's equation `E - e sin(E) = M` for the eccentric anomaly `E`.
+    fn kepler<'b>(x: Var<'b>, params: &[Var<'b>]) -> Var<'b> {
+        x - params[0] * x.sin() - params[1]
+    }
+
+    /// Linear map `x = A x + b` with a contraction `A` built from the parameters.
+    fn linear<'b>(x: &[Var<'b>], params: &[Var<'b>]) -> Vec<Var<'b>> {
+        vec![
+            x[0] * params[0] + x[1] * 0.2 + params[1],
+            x[0] * 0.1 - x[1] * params[0] + 1.,
+        ]
+    }
+
+    #[test]
+    fn test_solve_root() {
+        let g = Tape::new();
+        let params = g.add_vars(&[0.3, 1.2]);
+        let root = solve_root(kepler, &params, 1.);
+        // only the root is recorded
+        assert_eq!(g.len(), 3);
+        assert_approx_eq!(kepler(root, &params).val() + 1., 1.);
+        // implicit derivatives, from differentiating the equation
+        let denom = 1. - 0.3 * root.val().cos();
+        let grads = (root * 2.).grad().wrt(&params);
+        assert_approx_eq!(grads[0], 2. * root.val().sin() / denom);
+        assert_approx_eq!(grads[1], 2. / denom);
+    }
+
+    #[test]
+    fn test_fixed_point() {
+        let g = Tape::new();
+        let params = g.add_vars(&[0.4, -1.]);
+        let x = fixed_point(linear, &params, &[0., 0.]);
+        assert_eq!(g.len(), 4);
+        let y = linear(&x, &params);
+        assert_approx_eq!(x[0].val(), y[0].val());
+        assert_approx_eq!(x[1].val(), y[1].val());
+
+        // the closed form solution of the 2x2 system (I - A) x = b
+        let (a, b) = (params[0], params[1]);
+        let det = (1. - a) * (1. + a) - 0.02;
+        let x0 = (b * (1. + a) + 0.2) / det;
+        let x1 = ((1. - a) + b * 0.1) / det;
+        assert_approx_eq!(x[0].val(), x0.val());
+        assert_approx_eq!(x[1].val(), x1.val());
+        let loss = x[0] * 3. - x[1];
+        let expected = x0 * 3. - x1;
+        let (grads, expected) = (loss.grad().wrt(&params), expected.grad().wrt(&params));
+        for (grad, expected) in grads.iter().zip(expected) {
+            assert_approx_eq!(*grad, expected);
+        }
+    }
+}
@@ -0,0 +1,77 @@
+//! Hash-consing of nodes that only depend on constants, for tapes created with
+//! `Tape::with_interning`.
+
+use crate::Op;
+use std::collections::{HashMap, HashSet};
+
+/// Locations of the constant-derived nodes, keyed by their operation and dependencies.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct Interner {
+    nodes: HashMap<(String, [usize; 2]), usize>,
+    /// Every location in `nodes`.
+    constants: HashSet<usize>,
+}
+
+impl Interner {
+    /// Gets the location of an existing node that computes `op` from `deps`, if the node about to
+    /// be recorded at `location` is derived from constants only. Otherwise returns `None`, after
+    /// remembering the new node if it is constant-derived.
+    pub(crate) fn intern(&mut self, op: Op, deps: [usize; 2], location: usize) -> Option<usize> {
+        let deps = match op {
+            // constants depend on their own location, which says nothing about their value
+            Op::Const(_) => [usize::MAX; 2],
+            Op::Input => return None,
+            // only the scalar operations have their dependencies in `deps`, and those of the
+            // others (n-ary operations and blocks) are their own location, which is not interned
+            _ if deps.iter().all(|dep| self.constants.contains(dep)) => deps,
+            _ => return None,
+        };
+        // `Debug` writes the constants of the operation exactly, so equal keys give equal values
+        let key = (format!("{:?}", op), deps);
+        match self.nodes.get(&key) {
+            Some(&existing) => Some(existing),
+            None => {
+                self.nodes.insert(key, location);
+                self.constants.insert(location);
+                None
+            }
+        }
+    }
+
+    /// Forget the nodes at location `len` and above.
+    pub(crate) fn truncate(&mut self, len: usize) {
+        self.nodes.retain(|_, location| *location < len);
+        self.constants.retain(|&location| location < len);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{losses, Gradient, Tape, Var};
+
+    #[test]
+    fn test_interning() {
+        fn f<'a>(tape: &'a Tape, x: &[Var<'a>]) -> Var<'a> {
+            let c = tape.constant(2.).exp();
+            (0..10).fold(x[0], |acc, i| {
+                let t = (tape.constant(2.).exp() + 1.).ln();
+                acc * c + losses::mse(x, &[t, t * i as f64])
+            })
+        }
+        let (plain, interned) = (Tape::new(), Tape::with_interning());
+        let (x, y) = (plain.add_vars(&[0.5, -1.]), interned.add_vars(&[0.5, -1.]));
+        let (a, b) = (f(&plain, &x), f(&interned, &y));
+        assert_eq!(a.val, b.val);
+        assert_eq!(a.grad().wrt(&x), b.grad().wrt(&y));
+        assert!(interned.len() < plain.len());
+
+        // rewinding forgets the interned nodes that are deleted
+        let mark = interned.mark();
+        let c = interned.constant(3.);
+        assert_eq!(interned.constant(3.).location, c.location);
+        interned.rewind_to(mark);
+        let d = interned.constant(3.);
+        assert_eq!(d.location, interned.len() - 1);
+        assert_eq!(interned.constant(3.).location, d.location);
+    }
+}
@@ -0,0 +1,483 @@
+//! Interval arithmetic over compiled tapes, giving bounds on values and gradients that hold for
+//! every input in a box.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_var(0.);
+//! let y = x.sin() * x;
+//! let program = tape.compile().unwrap();
+//!
+//! let inputs = [Interval::new(-0.5, 1.)];
+//! let grads = program.grad_interval(&inputs, &y).unwrap();
+//! // every derivative `sin(x) + x cos(x)` over the box lies within the bound
+//! for t in [-0.5_f64, 0., 0.3, 1.] {
+//!     assert!(grads.wrt(&x).contains(t.sin() + t * t.cos()));
+//! }
+//! ```
+//!
+//! Endpoints are widened outwards by one unit in the last place after each operation, which
+//! covers the rounding of the arithmetic operations and, in practice, of the standard library's
+//! elementary functions. Operations outside their domain, such as `ln` of an interval containing
+//! zero, give the entire real line.
+
+use crate::{compile::Domain, Gradient, Op, Program, ReplayError, Var};
+use std::{
+    f64::consts::{FRAC_PI_2, PI},
+    fmt::{self, Display},
+    ops::{Add, Div, Mul, Neg, Sub},
+};
+
+/// Closed interval `[lo, hi]` of real numbers.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Interval {
+    pub lo: f64,
+    pub hi: f64,
+}
+
+impl Interval {
+    /// The whole real line.
+    pub const ENTIRE: Self = Self {
+        lo: f64::NEG_INFINITY,
+        hi: f64::INFINITY,
+    };
+
+    /// Create the interval `[lo, hi]`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `lo > hi` or either endpoint is NaN.
+    pub fn new(lo: f64, hi: f64) -> Self {
+        assert!(lo <= hi, "invalid interval [{}, {}]", lo, hi);
+        Self { lo, hi }
+    }
+
+    /// Create the interval holding only `x`.
+    pub fn point(x: f64) -> Self {
+        Self::new(x, x)
+    }
+
+    pub fn width(&self) -> f64 {
+        self.hi - self.lo
+    }
+
+    pub fn mid(&self) -> f64 {
+        self.lo + self.width() / 2.
+    }
+
+    /// Checks whether `x` lies in the interval.
+    pub fn contains(&self, x: f64) -> bool {
+        self.lo <= x && x <= self.hi
+    }
+
+    /// Interval of `[lo, hi]` rounded outwards, or the entire line if either endpoint is NaN.
+    fn outward(lo: f64, hi: f64) -> Self {
+        if lo.is_nan() || hi.is_nan() {
+            Self::ENTIRE
+        } else {
+            Self {
+                lo: -next_up(-lo),
+                hi: next_up(hi),
+            }
+        }
+    }
+
+    /// Image under the nondecreasing function `f`.
+    fn increasing(self, f: impl Fn(f64) -> f64) -> Self {
+        Self::outward(f(self.lo), f(self.hi))
+    }
+
+    /// Image under the nonincreasing function `f`.
+    fn decreasing(self, f: impl Fn(f64) -> f64) -> Self {
+        Self::outward(f(self.hi), f(self.lo))
+    }
+
+    /// Image under a continuous `f` that is decreasing below `min` and increasing above it.
+    fn valley(self, min: f64, f: impl Fn(f64) -> f64) -> Self {
+        if self.hi <= min {
+            self.decreasing(f)
+        } else if self.lo >= min {
+            self.increasing(f)
+        } else {
+            Self::outward(f(min), f(self.lo).max(f(self.hi)))
+        }
+    }
+
+    /// Image under `f`, restricted to a domain starting at `lo`, or the entire line if the
+    /// interval leaves the domain.
+    fn domain(self, lo: f64, f: impl Fn(Self) -> Self) -> Self {
+        if self.lo >= lo {
+            f(self)
+        } else {
+            Self::ENTIRE
+        }
+    }
+
+    pub fn recip(self) -> Self {
+        if self.contains(0.) {
+            Self::ENTIRE
+        } else {
+            self.decreasing(f64::recip)
+        }
+    }
+
+    pub fn sqr(self) -> Self {
+        self.valley(0., |x| x * x)
+    }
+
+    pub fn powi(self, n: i32) -> Self {
+        match n {
+            0 => Self::point(1.),
+            n if n < 0 => self.powi(-n).recip(),
+            n if n % 2 == 0 => self.valley(0., |x| x.powi(n)),
+            n => self.increasing(|x| x.powi(n)),
+        }
+    }
+
+    pub fn sqrt(self) -> Self {
+        self.domain(0., |x| x.increasing(f64::sqrt))
+    }
+
+    pub fn exp(self) -> Self {
+        self.increasing(f64::exp)
+    }
+
+    pub fn ln(self) -> Self {
+        self.domain(0., |x| x.increasing(f64::ln))
+    }
+
+    pub fn sin(self) -> Self {
+        self.periodic(FRAC_PI_2, f64::sin)
+    }
+
+    pub fn cos(self) -> Self {
+        self.periodic(0., f64::cos)
+    }
+
+    /// Image under `sin` or `cos`, which have their maxima at `peak + 2 k pi` and their minima
+    /// half a period later.
+    fn periodic(self, peak: f64, f: impl Fn(f64) -> f64) -> Self {
+        if self.width() >= 2. * PI || !self.width().is_finite() {
+            return Self::new(-1., 1.);
+        }
+        let hits = |offset: f64| {
+            let k = ((self.lo - offset) / (2. * PI)).ceil();
+            offset + k * 2. * PI <= self.hi
+        };
+        let (a, b) = (f(self.lo), f(self.hi));
+        let hi = if hits(peak) { 1. } else { a.max(b) };
+        let lo = if hits(peak + PI) { -1. } else { a.min(b) };
+        Self::outward(lo, hi).intersect(Self::new(-1., 1.))
+    }
+
+    pub fn atan(self) -> Self {
+        self.increasing(f64::atan)
+    }
+
+    pub fn tanh(self) -> Self {
+        self.increasing(f64::tanh)
+    }
+
+    pub fn abs(self) -> Self {
+        self.valley(0., f64::abs)
+    }
+
+    fn intersect(self, other: Self) -> Self {
+        Self::new(self.lo.max(other.lo), self.hi.min(other.hi))
+    }
+}
+
+/// Smallest float greater than `x`, or `x` itself if it is infinite or NaN.
+fn next_up(x: f64) -> f64 {
+    if x.is_nan() || x == f64::INFINITY {
+        x
+    } else if x == 0. {
+        f64::from_bits(1)
+    } else if x > 0. {
+        f64::from_bits(x.to_bits() + 1)
+    } else {
+        f64::from_bits(x.to_bits() - 1)
+    }
+}
+
+/// Product of two endpoints, taking `0 * inf` to be zero.
+fn mul_endpoints(a: f64, b: f64) -> f64 {
+    if a == 0. || b == 0. {
+        0.
+    } else {
+        a * b
+    }
+}
+
+impl Add for Interval {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self::outward(self.lo + rhs.lo, self.hi + rhs.hi)
+    }
+}
+
+impl Sub for Interval {
+    type Output = Self;
+
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl Neg for Interval {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Self {
+            lo: -self.hi,
+            hi: -self.lo,
+        }
+    }
+}
+
+impl Mul for Interval {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        let products = [
+            mul_endpoints(self.lo, rhs.lo),
+            mul_endpoints(self.lo, rhs.hi),
+            mul_endpoints(self.hi, rhs.lo),
+            mul_endpoints(self.hi, rhs.hi),
+        ];
+        Self::outward(
+            products.iter().copied().fold(f64::INFINITY, f64::min),
+            products.iter().copied().fold(f64::NEG_INFINITY, f64::max),
+        )
+    }
+}
+
+impl Div for Interval {
+    type Output = Self;
+
+    fn div(self, rhs: Self) -> Self {
+        self * rhs.recip()
+    }
+}
+
+impl Display for Interval {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[{}, {}]", self.lo, self.hi)
+    }
+}
+
+impl Domain for Interval {
+    fn constant(val: f64) -> Self {
+        Self::point(val)
+    }
+
+    fn eval(op: Op, x: Self, y: Self) -> Option<(Self, Self, Self)> {
+        let c = Self::point;
+        let one = c(1.);
+        let zero = c(0.);
+        let unary = |val: Self, deriv: Self| Some((val, deriv, zero));
+        match op {
+            Op::Add => Some((x + y, one, one)),
+            Op::AddConst(k) => unary(x + c(k), one),
+            Op::ConstSub(k) => unary(c(k) - x, c(-1.)),
+            Op::Mul => Some((x * y, y, x)),
+            Op::MulConst(k) => unary(x * c(k), c(k)),
+            // matches the weight recorded by `Op::eval`
+            Op::ConstDiv(k) => unary(c(k) / x, -x.recip()),
+            Op::Recip => unary(x.recip(), -x.sqr().recip()),
+            Op::Sin => unary(x.sin(), x.cos()),
+            Op::Cos => unary(x.cos(), -x.sin()),
+            Op::Ln => unary(x.ln(), x.domain(0., Self::recip)),
+            Op::Atan => unary(x.atan(), (one + x.sqr()).recip()),
+            Op::Tanh => {
+                let t = x.tanh();
+                unary(t, one - t.sqr())
+            }
+            Op::Exp => unary(x.exp(), x.exp()),
+            Op::ExpM1 => unary(x.increasing(f64::exp_m1), x.exp()),
+            Op::Sqrt => {
+                let s = x.sqrt();
+                unary(s, (c(2.) * s).recip())
+            }
+            Op::Abs => {
+                let sign = if x.lo > 0. {
+                    one
+                } else if x.hi < 0. {
+                    c(-1.)
+                } else {
+                    Self::new(-1., 1.)
+                };
+                unary(x.abs(), sign)
+            }
+            Op::Powi(n) => unary(x.powi(n), c(n as f64) * x.powi(n - 1)),
+            // real powers of a base that may be zero or negative are left unbounded
+            Op::PowfConst(_) | Op::Powf if x.lo <= 0. => {
+                Some((Self::ENTIRE, Self::ENTIRE, Self::ENTIRE))
+            }
+            Op::PowfConst(k) => {
+                let pow = |p: f64| (c(p) * x.ln()).exp();
+                unary(pow(k), c(k) * pow(k - 1.))
+            }
+            Op::Powf => {
+                let ln = x.ln();
+                let val = (y * ln).exp();
+                Some((val, y * ((y - one) * ln).exp(), val * ln))
+            }
+            Op::Floor => unary(x.increasing(f64::floor), zero),
+            Op::Ceil => unary(x.increasing(f64::ceil), zero),
+            Op::Round => unary(x.increasing(f64::round), zero),
+            Op::Trunc => unary(x.increasing(f64::trunc), zero),
+            Op::Signum => unary(x.increasing(f64::signum), zero),
+            _ => None,
+        }
+    }
+
+    fn eval_nary(op: Op, xs: &[Self]) -> Option<(Self, Vec<Self>)> {
+        let sum = |xs: &mut dyn Iterator<Item = Self>| xs.fold(Self::point(0.), |a, b| a + b);
+        match op {
+            Op::Sum | Op::CompensatedSum => Some((
+                sum(&mut xs.iter().copied()),
+                vec![Self::point(1.); xs.len()],
+            )),
+            Op::Mean => {
+                let n = Self::point(xs.len() as f64);
+                let weight = n.recip();
+                Some((sum(&mut xs.iter().copied()) / n, vec![weight; xs.len()]))
+            }
+            Op::Dot => Self::eval_nary(Op::Einsum(2), xs),
+            Op::Einsum(factors) => {
+                let product = |term: &[Self], skip: usize| {
+                    term.iter()
+                        .enumerate()
+                        .filter(|&(l, _)| l != skip)
+                        .fold(Self::point(1.), |acc, (_, &x)| acc * x)
+                };
+                let val = sum(&mut xs.chunks(factors).map(|term| product(term, usize::MAX)));
+                let weights = xs
+                    .chunks(factors)
+                    .flat_map(|term| (0..term.len()).map(move |k| product(term, k)))
+                    .collect();
+                Some((val, weights))
+            }
+            Op::SelectGt(threshold) => {
+                let (x, a, b) = (xs[0], xs[1], xs[2]);
+                // unless the comparison has the same outcome over all of `x`, either branch is
+                // possible
+                let (val, a_weight, b_weight) = if x.lo > threshold {
+                    (a, Self::point(1.), Self::point(0.))
+                } else if x.hi <= threshold {
+                    (b, Self::point(0.), Self::point(1.))
+                } else {
+                    let hull = Self::new(a.lo.min(b.lo), a.hi.max(b.hi));
+                    (hull, Self::new(0., 1.), Self::new(0., 1.))
+                };
+                Some((val, vec![Self::point(0.), a_weight, b_weight]))
+            }
+            Op::Affine => {
+                let (val, mut weights) = Self::eval_nary(Op::Dot, &xs[1..])?;
+                weights.insert(0, Self::point(1.));
+                Some((xs[0] + val, weights))
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Program {
+    /// Bound the value of every node over the box of `inputs`.
+    ///
+    /// Returns an error for the first node whose operation has no interval extension, which
+    /// currently covers arithmetic, powers, `sqrt`, `exp`, `ln`, `sin`, `cos`, `atan`, `tanh`,
+    /// `abs`, rounding, and the sums and products of `sum`, `mean`, `dot` and `einsum`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of inputs is wrong.
+    pub fn eval_interval(&self, inputs: &[Interval]) -> Result<Vec<Interval>, ReplayError> {
+        self.forward(inputs, false).map(|(vals, _, _)| vals)
+    }
+
+    /// Bound the gradient of `output` with respect to every node over the box of `inputs`. See
+    /// `eval_interval` for the supported operations.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of inputs is wrong or `output` was recorded after compiling.
+    pub fn grad_interval(
+        &self,
+        inputs: &[Interval],
+        output: &Var,
+    ) -> Result<Vec<Interval>, ReplayError> {
+        self.gradient(inputs, output)
+    }
+}
+
+/// Bound the gradient with respect to variable `v`.
+impl<'a> Gradient<&Var<'a>, Interval> for Vec<Interval> {
+    fn wrt(&self, v: &Var) -> Interval {
+        self[v.location]
+    }
+}
+
+/// Bound the gradient with respect to all variables in `v`, in the same order.
+impl<'a> Gradient<&[Var<'a>], Vec<Interval>> for Vec<Interval> {
+    fn wrt(&self, v: &[Var<'a>]) -> Vec<Interval> {
+        v.iter().map(|x| self.wrt(x)).collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dot, Tape};
+
+    #[test]
+    fn test_interval_ops() {
+        let a = Interval::new(-1., 2.);
+        let b = Interval::new(3., 4.);
+        let prod = a * b;
+        assert!(prod.contains(-4.) && prod.contains(8.) && !prod.contains(8.1));
+        assert!((a / b).contains(-1. / 3.));
+        assert_eq!(a.recip(), Interval::ENTIRE);
+        assert_eq!(a.sqr().lo, -f64::from_bits(1));
+
+        let s = Interval::new(0., 2.).sin();
+        assert_eq!(s.hi, 1.);
+        assert!(s.lo <= 0. && s.lo > -1e-15);
+        let c = Interval::new(3., 3.5).cos();
+        assert_eq!(c.lo, -1.);
+        assert!(c.hi >= 3_f64.cos().max(3.5_f64.cos()));
+        assert_eq!(Interval::new(-0.5, 0.5).ln(), Interval::ENTIRE);
+        assert_eq!(Interval::new(-2., 3.).powi(3).lo, -next_up(8.));
+    }
+
+    #[test]
+    fn test_grad_interval() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[1., 2.]);
+        let y = (xs[0] * xs[1]).exp() + dot(&xs, &xs).sqrt() - xs[0].powi(3) / 4.;
+        let program = g.compile().unwrap();
+
+        let inputs = [Interval::new(0.5, 1.5), Interval::new(-1., 0.25)];
+        let vals = program.eval_interval(&inputs).unwrap().wrt(&y);
+        let grads = program.grad_interval(&inputs, &y).unwrap().wrt(&xs[..]);
+        for i in 0..=10 {
+            for j in 0..=10 {
+                let a = 0.5 + i as f64 / 10.;
+                let b = -1. + 1.25 * j as f64 / 10.;
+                let point = [a, b];
+                assert!(vals.contains(program.eval(&point).wrt(&y)));
+                let exact = program.grad(&point, &y).wrt(&xs);
+                for (bound, grad) in grads.iter().zip(exact) {
+                    assert!(bound.contains(grad), "{} does not contain {}", bound, grad);
+                }
+            }
+        }
+
+        let z = xs[0].erf();
+        let program = g.compile().unwrap();
+        assert!(program.grad_interval(&inputs, &z).is_err());
+    }
+}
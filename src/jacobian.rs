@@ -0,0 +1,352 @@
+//! Jacobians of functions with several outputs.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2.]);
+//! let y = [x[0] * x[1], x[0].sin(), x[1] * 3.];
+//! let jac = jacobian(&y, &x);
+//! assert_eq!(jac.shape(), (3, 2));
+//! assert_eq!(jac.row(0), vec![2., 1.]);
+//! assert_eq!(jac.col(1), vec![1., 0., 3.]);
+//! assert_eq!(jac.get(1, 0), 1_f64.cos());
+//! ```
+
+use crate::{linalg::Lu, sparse_jacobian, Grad, Gradient, SparseJacobian, Tape, Var};
+
+/// Matrix of the partial derivatives of several outputs (rows) with respect to several inputs
+/// (columns), stored either densely or in compressed sparse row form.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jacobian {
+    rows: usize,
+    cols: usize,
+    storage: Storage,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Storage {
+    /// Entries in row-major order.
+    Dense(Vec<f64>),
+    Sparse(SparseJacobian),
+}
+
+impl Jacobian {
+    /// Calculate the Jacobian of `outputs` with respect to `inputs`, exploiting sparsity and
+    /// storing it sparsely as `sparse_jacobian` does.
+    pub fn sparse<'a>(outputs: &[Var<'a>], inputs: &[Var<'a>]) -> Self {
+        sparse_jacobian(outputs, inputs).into()
+    }
+
+    /// Calculate the Jacobian of `outputs` with respect to `inputs` like `jacobian`, but with one
+    /// backward pass for every `K` outputs, each carrying `K` adjoints per node.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let g = Tape::new();
+    /// let x = g.add_vars(&[1., 2.]);
+    /// let y = [x[0] * x[1], x[0].sin(), x[1].exp()];
+    /// assert_eq!(Jacobian::lanes::<4>(&y, &x), jacobian(&y, &x));
+    /// ```
+    pub fn lanes<'a, const K: usize>(outputs: &[Var<'a>], inputs: &[Var<'a>]) -> Self {
+        assert!(K > 0, "need at least one lane");
+        let mut entries = Vec::with_capacity(outputs.len() * inputs.len());
+        if let Some(first) = outputs.first() {
+            for chunk in outputs.chunks(K) {
+                let derivs = seeded_lanes::<K>("Jacobian::lanes", first.tape, chunk);
+                let derivs = &derivs;
+                entries.extend(
+                    (0..chunk.len())
+                        .flat_map(|k| inputs.iter().map(move |input| derivs[input.location][k])),
+                );
+            }
+        }
+        Self::from_row_major(outputs.len(), inputs.len(), entries)
+    }
+
+    /// Create a dense Jacobian from its entries in row-major order.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of entries is not `rows * cols`.
+    pub fn from_row_major(rows: usize, cols: usize, entries: Vec<f64>) -> Self {
+        assert_eq!(entries.len(), rows * cols, "wrong number of entries");
+        Self {
+            rows,
+            cols,
+            storage: Storage::Dense(entries),
+        }
+    }
+
+    /// Gets the number of rows (outputs) and columns (inputs).
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Gets the number of rows (outputs).
+    pub fn nrows(&self) -> usize {
+        self.rows
+    }
+
+    /// Gets the number of columns (inputs).
+    pub fn ncols(&self) -> usize {
+        self.cols
+    }
+
+    /// Checks whether the entries are stored sparsely.
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.storage, Storage::Sparse(_))
+    }
+
+    /// Gets the sparse representation, if the entries are stored sparsely.
+    pub fn as_sparse(&self) -> Option<&SparseJacobian> {
+        match &self.storage {
+            Storage::Sparse(sparse) => Some(sparse),
+            Storage::Dense(_) => None,
+        }
+    }
+
+    /// Gets the entry in row `i` and column `j`, the derivative of output `i` with respect to
+    /// input `j`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the index is out of bounds.
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        assert!(i < self.rows && j < self.cols, "index out of bounds");
+        match &self.storage {
+            Storage::Dense(entries) => entries[i * self.cols + j],
+            Storage::Sparse(sparse) => sparse.get(i, j),
+        }
+    }
+
+    /// Gets row `i`, the gradient of output `i` with respect to every input.
+    pub fn row(&self, i: usize) -> Vec<f64> {
+        assert!(i < self.rows, "index out of bounds");
+        match &self.storage {
+            Storage::Dense(entries) => entries[i * self.cols..(i + 1) * self.cols].to_vec(),
+            Storage::Sparse(sparse) => {
+                let mut row = vec![0.; self.cols];
+                let (cols, vals) = sparse.row(i);
+                for (&j, &val) in cols.iter().zip(vals) {
+                    row[j] = val;
+                }
+                row
+            }
+        }
+    }
+
+    /// Gets column `j`, the derivatives of every output with respect to input `j`.
+    pub fn col(&self, j: usize) -> Vec<f64> {
+        (0..self.rows).map(|i| self.get(i, j)).collect()
+    }
+
+    /// Gets the rows as vectors.
+    pub fn to_vec(&self) -> Vec<Vec<f64>> {
+        (0..self.rows).map(|i| self.row(i)).collect()
+    }
+
+    /// Convert to dense storage.
+    pub fn to_dense(&self) -> Self {
+        let entries = (0..self.rows).flat_map(|i| self.row(i)).collect();
+        Self::from_row_major(self.rows, self.cols, entries)
+    }
+}
+
+impl From<SparseJacobian> for Jacobian {
+    fn from(sparse: SparseJacobian) -> Self {
+        let (rows, cols) = sparse.shape();
+        Self {
+            rows,
+            cols,
+            storage: Storage::Sparse(sparse),
+        }
+    }
+}
+
+/// Calculate the Jacobian of `outputs` with respect to `inputs`, with one backward pass for each
+/// output. Row `i` is the gradient of `outputs[i]`.
+pub fn jacobian<'a>(outputs: &[Var<'a>], inputs: &[Var<'a>]) -> Jacobian {
+    let entries = outputs
+        .iter()
+        .flat_map(|output| output.grad().wrt(inputs))
+        .collect();
+    Jacobian::from_row_major(outputs.len(), inputs.len(), entries)
+}
+
+/// Number of residuals whose gradients `gauss_newton` computes in each backward pass.
+const GAUSS_NEWTON_LANES: usize = 8;
+
+/// Gauss–Newton approximation of the least squares problem of minimizing `½‖r‖²` over some
+/// parameters, from the Jacobian `J` of the residuals `r`, as computed by `gauss_newton`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GaussNewton {
+    /// `JᵀJ`, the approximate Hessian, in row-major order.
+    pub jtj: Vec<f64>,
+    /// `Jᵀr`, the gradient of `½‖r‖²`.
+    pub jtr: Vec<f64>,
+    /// `½‖r‖²`.
+    pub cost: f64,
+}
+
+impl GaussNewton {
+    /// Solve `(JᵀJ + damping·I) δ = -Jᵀr` for the step `δ` to add to the parameters, which is the
+    /// Gauss–Newton step for a zero `damping` and the Levenberg–Marquardt step otherwise. Returns
+    /// `None` if the system is singular.
+    pub fn step(&self, damping: f64) -> Option<Vec<f64>> {
+        let n = self.jtr.len();
+        let mut a = self.jtj.clone();
+        for i in 0..n {
+            a[i * n + i] += damping;
+        }
+        let minus_jtr = self.jtr.iter().map(|g| -g).collect::<Vec<_>>();
+        Lu::new(&a, n).map(|lu| lu.solve(&minus_jtr))
+    }
+}
+
+/// Assemble `JᵀJ` and `Jᵀr` for the `residuals` with respect to `params`, without forming `J`.
+/// The gradients of the residuals are computed several at a time, with one backward pass for
+/// every few residuals, and accumulated straight into the products.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// // fit y = a exp(b x) to points on 2 exp(x / 2)
+/// let data = [0., 1., 2., 3.].map(|x: f64| (x, 2. * (x / 2.).exp()));
+/// let mut params = vec![1., 0.3];
+/// for _ in 0..20 {
+///     let g = Tape::new();
+///     let p = g.add_vars(&params);
+///     let r = data
+///         .iter()
+///         .map(|&(x, y)| p[0] * (p[1] * x).exp() - y)
+///         .collect::<Vec<_>>();
+///     let step = gauss_newton(&r, &p).step(0.).unwrap();
+///     params = params.iter().zip(step).map(|(p, s)| p + s).collect();
+/// }
+/// assert!((params[0] - 2.).abs() < 1e-10 && (params[1] - 0.5).abs() < 1e-10);
+/// ```
+pub fn gauss_newton<'a>(residuals: &[Var<'a>], params: &[Var<'a>]) -> GaussNewton {
+    let n = params.len();
+    let mut jtj = vec![0.; n * n];
+    let mut jtr = vec![0.; n];
+    let mut cost = 0.;
+    if let Some(first) = residuals.first() {
+        let mut row = vec![0.; n];
+        for chunk in residuals.chunks(GAUSS_NEWTON_LANES) {
+            let derivs = seeded_lanes::<GAUSS_NEWTON_LANES>("GaussNewton::step", first.tape, chunk);
+            for (k, residual) in chunk.iter().enumerate() {
+                for (entry, param) in row.iter_mut().zip(params) {
+                    *entry = derivs[param.location][k];
+                }
+                cost += 0.5 * residual.val * residual.val;
+                for i in 0..n {
+                    jtr[i] += row[i] * residual.val;
+                    // the upper triangle, mirrored below at the end
+                    for j in i..n {
+                        jtj[i * n + j] += row[i] * row[j];
+                    }
+                }
+            }
+        }
+    }
+    for i in 0..n {
+        for j in 0..i {
+            jtj[i * n + j] = jtj[j * n + i];
+        }
+    }
+    GaussNewton { jtj, jtr, cost }
+}
+
+/// Calculate the gradients of each of `outputs` in a single backward pass, which carries one
+/// adjoint for each output per node.
+///
+/// # Panics
+///
+/// Panics if `outputs` is empty or its variables are on different tapes.
+pub fn grads<'a, const K: usize>(outputs: &[Var<'a>; K]) -> [Grad<'a>; K] {
+    assert!(K > 0, "need at least one output");
+    let tape = outputs[0].tape;
+    let derivs = seeded_lanes::<K>("grads", tape, outputs);
+    std::array::from_fn(|k| Grad::new(tape, derivs.iter().map(|lanes| lanes[k]).collect()))
+}
+
+/// Run a backward pass over `tape` seeding lane `k` with `outputs[k]`, for up to `K` outputs.
+fn seeded_lanes<const K: usize>(name: &'static str, tape: &Tape, outputs: &[Var]) -> Vec<[f64; K]> {
+    let mut derivs = vec![[0.; K]; tape.len()];
+    for (k, output) in outputs.iter().enumerate() {
+        crate::error::assert_same_tape(name, tape, output.tape);
+        derivs[output.location][k] = 1.;
+    }
+    tape.backward_lanes(&mut derivs);
+    derivs
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_jacobian() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.5, 1., 2., -1.]);
+        let y = (0..3).map(|i| x[i] * x[i + 1].exp()).collect::<Vec<_>>();
+        let dense = jacobian(&y, &x);
+        let sparse = Jacobian::sparse(&y, &x);
+        assert!(!dense.is_sparse() && sparse.is_sparse());
+        assert_eq!(sparse.as_sparse().unwrap().nnz(), 6);
+        assert_eq!(dense.shape(), sparse.shape());
+        assert_eq!(dense.to_vec(), sparse.to_vec());
+        assert_eq!(sparse.to_dense(), dense);
+        assert_eq!(dense.col(0), vec![1_f64.exp(), 0., 0.]);
+        assert_eq!(sparse.col(3), vec![0., 0., 2. * (-1_f64).exp()]);
+        assert_eq!((dense.nrows(), dense.ncols()), (3, 4));
+        assert_eq!(Jacobian::lanes::<2>(&y, &x), dense);
+    }
+
+    #[test]
+    fn test_gauss_newton() {
+        let g = Tape::new();
+        let p = g.add_vars(&[0.5, -1., 2.]);
+        let r = (0..11)
+            .map(|i| {
+                let x = i as f64 / 4.;
+                p[0] * (p[1] * x).sin() + p[2] * x * x - x.cos()
+            })
+            .collect::<Vec<_>>();
+        let gn = gauss_newton(&r, &p);
+        let j = jacobian(&r, &p);
+        for a in 0..3 {
+            let jtr = (0..r.len()).map(|i| j.get(i, a) * r[i].val).sum::<f64>();
+            assert_approx_eq!(gn.jtr[a], jtr);
+            for b in 0..3 {
+                let jtj = (0..r.len()).map(|i| j.get(i, a) * j.get(i, b)).sum::<f64>();
+                assert_approx_eq!(gn.jtj[a * 3 + b], jtj);
+            }
+        }
+        assert_approx_eq!(gn.cost, r.iter().map(|r| r.val.powi(2)).sum::<f64>() / 2.);
+
+        // the step solves the normal equations
+        let step = gn.step(0.1).unwrap();
+        for a in 0..3 {
+            let lhs = (0..3).map(|b| gn.jtj[a * 3 + b] * step[b]).sum::<f64>() + 0.1 * step[a];
+            assert_approx_eq!(lhs, -gn.jtr[a]);
+        }
+    }
+
+    #[test]
+    fn test_grads() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.5, 1.5]);
+        let m = crate::Mat::new(2, 2, vec![x[0], x[1], x[1].sin(), x[0] * x[0]]);
+        let z = crate::solve(&m, &[x[1], x[0].exp()]);
+        let y = [z[0] + z[1], crate::sum(&x).ln(), x[0] * z[1]];
+        y[1].register_hook(|adjoint| adjoint * 2.);
+        let [g0, g1, g2] = grads(&y);
+        assert_eq!(g0.wrt(&x), y[0].grad().wrt(&x));
+        assert_eq!(g1.wrt(&x), y[1].grad().wrt(&x));
+        assert_eq!(g2.wrt(&x), y[2].grad().wrt(&x));
+    }
+}
@@ -0,0 +1,394 @@
+//! Taylor-mode forward differentiation with truncated power series.
+//!
+//! A `Jet` holds the first few Taylor coefficients of a function of one variable around a point,
+//! `f(t0 + h) = c[0] + c[1] h + c[2] h^2 + ...`, and arithmetic on jets propagates all of them
+//! at once. The `k`th derivative is `k! c[k]`, so derivatives of any order along a direction cost
+//! a single pass, rather than nesting first-order passes.
+//!
+//! ```rust
+//! use reverse::jet::Jet;
+//!
+//! // derivatives of x exp(x) at x = 0 up to the fourth: k for the kth
+//! let x = Jet::var(0., 4);
+//! let y = &x * x.exp();
+//! for k in 0..=4 {
+//!     assert!((y.derivative(k) - k as f64).abs() < 1e-12);
+//! }
+//! ```
+//!
+//! The recurrences also give the Taylor coefficients of ODE solutions: for `y' = f(y)`, the
+//! coefficient `k + 1` of `y` is coefficient `k` of `f(y)` divided by `k + 1`, so each order only
+//! needs the jet of `y` truncated to the orders already known. `taylor_series` computes these
+//! coefficients and `taylor_step` sums them to advance the solution by one step.
+//!
+//! ```rust
+//! use reverse::jet::taylor_step;
+//!
+//! // y' = -y, y(0) = 1 has the solution exp(-t)
+//! let y = taylor_step(|y| -y, 1., 0.1, 8);
+//! assert!((y - (-0.1_f64).exp()).abs() < 1e-12);
+//! ```
+
+use std::ops::{Add, Div, Mul, Neg, Sub};
+
+/// Truncated Taylor series with coefficients up to a fixed degree.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Jet {
+    coeffs: Vec<f64>,
+}
+
+impl Jet {
+    /// Create a jet from its Taylor coefficients, starting with the value. The degree is one less
+    /// than the number of coefficients.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `coeffs` is empty.
+    pub fn new(coeffs: Vec<f64>) -> Self {
+        assert!(!coeffs.is_empty(), "a jet needs at least one coefficient");
+        Self { coeffs }
+    }
+
+    /// Jet of the constant `val`.
+    pub fn constant(val: f64, degree: usize) -> Self {
+        let mut coeffs = vec![0.; degree + 1];
+        coeffs[0] = val;
+        Self::new(coeffs)
+    }
+
+    /// Jet of the independent variable at `val`, so that derivatives are taken with respect to it.
+    pub fn var(val: f64, degree: usize) -> Self {
+        let mut jet = Self::constant(val, degree);
+        if degree > 0 {
+            jet.coeffs[1] = 1.;
+        }
+        jet
+    }
+
+    /// Gets the highest order that is tracked.
+    pub fn degree(&self) -> usize {
+        self.coeffs.len() - 1
+    }
+
+    /// Gets the Taylor coefficients, starting with the value.
+    pub fn coeffs(&self) -> &[f64] {
+        &self.coeffs
+    }
+
+    /// Get the value of the jet.
+    pub fn val(&self) -> f64 {
+        self.coeffs[0]
+    }
+
+    /// Get the `k`th derivative, `k!` times the `k`th coefficient.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `k` is larger than the degree.
+    pub fn derivative(&self, k: usize) -> f64 {
+        self.coeffs[k] * (1..=k).map(|i| i as f64).product::<f64>()
+    }
+
+    /// Apply `f` to the value, keeping the other coefficients.
+    fn map_val(&self, f: impl Fn(f64) -> f64) -> Self {
+        let mut coeffs = self.coeffs.clone();
+        coeffs[0] = f(coeffs[0]);
+        Self::new(coeffs)
+    }
+
+    /// Solve `y' = a' / b` for `y` with `y(0) = y0`, which gives the functions whose derivative is
+    /// a quotient, such as `ln` and `atan`.
+    fn integrate_quotient(a: &[f64], b: &[f64], y0: f64) -> Self {
+        let mut y = vec![0.; a.len()];
+        y[0] = y0;
+        for k in 1..a.len() {
+            let sum = (1..k).map(|j| j as f64 * y[j] * b[k - j]).sum::<f64>();
+            y[k] = (k as f64 * a[k] - sum) / (k as f64 * b[0]);
+        }
+        Self::new(y)
+    }
+
+    pub fn recip(&self) -> Self {
+        Jet::constant(1., self.degree()) / self
+    }
+
+    pub fn exp(&self) -> Self {
+        let a = &self.coeffs;
+        let mut e = vec![0.; a.len()];
+        e[0] = a[0].exp();
+        for k in 1..a.len() {
+            e[k] = (1..=k).map(|j| j as f64 * a[j] * e[k - j]).sum::<f64>() / k as f64;
+        }
+        Self::new(e)
+    }
+
+    pub fn ln(&self) -> Self {
+        Self::integrate_quotient(&self.coeffs, &self.coeffs, self.val().ln())
+    }
+
+    /// Calculate the sine and cosine together, as the recurrence for each needs the other.
+    pub fn sin_cos(&self) -> (Self, Self) {
+        let a = &self.coeffs;
+        let mut s = vec![0.; a.len()];
+        let mut c = vec![0.; a.len()];
+        s[0] = a[0].sin();
+        c[0] = a[0].cos();
+        for k in 1..a.len() {
+            let k_f = k as f64;
+            s[k] = (1..=k).map(|j| j as f64 * a[j] * c[k - j]).sum::<f64>() / k_f;
+            c[k] = -(1..=k).map(|j| j as f64 * a[j] * s[k - j]).sum::<f64>() / k_f;
+        }
+        (Self::new(s), Self::new(c))
+    }
+
+    pub fn sin(&self) -> Self {
+        self.sin_cos().0
+    }
+
+    pub fn cos(&self) -> Self {
+        self.sin_cos().1
+    }
+
+    pub fn tan(&self) -> Self {
+        let (s, c) = self.sin_cos();
+        s / c
+    }
+
+    pub fn sinh(&self) -> Self {
+        (self.exp() - (-self).exp()) / 2.
+    }
+
+    pub fn cosh(&self) -> Self {
+        (self.exp() + (-self).exp()) / 2.
+    }
+
+    pub fn tanh(&self) -> Self {
+        let e = (self * 2.).exp();
+        (&e - 1.) / (e + 1.)
+    }
+
+    pub fn atan(&self) -> Self {
+        let b = self * self + 1.;
+        Self::integrate_quotient(&self.coeffs, &b.coeffs, self.val().atan())
+    }
+
+    pub fn sqrt(&self) -> Self {
+        let a = &self.coeffs;
+        let mut r = vec![0.; a.len()];
+        r[0] = a[0].sqrt();
+        for k in 1..a.len() {
+            let sum = (1..k).map(|j| r[j] * r[k - j]).sum::<f64>();
+            r[k] = (a[k] - sum) / (2. * r[0]);
+        }
+        Self::new(r)
+    }
+
+    /// Raise to a real power. The value must be nonzero unless `p` is a nonnegative integer small
+    /// enough for `powi`.
+    pub fn powf(&self, p: f64) -> Self {
+        if p.fract() == 0. && (0. ..=i32::MAX as f64).contains(&p) {
+            return self.powi(p as i32);
+        }
+        let a = &self.coeffs;
+        let mut y = vec![0.; a.len()];
+        y[0] = a[0].powf(p);
+        for k in 1..a.len() {
+            let sum = (1..=k)
+                .map(|j| (p * j as f64 - (k - j) as f64) * a[j] * y[k - j])
+                .sum::<f64>();
+            y[k] = sum / (k as f64 * a[0]);
+        }
+        Self::new(y)
+    }
+
+    /// Raise to an integer power by repeated squaring, which also works when the value is zero.
+    pub fn powi(&self, n: i32) -> Self {
+        if n < 0 {
+            return self.powi(-n).recip();
+        }
+        let mut result = Jet::constant(1., self.degree());
+        let mut base = self.clone();
+        let mut n = n;
+        while n > 0 {
+            if n % 2 == 1 {
+                result = &result * &base;
+            }
+            base = &base * &base;
+            n /= 2;
+        }
+        result
+    }
+}
+
+/// Taylor coefficients up to `degree` of the solution of the autonomous ODE `y' = f(y)` with
+/// `y(0) = y0`. Each order is found from the coefficients before it, so `f` is evaluated `degree`
+/// times on jets of the given degree.
+pub fn taylor_series(f: impl Fn(&Jet) -> Jet, y0: f64, degree: usize) -> Jet {
+    let mut y = Jet::constant(y0, degree);
+    for k in 0..degree {
+        let coeff = f(&y).coeffs[k] / (k + 1) as f64;
+        y.coeffs[k + 1] = coeff;
+    }
+    y
+}
+
+/// Advance the solution of `y' = f(y)` from `y0` by the step `h`, using its Taylor polynomial of
+/// the given degree. The local error is of order `h^(degree + 1)`.
+pub fn taylor_step(f: impl Fn(&Jet) -> Jet, y0: f64, h: f64, degree: usize) -> f64 {
+    taylor_series(f, y0, degree)
+        .coeffs
+        .iter()
+        .rev()
+        .fold(0., |acc, c| acc * h + c)
+}
+
+/// Checks that `a` and `b` have the same degree.
+fn same_degree(a: &[f64], b: &[f64]) {
+    assert_eq!(
+        a.len(),
+        b.len(),
+        "jets of different degrees cannot be combined"
+    );
+}
+
+/// Truncated Cauchy product of two series.
+fn mul_coeffs(a: &[f64], b: &[f64]) -> Vec<f64> {
+    same_degree(a, b);
+    (0..a.len())
+        .map(|k| (0..=k).map(|j| a[j] * b[k - j]).sum())
+        .collect()
+}
+
+/// Truncated quotient of two series, by solving `b c = a` for `c` term by term.
+fn div_coeffs(a: &[f64], b: &[f64]) -> Vec<f64> {
+    same_degree(a, b);
+    let mut c = vec![0.; a.len()];
+    for k in 0..a.len() {
+        let sum = (1..=k).map(|j| b[j] * c[k - j]).sum::<f64>();
+        c[k] = (a[k] - sum) / b[0];
+    }
+    c
+}
+
+#[opimps::impl_uni_ops(Neg)]
+fn neg(self: Jet) -> Jet {
+    Jet::new(self.coeffs.iter().map(|c| -c).collect())
+}
+
+#[opimps::impl_ops(Add)]
+fn add(self: Jet, rhs: Jet) -> Jet {
+    same_degree(&self.coeffs, &rhs.coeffs);
+    Jet::new(
+        self.coeffs
+            .iter()
+            .zip(&rhs.coeffs)
+            .map(|(a, b)| a + b)
+            .collect(),
+    )
+}
+
+#[opimps::impl_ops_rprim(Add)]
+fn add(self: Jet, rhs: f64) -> Jet {
+    self.map_val(|val| val + rhs)
+}
+
+#[opimps::impl_ops_lprim(Add)]
+fn add(self: f64, rhs: Jet) -> Jet {
+    rhs + self
+}
+
+#[opimps::impl_ops(Sub)]
+fn sub(self: Jet, rhs: Jet) -> Jet {
+    self + -rhs
+}
+
+#[opimps::impl_ops_rprim(Sub)]
+fn sub(self: Jet, rhs: f64) -> Jet {
+    self.map_val(|val| val - rhs)
+}
+
+#[opimps::impl_ops_lprim(Sub)]
+fn sub(self: f64, rhs: Jet) -> Jet {
+    -rhs + self
+}
+
+#[opimps::impl_ops(Mul)]
+fn mul(self: Jet, rhs: Jet) -> Jet {
+    Jet::new(mul_coeffs(&self.coeffs, &rhs.coeffs))
+}
+
+#[opimps::impl_ops_rprim(Mul)]
+fn mul(self: Jet, rhs: f64) -> Jet {
+    Jet::new(self.coeffs.iter().map(|c| c * rhs).collect())
+}
+
+#[opimps::impl_ops_lprim(Mul)]
+fn mul(self: f64, rhs: Jet) -> Jet {
+    rhs * self
+}
+
+#[opimps::impl_ops(Div)]
+fn div(self: Jet, rhs: Jet) -> Jet {
+    Jet::new(div_coeffs(&self.coeffs, &rhs.coeffs))
+}
+
+#[opimps::impl_ops_rprim(Div)]
+fn div(self: Jet, rhs: f64) -> Jet {
+    Jet::new(self.coeffs.iter().map(|c| c / rhs).collect())
+}
+
+#[opimps::impl_ops_lprim(Div)]
+fn div(self: f64, rhs: Jet) -> Jet {
+    rhs.recip() * self
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_jet() {
+        let x = Jet::var(0.7, 5);
+        let (s, c) = x.sin_cos();
+        let one = &s * &s + &c * &c;
+        assert_approx_eq!(one.val(), 1.);
+        for k in 1..=5 {
+            assert!(one.coeffs()[k].abs() < 1e-14);
+        }
+        // the derivatives of sin cycle through cos, -sin, -cos, sin
+        let expected = [0.7_f64.cos(), -0.7_f64.sin(), -0.7_f64.cos(), 0.7_f64.sin()];
+        for (k, e) in expected.iter().enumerate() {
+            assert_approx_eq!(s.derivative(k + 1), *e);
+        }
+
+        let y = x.ln().exp() / &x;
+        assert_approx_eq!(y.val(), 1.);
+        assert!(y.coeffs()[1..].iter().all(|c| c.abs() < 1e-13));
+        let y = x.sqrt() * x.sqrt() - &x;
+        assert!(y.coeffs().iter().all(|c| c.abs() < 1e-13));
+        // d^3/dx^3 x^2.5 = 2.5 * 1.5 * 0.5 x^-0.5
+        assert_approx_eq!(x.powf(2.5).derivative(3), 1.875 / 0.7_f64.sqrt());
+        // d/dx atan(x) = 1 / (1 + x^2), d^2/dx^2 = -2x / (1 + x^2)^2
+        let t = x.atan();
+        assert_approx_eq!(t.derivative(1), 1. / 1.49);
+        assert_approx_eq!(t.derivative(2), -1.4 / 1.49_f64.powi(2));
+        assert_approx_eq!(x.tanh().derivative(1), 1. / 0.7_f64.cosh().powi(2));
+        assert_eq!(Jet::var(0., 3).powi(3).derivative(3), 6.);
+    }
+
+    #[test]
+    fn test_ode() {
+        // Taylor coefficients of y' = y^2, y(0) = 1, whose solution 1 / (1 - t) has all ones
+        let y = taylor_series(|y| y * y, 1., 6);
+        assert_eq!(y.coeffs(), &[1.; 7][..]);
+
+        // integrating y' = cos(y) over [0, 1] in steps, whose solution is 2 atan(tanh(t / 2))
+        let mut y = 0.;
+        for _ in 0..10 {
+            y = taylor_step(|y| y.cos(), y, 0.1, 8);
+        }
+        assert_approx_eq!(y, 2. * 0.5_f64.tanh().atan());
+        assert_eq!(taylor_step(|y| y * 3., 2., 0.5, 0), 2.);
+    }
+}
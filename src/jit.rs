@@ -0,0 +1,516 @@
+//! Native code for compiled programs with Cranelift, enabled with the `jit` feature.
+//!
+//! `Program::jit` translates every instruction of a program into machine code, for a function
+//! evaluating the nodes and another one evaluating them with their partial derivatives and then
+//! running the backward pass. Values live in buffers indexed by location, as in the interpreter.
+//! Additions and multiplications are emitted inline, and the other operations call the same Rust
+//! code as the interpreter, so the results are identical to those of `Program::eval` and
+//! `Program::grad`, without the cost of dispatching on every instruction.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2.]);
+//! let y = (x[0] * x[1]).ln() + x[1].powi(2);
+//! let program = tape.compile().unwrap();
+//! let native = program.jit().unwrap();
+//!
+//! for point in [[1., 2.], [0.5, 3.]] {
+//!     assert_eq!(native.eval(&point), program.eval(&point));
+//!     assert_eq!(native.grad(&point, &y), program.grad(&point, &y));
+//! }
+//! ```
+
+use crate::{
+    compile::{Instr, Program},
+    Op, Var,
+};
+use cranelift_codegen::{
+    ir::{types, AbiParam, InstBuilder, MemFlagsData, Signature, UserFuncName, Value},
+    settings::{self, Configurable},
+};
+use cranelift_frontend::{FunctionBuilder, FunctionBuilderContext};
+use cranelift_jit::{JITBuilder, JITModule};
+use cranelift_module::{default_libcall_names, Linkage, Module};
+use std::{
+    convert::TryFrom,
+    error::Error,
+    fmt::{self, Display},
+};
+
+/// Error returned by `Program::jit` when Cranelift cannot generate code for the program, such as
+/// on an unsupported host.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JitError(String);
+
+impl Display for JitError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "cannot compile the program to native code: {}", self.0)
+    }
+}
+
+impl Error for JitError {}
+
+/// Evaluates the nodes from the inputs into the values.
+type EvalFn = unsafe extern "C" fn(*const f64, *mut f64);
+
+/// Evaluates the nodes and their partial derivatives from the inputs, then propagates the
+/// adjoints, which must be seeded.
+type GradFn = unsafe extern "C" fn(*const f64, *mut f64, *mut f64, *mut f64);
+
+/// Program compiled to native code, created with `Program::jit`.
+pub struct JitProgram {
+    module: Option<JITModule>,
+    eval: EvalFn,
+    grad: GradFn,
+    /// Operations that are evaluated by calling back into Rust, which the code points into.
+    _ops: Box<[Op]>,
+    /// Locations of the operands of the n-ary operations, which the code points into.
+    _operands: Box<[usize]>,
+    len: usize,
+    inputs: usize,
+    partials: usize,
+}
+
+impl Program {
+    /// Translate the program into native code with Cranelift. This takes far longer than a single
+    /// evaluation, so it pays off for programs that are evaluated many times.
+    pub fn jit(&self) -> Result<JitProgram, JitError> {
+        let err = |e: &dyn Display| JitError(e.to_string());
+        let mut flags = settings::builder();
+        flags.set("opt_level", "speed").map_err(|e| err(&e))?;
+        let isa = cranelift_native::builder()
+            .map_err(|e| err(&e))?
+            .finish(settings::Flags::new(flags))
+            .map_err(|e| err(&e))?;
+        let mut module = JITModule::new(JITBuilder::with_isa(isa, default_libcall_names()));
+
+        let ops = self
+            .instrs
+            .iter()
+            .filter_map(|instr| match *instr {
+                Instr::Scalar(op, ..) | Instr::Nary(op, ..) => Some(op),
+                Instr::Input(_) | Instr::Const(_) => None,
+            })
+            .collect::<Box<[Op]>>();
+        let operands = self.operands.clone().into_boxed_slice();
+        let codegen = Codegen {
+            program: self,
+            ops: &ops,
+            operands: &operands,
+            partials: partial_offsets(self),
+        };
+
+        let config = module.target_config();
+        let ptr = config.pointer_type();
+        let mut ctx = module.make_context();
+        let mut func_ctx = FunctionBuilderContext::new();
+        let mut fns = vec![];
+        for &grad in &[false, true] {
+            let mut sig = module.make_signature();
+            let params = if grad { 4 } else { 2 };
+            sig.params.extend((0..params).map(|_| AbiParam::new(ptr)));
+            let name = if grad { "grad" } else { "eval" };
+            let id = module
+                .declare_function(name, Linkage::Local, &sig)
+                .map_err(|e| err(&e))?;
+            ctx.func.signature = sig;
+            ctx.func.name = UserFuncName::user(0, id.as_u32());
+            let mut b = FunctionBuilder::new(&mut ctx.func, &mut func_ctx);
+            let block = b.create_block();
+            b.append_block_params_for_function_params(block);
+            b.switch_to_block(block);
+            let args = b.block_params(block).to_vec();
+            let partials = if grad { Some(args[2]) } else { None };
+            codegen.forward(&mut b, ptr, args[0], args[1], partials);
+            if let Some(partials) = partials {
+                codegen.backward(&mut b, partials, args[3]);
+            }
+            b.ins().return_(&[]);
+            b.seal_all_blocks();
+            b.finalize(config);
+            module.define_function(id, &mut ctx).map_err(|e| err(&e))?;
+            module.clear_context(&mut ctx);
+            fns.push(id);
+        }
+        module.finalize_definitions().map_err(|e| err(&e))?;
+        let partials = codegen.partials.last().map_or(0, |&(_, end)| end);
+
+        // the code was generated with the signatures of these function types
+        let (eval, grad) = unsafe {
+            (
+                std::mem::transmute::<*const u8, EvalFn>(module.get_finalized_function(fns[0])),
+                std::mem::transmute::<*const u8, GradFn>(module.get_finalized_function(fns[1])),
+            )
+        };
+        Ok(JitProgram {
+            module: Some(module),
+            eval,
+            grad,
+            _ops: ops,
+            _operands: operands,
+            len: self.instrs.len(),
+            inputs: self.inputs,
+            partials,
+        })
+    }
+}
+
+impl JitProgram {
+    /// Gets the number of inputs the program expects.
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    /// Gets the number of nodes of the compiled tape.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Checks whether the program is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Evaluate every node at `inputs`, like `Program::eval`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of inputs is wrong.
+    pub fn eval(&self, inputs: &[f64]) -> Vec<f64> {
+        self.check_inputs(inputs);
+        let mut vals = vec![0.; self.len];
+        // the buffers have the lengths the code was generated for
+        unsafe { (self.eval)(inputs.as_ptr(), vals.as_mut_ptr()) };
+        vals
+    }
+
+    /// Calculate the gradient of `output` at `inputs` with respect to every node, like
+    /// `Program::grad`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of inputs is wrong or `output` was recorded after compiling.
+    pub fn grad(&self, inputs: &[f64], output: &Var) -> Vec<f64> {
+        self.check_inputs(inputs);
+        assert!(
+            output.location < self.len,
+            "output was recorded after compiling"
+        );
+        let mut vals = vec![0.; self.len];
+        let mut partials = vec![0.; self.partials];
+        let mut derivs = vec![0.; self.len];
+        derivs[output.location] = 1.;
+        unsafe {
+            (self.grad)(
+                inputs.as_ptr(),
+                vals.as_mut_ptr(),
+                partials.as_mut_ptr(),
+                derivs.as_mut_ptr(),
+            )
+        };
+        derivs
+    }
+
+    fn check_inputs(&self, inputs: &[f64]) {
+        assert_eq!(
+            inputs.len(),
+            self.inputs,
+            "wrong number of inputs for the program"
+        );
+    }
+}
+
+impl Drop for JitProgram {
+    fn drop(&mut self) {
+        // nothing can call the code once the program is gone
+        unsafe { self.module.take().unwrap().free_memory() };
+    }
+}
+
+impl fmt::Debug for JitProgram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JitProgram")
+            .field("len", &self.len)
+            .field("inputs", &self.inputs)
+            .finish()
+    }
+}
+
+/// Range of the partial derivatives of every instruction in the buffer of partials: two for a
+/// scalar operation, one for each operand of an n-ary one, and none for inputs and constants.
+fn partial_offsets(program: &Program) -> Vec<(usize, usize)> {
+    let mut end = 0;
+    program
+        .instrs
+        .iter()
+        .map(|instr| {
+            let start = end;
+            end += match *instr {
+                Instr::Scalar(..) => 2,
+                Instr::Nary(_, start, end) => end - start,
+                Instr::Input(_) | Instr::Const(_) => 0,
+            };
+            (start, end)
+        })
+        .collect()
+}
+
+/// Evaluate the scalar operation `op` at `x` and `y`, writing the partial derivatives to
+/// `partials` unless it is null.
+unsafe extern "C" fn eval_scalar(op: *const Op, x: f64, y: f64, partials: *mut f64) -> f64 {
+    let (val, d_x, d_y) = (*op).eval(x, y);
+    if !partials.is_null() {
+        *partials = d_x;
+        *partials.add(1) = d_y;
+    }
+    val
+}
+
+/// Evaluate the n-ary operation `op` on the values at the `len` locations `deps`, writing the
+/// weights of the operands to `partials` unless it is null.
+unsafe extern "C" fn eval_nary(
+    op: *const Op,
+    deps: *const usize,
+    len: usize,
+    vals: *const f64,
+    partials: *mut f64,
+) -> f64 {
+    let deps = std::slice::from_raw_parts(deps, len);
+    let xs = deps.iter().map(|&dep| *vals.add(dep)).collect::<Vec<_>>();
+    let (val, weights) = (*op).eval_nary(&xs);
+    if !partials.is_null() {
+        std::ptr::copy_nonoverlapping(weights.as_ptr(), partials, len);
+    }
+    val
+}
+
+/// Translation of the instructions of a program into Cranelift IR.
+struct Codegen<'p> {
+    program: &'p Program,
+    ops: &'p [Op],
+    operands: &'p [usize],
+    partials: Vec<(usize, usize)>,
+}
+
+impl<'p> Codegen<'p> {
+    /// Emit the evaluation of every node in order, storing the partial derivatives if there is a
+    /// buffer for them.
+    fn forward(
+        &self,
+        b: &mut FunctionBuilder,
+        ptr: types::Type,
+        inputs: Value,
+        vals: Value,
+        partials: Option<Value>,
+    ) {
+        let mut scalar_sig = Signature::new(b.func.signature.call_conv);
+        scalar_sig.params.extend([
+            AbiParam::new(ptr),
+            AbiParam::new(types::F64),
+            AbiParam::new(types::F64),
+            AbiParam::new(ptr),
+        ]);
+        scalar_sig.returns.push(AbiParam::new(types::F64));
+        let scalar_sig = b.import_signature(scalar_sig);
+        let mut nary_sig = Signature::new(b.func.signature.call_conv);
+        nary_sig.params.extend((0..5).map(|_| AbiParam::new(ptr)));
+        nary_sig.returns.push(AbiParam::new(types::F64));
+        let nary_sig = b.import_signature(nary_sig);
+        let address = |b: &mut FunctionBuilder, p: *const u8| b.ins().iconst(ptr, p as i64);
+
+        let mut ops = self.ops.iter();
+        for (idx, instr) in self.program.instrs.iter().enumerate() {
+            let (offset, _) = self.partials[idx];
+            let store_partials = |b: &mut FunctionBuilder, ds: &[Value]| {
+                if let Some(partials) = partials {
+                    for (i, &d) in ds.iter().enumerate() {
+                        store(b, d, partials, offset + i);
+                    }
+                }
+            };
+            let val = match *instr {
+                Instr::Input(i) => load(b, inputs, i),
+                Instr::Const(val) => b.ins().f64const(val),
+                Instr::Scalar(op, x, y) => {
+                    let op_ptr = ops.next().unwrap() as *const Op;
+                    let (x, y) = (load(b, vals, x), load(b, vals, y));
+                    let (one, zero) = (b.ins().f64const(1.), b.ins().f64const(0.));
+                    match op {
+                        Op::Add => {
+                            store_partials(b, &[one, one]);
+                            b.ins().fadd(x, y)
+                        }
+                        Op::AddConst(c) => {
+                            store_partials(b, &[one, zero]);
+                            let c = b.ins().f64const(c);
+                            b.ins().fadd(x, c)
+                        }
+                        Op::ConstSub(c) => {
+                            let minus_one = b.ins().f64const(-1.);
+                            store_partials(b, &[minus_one, zero]);
+                            let c = b.ins().f64const(c);
+                            b.ins().fsub(c, x)
+                        }
+                        Op::Mul => {
+                            store_partials(b, &[y, x]);
+                            b.ins().fmul(x, y)
+                        }
+                        Op::MulConst(c) => {
+                            let c = b.ins().f64const(c);
+                            store_partials(b, &[c, zero]);
+                            b.ins().fmul(x, c)
+                        }
+                        _ => {
+                            let callee = address(b, eval_scalar as *const u8);
+                            let op_ptr = address(b, op_ptr as *const u8);
+                            let out = match partials {
+                                Some(partials) => b.ins().iadd_imm_s(partials, 8 * offset as i64),
+                                None => b.ins().iconst(ptr, 0),
+                            };
+                            let call =
+                                b.ins()
+                                    .call_indirect(scalar_sig, callee, &[op_ptr, x, y, out]);
+                            b.inst_results(call)[0]
+                        }
+                    }
+                }
+                Instr::Nary(op, start, end) => {
+                    let op_ptr = ops.next().unwrap() as *const Op;
+                    if op == Op::Linear {
+                        let mut acc = b.ins().f64const(0.);
+                        for (i, (&dep, &c)) in self.program.operands[start..end]
+                            .iter()
+                            .zip(&self.program.coeffs[start..end])
+                            .enumerate()
+                        {
+                            let c = b.ins().f64const(c);
+                            if let Some(partials) = partials {
+                                store(b, c, partials, offset + i);
+                            }
+                            let x = load(b, vals, dep);
+                            let term = b.ins().fmul(c, x);
+                            acc = b.ins().fadd(acc, term);
+                        }
+                        acc
+                    } else {
+                        let callee = address(b, eval_nary as *const u8);
+                        let args = [
+                            address(b, op_ptr as *const u8),
+                            address(b, self.operands[start..].as_ptr() as *const u8),
+                            b.ins().iconst(ptr, (end - start) as i64),
+                            vals,
+                            match partials {
+                                Some(partials) => b.ins().iadd_imm_s(partials, 8 * offset as i64),
+                                None => b.ins().iconst(ptr, 0),
+                            },
+                        ];
+                        let call = b.ins().call_indirect(nary_sig, callee, &args);
+                        b.inst_results(call)[0]
+                    }
+                }
+            };
+            store(b, val, vals, idx);
+        }
+    }
+
+    /// Emit the backward pass, accumulating the seeded adjoints in the same order as
+    /// `Program::grad`.
+    fn backward(&self, b: &mut FunctionBuilder, partials: Value, derivs: Value) {
+        let accumulate = |b: &mut FunctionBuilder, dep: usize, weight: Value, deriv: Value| {
+            let prev = load(b, derivs, dep);
+            let term = b.ins().fmul(weight, deriv);
+            let sum = b.ins().fadd(prev, term);
+            store(b, sum, derivs, dep);
+        };
+        for (idx, instr) in self.program.instrs.iter().enumerate().rev() {
+            let (offset, _) = self.partials[idx];
+            match *instr {
+                Instr::Scalar(_, x, y) => {
+                    let deriv = load(b, derivs, idx);
+                    let d_x = load(b, partials, offset);
+                    accumulate(b, x, d_x, deriv);
+                    let d_y = load(b, partials, offset + 1);
+                    accumulate(b, y, d_y, deriv);
+                }
+                Instr::Nary(_, start, end) => {
+                    let deriv = load(b, derivs, idx);
+                    for (i, &dep) in self.program.operands[start..end].iter().enumerate() {
+                        let weight = load(b, partials, offset + i);
+                        accumulate(b, dep, weight, deriv);
+                    }
+                }
+                Instr::Input(_) | Instr::Const(_) => {}
+            }
+        }
+    }
+}
+
+/// Address of the `f64` at `index` of the buffer `base`, as a base and an offset.
+fn slot(b: &mut FunctionBuilder, base: Value, index: usize) -> (Value, i32) {
+    let offset = 8 * index as i64;
+    match i32::try_from(offset) {
+        Ok(offset) => (base, offset),
+        Err(_) => (b.ins().iadd_imm_s(base, offset), 0),
+    }
+}
+
+fn load(b: &mut FunctionBuilder, base: Value, index: usize) -> Value {
+    let (base, offset) = slot(b, base, index);
+    b.ins()
+        .load(types::F64, MemFlagsData::trusted(), base, offset)
+}
+
+fn store(b: &mut FunctionBuilder, val: Value, base: Value, index: usize) {
+    let (base, offset) = slot(b, base, index);
+    b.ins().store(MemFlagsData::trusted(), val, base, offset);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{det, logsumexp, sum, weighted_sum, Gradient, Mat, Powf, Tape};
+
+    #[test]
+    fn test_jit() {
+        fn f<'a>(x: &[Var<'a>]) -> Var<'a> {
+            let m = Mat::new(2, 2, vec![x[0], x[1], x[2], x[0] * x[1]]);
+            let y = det(&m) * x[2].tanh() + sum(x).sqrt() - 3. / x[1];
+            let z = y * 2. + logsumexp(&[x[0].sin(), x[1] + 1., 0.5 - x[2]]) + x[0].powf(x[2]);
+            weighted_sum(&[0.1, -3., 0.7], &[z, x[1], z * x[2]])
+        }
+
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2., 3.]);
+        let y = f(&x);
+        let program = g.compile().unwrap();
+        let native = program.jit().unwrap();
+        assert_eq!(native.len(), program.len());
+        assert_eq!(native.inputs(), 3);
+
+        for point in [[1., 2., 3.], [0.3, -1.5, 4.], [0., 0., 0.]] {
+            // the same operations in the same order give the same bits, including NaNs
+            let bits = |v: Vec<f64>| v.into_iter().map(f64::to_bits).collect::<Vec<_>>();
+            assert_eq!(bits(native.eval(&point)), bits(program.eval(&point)));
+            assert_eq!(
+                bits(native.grad(&point, &y)),
+                bits(program.grad(&point, &y))
+            );
+        }
+        assert_eq!(native.grad(&[1., 2., 3.], &y).wrt(&x), y.grad().wrt(&x));
+
+        let empty = Tape::new().compile().unwrap().jit().unwrap();
+        assert!(empty.is_empty());
+        assert!(empty.eval(&[]).is_empty());
+    }
+
+    #[test]
+    #[should_panic(expected = "wrong number of inputs")]
+    fn test_jit_inputs() {
+        let g = Tape::new();
+        let x = g.add_var(1.);
+        let _ = x.exp();
+        let _ = g.compile().unwrap().jit().unwrap().eval(&[1., 2.]);
+    }
+}
@@ -0,0 +1,342 @@
+//! Gradients with respect to the variables added with `Tape::add_var` only.
+//!
+//! `Var::grad` returns the adjoint of every node on the tape, most of which are intermediate
+//! values. `Var::grad_leaves` instead keeps the adjoints of the nodes that are still waiting for
+//! contributions in a map, dropping each one once its node has been processed, and only returns
+//! the adjoints of the leaves. For deep expressions this needs far less memory, at the cost of a
+//! slower backward pass. `Var::grad_segmented` stores the same adjoints in fixed-size segments of
+//! the tape instead, freeing each segment once the backward pass is past it.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[0.5, 1.5]);
+//! let mut y = x[0] * x[1];
+//! for _ in 0..1000 {
+//!     y = y.sin() + x[0];
+//! }
+//! let grads = y.grad_leaves();
+//! assert_eq!(grads.len(), 2);
+//! assert_eq!(grads.wrt(&x), y.grad().wrt(&x));
+//! ```
+
+use crate::{Gradient, Op, Var};
+use std::collections::HashMap;
+
+/// Gradient with respect to the leaves of a tape, as returned by `Var::grad_leaves`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LeafGradient {
+    output: usize,
+    /// Locations of the leaves recorded up to the output, in increasing order.
+    leaves: Vec<usize>,
+    grads: Vec<f64>,
+}
+
+impl LeafGradient {
+    /// Gets the number of leaves with a stored gradient.
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    /// Checks whether no leaves were recorded before the output.
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    /// Gets the gradients with respect to the leaves, in the order they were added to the tape.
+    pub fn as_slice(&self) -> &[f64] {
+        &self.grads
+    }
+
+    fn get(&self, location: usize) -> f64 {
+        match self.leaves.binary_search(&location) {
+            Ok(i) => self.grads[i],
+            // the output cannot depend on anything recorded after it
+            Err(_) if location > self.output => 0.,
+            Err(_) => panic!("gradient is only available for leaf variables"),
+        }
+    }
+}
+
+impl<'a> Var<'a> {
+    /// Calculate the gradients of this variable with respect to the variables added with
+    /// `add_var`, without keeping the gradients of intermediate values. The results are the same
+    /// as those of `grad`, but `wrt` panics for variables that are not leaves.
+    pub fn grad_leaves(&self) -> LeafGradient {
+        self.sweep_leaves(&mut HashMap::new())
+    }
+
+    /// Calculate the same gradients as `grad_leaves`, storing adjoints in segments of `segment`
+    /// consecutive nodes. A segment is only allocated once something is propagated into it, and
+    /// is freed as soon as the backward pass has moved past it, so peak memory depends on how far
+    /// back the dependencies of each node reach rather than on the length of the tape. This is
+    /// faster than `grad_leaves` for long tapes whose nodes mostly depend on recent ones, such as
+    /// unrolled simulations.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let tape = Tape::new();
+    /// let x = tape.add_var(0.5);
+    /// let mut y = x;
+    /// for _ in 0..10_000 {
+    ///     y = y - 0.01 * y.sin();
+    /// }
+    /// assert_eq!(y.grad_segmented(256).wrt(&x), y.grad().wrt(&x));
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if `segment` is zero.
+    pub fn grad_segmented(&self, segment: usize) -> LeafGradient {
+        assert!(segment > 0, "segments must hold at least one node");
+        self.sweep_leaves(&mut Segments {
+            len: segment,
+            segments: vec![None; self.location / segment + 1],
+        })
+    }
+
+    /// Backward pass from `self` that only keeps the adjoints of the leaves, storing the other
+    /// adjoints in `store` until they are propagated.
+    fn sweep_leaves(&self, store: &mut impl AdjointStore) -> LeafGradient {
+        let tape = self.tape;
+        let nodes = tape.nodes.borrow();
+        let ops = tape.ops.borrow();
+        let operands = tape.operands.borrow();
+        let spans = tape.spans.borrow();
+        let mut spans = spans
+            .iter()
+            .rev()
+            .skip_while(|span| span.node > self.location);
+        let mut span = spans.next();
+        let blocks = tape.blocks.borrow();
+        let mut blocks = blocks
+            .iter()
+            .rev()
+            .skip_while(|block| block.start > self.location)
+            .peekable();
+        let hooks = tape.hooks.borrow();
+        let mut hooks = hooks
+            .iter()
+            .rev()
+            .skip_while(|hook| hook.location > self.location)
+            .peekable();
+
+        store.add(self.location, 1.);
+        let (mut leaves, mut grads) = (vec![], vec![]);
+        for idx in (0..=self.location).rev() {
+            while let Some(hook) = hooks.next_if(|hook| hook.location == idx) {
+                let deriv = store.take(idx).unwrap_or(0.);
+                store.add(idx, (hook.f)(deriv));
+            }
+            let has_span = matches!(span, Some(span) if span.node == idx);
+            let block = blocks.next_if(|block| block.start == idx);
+            if matches!(ops[idx], Op::Input) {
+                leaves.push(idx);
+                grads.push(store.take(idx).unwrap_or(0.));
+            } else {
+                // the adjoints of block outputs are needed together at the first output
+                let in_block = block.is_some()
+                    || matches!(blocks.peek(), Some(block) if block.start < idx && idx < block.end);
+                let deriv = if in_block {
+                    store.get(idx)
+                } else {
+                    store.take(idx)
+                };
+                if let Some(deriv) = deriv {
+                    let node = &nodes[idx];
+                    for (&dep, &weight) in node.dependencies().iter().zip(&node.weights) {
+                        if dep != idx {
+                            store.add(dep, weight * deriv);
+                        }
+                    }
+                    if has_span {
+                        let span = span.unwrap();
+                        for &(dep, weight) in &operands[span.start..span.end] {
+                            store.add(dep, weight * deriv);
+                        }
+                    }
+                }
+            }
+            if has_span {
+                span = spans.next();
+            }
+            if let Some(block) = block {
+                let output_derivs = (block.start..block.end)
+                    .map(|i| store.take(i).unwrap_or(0.))
+                    .collect::<Vec<_>>();
+                let mut input_derivs = vec![0.; block.inputs.len()];
+                (block.backward)(&output_derivs, &mut input_derivs);
+                for (&input, deriv) in block.inputs.iter().zip(input_derivs) {
+                    store.add(input, deriv);
+                }
+            }
+            // adjoints of the outputs of a block are kept until the whole block has been handled
+            if !matches!(blocks.peek(), Some(block) if block.start < idx && idx < block.end) {
+                store.release(idx);
+            }
+        }
+        leaves.reverse();
+        grads.reverse();
+        LeafGradient {
+            output: self.location,
+            leaves,
+            grads,
+        }
+    }
+}
+
+/// Storage for the adjoints waiting to be propagated during a backward pass.
+trait AdjointStore {
+    /// Gets the adjoint at `location`, if anything was propagated to it.
+    fn get(&mut self, location: usize) -> Option<f64>;
+
+    /// Gets the adjoint at `location`, which will not be needed again.
+    fn take(&mut self, location: usize) -> Option<f64>;
+
+    fn add(&mut self, location: usize, deriv: f64);
+
+    /// Called once the adjoints at `location` and above are no longer needed.
+    fn release(&mut self, location: usize);
+}
+
+impl AdjointStore for HashMap<usize, f64> {
+    fn get(&mut self, location: usize) -> Option<f64> {
+        HashMap::get(self, &location).copied()
+    }
+
+    fn take(&mut self, location: usize) -> Option<f64> {
+        self.remove(&location)
+    }
+
+    fn add(&mut self, location: usize, deriv: f64) {
+        *self.entry(location).or_insert(0.) += deriv;
+    }
+
+    fn release(&mut self, _: usize) {}
+}
+
+/// Adjoints in fixed-size segments that are allocated on first use.
+struct Segments {
+    len: usize,
+    segments: Vec<Option<Vec<f64>>>,
+}
+
+impl AdjointStore for Segments {
+    fn get(&mut self, location: usize) -> Option<f64> {
+        self.segments[location / self.len]
+            .as_ref()
+            .map(|segment| segment[location % self.len])
+    }
+
+    fn take(&mut self, location: usize) -> Option<f64> {
+        self.get(location)
+    }
+
+    fn add(&mut self, location: usize, deriv: f64) {
+        let len = self.len;
+        self.segments[location / len].get_or_insert_with(|| vec![0.; len])[location % len] += deriv;
+    }
+
+    fn release(&mut self, location: usize) {
+        // only whole segments are freed
+        if location % self.len == 0 {
+            self.segments[location / self.len..]
+                .iter_mut()
+                .for_each(|segment| *segment = None);
+        }
+    }
+}
+
+/// Calculate the gradient with respect to the leaf `v`.
+impl<'a> Gradient<&Var<'a>, f64> for LeafGradient {
+    fn wrt(&self, v: &Var<'a>) -> f64 {
+        self.get(v.location)
+    }
+}
+
+/// Calculate the gradient with respect to all leaves in `v`, in the same order.
+impl<'a> Gradient<&[Var<'a>], Vec<f64>> for LeafGradient {
+    fn wrt(&self, v: &[Var<'a>]) -> Vec<f64> {
+        v.iter().map(|v| self.wrt(v)).collect()
+    }
+}
+
+/// Calculate the gradient with respect to all leaves in `v`, in the same order.
+impl<'a> Gradient<&Vec<Var<'a>>, Vec<f64>> for LeafGradient {
+    fn wrt(&self, v: &Vec<Var<'a>>) -> Vec<f64> {
+        self.wrt(v.as_slice())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{det, solve, sum, Mat, Tape};
+
+    #[test]
+    fn test_grad_leaves() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2., 3.]);
+        let m = Mat::new(2, 2, vec![x[0], x[1] * 2., x[2].exp(), x[0].sin()]);
+        let a = g.add_var(0.5);
+        let y = det(&m) * a + sum(&x).ln() - 3. / x[1];
+        let later = g.add_var(4.);
+
+        let leaves = y.grad_leaves();
+        assert_eq!(leaves.len(), 4);
+        assert_eq!(leaves.wrt(&x), y.grad().wrt(&x));
+        assert_eq!(leaves.wrt(&a), y.grad().wrt(&a));
+        assert_eq!(leaves.wrt(&later), 0.);
+
+        // solutions are blocks, whose outputs are differentiated together
+        let z = solve(&m, &[x[0], a]);
+        let w = z[0] * z[1] + z[1];
+        assert_eq!(w.grad_leaves().wrt(&x), w.grad().wrt(&x));
+        assert_eq!(z[1].grad_leaves().wrt(&a), z[1].grad().wrt(&a));
+    }
+
+    #[test]
+    fn test_grad_segmented() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.3, -0.2]);
+        let mut state = x.clone();
+        for i in 0..200 {
+            let next = state[0] * state[1].cos() + x[1] * 0.01;
+            state = vec![next, state[0].tanh()];
+            if i % 50 == 0 {
+                let m = Mat::new(1, 1, vec![state[0] + 2.]);
+                state[1] = solve(&m, &[state[1]])[0];
+            }
+        }
+        let y = state[0] + state[1];
+        let expected = y.grad().wrt(&x);
+        for &segment in &[1, 3, 64, 10_000] {
+            assert_eq!(y.grad_segmented(segment).wrt(&x), expected);
+        }
+
+        // the outputs of a block can straddle a segment boundary
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2., 3., 4., 0.5, -1.]);
+        let a = x[0] * 1.;
+        let b = x[1] + 0.;
+        let m = Mat::new(2, 2, vec![a, b, x[2], x[3]]);
+        let z = solve(&m, &[x[4], x[5]]);
+        assert_eq!(z[0].location, 8);
+        let y = z[0] * 3. + z[1].powi(2);
+        let expected = y.grad().wrt(&x);
+        for &segment in &[1, 2, 3, 4, 8, 9] {
+            assert_eq!(y.grad_segmented(segment).wrt(&x), expected);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "leaf variables")]
+    fn test_grad_leaves_intermediate() {
+        let g = Tape::new();
+        let x = g.add_var(1.);
+        let y = x.sin();
+        let _ = (y * 2.).grad_leaves().wrt(&y);
+    }
+}
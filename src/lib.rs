@@ -37,14 +37,195 @@
 //! ```
 
 #![allow(clippy::suspicious_arithmetic_impl)]
+// lets code generated by the derive macros refer to the crate by name
+extern crate self as reverse;
+#[cfg(feature = "argmin")]
+pub mod argmin;
+#[cfg(feature = "ndarray")]
+pub mod array;
+mod attention;
+#[cfg(feature = "approx")]
+mod compare;
+mod compile;
+mod conv;
+mod differentiable;
+pub mod distributions;
+#[cfg(feature = "nalgebra")]
+mod dmatrix;
+pub mod elementwise;
+mod error;
+mod expr;
+mod fft;
+mod grad;
+mod gradcheck;
+pub mod handle;
+mod higher;
+mod hook;
+mod implicit;
+mod intern;
+mod interval;
+mod jacobian;
+pub mod jet;
+#[cfg(feature = "jit")]
+mod jit;
+mod leaf;
+mod linalg;
+pub mod losses;
+mod matrix;
+#[cfg(feature = "nn")]
+pub mod nn;
+mod normalize;
+pub mod ode;
+mod op;
 mod ops;
-
-use std::{cell::RefCell, fmt::Display};
+pub mod optim;
+mod optimize;
+mod parse;
+mod piecewise;
+mod pool;
+mod provenance;
+#[cfg(feature = "python")]
+pub mod python;
+mod real;
+mod reduce;
+mod replay;
+#[cfg(any(feature = "nn", feature = "sample"))]
+mod rng;
+#[cfg(feature = "sample")]
+pub mod sample;
+mod save;
+mod soft;
+mod sparse;
+mod special;
+mod store;
+mod tensor;
+mod varvec;
+mod vjp;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+pub use attention::attention;
+pub use compile::Program;
+pub use conv::{conv1d, conv2d};
+pub use differentiable::Differentiable;
+pub use error::TapeMismatchError;
+pub use expr::SymbolicError;
+pub use fft::{fft, ifft, rfft};
+pub use grad::{Grad, GradReport, GradRow};
+pub use gradcheck::{gradcheck, GradCheck};
+pub use implicit::{fixed_point, solve_root};
+pub use interval::Interval;
+pub use jacobian::{gauss_newton, grads, jacobian, GaussNewton, Jacobian};
+#[cfg(feature = "jit")]
+pub use jit::{JitError, JitProgram};
+pub use leaf::LeafGradient;
+pub use matrix::{
+    det, eigh, inv, logdet, solve, try_det, try_eigh, try_inv, try_logdet, try_solve, Eigh, Mat,
+};
+pub use normalize::{batch_norm, layer_norm};
+pub use optimize::Remap;
+pub use parse::{Formula, ParseError};
+pub use piecewise::{select, select_gt, Piecewise};
+pub use pool::{with_tape, TapePool};
+pub use provenance::{NonFinite, NonFiniteKind};
+pub use real::Real;
+pub use reduce::{
+    axpy, cumprod, cumsum, dot, logsumexp, max_of, mean, min_of, norm_l1, norm_l2, norm_lp,
+    polyval, std, sum, try_axpy, try_dot, try_logsumexp, try_max_of, try_mean, try_min_of,
+    try_norm_l1, try_norm_l2, try_norm_lp, try_polyval, try_std, try_sum, try_variance,
+    try_weighted_sum, variance, weighted_sum,
+};
+pub use replay::ReplayError;
+#[cfg(feature = "derive")]
+pub use reverse_derive::{diff_fn, Differentiable};
+pub use soft::{soft_sort, soft_top_k};
+pub use sparse::{sparse_jacobian, SparseJacobian};
+pub use special::{beta, ln_beta};
+pub use tensor::{einsum, Tensor};
+pub use varvec::VarVec;
+pub use vjp::{grad_weighted, jvp, vjp};
+
+use error::assert_same_tape;
+use hook::Hook;
+use intern::Interner;
+use op::{Compensated, Op};
+use std::{
+    borrow::Borrow,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::{Debug, Display},
+    hash::{BuildHasher, Hash},
+    sync::Arc,
+};
+use store::Store;
+
+/// Index type for the dependencies of a node. The `compact` feature makes it `u32`, which shrinks
+/// nodes from 32 to 24 bytes but limits a tape to `u32::MAX` nodes.
+#[cfg(feature = "compact")]
+type NodeIndex = u32;
+#[cfg(not(feature = "compact"))]
+type NodeIndex = usize;
 
 #[derive(Debug, Clone, Copy)]
 pub(crate) struct Node {
     weights: [f64; 2],
-    dependencies: [usize; 2],
+    dependencies: [NodeIndex; 2],
+}
+
+impl Node {
+    pub(crate) fn new(weights: [f64; 2], dependencies: [usize; 2]) -> Self {
+        Self {
+            weights,
+            dependencies: [dependencies[0] as NodeIndex, dependencies[1] as NodeIndex],
+        }
+    }
+
+    /// Gets the locations of the node's dependencies.
+    #[inline]
+    #[allow(clippy::unnecessary_cast)]
+    pub(crate) fn dependencies(&self) -> [usize; 2] {
+        [self.dependencies[0] as usize, self.dependencies[1] as usize]
+    }
+}
+
+/// Backward pass of an operation recorded as a block, which receives the adjoints of the block's
+/// outputs and accumulates into the adjoints of its inputs.
+pub(crate) type Backward = Arc<dyn Fn(&[f64], &mut [f64]) + Send + Sync>;
+
+/// Operation with several outputs whose backward pass is a function rather than stored weights.
+/// The outputs are consecutive nodes without dependencies of their own.
+#[derive(Clone)]
+pub(crate) struct Block {
+    /// Location of the first output.
+    start: usize,
+    /// One past the location of the last output.
+    end: usize,
+    inputs: Vec<usize>,
+    backward: Backward,
+}
+
+impl Debug for Block {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Block")
+            .field("start", &self.start)
+            .field("end", &self.end)
+            .field("inputs", &self.inputs)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Dependencies of a node recorded with `Tape::add_nary_node`, as a range of `Tape::operands`.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Span {
+    node: usize,
+    start: usize,
+    end: usize,
+}
+
+/// Position on a tape, as returned by `Tape::mark`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Mark {
+    len: usize,
 }
 
 #[derive(Debug, Clone, Copy)]
@@ -61,18 +242,143 @@ pub struct Var<'a> {
 #[derive(Debug, Clone)]
 /// Tape (Wengert list) that tracks differentiable variables, intermediate values, and the
 /// operations applied to each.
+///
+/// By default the whole recording is kept in memory, so its length is limited by the available
+/// RAM. With the `mmap` feature, `Tape::with_mapped_storage` keeps the nodes and their operations
+/// in memory-mapped files instead, which the operating system pages out to disk. Recordings can
+/// also be kept short by rewinding with `mark` and `rewind_to`, or by clearing the tape between
+/// evaluations as `optim::minimize` does.
 pub struct Tape {
     /// Variables and operations that are tracked.
-    nodes: RefCell<Vec<Node>>,
+    nodes: RefCell<Store<Node>>,
+    /// Spans of the nodes recorded with `add_nary_node`, ordered by node location.
+    spans: RefCell<Store<Span>>,
+    /// Locations and weights referred to by `spans`.
+    operands: RefCell<Store<(usize, f64)>>,
+    /// Operations with a custom backward pass, ordered by location.
+    blocks: RefCell<Vec<Block>>,
+    /// Functions applied to adjoints during the backward pass, ordered by location.
+    hooks: RefCell<Vec<Hook>>,
+    /// Operation that produced each node.
+    ops: RefCell<Store<Op>>,
+    /// Value of every node, only recorded by tapes created with `Tape::with_provenance`.
+    provenance: Option<RefCell<Vec<f64>>>,
+    /// Whether sums and adjoints are accumulated with compensation for rounding errors.
+    compensated: bool,
+    /// Nodes derived from constants only, kept by tapes created with `Tape::with_interning`.
+    interner: Option<RefCell<Interner>>,
 }
 
 impl Tape {
     /// Create a new tape.
     pub fn new() -> Self {
         Self {
-            nodes: RefCell::new(vec![]),
+            nodes: RefCell::new(Store::new()),
+            spans: RefCell::new(Store::new()),
+            operands: RefCell::new(Store::new()),
+            blocks: RefCell::new(vec![]),
+            hooks: RefCell::new(vec![]),
+            ops: RefCell::new(Store::new()),
+            provenance: None,
+            compensated: false,
+            interner: None,
         }
     }
+
+    /// Create a new tape that also records the value of every node, so that
+    /// `find_non_finite` can report where a NaN or infinity came from. This makes recording
+    /// slower and uses more memory, so it is meant for debugging.
+    pub fn with_provenance() -> Self {
+        Self {
+            provenance: Some(RefCell::new(vec![])),
+            ..Self::new()
+        }
+    }
+
+    /// Create a new tape that accumulates with compensated (Kahan) summation, both in `sum` and
+    /// in the backward pass, where each adjoint is the sum of the contributions of every use of its
+    /// node. This keeps the gradients of long chains and of sums over millions of terms accurate,
+    /// at the cost of a slower backward pass that needs twice the memory.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let tape = Tape::with_compensation();
+    /// let x = tape.add_var(1.);
+    /// let xs = std::iter::once(x * 1e16).chain((0..1000).map(|_| x * 1.)).collect::<Vec<_>>();
+    /// assert_eq!(sum(&xs).val, 1e16 + 1000.);
+    ///
+    /// // the adjoint of `x` receives 1e16 first, followed by 1000 ones
+    /// let ones = (1..1000).fold(x, |y, _| y + x);
+    /// let y = ones + x * 1e16;
+    /// assert_eq!(y.grad().wrt(&x), 1e16 + 1000.);
+    /// ```
+    pub fn with_compensation() -> Self {
+        Self {
+            compensated: true,
+            ..Self::new()
+        }
+    }
+
+    /// Create a new tape that records each value computed from constants alone only once, so
+    /// that repeating it (such as the targets of a loss in a batch loop) does not grow the tape.
+    /// Recording an identical operation on constant-derived nodes returns the existing node.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let tape = Tape::with_interning();
+    /// let x = tape.add_var(0.5);
+    /// for _ in 0..100 {
+    ///     let _ = losses::mse(&[x], &[2.]);
+    /// }
+    /// // the input, the target and one loss for each iteration
+    /// assert_eq!(tape.len(), 102);
+    /// ```
+    pub fn with_interning() -> Self {
+        Self {
+            interner: Some(RefCell::new(Interner::default())),
+            ..Self::new()
+        }
+    }
+
+    /// Create a new tape that keeps its nodes, operations and the operands of n-ary nodes in
+    /// temporary files in the directory `dir`, mapped into memory. Recordings can then grow beyond
+    /// the available RAM, as the operating system writes pages out to the files and reads them
+    /// back when the backward pass reaches them. The files are deleted when the tape is dropped.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let f = |tape: &Tape| {
+    ///     let x = tape.add_var(0.5);
+    ///     let y = (0..100_000).fold(x, |y, _| y.sin() * x + 0.5);
+    ///     (y.val(), y.grad().wrt(&x))
+    /// };
+    /// let mapped = Tape::with_mapped_storage(std::env::temp_dir()).unwrap();
+    /// assert_eq!(f(&mapped), f(&Tape::new()));
+    /// assert_eq!(mapped.len(), 300_001);
+    /// ```
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the files cannot be created in `dir`.
+    ///
+    /// # Panics
+    ///
+    /// Operations on the tape panic if a file cannot be grown, such as when the disk is full.
+    #[cfg(feature = "mmap")]
+    pub fn with_mapped_storage(dir: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let dir = dir.as_ref();
+        Ok(Self {
+            nodes: RefCell::new(Store::mapped(dir)?),
+            spans: RefCell::new(Store::mapped(dir)?),
+            operands: RefCell::new(Store::mapped(dir)?),
+            ops: RefCell::new(Store::mapped(dir)?),
+            ..Self::new()
+        })
+    }
+
     /// Gets the number of nodes (differentiable variables and intermediate values) in the tape.
     pub fn len(&self) -> usize {
         self.nodes.borrow().len()
@@ -82,22 +388,103 @@ impl Tape {
         self.len() == 0
     }
 
-    pub(crate) fn add_node(&self, loc1: usize, loc2: usize, grad1: f64, grad2: f64) -> usize {
+    /// Record a node with value `val` produced by `op`, which depends on `loc1` and `loc2` with
+    /// partial derivatives `grad1` and `grad2`.
+    pub(crate) fn add_node(
+        &self,
+        op: Op,
+        val: f64,
+        loc1: usize,
+        loc2: usize,
+        grad1: f64,
+        grad2: f64,
+    ) -> usize {
         let mut nodes = self.nodes.borrow_mut();
         let n = nodes.len();
-        nodes.push(Node {
-            weights: [grad1, grad2],
-            dependencies: [loc1, loc2],
-        });
+        if let Some(interner) = &self.interner {
+            if let Some(existing) = interner.borrow_mut().intern(op, [loc1, loc2], n) {
+                return existing;
+            }
+        }
+        // `backward_unchecked` relies on this to index the adjoints without bounds checks
+        assert!(
+            loc1 <= n && loc2 <= n,
+            "dependencies must be recorded before the node"
+        );
+        // dependencies precede the node, so they fit whenever its own location does
+        #[cfg(feature = "compact")]
+        assert!(
+            n <= NodeIndex::MAX as usize,
+            "tape exceeds the capacity of compact nodes"
+        );
+        nodes.push(Node::new([grad1, grad2], [loc1, loc2]));
+        self.ops.borrow_mut().push(op);
+        if let Some(provenance) = &self.provenance {
+            provenance.borrow_mut().push(val);
+        }
         n
     }
 
+    /// Record a node that depends on any number of locations, with `weights[i]` being the partial
+    /// derivative with respect to `deps[i]`. The dependencies are kept in a side table, so a
+    /// reduction over `n` variables is still a single node.
+    pub(crate) fn add_nary_node(&self, op: Op, val: f64, deps: &[usize], weights: &[f64]) -> usize {
+        assert_eq!(deps.len(), weights.len());
+        let len = self.len();
+        assert!(
+            deps.iter().all(|&dep| dep < len),
+            "dependencies must be recorded before the node"
+        );
+        let mut operands = self.operands.borrow_mut();
+        let start = operands.len();
+        operands.extend(deps.iter().copied().zip(weights.iter().copied()));
+        self.spans.borrow_mut().push(Span {
+            node: len,
+            start,
+            end: operands.len(),
+        });
+        self.add_node(op, val, len, len, 0., 0.)
+    }
+
     /// Add a variable with value `val` to the tape. Returns a `Var<'a>` which can be used like an `f64`.
-    pub fn add_var(&self, val: f64) -> Var {
+    pub fn add_var(&self, val: f64) -> Var<'_> {
         let len = self.len();
         Var {
             val,
-            location: self.add_node(len, len, 0., 0.),
+            location: self.add_node(Op::Input, val, len, len, 0., 0.),
+            tape: self,
+        }
+    }
+
+    /// Record an operation with outputs `vals` that depend on the locations `inputs` through
+    /// `backward`, which maps the adjoints of the outputs to the adjoints of the inputs. Returns
+    /// the location of the first output.
+    pub(crate) fn add_block(
+        &self,
+        op: Op,
+        vals: &[f64],
+        inputs: Vec<usize>,
+        backward: impl Fn(&[f64], &mut [f64]) + Send + Sync + 'static,
+    ) -> usize {
+        let start = self.len();
+        for (i, &val) in vals.iter().enumerate() {
+            self.add_node(op, val, start + i, start + i, 0., 0.);
+        }
+        self.blocks.borrow_mut().push(Block {
+            start,
+            end: start + vals.len(),
+            inputs,
+            backward: Arc::new(backward),
+        });
+        start
+    }
+
+    /// Add a node holding a constant value, which has no dependencies and receives no gradient.
+    pub(crate) fn constant(&self, val: f64) -> Var<'_> {
+        let len = self.len();
+        Var {
+            val,
+            location: self.add_node(Op::Const(val), val, len, len, 0., 0.),
             tape: self,
         }
     }
@@ -113,11 +500,256 @@ impl Tape {
             .borrow_mut()
             .iter_mut()
             .for_each(|n| n.weights = [0., 0.]);
+        self.operands
+            .borrow_mut()
+            .iter_mut()
+            .for_each(|(_, weight)| *weight = 0.);
+        self.blocks.borrow_mut().clear();
     }
 
     /// Clear the tape by deleting all nodes (useful for clearing out intermediate values).
     pub fn clear(&self) {
-        self.nodes.borrow_mut().clear();
+        self.truncate(0);
+    }
+
+    /// Take a marker of the current position on the tape, to later discard everything recorded
+    /// after it with `rewind_to`.
+    pub fn mark(&self) -> Mark {
+        Mark { len: self.len() }
+    }
+
+    /// Delete every node recorded after `mark` was taken. Variables created before the mark stay
+    /// valid, while those created after it must not be used again.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let tape = Tape::new();
+    /// let x = tape.add_var(2.);
+    /// let mark = tape.mark();
+    /// for trial in [1., 2., 3.] {
+    ///     let y = (x * trial).sin();
+    ///     assert_eq!(y.grad().wrt(&x), trial * (2. * trial).cos());
+    ///     tape.rewind_to(mark);
+    /// }
+    /// assert_eq!(tape.len(), 1);
+    /// ```
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tape has since been cleared or rewound to before `mark`.
+    pub fn rewind_to(&self, mark: Mark) {
+        assert!(
+            mark.len <= self.len(),
+            "cannot rewind to a mark past the end of the tape"
+        );
+        self.truncate(mark.len);
+    }
+
+    /// Delete every node at location `len` and above.
+    fn truncate(&self, len: usize) {
+        self.nodes.borrow_mut().truncate(len);
+        let mut spans = self.spans.borrow_mut();
+        let kept = spans.partition_point(|span| span.node < len);
+        let operands = spans
+            .get(kept)
+            .map_or(self.operands.borrow().len(), |s| s.start);
+        spans.truncate(kept);
+        self.operands.borrow_mut().truncate(operands);
+        self.blocks.borrow_mut().retain(|block| block.start < len);
+        self.hooks.borrow_mut().retain(|hook| hook.location < len);
+        self.ops.borrow_mut().truncate(len);
+        if let Some(provenance) = &self.provenance {
+            provenance.borrow_mut().truncate(len);
+        }
+        if let Some(interner) = &self.interner {
+            interner.borrow_mut().truncate(len);
+        }
+    }
+
+    /// Propagate the seeded derivatives in `derivs` backwards through the tape, so that each
+    /// entry ends up holding the derivative with respect to that location.
+    pub(crate) fn backward(&self, derivs: &mut [f64]) {
+        if self.compensated {
+            self.backward_compensated(derivs);
+            return;
+        }
+        let operands = self.operands.borrow();
+        let spans = self.spans.borrow();
+        let mut spans = spans.iter().rev().peekable();
+        let blocks = self.blocks.borrow();
+        let mut blocks = blocks.iter().rev().peekable();
+        let hooks = self.hooks.borrow();
+        let mut hooks = hooks.iter().rev().peekable();
+
+        for (idx, n) in self.nodes.borrow().iter().enumerate().rev() {
+            while let Some(hook) = hooks.next_if(|hook| hook.location == idx) {
+                derivs[idx] = (hook.f)(derivs[idx]);
+            }
+            let deriv = derivs[idx];
+            let [dep1, dep2] = n.dependencies();
+            derivs[dep1] += n.weights[0] * deriv;
+            derivs[dep2] += n.weights[1] * deriv;
+            if let Some(span) = spans.next_if(|span| span.node == idx) {
+                for &(dep, weight) in &operands[span.start..span.end] {
+                    derivs[dep] += weight * deriv;
+                }
+            }
+            if let Some(block) = blocks.next_if(|block| block.start == idx) {
+                let mut input_derivs = vec![0.; block.inputs.len()];
+                (block.backward)(&derivs[block.start..block.end], &mut input_derivs);
+                for (&input, deriv) in block.inputs.iter().zip(input_derivs) {
+                    derivs[input] += deriv;
+                }
+            }
+        }
+    }
+}
+
+impl Tape {
+    /// Propagate `K` independent sets of seeded derivatives backwards in a single sweep, with
+    /// `derivs[i][k]` holding the adjoint of location `i` in lane `k`. Hooks and blocks are applied
+    /// to each lane separately. Compensated tapes use plain accumulation here.
+    pub(crate) fn backward_lanes<const K: usize>(&self, derivs: &mut [[f64; K]]) {
+        let operands = self.operands.borrow();
+        let spans = self.spans.borrow();
+        let mut spans = spans.iter().rev().peekable();
+        let blocks = self.blocks.borrow();
+        let mut blocks = blocks.iter().rev().peekable();
+        let hooks = self.hooks.borrow();
+        let mut hooks = hooks.iter().rev().peekable();
+
+        for (idx, n) in self.nodes.borrow().iter().enumerate().rev() {
+            while let Some(hook) = hooks.next_if(|hook| hook.location == idx) {
+                for deriv in &mut derivs[idx] {
+                    *deriv = (hook.f)(*deriv);
+                }
+            }
+            let deriv = derivs[idx];
+            for (dep, weight) in n.dependencies().iter().zip(n.weights) {
+                let lanes = &mut derivs[*dep];
+                for k in 0..K {
+                    lanes[k] += weight * deriv[k];
+                }
+            }
+            if let Some(span) = spans.next_if(|span| span.node == idx) {
+                for &(dep, weight) in &operands[span.start..span.end] {
+                    let lanes = &mut derivs[dep];
+                    for k in 0..K {
+                        lanes[k] += weight * deriv[k];
+                    }
+                }
+            }
+            if let Some(block) = blocks.next_if(|block| block.start == idx) {
+                let mut output_derivs = vec![0.; block.end - block.start];
+                let mut input_derivs = vec![0.; block.inputs.len()];
+                for k in 0..K {
+                    for (output, lanes) in output_derivs
+                        .iter_mut()
+                        .zip(&derivs[block.start..block.end])
+                    {
+                        *output = lanes[k];
+                    }
+                    input_derivs.iter_mut().for_each(|deriv| *deriv = 0.);
+                    (block.backward)(&output_derivs, &mut input_derivs);
+                    for (&input, deriv) in block.inputs.iter().zip(&input_derivs) {
+                        derivs[input][k] += deriv;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Same as `backward`, but without bounds checks when accumulating the adjoints of nodes and
+    /// n-ary operands. Falls back to `backward` on compensated tapes.
+    fn backward_unchecked(&self, derivs: &mut [f64]) {
+        if self.compensated {
+            self.backward_compensated(derivs);
+            return;
+        }
+        let nodes = self.nodes.borrow();
+        assert_eq!(derivs.len(), nodes.len());
+        let operands = self.operands.borrow();
+        let spans = self.spans.borrow();
+        let mut spans = spans.iter().rev().peekable();
+        let blocks = self.blocks.borrow();
+        let mut blocks = blocks.iter().rev().peekable();
+        let hooks = self.hooks.borrow();
+        let mut hooks = hooks.iter().rev().peekable();
+
+        for (idx, n) in nodes.iter().enumerate().rev() {
+            while let Some(hook) = hooks.next_if(|hook| hook.location == idx) {
+                derivs[idx] = (hook.f)(derivs[idx]);
+            }
+            // SAFETY: `idx` is below `nodes.len()`, which is the length of `derivs`. `add_node`
+            // and `add_nary_node` only record dependencies at or below the location of the node
+            // being added, and the tape can only shrink by truncation, which drops every node and
+            // operand above the new length along with it. The nodes stay borrowed for the whole
+            // sweep, so nothing is recorded in the meantime.
+            unsafe {
+                let deriv = *derivs.get_unchecked(idx);
+                let [dep1, dep2] = n.dependencies();
+                *derivs.get_unchecked_mut(dep1) += n.weights[0] * deriv;
+                *derivs.get_unchecked_mut(dep2) += n.weights[1] * deriv;
+                if let Some(span) = spans.next_if(|span| span.node == idx) {
+                    for &(dep, weight) in operands.get_unchecked(span.start..span.end) {
+                        *derivs.get_unchecked_mut(dep) += weight * deriv;
+                    }
+                }
+            }
+            if let Some(block) = blocks.next_if(|block| block.start == idx) {
+                let mut input_derivs = vec![0.; block.inputs.len()];
+                (block.backward)(&derivs[block.start..block.end], &mut input_derivs);
+                for (&input, deriv) in block.inputs.iter().zip(input_derivs) {
+                    derivs[input] += deriv;
+                }
+            }
+        }
+    }
+
+    /// Same as `backward`, but with compensated accumulation of the adjoints.
+    fn backward_compensated(&self, derivs: &mut [f64]) {
+        let operands = self.operands.borrow();
+        let spans = self.spans.borrow();
+        let mut spans = spans.iter().rev().peekable();
+        let blocks = self.blocks.borrow();
+        let mut blocks = blocks.iter().rev().peekable();
+        let hooks = self.hooks.borrow();
+        let mut hooks = hooks.iter().rev().peekable();
+
+        let mut sums = derivs
+            .iter()
+            .map(|&deriv| {
+                let mut sum = Compensated::default();
+                sum.add(deriv);
+                sum
+            })
+            .collect::<Vec<_>>();
+        for (idx, n) in self.nodes.borrow().iter().enumerate().rev() {
+            // every contribution to this node has been accumulated
+            derivs[idx] = sums[idx].total();
+            while let Some(hook) = hooks.next_if(|hook| hook.location == idx) {
+                derivs[idx] = (hook.f)(derivs[idx]);
+            }
+            let deriv = derivs[idx];
+            for (&dep, &weight) in n.dependencies().iter().zip(&n.weights) {
+                if dep != idx {
+                    sums[dep].add(weight * deriv);
+                }
+            }
+            if let Some(span) = spans.next_if(|span| span.node == idx) {
+                for &(dep, weight) in &operands[span.start..span.end] {
+                    sums[dep].add(weight * deriv);
+                }
+            }
+            if let Some(block) = blocks.next_if(|block| block.start == idx) {
+                let mut input_derivs = vec![0.; block.inputs.len()];
+                (block.backward)(&derivs[block.start..block.end], &mut input_derivs);
+                for (&input, deriv) in block.inputs.iter().zip(input_derivs) {
+                    sums[input].add(deriv);
+                }
+            }
+        }
     }
 }
 
@@ -135,86 +767,95 @@ impl<'a> Var<'a> {
 
     /// Calculate the gradients of this variable with respect to all other (possibly intermediate)
     /// variables that it depends on.
-    pub fn grad(&self) -> Vec<f64> {
+    pub fn grad(&self) -> Grad<'a> {
+        self.grad_with_seed(1.)
+    }
+
+    /// Calculate the gradients of `seed` times this variable, starting the backward pass from
+    /// `seed` instead of one.
+    pub fn grad_with_seed(&self, seed: f64) -> Grad<'a> {
         let n = self.tape.len();
         let mut derivs = vec![0.; n];
-        derivs[self.location] = 1.;
-
-        for (idx, n) in self.tape.nodes.borrow().iter().enumerate().rev() {
-            derivs[n.dependencies[0]] += n.weights[0] * derivs[idx];
-            derivs[n.dependencies[1]] += n.weights[1] * derivs[idx];
-        }
+        derivs[self.location] = seed;
+        self.tape.backward(&mut derivs);
+        Grad::new(self.tape, derivs)
+    }
 
-        derivs
+    /// Same as `grad`, but skips the bounds checks in the backward pass. The tape only ever
+    /// records dependencies on earlier nodes, which is checked as they are recorded, so every
+    /// index is known to be in range.
+    pub fn grad_unchecked(&self) -> Grad<'a> {
+        let n = self.tape.len();
+        let mut derivs = vec![0.; n];
+        derivs[self.location] = 1.;
+        self.tape.backward_unchecked(&mut derivs);
+        Grad::new(self.tape, derivs)
     }
 
-    pub fn recip(&self) -> Self {
-        Self {
-            val: self.val.recip(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                -1. / (self.val.powi(2)),
-                0.,
-            ),
-            tape: self.tape,
-        }
+    /// Record the scalar operation `op` applied to `self`.
+    pub(crate) fn unary(&self, op: Op) -> Self {
+        let (val, deriv, _) = op.eval(self.val, 0.);
+        self.unary_with(op, val, deriv)
     }
 
-    pub fn sin(&self) -> Self {
+    /// Record the scalar operation `op` applied to `self`, with an already computed value and
+    /// derivative.
+    fn unary_with(&self, op: Op, val: f64, deriv: f64) -> Self {
         Self {
-            val: self.val.sin(),
+            val,
             location: self
                 .tape
-                .add_node(self.location, self.location, self.val.cos(), 0.),
+                .add_node(op, val, self.location, self.location, deriv, 0.),
             tape: self.tape,
         }
     }
 
-    pub fn cos(&self) -> Self {
+    /// Record the scalar operation `op` applied to `self` and `rhs`, reporting a mismatch of
+    /// tapes as coming from `name`.
+    pub(crate) fn binary(&self, name: &'static str, rhs: impl Borrow<Self>, op: Op) -> Self {
+        let rhs = rhs.borrow();
+        assert_same_tape(name, self.tape, rhs.tape);
+        let (val, d_self, d_rhs) = op.eval(self.val, rhs.val);
         Self {
-            val: self.val.cos(),
+            val,
             location: self
                 .tape
-                .add_node(self.location, self.location, -self.val.sin(), 0.),
+                .add_node(op, val, self.location, rhs.location, d_self, d_rhs),
             tape: self.tape,
         }
     }
 
+    pub fn recip(&self) -> Self {
+        self.unary(Op::Recip)
+    }
+
+    pub fn sin(&self) -> Self {
+        self.unary(Op::Sin)
+    }
+
+    pub fn cos(&self) -> Self {
+        self.unary(Op::Cos)
+    }
+
+    /// Calculate the sine and cosine together, evaluating the trigonometric functions once.
+    pub fn sin_cos(&self) -> (Self, Self) {
+        let (sin, cos) = self.val.sin_cos();
+        (
+            self.unary_with(Op::Sin, sin, cos),
+            self.unary_with(Op::Cos, cos, -sin),
+        )
+    }
+
     pub fn tan(&self) -> Self {
-        Self {
-            val: self.val.tan(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / self.val.cos().powi(2),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Tan)
     }
 
     pub fn ln(&self) -> Self {
-        Self {
-            val: self.val.ln(),
-            location: self
-                .tape
-                .add_node(self.location, self.location, 1. / self.val, 0.),
-            tape: self.tape,
-        }
+        self.unary(Op::Ln)
     }
 
     pub fn log(&self, base: f64) -> Self {
-        Self {
-            val: self.val.log(base),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / (self.val * base.ln()),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Log(base))
     }
 
     pub fn log10(&self) -> Self {
@@ -226,160 +867,60 @@ impl<'a> Var<'a> {
     }
 
     pub fn ln_1p(&self) -> Self {
-        Self {
-            val: self.val.ln_1p(),
-            location: self
-                .tape
-                .add_node(self.location, self.location, 1. / (1. + self.val), 0.),
-            tape: self.tape,
-        }
+        self.unary(Op::Ln1p)
     }
 
     pub fn asin(&self) -> Self {
-        Self {
-            val: self.val.asin(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / (1. - self.val.powi(2)).sqrt(),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Asin)
     }
 
     pub fn acos(&self) -> Self {
-        Self {
-            val: self.val.acos(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                -1. / (1. - self.val.powi(2)).sqrt(),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Acos)
     }
 
     pub fn atan(&self) -> Self {
-        Self {
-            val: self.val.atan(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / (1. + self.val.powi(2)),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Atan)
     }
 
     pub fn sinh(&self) -> Self {
-        Self {
-            val: self.val.sinh(),
-            location: self
-                .tape
-                .add_node(self.location, self.location, self.val.cosh(), 0.),
-            tape: self.tape,
-        }
+        self.unary(Op::Sinh)
     }
 
     pub fn cosh(&self) -> Self {
-        Self {
-            val: self.val.cosh(),
-            location: self
-                .tape
-                .add_node(self.location, self.location, self.val.sinh(), 0.),
-            tape: self.tape,
-        }
+        self.unary(Op::Cosh)
     }
 
     pub fn tanh(&self) -> Self {
-        Self {
-            val: self.val.tanh(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / (self.val.cosh().powi(2)),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Tanh)
     }
 
     pub fn asinh(&self) -> Self {
-        Self {
-            val: self.val.asinh(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / (1. + self.val.powi(2)).sqrt(),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Asinh)
     }
 
     pub fn acosh(&self) -> Self {
-        Self {
-            val: self.val.acosh(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / (self.val.powi(2) - 1.).sqrt(),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Acosh)
     }
 
     pub fn atanh(&self) -> Self {
-        Self {
-            val: self.val.atanh(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / (1. - self.val.powi(2)),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Atanh)
     }
 
     pub fn exp(&self) -> Self {
-        Self {
-            val: self.val.exp(),
-            location: self
-                .tape
-                .add_node(self.location, self.location, self.val.exp(), 0.),
-            tape: self.tape,
-        }
+        self.unary(Op::Exp)
+    }
+
+    /// Calculate `exp(x) - 1`, accurately even when `x` is close to zero.
+    pub fn exp_m1(&self) -> Self {
+        self.unary(Op::ExpM1)
     }
 
     pub fn exp2(self) -> Self {
-        Self {
-            val: self.val.exp2(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                self.val.exp2() * 2_f64.ln(),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Exp2)
     }
 
     pub fn sqrt(&self) -> Self {
-        Self {
-            val: self.val.sqrt(),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                1. / (2. * self.val.sqrt()),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Sqrt)
     }
 
     pub fn cbrt(&self) -> Self {
@@ -387,34 +928,57 @@ impl<'a> Var<'a> {
     }
 
     pub fn abs(&self) -> Self {
-        let val = self.val.abs();
-        Self {
-            val,
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                if self.val == 0. {
-                    f64::NAN
-                } else {
-                    self.val / val
-                },
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Abs)
+    }
+
+    /// Calculate the Huber loss with threshold `delta`, which is `x^2 / 2` for `|x| <= delta`
+    /// and grows linearly with slope `delta` beyond it.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `delta` is not positive.
+    pub fn huber(&self, delta: f64) -> Self {
+        assert!(delta > 0., "the Huber threshold must be positive");
+        self.unary(Op::Huber(delta))
+    }
+
+    /// Calculate `sqrt(x^2 + eps^2) - eps`, a smooth approximation of `abs` that is zero at zero
+    /// with a zero gradient there.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `eps` is not positive.
+    pub fn smooth_abs(&self, eps: f64) -> Self {
+        assert!(eps > 0., "the smoothing of smooth_abs must be positive");
+        self.unary(Op::SmoothAbs(eps))
     }
 
     pub fn powi(&self, n: i32) -> Self {
-        Self {
-            val: self.val.powi(n),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                n as f64 * self.val.powi(n - 1),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::Powi(n))
+    }
+
+    pub fn floor(&self) -> Self {
+        self.unary(Op::Floor)
+    }
+
+    pub fn ceil(&self) -> Self {
+        self.unary(Op::Ceil)
+    }
+
+    pub fn round(&self) -> Self {
+        self.unary(Op::Round)
+    }
+
+    pub fn trunc(&self) -> Self {
+        self.unary(Op::Trunc)
+    }
+
+    pub fn signum(&self) -> Self {
+        self.unary(Op::Signum)
+    }
+
+    pub fn fract(&self) -> Self {
+        self.unary(Op::Fract)
     }
 }
 
@@ -466,6 +1030,34 @@ pub trait Gradient<T, S> {
     fn wrt(&self, v: T) -> S;
 }
 
+/// Implements `Gradient` for tuples of groups of variables, reading each group with the impl for
+/// its own type.
+macro_rules! tuple_gradient {
+    ($(($($t:ident $s:ident $v:ident),+))*) => {
+        $(
+            /// Calculate the gradients with respect to each group of variables in the tuple `v`.
+            /// Returns a tuple of the gradients with respect to every group, in the same order.
+            impl<G, $($t, $s),+> Gradient<($($t,)+), ($($s,)+)> for G
+            where
+                $(G: Gradient<$t, $s>),+
+            {
+                fn wrt(&self, v: ($($t,)+)) -> ($($s,)+) {
+                    let ($($v,)+) = v;
+                    ($(self.wrt($v),)+)
+                }
+            }
+        )*
+    };
+}
+
+tuple_gradient! {
+    (A SA a, B SB b)
+    (A SA a, B SB b, C SC c)
+    (A SA a, B SB b, C SC c, D SD d)
+    (A SA a, B SB b, C SC c, D SD d, E SE e)
+    (A SA a, B SB b, C SC c, D SD d, E SE e, F SF f)
+}
+
 /// Calculate the gradient with respect to variable `v`.
 impl<'a> Gradient<&Var<'a>, f64> for Vec<f64> {
     fn wrt(&self, v: &Var) -> f64 {
@@ -525,6 +1117,30 @@ impl<'a, const N: usize> Gradient<&[Var<'a>; N], Vec<f64>> for Vec<f64> {
     }
 }
 
+/// Calculate the gradient with respect to all variables in the map `v`. Returns a map from the
+/// same keys to the gradients with respect to their variables.
+impl<'a, K, H> Gradient<&HashMap<K, Var<'a>, H>, HashMap<K, f64, H>> for Vec<f64>
+where
+    K: Clone + Eq + Hash,
+    H: BuildHasher + Clone,
+{
+    fn wrt(&self, v: &HashMap<K, Var<'a>, H>) -> HashMap<K, f64, H> {
+        let mut grads = HashMap::with_capacity_and_hasher(v.len(), v.hasher().clone());
+        grads.extend(v.iter().map(|(k, var)| (k.clone(), self.wrt(var))));
+        grads
+    }
+}
+
+/// Calculate the gradient with respect to all variables in the map `v`. Returns a map from the
+/// same keys to the gradients with respect to their variables.
+impl<'a, K: Clone + Ord> Gradient<&BTreeMap<K, Var<'a>>, BTreeMap<K, f64>> for Vec<f64> {
+    fn wrt(&self, v: &BTreeMap<K, Var<'a>>) -> BTreeMap<K, f64> {
+        v.iter()
+            .map(|(k, var)| (k.clone(), self.wrt(var)))
+            .collect()
+    }
+}
+
 /// Trait for calculating expressions and tracking gradients for float power operations.
 pub trait Powf<Rhs = Self> {
     type Output;
@@ -533,6 +1149,42 @@ pub trait Powf<Rhs = Self> {
     fn powf(self, other: Rhs) -> Self::Output;
 }
 
+/// Trait for calculating expressions and tracking gradients for the four-quadrant arctangent.
+pub trait Atan2<Rhs = Self> {
+    type Output;
+
+    /// Calculate `atan2` for self, where `self` is the y-coordinate and `other` is the x-coordinate.
+    fn atan2(self, other: Rhs) -> Self::Output;
+}
+
+/// Trait for calculating expressions and tracking gradients for the length of the hypotenuse.
+pub trait Hypot<Rhs = Self> {
+    type Output;
+
+    /// Calculate `sqrt(self^2 + other^2)` without intermediate overflow. The gradient at the
+    /// origin is taken to be zero.
+    fn hypot(self, other: Rhs) -> Self::Output;
+}
+
+/// Trait for calculating expressions and tracking gradients for sums in log space.
+pub trait LogAddExp<Rhs = Self> {
+    type Output;
+
+    /// Calculate `ln(exp(self) + exp(other))` without overflowing, by shifting both exponents by
+    /// the larger one.
+    fn logaddexp(self, other: Rhs) -> Self::Output;
+}
+
+/// Trait for calculating expressions and tracking gradients for transferring a sign onto a
+/// magnitude.
+pub trait Copysign<Rhs = Self> {
+    type Output;
+
+    /// Calculate a value with the magnitude of `self` and the sign of `sign`. Gradients only flow
+    /// through the magnitude.
+    fn copysign(self, sign: Rhs) -> Self::Output;
+}
+
 #[cfg(test)]
 mod test {
     use super::*;
@@ -551,6 +1203,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_ad1() {
         let tape = Tape::new();
         let vars = (0..6).map(|x| tape.add_var(x as f64)).collect::<Vec<_>>();
@@ -615,6 +1268,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::useless_vec)]
     fn test_ad5() {
         let g = Tape::new();
         let a = g.add_var(2.);
@@ -635,6 +1289,7 @@ mod test {
     }
 
     #[test]
+    #[allow(clippy::useless_vec, clippy::neg_multiply)]
     fn test_ad6() {
         let g = Tape::new();
         let a = g.add_var(10.1);
@@ -718,6 +1373,171 @@ mod test {
         assert_approx_eq!(grad[1], 200. * (-2. - 5_f64.powi(2)));
     }
 
+    #[test]
+    fn test_atan2() {
+        let g = Tape::new();
+        let y = g.add_var(-1.5);
+        let x = g.add_var(-2.);
+        let res = y.atan2(x);
+        assert_approx_eq!(res.val(), (-1.5_f64).atan2(-2.));
+        let grads = res.grad();
+        assert_approx_eq!(grads.wrt(&y), -2. / 6.25);
+        assert_approx_eq!(grads.wrt(&x), 1.5 / 6.25);
+
+        let res = Atan2::atan2(3., x);
+        assert_approx_eq!(res.grad().wrt(&x), -3. / 13.);
+        let res = y.atan2(4.);
+        assert_approx_eq!(res.grad().wrt(&y), 4. / 18.25);
+    }
+
+    #[test]
+    fn test_powf() {
+        let g = Tape::new();
+        let x = g.add_var(3.);
+        let y = g.add_var(2.);
+        let res = x.powf(y);
+        let grads = res.grad();
+        assert_approx_eq!(grads.wrt(&x), 6.);
+        assert_approx_eq!(grads.wrt(&y), 9. * 3_f64.ln());
+
+        let res = Powf::powf(2., x);
+        assert_approx_eq!(res.val(), 8.);
+        assert_approx_eq!(res.grad().wrt(&x), 8. * 2_f64.ln());
+        let res = y.powf(3.);
+        assert_approx_eq!(res.grad().wrt(&y), 12.);
+    }
+
+    #[test]
+    fn test_hypot() {
+        let g = Tape::new();
+        let x = g.add_var(3e200);
+        let y = g.add_var(-4e200);
+        let res = x.hypot(y);
+        assert_approx_eq!(res.val(), 5e200);
+        let grads = res.grad();
+        assert_approx_eq!(grads.wrt(&x), 0.6);
+        assert_approx_eq!(grads.wrt(&y), -0.8);
+
+        let z = g.add_var(0.);
+        let res = z.hypot(0.);
+        assert_eq!(res.grad().wrt(&z), 0.);
+    }
+
+    #[test]
+    fn test_sin_cos() {
+        let g = Tape::new();
+        let x = g.add_var(0.7);
+        let (s, c) = x.sin_cos();
+        assert_eq!((s.val(), c.val()), (x.sin().val(), x.cos().val()));
+        let res = s * 2. + c * 3.;
+        assert_approx_eq!(res.grad().wrt(&x), 2. * 0.7_f64.cos() - 3. * 0.7_f64.sin());
+    }
+
+    #[test]
+    fn test_exp_m1() {
+        let g = Tape::new();
+        let x = g.add_var(1e-10);
+        let res = x.exp_m1();
+        assert_eq!(res.val(), 1e-10_f64.exp_m1());
+        assert_ne!(res.val(), x.exp().val() - 1.);
+        assert_eq!(res.grad().wrt(&x), 1e-10_f64.exp());
+    }
+
+    #[test]
+    fn test_logaddexp() {
+        let g = Tape::new();
+        let x = g.add_var(1000.);
+        let y = g.add_var(1000. + 2_f64.ln());
+        let res = x.logaddexp(y);
+        assert_approx_eq!(res.val(), 1000. + 3_f64.ln());
+        let grads = res.grad();
+        assert_approx_eq!(grads.wrt(&x), 1. / 3.);
+        assert_approx_eq!(grads.wrt(&y), 2. / 3.);
+
+        let res = x.logaddexp(f64::NEG_INFINITY) + f64::NEG_INFINITY.logaddexp(x);
+        assert_eq!(res.val(), 2000.);
+        assert_eq!(res.grad().wrt(&x), 2.);
+
+        let z = g.add_var(f64::INFINITY);
+        let res = z.logaddexp(z);
+        assert_eq!(res.val(), f64::INFINITY);
+        assert_eq!(res.grad().wrt(&z), 1.);
+    }
+
+    #[test]
+    fn test_huber() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.5, -3., 0.]);
+        let res = x[0].huber(1.) + x[1].huber(1.) * 2. + x[2].huber(1.);
+        assert_eq!(res.val(), 0.125 + 2. * 2.5);
+        assert_eq!(res.grad().wrt(&x), vec![0.5, -2., 0.]);
+
+        let res = x[0].smooth_abs(1e-3) + x[2].smooth_abs(1e-3);
+        assert_approx_eq!(res.val(), 0.5 - 1e-3, 1e-5);
+        let grads = res.grad().wrt(&x);
+        assert_approx_eq!(grads[0], 1., 1e-5);
+        assert_eq!(grads[2], 0.);
+    }
+
+    #[test]
+    fn test_rounding() {
+        let g = Tape::new();
+        let a = g.add_var(-2.75);
+        let res = a.floor() + a.ceil() + a.round() + a.trunc();
+        assert_eq!(res.val(), -3. - 2. - 3. - 2.);
+        assert_eq!(res.grad().wrt(&a), 0.);
+
+        let res = 3. * a.fract();
+        assert_eq!(res.val(), 3. * -0.75);
+        assert_eq!(res.grad().wrt(&a), 3.);
+    }
+
+    #[test]
+    fn test_copysign() {
+        let g = Tape::new();
+        let a = g.add_var(-2.);
+        let b = g.add_var(3.);
+        let res = a.copysign(b);
+        assert_eq!(res.val(), 2.);
+        let grads = res.grad();
+        assert_eq!(grads.wrt(&a), -1.);
+        assert_eq!(grads.wrt(&b), 0.);
+
+        let res = b.copysign(-1.);
+        assert_eq!(res.val(), -3.);
+        assert_eq!(res.grad().wrt(&b), -1.);
+
+        let res = a.signum() * b;
+        assert_eq!(res.grad().wrt(&a), 0.);
+    }
+
+    #[test]
+    fn test_rewind() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[1., 2., 3., 4.]);
+        let before = sum(&xs);
+        let mark = g.mark();
+        let _ = dot(&xs, &xs) + solve(&Mat::from_vals(&g, 1, 1, &[2.]), &xs[..1])[0];
+        g.rewind_to(mark);
+        assert_eq!(g.len(), 5);
+        assert_eq!(before.grad().wrt(&xs), vec![1.; 4]);
+        let after = dot(&xs, &xs);
+        assert_eq!(after.grad().wrt(&xs), vec![2., 4., 6., 8.]);
+        assert!(mark < g.mark());
+    }
+
+    #[test]
+    fn test_nary_node() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[1., 2., 3., 4.]);
+        let res = dot(&xs, &xs) * xs[0];
+        assert_eq!(g.len(), 6);
+        assert_eq!(res.grad().wrt(&xs), vec![32., 4., 6., 8.]);
+
+        g.zero_grad();
+        assert_eq!(res.grad().wrt(&xs), vec![0.; 4]);
+    }
+
     #[test]
     fn test_assign() {
         let g = Tape::new();
@@ -731,4 +1551,109 @@ mod test {
         assert_eq!(gradb, 1.5);
         assert_eq!(b.val(), 2.5);
     }
+
+    #[test]
+    fn test_map_gradients() {
+        let g = Tape::new();
+        let mut params = HashMap::new();
+        params.insert("w".to_string(), g.add_var(2.));
+        params.insert("b".to_string(), g.add_var(-1.));
+        let res = params["w"] * 3. + params["b"].powi(2);
+        let grads = res.grad().wrt(&params);
+        assert_eq!(grads.len(), 2);
+        assert_eq!(grads["w"], 3.);
+        assert_eq!(grads["b"], -2.);
+
+        let layers = params.into_iter().collect::<BTreeMap<_, _>>();
+        let grads = res.grad().wrt(&layers);
+        assert_eq!(
+            grads.into_iter().collect::<Vec<_>>(),
+            vec![("b".to_string(), -2.), ("w".to_string(), 3.)]
+        );
+    }
+
+    #[test]
+    fn test_tuple_gradients() {
+        let g = Tape::new();
+        let w = g.add_vars(&[1., 2.]);
+        let b = g.add_var(0.5);
+        let v = g.add_vars(&[3.]);
+        let res = w[0] * w[1] + b * v[0];
+        let (dw, db, dv) = res.grad().wrt((&w[..], &b, &v));
+        assert_eq!(dw, vec![2., 1.]);
+        assert_eq!(db, 3.);
+        assert_eq!(dv, vec![0.5]);
+        let (db, dw) = res.grad().into_vec().wrt((&b, &w));
+        assert_eq!((db, dw), (3., vec![2., 1.]));
+    }
+
+    #[test]
+    fn test_compensated() {
+        fn f<'a>(x: &[Var<'a>]) -> Var<'a> {
+            let m = Mat::new(2, 2, vec![x[0], x[1], x[1] * 0.5, x[0] + 3.]);
+            let z = solve(&m, &[x[1].sin(), x[0]]);
+            z[0] * z[1] + sum(&x.iter().map(|x| x.powi(3)).collect::<Vec<_>>())
+        }
+        let (plain, compensated) = (Tape::new(), Tape::with_compensation());
+        let xs = [plain.add_vars(&[2., 0.5]), compensated.add_vars(&[2., 0.5])];
+        let ys = [f(&xs[0]), f(&xs[1])];
+        ys[1].register_hook(|adjoint| adjoint * 2.);
+        assert_eq!(ys[0].val, ys[1].val);
+        let (g0, g1) = (ys[0].grad().wrt(&xs[0]), ys[1].grad().wrt(&xs[1]));
+        assert_approx_eq!(g0[0] * 2., g1[0]);
+        assert_approx_eq!(g0[1] * 2., g1[1]);
+    }
+
+    #[test]
+    fn test_grad_unchecked() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.5, 2., -1.5]);
+        let y = sum(&x).sin() * x[0].powf(x[1]) / dot(&x, &x);
+        y.register_hook(|adjoint| adjoint * 3.);
+        let z = y.exp() - solve(&Mat::new(1, 1, vec![x[2]]), &[x[1]])[0];
+        assert_eq!(z.grad_unchecked().wrt(&x), z.grad().wrt(&x));
+    }
+
+    #[test]
+    #[should_panic(expected = "dependencies must be recorded before the node")]
+    fn test_stale_dependency() {
+        let g = Tape::new();
+        let mark = g.mark();
+        let x = g.add_vars(&[1., 2.]);
+        g.rewind_to(mark);
+        let _ = x[1].sin();
+    }
+
+    #[cfg(feature = "compact")]
+    #[test]
+    fn test_compact() {
+        assert_eq!(std::mem::size_of::<Node>(), 24);
+        let g = Tape::new();
+        let x = g.add_var(3.);
+        let y = x.sin() * x;
+        assert_eq!(y.grad().wrt(&x), 3f64.sin() + 3. * 3f64.cos());
+    }
+
+    #[cfg(feature = "derive")]
+    #[test]
+    fn test_diff_fn() {
+        let f = diff_fn!(|x, y: f64| (x.exp() - 2 * y).hypot(y) / x.atan2(y).powf(y.sqrt()));
+        let g = Tape::new();
+        let (x, y) = (g.add_var(0.7), g.add_var(1.3));
+        let res =
+            (x.exp() - y * 2.).hypot(y) / (g.constant(1.) / x.atan2(y).powf(y.sqrt()).recip());
+        let (val, grad) = f(0.7, 1.3);
+        let grads = res.grad();
+        assert_approx_eq!(val, res.val);
+        assert_approx_eq!(grad[0], grads.wrt(&x));
+        assert_approx_eq!(grad[1], grads.wrt(&y));
+
+        // falls back to the tape for functions of variables
+        let scale = 3.;
+        let f = diff_fn!(|x, y| max_of(&[x * y, x.ln()]) * scale);
+        let (val, [dx, dy]) = f(2., 0.25);
+        assert_eq!(val, 2f64.ln() * 3.);
+        assert_eq!(dx, 1.5);
+        assert_eq!(dy, 0.);
+    }
 }
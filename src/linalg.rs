@@ -0,0 +1,274 @@
+//! Dense `f64` linear algebra kernels backing the differentiable matrix operations.
+
+/// Product of the row-major `n x m` matrix `a` and `m x p` matrix `b`.
+pub(crate) fn matmul(a: &[f64], b: &[f64], n: usize, m: usize, p: usize) -> Vec<f64> {
+    let mut c = vec![0.; n * p];
+    for i in 0..n {
+        for k in 0..m {
+            for j in 0..p {
+                c[i * p + j] += a[i * m + k] * b[k * p + j];
+            }
+        }
+    }
+    c
+}
+
+/// Transpose of the row-major `rows x cols` matrix `a`.
+pub(crate) fn transpose(a: &[f64], rows: usize, cols: usize) -> Vec<f64> {
+    (0..rows * cols)
+        .map(|k| a[(k % rows) * cols + k / rows])
+        .collect()
+}
+
+/// Minor of the row-major `n x n` matrix `a` without row `i` and column `j`.
+fn minor(a: &[f64], n: usize, i: usize, j: usize) -> Vec<f64> {
+    (0..n * n)
+        .filter(|&k| k / n != i && k % n != j)
+        .map(|k| a[k])
+        .collect()
+}
+
+/// Cofactors of the row-major `n x n` matrix `a`, which are the partial derivatives of its
+/// determinant. Unlike `det(A) A^-T`, they are defined for singular matrices too.
+pub(crate) fn cofactors(a: &[f64], n: usize) -> Vec<f64> {
+    (0..n * n)
+        .map(|k| {
+            let (i, j) = (k / n, k % n);
+            let sign = if (i + j) % 2 == 0 { 1. } else { -1. };
+            Lu::new(&minor(a, n, i, j), n - 1).map_or(0., |lu| sign * lu.det())
+        })
+        .collect()
+}
+
+/// Eigendecomposition of the symmetric `n x n` matrix `a` by cyclic Jacobi rotations. Returns the
+/// eigenvalues in ascending order and the row-major matrix whose columns are the matching unit
+/// eigenvectors.
+pub(crate) fn symmetric_eigen(a: &[f64], n: usize) -> (Vec<f64>, Vec<f64>) {
+    assert_eq!(a.len(), n * n);
+    let mut a = a.to_vec();
+    let mut v = vec![0.; n * n];
+    for i in 0..n {
+        v[i * n + i] = 1.;
+    }
+    let norm = a.iter().map(|x| x * x).sum::<f64>();
+    for _ in 0..100 {
+        let off = (0..n)
+            .flat_map(|p| (0..n).filter(move |&q| q != p).map(move |q| p * n + q))
+            .map(|k| a[k] * a[k])
+            .sum::<f64>();
+        if off <= f64::EPSILON * f64::EPSILON * norm {
+            break;
+        }
+        for p in 0..n {
+            for q in p + 1..n {
+                let apq = a[p * n + q];
+                if apq == 0. {
+                    continue;
+                }
+                let theta = (a[q * n + q] - a[p * n + p]) / (2. * apq);
+                let t = if theta == 0. {
+                    1.
+                } else {
+                    theta.signum() / (theta.abs() + theta.hypot(1.))
+                };
+                let c = 1. / t.hypot(1.);
+                let s = t * c;
+                for k in 0..n {
+                    let (akp, akq) = (a[k * n + p], a[k * n + q]);
+                    a[k * n + p] = c * akp - s * akq;
+                    a[k * n + q] = s * akp + c * akq;
+                }
+                for k in 0..n {
+                    let (apk, aqk) = (a[p * n + k], a[q * n + k]);
+                    a[p * n + k] = c * apk - s * aqk;
+                    a[q * n + k] = s * apk + c * aqk;
+                }
+                for k in 0..n {
+                    let (vkp, vkq) = (v[k * n + p], v[k * n + q]);
+                    v[k * n + p] = c * vkp - s * vkq;
+                    v[k * n + q] = s * vkp + c * vkq;
+                }
+            }
+        }
+    }
+    let mut order = (0..n).collect::<Vec<_>>();
+    order.sort_by(|&i, &j| a[i * n + i].total_cmp(&a[j * n + j]));
+    let values = order.iter().map(|&i| a[i * n + i]).collect();
+    let vectors = (0..n * n).map(|k| v[k / n * n + order[k % n]]).collect();
+    (values, vectors)
+}
+
+/// LU decomposition with partial pivoting of a square row-major matrix, `PA = LU`.
+#[derive(Debug, Clone)]
+pub(crate) struct Lu {
+    /// `L` below the diagonal (with an implicit unit diagonal) and `U` on and above it.
+    lu: Vec<f64>,
+    /// Row `i` of `PA` is row `perm[i]` of `A`.
+    perm: Vec<usize>,
+    /// Sign of the permutation.
+    sign: f64,
+    n: usize,
+}
+
+impl Lu {
+    /// Decompose the `n x n` matrix `a`, returning `None` if it is singular.
+    pub(crate) fn new(a: &[f64], n: usize) -> Option<Self> {
+        assert_eq!(a.len(), n * n);
+        let mut lu = a.to_vec();
+        let mut perm = (0..n).collect::<Vec<_>>();
+        let mut sign = 1.;
+        for k in 0..n {
+            let pivot = (k..n)
+                .max_by(|&i, &j| lu[i * n + k].abs().total_cmp(&lu[j * n + k].abs()))
+                .unwrap();
+            if lu[pivot * n + k] == 0. {
+                return None;
+            }
+            if pivot != k {
+                for j in 0..n {
+                    lu.swap(k * n + j, pivot * n + j);
+                }
+                perm.swap(k, pivot);
+                sign = -sign;
+            }
+            for i in k + 1..n {
+                let factor = lu[i * n + k] / lu[k * n + k];
+                lu[i * n + k] = factor;
+                for j in k + 1..n {
+                    lu[i * n + j] -= factor * lu[k * n + j];
+                }
+            }
+        }
+        Some(Self { lu, perm, sign, n })
+    }
+
+    /// Solve `Ax = b`.
+    pub(crate) fn solve(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.n;
+        let mut x = self.perm.iter().map(|&p| b[p]).collect::<Vec<_>>();
+        for i in 0..n {
+            for j in 0..i {
+                x[i] -= self.lu[i * n + j] * x[j];
+            }
+        }
+        for i in (0..n).rev() {
+            for j in i + 1..n {
+                x[i] -= self.lu[i * n + j] * x[j];
+            }
+            x[i] /= self.lu[i * n + i];
+        }
+        x
+    }
+
+    pub(crate) fn det(&self) -> f64 {
+        self.sign
+            * (0..self.n)
+                .map(|i| self.lu[i * self.n + i])
+                .product::<f64>()
+    }
+
+    /// Natural logarithm of the absolute value of the determinant.
+    pub(crate) fn ln_abs_det(&self) -> f64 {
+        (0..self.n)
+            .map(|i| self.lu[i * self.n + i].abs().ln())
+            .sum()
+    }
+
+    /// Inverse of `A` in row-major order.
+    pub(crate) fn inverse(&self) -> Vec<f64> {
+        let n = self.n;
+        let mut inv = vec![0.; n * n];
+        let mut e = vec![0.; n];
+        for j in 0..n {
+            e[j] = 1.;
+            for (i, x) in self.solve(&e).into_iter().enumerate() {
+                inv[i * n + j] = x;
+            }
+            e[j] = 0.;
+        }
+        inv
+    }
+
+    /// Solve `A^T x = b`.
+    pub(crate) fn solve_transpose(&self, b: &[f64]) -> Vec<f64> {
+        let n = self.n;
+        let mut w = b.to_vec();
+        for i in 0..n {
+            for j in 0..i {
+                w[i] -= self.lu[j * n + i] * w[j];
+            }
+            w[i] /= self.lu[i * n + i];
+        }
+        for i in (0..n).rev() {
+            for j in i + 1..n {
+                w[i] -= self.lu[j * n + i] * w[j];
+            }
+        }
+        let mut x = vec![0.; n];
+        for (i, &p) in self.perm.iter().enumerate() {
+            x[p] = w[i];
+        }
+        x
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_lu() {
+        let a = [0., 2., 1., 1., 1., 1., 4., -1., 3.];
+        let lu = Lu::new(&a, 3).unwrap();
+        let b = [1., 2., 3.];
+        let x = lu.solve(&b);
+        for i in 0..3 {
+            let row = (0..3).map(|j| a[i * 3 + j] * x[j]).sum::<f64>();
+            assert_approx_eq!(row, b[i]);
+        }
+        let y = lu.solve_transpose(&b);
+        for i in 0..3 {
+            let col = (0..3).map(|j| a[j * 3 + i] * y[j]).sum::<f64>();
+            assert_approx_eq!(col, b[i]);
+        }
+        assert_approx_eq!(lu.det(), -3.);
+        assert_approx_eq!(lu.ln_abs_det(), 3_f64.ln());
+        let inv = lu.inverse();
+        for i in 0..3 {
+            for j in 0..3 {
+                let entry = (0..3).map(|k| a[i * 3 + k] * inv[k * 3 + j]).sum::<f64>();
+                assert_approx_eq!(entry, if i == j { 1. } else { 0. });
+            }
+        }
+        assert!(Lu::new(&[1., 2., 2., 4.], 2).is_none());
+
+        let cof = cofactors(&a, 3);
+        let expected = transpose(&inv, 3, 3).into_iter().map(|x| lu.det() * x);
+        for (cof, expected) in cof.iter().zip(expected) {
+            assert_approx_eq!(*cof + 1., expected + 1.);
+        }
+        assert_eq!(cofactors(&[1., 2., 2., 4.], 2), vec![4., -2., -2., 1.]);
+        assert_eq!(cofactors(&[0.], 1), vec![1.]);
+    }
+
+    #[test]
+    fn test_symmetric_eigen() {
+        let a = [4., 1., -2., 1., 2., 0., -2., 0., 3.];
+        let (values, vectors) = symmetric_eigen(&a, 3);
+        assert!(values.windows(2).all(|w| w[0] <= w[1]));
+        assert_approx_eq!(values.iter().sum::<f64>(), 9.);
+        let av = matmul(&a, &vectors, 3, 3, 3);
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_approx_eq!(av[i * 3 + j] + 1., values[j] * vectors[i * 3 + j] + 1.);
+            }
+        }
+        let vtv = matmul(&transpose(&vectors, 3, 3), &vectors, 3, 3, 3);
+        for i in 0..3 {
+            for j in 0..3 {
+                assert_approx_eq!(vtv[i * 3 + j] + 1., if i == j { 2. } else { 1. });
+            }
+        }
+    }
+}
@@ -0,0 +1,488 @@
+//! Loss functions comparing predictions with targets, each recorded as a single node.
+//!
+//! Targets can be `f64` constants or variables on the same tape as the predictions, in which case
+//! the losses are also differentiable with respect to the targets.
+//!
+//! ```rust
+//! use reverse::*;
+//! use reverse::losses::{binary_cross_entropy, mse};
+//!
+//! let tape = Tape::new();
+//! let params = tape.add_vars(&[0.5, -1.]);
+//! let xs = [1., 2., 3.];
+//! let predictions = xs.iter().map(|&x| params[0] * x + params[1]).collect::<Vec<_>>();
+//! let loss = mse(&predictions, &[-0.5, 0., 0.5]);
+//! assert_eq!(loss.val(), 0.);
+//! assert_eq!(loss.grad().wrt(&params), vec![0., 0.]);
+//!
+//! // logits of any size are fine, as the loss is computed without `exp` overflowing
+//! let logit = tape.add_var(800.);
+//! let loss = binary_cross_entropy(&[logit], &[0.]);
+//! assert_eq!(loss.val(), 800.);
+//! assert_eq!(loss.grad().wrt(&logit), 1.);
+//! ```
+
+use crate::{error::assert_same_tape, Op, Tape, TapeMismatchError, Var};
+
+/// Targets of a loss function, either constants or variables.
+pub trait Target<'a>: Copy {
+    /// Gets the target as a variable on `tape`.
+    fn to_var(self, tape: &'a Tape) -> Var<'a>;
+
+    /// Gets the tape of the target, if it is a variable.
+    fn tape(self) -> Option<&'a Tape> {
+        None
+    }
+}
+
+impl<'a> Target<'a> for f64 {
+    fn to_var(self, tape: &'a Tape) -> Var<'a> {
+        tape.constant(self)
+    }
+}
+
+impl<'a> Target<'a> for Var<'a> {
+    fn to_var(self, tape: &'a Tape) -> Var<'a> {
+        assert_same_tape("to_var", tape, self.tape);
+        self
+    }
+
+    fn tape(self) -> Option<&'a Tape> {
+        Some(self.tape)
+    }
+}
+
+/// Record `op` over the predictions and targets, interleaved in pairs, as a single node, for the
+/// loss `name`.
+fn pairwise<'a, T: Target<'a>>(
+    name: &'static str,
+    op: Op,
+    predictions: &[Var<'a>],
+    targets: &[T],
+) -> Var<'a> {
+    assert_eq!(
+        predictions.len(),
+        targets.len(),
+        "need one target for each prediction"
+    );
+    let tape = predictions.first().expect("no predictions").tape;
+    let mut vals = Vec::with_capacity(2 * predictions.len());
+    let mut deps = Vec::with_capacity(2 * predictions.len());
+    for (prediction, &target) in predictions.iter().zip(targets) {
+        assert_same_tape(name, tape, prediction.tape);
+        if let Some(target) = target.tape() {
+            assert_same_tape(name, tape, target);
+        }
+        let target = target.to_var(tape);
+        vals.extend([prediction.val, target.val]);
+        deps.extend([prediction.location, target.location]);
+    }
+    let (val, weights) = op.eval_nary(&vals);
+    Var {
+        val,
+        location: tape.add_nary_node(op, val, &deps, &weights),
+        tape,
+    }
+}
+
+/// Calculate the mean squared error between `predictions` and `targets`.
+///
+/// # Panics
+///
+/// Panics if there are no predictions or the lengths differ.
+pub fn mse<'a, T: Target<'a>>(predictions: &[Var<'a>], targets: &[T]) -> Var<'a> {
+    pairwise("mse", Op::Mse, predictions, targets)
+}
+
+/// Calculate the mean absolute error between `predictions` and `targets`. The gradient of an
+/// exact prediction is taken to be zero.
+///
+/// # Panics
+///
+/// Panics if there are no predictions or the lengths differ.
+pub fn mae<'a, T: Target<'a>>(predictions: &[Var<'a>], targets: &[T]) -> Var<'a> {
+    pairwise("mae", Op::Mae, predictions, targets)
+}
+
+/// Calculate the mean binary cross-entropy between the probabilities `sigmoid(logits)` and
+/// `targets` in `[0, 1]`. The loss is computed from the logits directly, as
+/// `max(z, 0) - z * t + ln(1 + exp(-|z|))`, so it stays finite for any logit.
+///
+/// # Panics
+///
+/// Panics if there are no logits or the lengths differ.
+pub fn binary_cross_entropy<'a, T: Target<'a>>(logits: &[Var<'a>], targets: &[T]) -> Var<'a> {
+    pairwise(
+        "binary_cross_entropy",
+        Op::BinaryCrossEntropy,
+        logits,
+        targets,
+    )
+}
+
+/// Calculate the cross-entropy between the distribution `softmax(logits)` over classes and the
+/// target distribution `targets`, which should sum to one. The log-softmax is computed stably, by
+/// shifting the logits by their maximum.
+///
+/// # Panics
+///
+/// Panics if there are no logits or the lengths differ.
+pub fn cross_entropy<'a, T: Target<'a>>(logits: &[Var<'a>], targets: &[T]) -> Var<'a> {
+    pairwise("cross_entropy", Op::CrossEntropy, logits, targets)
+}
+
+/// Calculate the cross-entropy between the distribution `softmax(logits)` and the class `class`,
+/// the negative log-likelihood of the class.
+///
+/// # Panics
+///
+/// Panics if there are no logits or `class` is out of bounds.
+pub fn cross_entropy_class<'a>(logits: &[Var<'a>], class: usize) -> Var<'a> {
+    assert!(class < logits.len(), "class out of bounds");
+    let targets = (0..logits.len())
+        .map(|i| if i == class { 1. } else { 0. })
+        .collect::<Vec<_>>();
+    cross_entropy(logits, &targets)
+}
+
+/// Calculate the mean hinge loss `max(0, 1 - t * y)` of the predictions `y` for the labels `t`,
+/// which are `-1` or `1`.
+///
+/// # Panics
+///
+/// Panics if there are no predictions or the lengths differ.
+pub fn hinge<'a, T: Target<'a>>(predictions: &[Var<'a>], labels: &[T]) -> Var<'a> {
+    pairwise("hinge", Op::Hinge, predictions, labels)
+}
+
+/// Calculate the entropy `-sum(p ln p)` of the distribution `p`, recorded as a single node. Zero
+/// probabilities contribute nothing, and their gradient is taken to be zero rather than infinite,
+/// so that probabilities that underflowed in a softmax do not make the gradient NaN.
+///
+/// ```rust
+/// use reverse::*;
+/// use reverse::losses::entropy;
+///
+/// let tape = Tape::new();
+/// let p = tape.add_vars(&[0.5, 0.5, 0.]);
+/// let h = entropy(&p);
+/// assert_eq!(h.val(), 2_f64.ln());
+/// assert_eq!(h.grad().wrt(&p)[2], 0.);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `p` is empty or the variables are on different tapes.
+pub fn entropy<'a>(p: &[Var<'a>]) -> Var<'a> {
+    let tape = p.first().expect("no probabilities").tape;
+    let vals = p
+        .iter()
+        .map(|x| {
+            assert_same_tape("entropy", tape, x.tape);
+            x.val
+        })
+        .collect::<Vec<_>>();
+    let deps = p.iter().map(|x| x.location).collect::<Vec<_>>();
+    let (val, weights) = Op::Entropy.eval_nary(&vals);
+    Var {
+        val,
+        location: tape.add_nary_node(Op::Entropy, val, &deps, &weights),
+        tape,
+    }
+}
+
+/// Calculate the Kullback-Leibler divergence `sum(p ln(p / q))` of the distribution `q` from the
+/// distribution `p`, where `q` holds variables or constants. Terms with a zero probability in `p`
+/// are zero, with zero gradients, even where `q` is zero too.
+///
+/// # Panics
+///
+/// Panics if `p` is empty or the lengths differ.
+pub fn kl_div<'a, T: Target<'a>>(p: &[Var<'a>], q: &[T]) -> Var<'a> {
+    pairwise("kl_div", Op::KlDiv, p, q)
+}
+
+/// Calculate the Kullback-Leibler divergence of `softmax(q_logits)` from `softmax(p_logits)`,
+/// like `kl_div` but from the logits of both distributions. The log-probabilities are computed
+/// stably, so the divergence stays finite for any logits.
+///
+/// # Panics
+///
+/// Panics if `p_logits` is empty or the lengths differ.
+pub fn kl_div_logits<'a, T: Target<'a>>(p_logits: &[Var<'a>], q_logits: &[T]) -> Var<'a> {
+    pairwise("kl_div_logits", Op::KlDivLogits, p_logits, q_logits)
+}
+
+/// Checks that the predictions and any variable targets are on the same tape, for the loss `name`.
+fn check_pairs<'a, T: Target<'a>>(
+    name: &'static str,
+    predictions: &[Var<'a>],
+    targets: &[T],
+) -> Result<(), TapeMismatchError> {
+    TapeMismatchError::check_all(name, predictions)?;
+    match predictions.first() {
+        Some(first) => targets
+            .iter()
+            .filter_map(|target| target.tape())
+            .try_for_each(|tape| TapeMismatchError::check(name, first.tape, tape)),
+        None => Ok(()),
+    }
+}
+
+/// Like `mse`, but returns an error if the variables are on different tapes.
+pub fn try_mse<'a, T: Target<'a>>(
+    predictions: &[Var<'a>],
+    targets: &[T],
+) -> Result<Var<'a>, TapeMismatchError> {
+    check_pairs("mse", predictions, targets).map(|_| mse(predictions, targets))
+}
+
+/// Like `mae`, but returns an error if the variables are on different tapes.
+pub fn try_mae<'a, T: Target<'a>>(
+    predictions: &[Var<'a>],
+    targets: &[T],
+) -> Result<Var<'a>, TapeMismatchError> {
+    check_pairs("mae", predictions, targets).map(|_| mae(predictions, targets))
+}
+
+/// Like `binary_cross_entropy`, but returns an error if the variables are on different tapes.
+pub fn try_binary_cross_entropy<'a, T: Target<'a>>(
+    logits: &[Var<'a>],
+    targets: &[T],
+) -> Result<Var<'a>, TapeMismatchError> {
+    check_pairs("binary_cross_entropy", logits, targets)
+        .map(|_| binary_cross_entropy(logits, targets))
+}
+
+/// Like `cross_entropy`, but returns an error if the variables are on different tapes.
+pub fn try_cross_entropy<'a, T: Target<'a>>(
+    logits: &[Var<'a>],
+    targets: &[T],
+) -> Result<Var<'a>, TapeMismatchError> {
+    check_pairs("cross_entropy", logits, targets).map(|_| cross_entropy(logits, targets))
+}
+
+/// Like `hinge`, but returns an error if the variables are on different tapes.
+pub fn try_hinge<'a, T: Target<'a>>(
+    predictions: &[Var<'a>],
+    labels: &[T],
+) -> Result<Var<'a>, TapeMismatchError> {
+    check_pairs("hinge", predictions, labels).map(|_| hinge(predictions, labels))
+}
+
+/// Like `kl_div`, but returns an error if the variables are on different tapes.
+pub fn try_kl_div<'a, T: Target<'a>>(p: &[Var<'a>], q: &[T]) -> Result<Var<'a>, TapeMismatchError> {
+    check_pairs("kl_div", p, q).map(|_| kl_div(p, q))
+}
+
+/// Like `kl_div_logits`, but returns an error if the variables are on different tapes.
+pub fn try_kl_div_logits<'a, T: Target<'a>>(
+    p_logits: &[Var<'a>],
+    q_logits: &[T],
+) -> Result<Var<'a>, TapeMismatchError> {
+    check_pairs("kl_div_logits", p_logits, q_logits).map(|_| kl_div_logits(p_logits, q_logits))
+}
+
+/// Like `cross_entropy_class`, but returns an error if the variables are on different tapes.
+pub fn try_cross_entropy_class<'a>(
+    logits: &[Var<'a>],
+    class: usize,
+) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("cross_entropy", logits)
+        .map(|_| cross_entropy_class(logits, class))
+}
+
+/// Like `entropy`, but returns an error if the variables are on different tapes.
+pub fn try_entropy<'a>(p: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("entropy", p).map(|_| entropy(p))
+}
+
+/// Value and partial derivatives of the loss `op` over predictions and targets interleaved in
+/// pairs, as recorded by `pairwise`, or of the entropy over the probabilities `xs`.
+pub(crate) fn eval(op: Op, xs: &[f64]) -> (f64, Vec<f64>) {
+    let n = (xs.len() / 2) as f64;
+    let pairs = xs.chunks(2).map(|pair| (pair[0], pair[1]));
+    let mut val = 0.;
+    let mut weights = Vec::with_capacity(xs.len());
+    match op {
+        Op::Mse => {
+            for (y, t) in pairs {
+                val += (y - t).powi(2) / n;
+                let weight = 2. * (y - t) / n;
+                weights.extend([weight, -weight]);
+            }
+        }
+        Op::Mae => {
+            for (y, t) in pairs {
+                val += (y - t).abs() / n;
+                let weight = if y == t { 0. } else { (y - t).signum() / n };
+                weights.extend([weight, -weight]);
+            }
+        }
+        Op::BinaryCrossEntropy => {
+            for (z, t) in pairs {
+                val += (z.max(0.) - z * t + (-z.abs()).exp().ln_1p()) / n;
+                let sigmoid = if z >= 0. {
+                    1. / (1. + (-z).exp())
+                } else {
+                    z.exp() / (1. + z.exp())
+                };
+                weights.extend([(sigmoid - t) / n, -z / n]);
+            }
+        }
+        Op::CrossEntropy => {
+            let logits = xs.iter().step_by(2).copied().collect::<Vec<_>>();
+            let (lse, softmax) = Op::LogSumExp.eval_nary(&logits);
+            let total = pairs.clone().map(|(_, t)| t).sum::<f64>();
+            for ((z, t), p) in pairs.zip(softmax) {
+                val += t * (lse - z);
+                weights.extend([p * total - t, lse - z]);
+            }
+        }
+        Op::Entropy => {
+            for &p in xs {
+                if p == 0. {
+                    weights.push(0.);
+                } else {
+                    val -= p * p.ln();
+                    weights.push(-(p.ln() + 1.));
+                }
+            }
+        }
+        Op::KlDiv => {
+            for (p, q) in pairs {
+                if p == 0. {
+                    weights.extend([0., 0.]);
+                } else {
+                    let ratio = (p / q).ln();
+                    val += p * ratio;
+                    weights.extend([ratio + 1., -p / q]);
+                }
+            }
+        }
+        Op::KlDivLogits => {
+            let log_softmax = |logits: Vec<f64>| {
+                let (lse, _) = Op::LogSumExp.eval_nary(&logits);
+                logits.into_iter().map(|z| z - lse).collect::<Vec<_>>()
+            };
+            let lp = log_softmax(pairs.clone().map(|(a, _)| a).collect());
+            let lq = log_softmax(pairs.map(|(_, b)| b).collect());
+            val = lp.iter().zip(&lq).map(|(a, b)| a.exp() * (a - b)).sum();
+            for (a, b) in lp.iter().zip(&lq) {
+                weights.extend([a.exp() * (a - b - val), b.exp() - a.exp()]);
+            }
+        }
+        Op::Hinge => {
+            for (y, t) in pairs {
+                let margin = 1. - t * y;
+                if margin > 0. {
+                    val += margin / n;
+                    weights.extend([-t / n, -y / n]);
+                } else {
+                    weights.extend([0., 0.]);
+                }
+            }
+        }
+        op => panic!("{:?} is not a loss function", op),
+    }
+    (val, weights)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Gradient;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_regression_losses() {
+        let g = Tape::new();
+        let y = g.add_vars(&[1., -2., 0.5]);
+        let t = g.add_var(3.);
+        let loss = mse(&y, &[0., -2., 1.5]);
+        assert_approx_eq!(loss.val(), 2. / 3.);
+        assert_eq!(loss.grad().wrt(&y), vec![2. / 3., 0., -2. / 3.]);
+
+        let loss = mae(&y, &[0., -2., 1.5]);
+        assert_approx_eq!(loss.val(), 2. / 3.);
+        assert_eq!(loss.grad().wrt(&y), vec![1. / 3., 0., -1. / 3.]);
+
+        let loss = mse(&y[..1], &[t]);
+        assert_eq!(loss.val(), 4.);
+        assert_eq!(loss.grad().wrt(&[y[0], t]), vec![-4., 4.]);
+
+        let loss = hinge(&y, &[1., -1., -1.]);
+        assert_approx_eq!(loss.val(), 1.5 / 3.);
+        assert_eq!(loss.grad().wrt(&y), vec![0., 0., 1. / 3.]);
+    }
+
+    #[test]
+    fn test_divergences() {
+        let g = Tape::new();
+        let p = g.add_vars(&[0.2, 0.8, 0.]);
+        let q = g.add_vars(&[0.5, 0.25, 0.25]);
+        let naive = -(p[0] * p[0].ln() + p[1] * p[1].ln());
+        let h = entropy(&p);
+        assert_approx_eq!(h.val(), naive.val());
+        let grads = h.grad().wrt(&p);
+        assert_approx_eq!(grads[0], -(0.2_f64.ln() + 1.));
+        assert_eq!(grads[2], 0.);
+
+        let d = kl_div(&p, &q);
+        let naive = p[0] * (p[0] / q[0]).ln() + p[1] * (p[1] / q[1]).ln();
+        assert_approx_eq!(d.val(), naive.val());
+        let (grads, expected) = (d.grad().wrt(&q), naive.grad().wrt(&q));
+        for (grad, expected) in grads.iter().zip(expected) {
+            assert_approx_eq!(*grad, expected);
+        }
+        // a zero in `q` only matters where `p` is positive
+        assert_eq!(kl_div(&p[2..], &[0.]).val(), 0.);
+        assert!(kl_div(&p[..1], &[0.]).val().is_infinite());
+
+        let a = g.add_vars(&[1., -0.5, 2.]);
+        let b = g.add_vars(&[0.3, 0.3, -1.]);
+        fn softmax<'a>(z: &[Var<'a>]) -> Vec<Var<'a>> {
+            let total = z.iter().map(|z| z.exp()).sum::<Var>();
+            z.iter().map(|z| z.exp() / total).collect()
+        }
+        let naive = kl_div(&softmax(&a), &softmax(&b));
+        let d = kl_div_logits(&a, &b);
+        assert_approx_eq!(d.val(), naive.val());
+        let ab = [&a[..], &b[..]].concat();
+        let (grads, expected) = (d.grad().wrt(&ab), naive.grad().wrt(&ab));
+        for (grad, expected) in grads.iter().zip(expected) {
+            assert_approx_eq!(*grad, expected);
+        }
+        assert!(kl_div_logits(&a, &[800., 0., -800.]).val().is_finite());
+    }
+
+    #[test]
+    fn test_cross_entropy() {
+        let g = Tape::new();
+        let z = g.add_vars(&[0.5, -1., 2.]);
+        let naive = z.iter().map(|z| z.exp()).sum::<Var>().ln() - z[1];
+        let loss = cross_entropy_class(&z, 1);
+        assert_approx_eq!(loss.val(), naive.val());
+        let (grads, expected) = (loss.grad().wrt(&z), naive.grad().wrt(&z));
+        for (grad, expected) in grads.iter().zip(expected) {
+            assert_approx_eq!(*grad, expected);
+        }
+
+        let t = [0.2, 0.7, 0.];
+        let loss = binary_cross_entropy(&z, &t);
+        let naive = z
+            .iter()
+            .zip(&t)
+            .map(|(z, &t)| {
+                let p = (1. + (-*z).exp()).recip();
+                -(t * p.ln() + (1. - t) * (1. - p).ln())
+            })
+            .sum::<Var>()
+            / 3.;
+        assert_approx_eq!(loss.val(), naive.val());
+        let (grads, expected) = (loss.grad().wrt(&z), naive.grad().wrt(&z));
+        for (grad, expected) in grads.iter().zip(expected) {
+            assert_approx_eq!(*grad, expected);
+        }
+    }
+}
@@ -0,0 +1,519 @@
+use crate::{
+    error::assert_same_tape,
+    linalg::{matmul, symmetric_eigen, transpose, Lu},
+    reduce::fused_dot,
+    Grad, Gradient, Op, Tape, TapeMismatchError, Var,
+};
+use std::ops::Index;
+
+/// Dense matrix of differentiable variables, stored in row-major order.
+///
+/// Each entry of a matrix product is recorded on the tape as a single fused node, rather than as
+/// a chain of scalar multiplications and additions.
+#[derive(Debug, Clone)]
+pub struct Mat<'a> {
+    data: Vec<Var<'a>>,
+    rows: usize,
+    cols: usize,
+}
+
+impl<'a> Mat<'a> {
+    /// Create a `rows x cols` matrix from variables given in row-major order.
+    pub fn new(rows: usize, cols: usize, data: Vec<Var<'a>>) -> Self {
+        assert_eq!(
+            data.len(),
+            rows * cols,
+            "data does not match the matrix shape"
+        );
+        Self { data, rows, cols }
+    }
+
+    /// Add the row-major values `vals` to `tape` as a `rows x cols` matrix of variables.
+    pub fn from_vals(tape: &'a Tape, rows: usize, cols: usize, vals: &[f64]) -> Self {
+        Self::new(rows, cols, tape.add_vars(vals))
+    }
+
+    pub fn rows(&self) -> usize {
+        self.rows
+    }
+
+    pub fn cols(&self) -> usize {
+        self.cols
+    }
+
+    /// Gets the shape of the matrix as `(rows, cols)`.
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Gets the entries of the matrix in row-major order.
+    pub fn as_slice(&self) -> &[Var<'a>] {
+        &self.data
+    }
+
+    /// Gets the values of the entries in row-major order.
+    pub fn vals(&self) -> Vec<f64> {
+        self.data.iter().map(|v| v.val).collect()
+    }
+
+    pub fn row(&self, i: usize) -> &[Var<'a>] {
+        &self.data[i * self.cols..(i + 1) * self.cols]
+    }
+
+    pub fn transpose(&self) -> Self {
+        let data = (0..self.cols)
+            .flat_map(|j| (0..self.rows).map(move |i| (i, j)))
+            .map(|(i, j)| self[(i, j)])
+            .collect();
+        Self::new(self.cols, self.rows, data)
+    }
+
+    /// Calculate the matrix product `self * other`.
+    pub fn matmul(&self, other: &Mat<'a>) -> Self {
+        assert_eq!(self.cols, other.rows, "incompatible shapes for matmul");
+        let data = (0..self.rows)
+            .flat_map(|i| (0..other.cols).map(move |j| (i, j)))
+            .map(|(i, j)| {
+                let pairs = (0..self.cols).map(|k| (self[(i, k)], other[(k, j)]));
+                fused_dot("matmul", self.data[0].tape, pairs)
+            })
+            .collect();
+        Self::new(self.rows, other.cols, data)
+    }
+
+    /// Calculate the matrix-vector product `self * v`.
+    pub fn matvec(&self, v: &[Var<'a>]) -> Vec<Var<'a>> {
+        assert_eq!(self.cols, v.len(), "incompatible shapes for matvec");
+        (0..self.rows)
+            .map(|i| {
+                fused_dot(
+                    "matvec",
+                    v[0].tape,
+                    self.row(i).iter().copied().zip(v.iter().copied()),
+                )
+            })
+            .collect()
+    }
+}
+
+/// Tape of a square matrix, checking that its entries share it for the function `name`. An empty
+/// matrix has no entries to get the tape from.
+fn square_tape<'a>(name: &'static str, a: &Mat<'a>) -> &'a Tape {
+    assert_eq!(a.rows, a.cols, "expected a square matrix");
+    let tape = a.data.first().expect("expected a non-empty matrix").tape;
+    for v in &a.data {
+        assert_same_tape(name, tape, v.tape);
+    }
+    tape
+}
+
+/// LU decomposition of a square matrix, checking that its entries share a tape.
+fn decompose<'a>(name: &'static str, a: &Mat<'a>) -> (&'a Tape, Lu) {
+    let tape = square_tape(name, a);
+    let lu = Lu::new(&a.vals(), a.rows).expect("matrix is singular");
+    (tape, lu)
+}
+
+/// Solve the linear system `Ax = b` for `x`.
+///
+/// The solution is computed with an LU decomposition of `a`. Rather than recording the elimination
+/// steps, the backward pass solves with the transposed system: if `λ = A^-T x̄`, then `b̄ = λ` and
+/// `Ā = -λ x^T`.
+///
+/// # Panics
+///
+/// Panics if `a` is not square, the shapes do not match or `a` is singular.
+pub fn solve<'a>(a: &Mat<'a>, b: &[Var<'a>]) -> Vec<Var<'a>> {
+    let n = a.rows;
+    assert_eq!(n, b.len(), "incompatible shapes for solve");
+    let (tape, lu) = decompose("solve", a);
+    for v in b {
+        assert_same_tape("solve", tape, v.tape);
+    }
+
+    let x = lu.solve(&b.iter().map(|v| v.val).collect::<Vec<_>>());
+    let inputs = a.data.iter().chain(b).map(|v| v.location).collect();
+    let xs = x.clone();
+    let start = tape.add_block(Op::Solve, &x, inputs, move |x_bar, input_bars| {
+        let lambda = lu.solve_transpose(x_bar);
+        let (a_bar, b_bar) = input_bars.split_at_mut(n * n);
+        for i in 0..n {
+            for j in 0..n {
+                a_bar[i * n + j] = -lambda[i] * xs[j];
+            }
+        }
+        b_bar.copy_from_slice(&lambda);
+    });
+    x.into_iter()
+        .enumerate()
+        .map(|(i, val)| Var {
+            val,
+            location: start + i,
+            tape,
+        })
+        .collect()
+}
+
+/// Natural logarithm of the absolute value of the determinant of `a`, with gradient `A^-T`. For a
+/// singular matrix it is negative infinity, and the gradient is the matrix of cofactors divided by
+/// zero, as for `ln` at zero.
+///
+/// # Panics
+///
+/// Panics if `a` is empty or not square.
+pub fn logdet<'a>(a: &Mat<'a>) -> Var<'a> {
+    determinant("logdet", a, Op::LogDet)
+}
+
+/// Determinant of `a`, with gradient `det(A) A^-T`. For a singular matrix it is zero, and the
+/// gradient is the matrix of cofactors, which is what `det(A) A^-T` extends to.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let a = Mat::from_vals(&tape, 2, 2, &[1., 2., 2., 4.]);
+/// let d = det(&a);
+/// assert_eq!(d.val(), 0.);
+/// assert_eq!(d.grad().wrt(&a), vec![4., -2., -2., 1.]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `a` is empty or not square.
+pub fn det<'a>(a: &Mat<'a>) -> Var<'a> {
+    determinant("det", a, Op::Det)
+}
+
+/// Record `Op::Det` or `Op::LogDet` of `a` as a single node depending on every entry, for the
+/// function `name`.
+fn determinant<'a>(name: &'static str, a: &Mat<'a>, op: Op) -> Var<'a> {
+    let tape = square_tape(name, a);
+    let (val, weights) = op.eval_nary(&a.vals());
+    let deps = a.data.iter().map(|v| v.location).collect::<Vec<_>>();
+    Var {
+        val,
+        location: tape.add_nary_node(op, val, &deps, &weights),
+        tape,
+    }
+}
+
+/// Inverse of `a`. The backward pass maps the adjoint `Ḡ` of the inverse to
+/// `Ā = -A^-T Ḡ A^-T`.
+///
+/// Prefer `solve` when the inverse is only used to multiply vectors, as it is cheaper and more
+/// accurate.
+///
+/// # Panics
+///
+/// Panics if `a` is not square or is singular.
+pub fn inv<'a>(a: &Mat<'a>) -> Mat<'a> {
+    let n = a.rows;
+    let (tape, lu) = decompose("inv", a);
+    let inverse = lu.inverse();
+    let inverse_t = transpose(&inverse, n, n);
+    let inputs = a.data.iter().map(|v| v.location).collect();
+    let start = tape.add_block(Op::Inv, &inverse, inputs, move |g_bar, a_bar| {
+        let prod = matmul(&matmul(&inverse_t, g_bar, n, n, n), &inverse_t, n, n, n);
+        for (a_bar, p) in a_bar.iter_mut().zip(prod) {
+            *a_bar = -p;
+        }
+    });
+    let data = inverse
+        .into_iter()
+        .enumerate()
+        .map(|(i, val)| Var {
+            val,
+            location: start + i,
+            tape,
+        })
+        .collect();
+    Mat::new(n, n, data)
+}
+
+/// Eigendecomposition of a symmetric matrix, as returned by `eigh`.
+#[derive(Debug, Clone)]
+pub struct Eigh<'a> {
+    /// Eigenvalues in ascending order.
+    pub values: Vec<Var<'a>>,
+    /// Unit eigenvectors, stored as the columns of the matrix.
+    pub vectors: Mat<'a>,
+    /// Whether two eigenvalues are too close to tell apart. The eigenvectors of a repeated
+    /// eigenvalue are not unique, so their gradients are only meaningful if the result does not
+    /// depend on the choice of basis for that eigenspace.
+    pub degenerate: bool,
+}
+
+/// Eigendecomposition of the symmetric matrix `a`, computed with Jacobi rotations.
+///
+/// Only the symmetric part of `a` is differentiated: the gradient of each off-diagonal pair
+/// `a[(i, j)]`, `a[(j, i)]` is split evenly between the two. Pairs of eigenvalues closer than a
+/// relative tolerance are treated as degenerate and do not contribute to the eigenvector
+/// gradients; `Eigh::degenerate` reports when this happened.
+///
+/// # Panics
+///
+/// Panics if `a` is not square.
+pub fn eigh<'a>(a: &Mat<'a>) -> Eigh<'a> {
+    let n = a.rows;
+    let tape = square_tape("eigh", a);
+    let (values, vectors) = symmetric_eigen(&a.vals(), n);
+
+    let scale = values.iter().fold(1_f64, |m, x| m.max(x.abs()));
+    let tol = f64::EPSILON.sqrt() * scale;
+    let degenerate = values.windows(2).any(|w| w[1] - w[0] <= tol);
+
+    let inputs = a.data.iter().map(|v| v.location).collect();
+    let (lambda, v) = (values.clone(), vectors.clone());
+    let outputs = values.iter().chain(&vectors).copied().collect::<Vec<_>>();
+    let start = tape.add_block(Op::Eigh, &outputs, inputs, move |out_bars, a_bar| {
+        let (lambda_bar, v_bar) = out_bars.split_at(n);
+        let mut m = matmul(&transpose(&v, n, n), v_bar, n, n, n);
+        for i in 0..n {
+            for j in 0..n {
+                let gap = lambda[j] - lambda[i];
+                m[i * n + j] = if i == j {
+                    lambda_bar[i]
+                } else if gap.abs() <= tol {
+                    0.
+                } else {
+                    m[i * n + j] / gap
+                };
+            }
+        }
+        let full = matmul(&matmul(&v, &m, n, n, n), &transpose(&v, n, n), n, n, n);
+        for i in 0..n {
+            for j in 0..n {
+                a_bar[i * n + j] = 0.5 * (full[i * n + j] + full[j * n + i]);
+            }
+        }
+    });
+    let mut outputs = outputs.into_iter().enumerate().map(|(i, val)| Var {
+        val,
+        location: start + i,
+        tape,
+    });
+    Eigh {
+        values: outputs.by_ref().take(n).collect(),
+        vectors: Mat::new(n, n, outputs.collect()),
+        degenerate,
+    }
+}
+
+impl<'a> Index<(usize, usize)> for Mat<'a> {
+    type Output = Var<'a>;
+
+    fn index(&self, (i, j): (usize, usize)) -> &Self::Output {
+        assert!(i < self.rows && j < self.cols, "index out of bounds");
+        &self.data[i * self.cols + j]
+    }
+}
+
+/// Calculate the gradient with respect to all entries of the matrix `m`, in row-major order.
+impl<'a> Gradient<&Mat<'a>, Vec<f64>> for Vec<f64> {
+    fn wrt(&self, m: &Mat<'a>) -> Vec<f64> {
+        self.wrt(m.as_slice())
+    }
+}
+
+/// Calculate the gradient with respect to all entries of the matrix `m`, in row-major order.
+impl<'a> Gradient<&Mat<'a>, Vec<f64>> for Grad<'a> {
+    fn wrt(&self, m: &Mat<'a>) -> Vec<f64> {
+        self.wrt(m.as_slice())
+    }
+}
+
+/// Like `solve`, but returns an error if the variables are on different tapes.
+pub fn try_solve<'a>(a: &Mat<'a>, b: &[Var<'a>]) -> Result<Vec<Var<'a>>, TapeMismatchError> {
+    TapeMismatchError::check_all("solve", a.data.iter().chain(b)).map(|_| solve(a, b))
+}
+
+/// Like `det`, but returns an error if the variables are on different tapes.
+pub fn try_det<'a>(a: &Mat<'a>) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("det", &a.data).map(|_| det(a))
+}
+
+/// Like `logdet`, but returns an error if the variables are on different tapes.
+pub fn try_logdet<'a>(a: &Mat<'a>) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("logdet", &a.data).map(|_| logdet(a))
+}
+
+/// Like `inv`, but returns an error if the variables are on different tapes.
+pub fn try_inv<'a>(a: &Mat<'a>) -> Result<Mat<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("inv", &a.data).map(|_| inv(a))
+}
+
+/// Like `eigh`, but returns an error if the variables are on different tapes.
+pub fn try_eigh<'a>(a: &Mat<'a>) -> Result<Eigh<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("eigh", &a.data).map(|_| eigh(a))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_matmul() {
+        let g = Tape::new();
+        let a = Mat::from_vals(&g, 2, 3, &[1., 2., 3., 4., 5., 6.]);
+        let b = Mat::from_vals(&g, 3, 2, &[-1., 0.5, 2., 1., 0., -3.]);
+        let c = a.matmul(&b);
+        assert_eq!(c.shape(), (2, 2));
+        assert_eq!(c.vals(), vec![3., -6.5, 6., -11.]);
+
+        // d(sum(A B)) / dA_ik = sum_j B_kj, d(sum(A B)) / dB_kj = sum_i A_ik
+        let total = c.as_slice().iter().copied().sum::<Var>();
+        let grads = total.grad();
+        for (grad, expected) in grads.wrt(&a).iter().zip([-0.5, 3., -3., -0.5, 3., -3.]) {
+            assert_approx_eq!(*grad, expected);
+        }
+        for (grad, expected) in grads.wrt(&b).iter().zip([5., 5., 7., 7., 9., 9.]) {
+            assert_approx_eq!(*grad, expected);
+        }
+
+        let v = g.add_vars(&[1., -1., 2.]);
+        let w = a.transpose().transpose().matvec(&v);
+        assert_eq!(w[0].val(), 5.);
+        assert_eq!(w[1].grad().wrt(&v), vec![4., 5., 6.]);
+    }
+
+    #[test]
+    fn test_solve() {
+        let g = Tape::new();
+        let a = Mat::from_vals(&g, 2, 2, &[2., 1., -1., 3.]);
+        let b = g.add_vars(&[4., 5.]);
+        let x = solve(&a, &b);
+        assert_approx_eq!(x[0].val(), 1.);
+        assert_approx_eq!(x[1].val(), 2.);
+        let res = x[0] - 3. * x[1];
+
+        // Cramer's rule with scalar operations
+        let (p, q, r, s) = (a[(0, 0)], a[(0, 1)], a[(1, 0)], a[(1, 1)]);
+        let det = p * s - q * r;
+        let expected = (s * b[0] - q * b[1]) / det - 3. * (p * b[1] - r * b[0]) / det;
+        assert_approx_eq!(res.val(), expected.val());
+
+        let grads = res.grad();
+        let expected_grads = expected.grad();
+        for v in a.as_slice().iter().chain(&b) {
+            assert_approx_eq!(grads.wrt(v), expected_grads.wrt(v));
+        }
+    }
+
+    #[test]
+    fn test_det() {
+        let g = Tape::new();
+        let a = Mat::from_vals(&g, 3, 3, &[0., 2., 1., 1., 1., 1., 4., -1., 3.]);
+        let res = det(&a);
+        assert_approx_eq!(res.val(), -3.);
+        // the gradient of the determinant is the cofactor matrix
+        let cofactors = [4., 1., -5., -7., -4., 8., 1., 1., -2.];
+        for (grad, expected) in res.grad().wrt(&a).iter().zip(cofactors) {
+            assert_approx_eq!(*grad, expected);
+        }
+
+        let res = logdet(&a);
+        assert_approx_eq!(res.val(), 3_f64.ln());
+        for (grad, expected) in res.grad().wrt(&a).iter().zip(cofactors) {
+            assert_approx_eq!(*grad, expected / -3.);
+        }
+
+        // singular matrices have a zero determinant with the cofactors as its gradient
+        let g = Tape::new();
+        let s = Mat::from_vals(&g, 3, 3, &[1., 2., 3., 2., 4., 6., 0., 1., 1.]);
+        let res = det(&s);
+        assert_eq!(res.val(), 0.);
+        let cofactors = [-2., -2., 2., 1., 1., -1., 0., 0., 0.];
+        for (grad, expected) in res.grad().wrt(&s).iter().zip(cofactors) {
+            assert_approx_eq!(*grad + 1., expected + 1.);
+        }
+        assert_eq!(logdet(&s).val(), f64::NEG_INFINITY);
+
+        // and so replaying through a singular point succeeds
+        let g = Tape::new();
+        let a = Mat::from_vals(&g, 2, 2, &[2., 1., -1., 3.]);
+        let res = det(&a);
+        let vals = g.replay(&[1., 2., 2., 4.]).unwrap();
+        assert_eq!(vals.wrt(&res), 0.);
+        assert_eq!(res.grad().wrt(&a), vec![4., -2., -2., 1.]);
+        let vals = g.replay(&[2., 1., -1., 3.]).unwrap();
+        assert_approx_eq!(vals.wrt(&res), 7.);
+    }
+
+    #[test]
+    #[should_panic(expected = "non-empty")]
+    fn test_det_empty() {
+        let _ = det(&Mat::new(0, 0, vec![]));
+    }
+
+    #[test]
+    fn test_inv() {
+        let g = Tape::new();
+        let a = Mat::from_vals(&g, 2, 2, &[2., 1., -1., 3.]);
+        let a_inv = inv(&a);
+        for (val, expected) in a_inv
+            .vals()
+            .iter()
+            .zip([3. / 7., -1. / 7., 1. / 7., 2. / 7.])
+        {
+            assert_approx_eq!(*val, expected);
+        }
+        let res = a_inv[(0, 0)] + 2. * a_inv[(1, 0)];
+
+        let (p, q, r, s) = (a[(0, 0)], a[(0, 1)], a[(1, 0)], a[(1, 1)]);
+        let det = p * s - q * r;
+        let expected = s / det + 2. * -r / det;
+        assert_approx_eq!(res.val(), expected.val());
+        let grads = res.grad();
+        let expected_grads = expected.grad();
+        for v in a.as_slice() {
+            assert_approx_eq!(grads.wrt(v), expected_grads.wrt(v));
+        }
+    }
+
+    #[test]
+    fn test_eigh() {
+        let vals = [4., 1., -2., 1., 2., 0.5, -2., 0.5, 3.];
+        // sign-invariant function of the spectrum and the eigenvectors
+        fn f<'a>(g: &'a Tape, vals: &[f64]) -> (Mat<'a>, Var<'a>, bool) {
+            let a = Mat::from_vals(g, 3, 3, vals);
+            let eig = eigh(&a);
+            let v = &eig.vectors;
+            let res = eig.values[2] * 2. + v[(0, 0)] * v[(1, 0)] + v[(2, 1)].powi(2);
+            (a, res, eig.degenerate)
+        }
+        let g = Tape::new();
+        let (a, res, degenerate) = f(&g, &vals);
+        assert!(!degenerate);
+        let grads = res.grad().wrt(&a);
+
+        let eps = 1e-6;
+        for i in 0..3 {
+            for j in i..3 {
+                let mut plus = vals;
+                let mut minus = vals;
+                plus[i * 3 + j] += eps;
+                minus[i * 3 + j] -= eps;
+                if i != j {
+                    plus[j * 3 + i] += eps;
+                    minus[j * 3 + i] -= eps;
+                }
+                let g = Tape::new();
+                let diff = (f(&g, &plus).1.val() - f(&g, &minus).1.val()) / (2. * eps);
+                let grad = if i == j {
+                    grads[i * 3 + j]
+                } else {
+                    grads[i * 3 + j] + grads[j * 3 + i]
+                };
+                assert!((grad - diff).abs() < 1e-6, "{} != {}", grad, diff);
+            }
+        }
+
+        let g = Tape::new();
+        let a = Mat::from_vals(&g, 2, 2, &[2., 0., 0., 2.]);
+        let eig = eigh(&a);
+        assert!(eig.degenerate);
+        assert_eq!(eig.values[0].grad().wrt(&a), vec![1., 0., 0., 0.]);
+    }
+}
@@ -0,0 +1,520 @@
+//! Minimal neural network layers, enabled with the `nn` feature.
+//!
+//! The parameters of a layer are variables on a tape, so the gradient of a loss with respect to a
+//! layer (or a whole model) is available with `Gradient::wrt`, flattened in the same order as
+//! `params`. Between training steps, update the parameter values, clear the tape and `rebind` the
+//! model to it so that the tape does not keep growing.
+//!
+//! ```rust
+//! use reverse::*;
+//! use reverse::nn::{Activation, Dense, Sequential};
+//!
+//! let tape = Tape::new();
+//! let mut model = Sequential::new(vec![
+//!     Dense::new(&tape, 1, 8, Activation::Tanh, 1),
+//!     Dense::new(&tape, 8, 1, Activation::Identity, 2),
+//! ]);
+//! let data = [(-1., 1.), (0., 0.), (1., 1.)];
+//! let mut losses = vec![];
+//! for _ in 0..200 {
+//!     let loss = data
+//!         .iter()
+//!         .map(|&(x, y)| (model.forward(&[tape.add_var(x)])[0] - y).powi(2))
+//!         .sum::<Var>();
+//!     losses.push(loss.val());
+//!     let grads = loss.grad().wrt(&model);
+//!     let vals = model.param_vals();
+//!     let updated = vals.iter().zip(grads).map(|(v, g)| v - 0.05 * g).collect::<Vec<_>>();
+//!     tape.clear();
+//!     model = model.rebind(&tape, &updated);
+//! }
+//! assert!(losses[199] < 0.1 * losses[0]);
+//! ```
+
+use crate::{error::assert_same_tape, rng::Rng, Grad, Gradient, Mat, Op, Tape, Var};
+
+/// Elementwise nonlinearity applied to the output of a layer.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Activation {
+    Identity,
+    Relu,
+    Sigmoid,
+    Tanh,
+}
+
+impl Activation {
+    pub fn apply<'a>(&self, x: Var<'a>) -> Var<'a> {
+        match self {
+            Self::Identity => x,
+            Self::Relu => x.unary(Op::Relu),
+            Self::Sigmoid => x.unary(Op::Sigmoid),
+            Self::Tanh => x.tanh(),
+        }
+    }
+}
+
+/// Record `b + sum(w_i * x_i)` over `pairs` of weights and inputs as a single fused node, for the
+/// function `name`.
+fn affine<'a>(
+    name: &'static str,
+    tape: &'a Tape,
+    b: Var<'a>,
+    pairs: impl IntoIterator<Item = (Var<'a>, Var<'a>)>,
+) -> Var<'a> {
+    let mut vals = vec![b.val];
+    let mut deps = vec![b.location];
+    for (w, x) in pairs {
+        assert_same_tape(name, tape, w.tape);
+        assert_same_tape(name, tape, x.tape);
+        vals.extend([w.val, x.val]);
+        deps.extend([w.location, x.location]);
+    }
+    let (val, partials) = Op::Affine.eval_nary(&vals);
+    Var {
+        val,
+        location: tape.add_nary_node(Op::Affine, val, &deps, &partials),
+        tape,
+    }
+}
+
+/// Pre-activation of gate row `i` of the recurrent cell `name`, `b[i] + W[i] x + U[i] h`, recorded
+/// as a single fused node.
+fn gate<'a>(
+    name: &'static str,
+    i: usize,
+    x: &[Var<'a>],
+    h: &[Var<'a>],
+    w: &Mat<'a>,
+    u: &Mat<'a>,
+    b: &[Var<'a>],
+) -> Var<'a> {
+    let pairs = w.row(i).iter().zip(x).chain(u.row(i).iter().zip(h));
+    affine(name, b[i].tape, b[i], pairs.map(|(&w, &x)| (w, x)))
+}
+
+/// Check the shapes of the weights of a recurrent cell with `gates` gates, returning the hidden
+/// size.
+fn check_cell(gates: usize, x: &[Var], h: &[Var], w: &Mat, u: &Mat, b: &[Var]) -> usize {
+    let hidden = h.len();
+    assert_eq!(
+        w.shape(),
+        (gates * hidden, x.len()),
+        "input weights do not match the cell shape"
+    );
+    assert_eq!(
+        u.shape(),
+        (gates * hidden, hidden),
+        "recurrent weights do not match the cell shape"
+    );
+    assert_eq!(
+        b.len(),
+        gates * hidden,
+        "biases do not match the cell shape"
+    );
+    hidden
+}
+
+/// One step of an LSTM cell with input `x`, hidden state `h` and cell state `c`, returning the
+/// new hidden and cell states.
+///
+/// With hidden size `n`, the input weights `w` are `4n x inputs`, the recurrent weights `u` are
+/// `4n x n` and the biases `b` have `4n` entries, stacked for the input, forget, cell and output
+/// gates in that order. Each gate pre-activation `b + W x + U h` is recorded as a single fused
+/// node, and the states are updated as
+///
+/// `c' = f * c + i * g`, `h' = o * tanh(c')`.
+///
+/// ```rust
+/// use reverse::*;
+/// use reverse::nn::lstm_cell;
+///
+/// let tape = Tape::new();
+/// let w = Mat::from_vals(&tape, 4, 1, &[0.5, -0.3, 0.8, 0.2]);
+/// let u = Mat::from_vals(&tape, 4, 1, &[0.1, 0.4, -0.6, 0.3]);
+/// let b = tape.add_vars(&[0., 1., 0., 0.]);
+/// let (mut h, mut c) = (vec![tape.add_var(0.)], vec![tape.add_var(0.)]);
+/// for x in [1., -0.5, 2.] {
+///     let (h_next, c_next) = lstm_cell(&[tape.add_var(x)], &h, &c, &w, &u, &b);
+///     h = h_next;
+///     c = c_next;
+/// }
+/// assert!(h[0].val().abs() < 1.);
+/// let grad = h[0].grad().wrt(w.as_slice());
+/// assert_eq!(grad.len(), 4);
+/// ```
+///
+/// # Panics
+///
+/// Panics if the shapes do not match, or the variables are on different tapes.
+pub fn lstm_cell<'a>(
+    x: &[Var<'a>],
+    h: &[Var<'a>],
+    c: &[Var<'a>],
+    w: &Mat<'a>,
+    u: &Mat<'a>,
+    b: &[Var<'a>],
+) -> (Vec<Var<'a>>, Vec<Var<'a>>) {
+    let n = check_cell(4, x, h, w, u, b);
+    assert_eq!(c.len(), n, "cell state does not match the hidden state");
+    let gate = |k: usize, j: usize| gate("lstm_cell", k * n + j, x, h, w, u, b);
+    (0..n)
+        .map(|j| {
+            let i = Activation::Sigmoid.apply(gate(0, j));
+            let f = Activation::Sigmoid.apply(gate(1, j));
+            let g = gate(2, j).tanh();
+            let o = Activation::Sigmoid.apply(gate(3, j));
+            let c = f * c[j] + i * g;
+            (o * c.tanh(), c)
+        })
+        .unzip()
+}
+
+/// One step of a GRU cell with input `x` and hidden state `h`, returning the new hidden state.
+///
+/// With hidden size `n`, the input weights `w` are `3n x inputs`, the recurrent weights `u` are
+/// `3n x n` and the biases `b` have `3n` entries, stacked for the reset, update and candidate
+/// gates in that order. Each gate pre-activation is recorded as a single fused node, and the state
+/// is updated as
+///
+/// `r = sigmoid(W_r x + U_r h + b_r)`, `z = sigmoid(W_z x + U_z h + b_z)`,
+/// `n = tanh(W_n x + U_n (r * h) + b_n)`, `h' = (1 - z) * n + z * h`.
+///
+/// # Panics
+///
+/// Panics if the shapes do not match, or the variables are on different tapes.
+pub fn gru_cell<'a>(
+    x: &[Var<'a>],
+    h: &[Var<'a>],
+    w: &Mat<'a>,
+    u: &Mat<'a>,
+    b: &[Var<'a>],
+) -> Vec<Var<'a>> {
+    let n = check_cell(3, x, h, w, u, b);
+    let r = (0..n)
+        .map(|j| Activation::Sigmoid.apply(gate("gru_cell", j, x, h, w, u, b)) * h[j])
+        .collect::<Vec<_>>();
+    (0..n)
+        .map(|j| {
+            let z = Activation::Sigmoid.apply(gate("gru_cell", n + j, x, h, w, u, b));
+            let candidate = gate("gru_cell", 2 * n + j, x, &r, w, u, b).tanh();
+            (1. - z) * candidate + z * h[j]
+        })
+        .collect()
+}
+
+/// Fully connected layer computing `activation(W x + b)`.
+#[derive(Debug, Clone)]
+pub struct Dense<'a> {
+    /// Weights in row-major order (one row per output), followed by the biases.
+    params: Vec<Var<'a>>,
+    inputs: usize,
+    outputs: usize,
+    activation: Activation,
+}
+
+impl<'a> Dense<'a> {
+    /// Create a layer with weights drawn from the Glorot uniform distribution using `seed`, and
+    /// zero biases.
+    pub fn new(
+        tape: &'a Tape,
+        inputs: usize,
+        outputs: usize,
+        activation: Activation,
+        seed: u64,
+    ) -> Self {
+        let mut rng = Rng::new(seed);
+        let limit = (6. / (inputs + outputs) as f64).sqrt();
+        let params = (0..inputs * outputs)
+            .map(|_| (2. * rng.uniform() - 1.) * limit)
+            .chain(std::iter::repeat(0.).take(outputs))
+            .collect::<Vec<_>>();
+        Self::from_params(tape, inputs, outputs, activation, &params)
+    }
+
+    /// Create a layer from flattened parameters, laid out as in `params`.
+    pub fn from_params(
+        tape: &'a Tape,
+        inputs: usize,
+        outputs: usize,
+        activation: Activation,
+        params: &[f64],
+    ) -> Self {
+        assert_eq!(
+            params.len(),
+            (inputs + 1) * outputs,
+            "wrong number of parameters for the layer shape"
+        );
+        Self {
+            params: tape.add_vars(params),
+            inputs,
+            outputs,
+            activation,
+        }
+    }
+
+    pub fn inputs(&self) -> usize {
+        self.inputs
+    }
+
+    pub fn outputs(&self) -> usize {
+        self.outputs
+    }
+
+    /// Gets the parameters: the weights in row-major order (one row per output), followed by the
+    /// biases.
+    pub fn params(&self) -> &[Var<'a>] {
+        &self.params
+    }
+
+    /// Gets the values of the parameters, in the same order as `params`.
+    pub fn param_vals(&self) -> Vec<f64> {
+        self.params.iter().map(|v| v.val).collect()
+    }
+
+    /// Add the parameters `params` to `tape` as a layer of the same shape and activation.
+    pub fn rebind<'b>(&self, tape: &'b Tape, params: &[f64]) -> Dense<'b> {
+        Dense::from_params(tape, self.inputs, self.outputs, self.activation, params)
+    }
+
+    /// Apply the layer to `x`. Each pre-activation output is recorded as a single fused node.
+    pub fn forward(&self, x: &[Var<'a>]) -> Vec<Var<'a>> {
+        assert_eq!(x.len(), self.inputs, "wrong number of inputs for the layer");
+        let tape = self.params[0].tape;
+        let (weights, bias) = self.params.split_at(self.inputs * self.outputs);
+        weights
+            .chunks(self.inputs)
+            .zip(bias)
+            .map(|(row, &b)| {
+                let z = affine(
+                    "Dense::forward",
+                    tape,
+                    b,
+                    row.iter().copied().zip(x.iter().copied()),
+                );
+                self.activation.apply(z)
+            })
+            .collect()
+    }
+}
+
+/// Layers applied one after another.
+#[derive(Debug, Clone)]
+pub struct Sequential<'a> {
+    layers: Vec<Dense<'a>>,
+}
+
+impl<'a> Sequential<'a> {
+    pub fn new(layers: Vec<Dense<'a>>) -> Self {
+        for pair in layers.windows(2) {
+            assert_eq!(
+                pair[0].outputs, pair[1].inputs,
+                "consecutive layers have mismatched sizes"
+            );
+        }
+        Self { layers }
+    }
+
+    pub fn layers(&self) -> &[Dense<'a>] {
+        &self.layers
+    }
+
+    /// Gets the parameters of every layer, flattened in order.
+    pub fn params(&self) -> Vec<Var<'a>> {
+        self.layers
+            .iter()
+            .flat_map(|l| l.params.iter().copied())
+            .collect()
+    }
+
+    /// Gets the values of the parameters, in the same order as `params`.
+    pub fn param_vals(&self) -> Vec<f64> {
+        self.layers.iter().flat_map(|l| l.param_vals()).collect()
+    }
+
+    /// Add the flattened parameters `params` to `tape` as a model of the same architecture.
+    pub fn rebind<'b>(&self, tape: &'b Tape, params: &[f64]) -> Sequential<'b> {
+        assert_eq!(
+            params.len(),
+            self.layers.iter().map(|l| l.params.len()).sum::<usize>(),
+            "wrong number of parameters for the model"
+        );
+        let mut rest = params;
+        let layers = self
+            .layers
+            .iter()
+            .map(|l| {
+                let (head, tail) = rest.split_at(l.params.len());
+                rest = tail;
+                l.rebind(tape, head)
+            })
+            .collect();
+        Sequential { layers }
+    }
+
+    pub fn forward(&self, x: &[Var<'a>]) -> Vec<Var<'a>> {
+        self.layers
+            .iter()
+            .fold(x.to_vec(), |x, layer| layer.forward(&x))
+    }
+}
+
+/// Calculate the gradient with respect to the parameters of the layer `l`, in the order of
+/// `Dense::params`.
+impl<'a> Gradient<&Dense<'a>, Vec<f64>> for Vec<f64> {
+    fn wrt(&self, l: &Dense<'a>) -> Vec<f64> {
+        self.wrt(l.params())
+    }
+}
+
+/// Calculate the gradient with respect to the parameters of the layer `l`, in the order of
+/// `Dense::params`.
+impl<'a> Gradient<&Dense<'a>, Vec<f64>> for Grad<'a> {
+    fn wrt(&self, l: &Dense<'a>) -> Vec<f64> {
+        self.wrt(l.params())
+    }
+}
+
+/// Calculate the gradient with respect to the parameters of the model `m`, in the order of
+/// `Sequential::params`.
+impl<'a> Gradient<&Sequential<'a>, Vec<f64>> for Vec<f64> {
+    fn wrt(&self, m: &Sequential<'a>) -> Vec<f64> {
+        self.wrt(&m.params())
+    }
+}
+
+/// Calculate the gradient with respect to the parameters of the model `m`, in the order of
+/// `Sequential::params`.
+impl<'a> Gradient<&Sequential<'a>, Vec<f64>> for Grad<'a> {
+    fn wrt(&self, m: &Sequential<'a>) -> Vec<f64> {
+        self.wrt(&m.params())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_dense() {
+        let g = Tape::new();
+        let layer = Dense::from_params(&g, 2, 2, Activation::Relu, &[1., -1., 2., 0.5, 0.25, -7.]);
+        let x = g.add_vars(&[3., 1.]);
+        let y = layer.forward(&x);
+        assert_eq!(y[0].val(), 2.25);
+        assert_eq!(y[1].val(), 0.);
+        let grads = y[0].grad();
+        assert_eq!(grads.wrt(&layer), vec![3., 1., 0., 0., 1., 0.]);
+        assert_eq!(grads.wrt(&x), vec![1., -1.]);
+
+        let sigmoid = Activation::Sigmoid.apply(g.add_var(0.));
+        assert_eq!(sigmoid.val(), 0.5);
+        assert_eq!(sigmoid.grad().wrt(&sigmoid), 1.);
+
+        let init = Dense::new(&g, 3, 2, Activation::Identity, 7);
+        let limit = 6_f64.sqrt() / 5_f64.sqrt();
+        let vals = init.param_vals();
+        assert!(vals[..6].iter().all(|w| w.abs() < limit));
+        assert_eq!(vals[6..], [0., 0.]);
+        assert_eq!(
+            init.param_vals(),
+            Dense::new(&g, 3, 2, Activation::Identity, 7).param_vals()
+        );
+    }
+
+    fn sigmoid(z: Var) -> Var {
+        (1. + (-z).exp()).recip()
+    }
+
+    #[test]
+    fn test_recurrent_cells() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.5, -1.]);
+        let h = g.add_vars(&[0.2, 0.]);
+        let c = g.add_vars(&[-0.3, 0.4]);
+        let w = Mat::from_vals(
+            &g,
+            8,
+            2,
+            &(0..16).map(|i| (i as f64 * 0.37).sin()).collect::<Vec<_>>(),
+        );
+        let u = Mat::from_vals(
+            &g,
+            8,
+            2,
+            &(0..16).map(|i| (i as f64 * 0.73).cos()).collect::<Vec<_>>(),
+        );
+        let b = g.add_vars(&[0.1, 0., 1., 1., -0.2, 0.3, 0., 0.]);
+        let (h1, c1) = lstm_cell(&x, &h, &c, &w, &u, &b);
+
+        // the same step written out with scalar operations
+        let pre = |i: usize| {
+            let mut z = b[i];
+            for j in 0..2 {
+                z = z + w[(i, j)] * x[j] + u[(i, j)] * h[j];
+            }
+            z
+        };
+        for j in 0..2 {
+            let c_expected = sigmoid(pre(2 + j)) * c[j] + sigmoid(pre(j)) * pre(4 + j).tanh();
+            let h_expected = sigmoid(pre(6 + j)) * c_expected.tanh();
+            assert_approx_eq!(c1[j].val(), c_expected.val());
+            assert_approx_eq!(h1[j].val(), h_expected.val());
+            let (grads, expected) = (h1[j].grad(), h_expected.grad());
+            for (a, e) in grads
+                .wrt(w.as_slice())
+                .iter()
+                .zip(expected.wrt(w.as_slice()))
+            {
+                assert_approx_eq!(*a, e);
+            }
+            for (a, e) in grads.wrt(&b).iter().zip(expected.wrt(&b)) {
+                assert_approx_eq!(*a, e);
+            }
+        }
+
+        let w = Mat::new(6, 2, w.as_slice()[..12].to_vec());
+        let u = Mat::new(6, 2, u.as_slice()[..12].to_vec());
+        let h1 = gru_cell(&x, &h, &w, &u, &b[..6]);
+        for j in 0..2 {
+            let r = [sigmoid(pre(0)) * h[0], sigmoid(pre(1)) * h[1]];
+            let mut candidate = b[4 + j];
+            for k in 0..2 {
+                candidate = candidate + w[(4 + j, k)] * x[k] + u[(4 + j, k)] * r[k];
+            }
+            let z = sigmoid(pre(2 + j));
+            let expected = (1. - z) * candidate.tanh() + z * h[j];
+            assert_approx_eq!(h1[j].val(), expected.val());
+            for (a, e) in h1[j].grad().wrt(&x).iter().zip(expected.grad().wrt(&x)) {
+                assert_approx_eq!(*a, e);
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequential() {
+        let g = Tape::new();
+        let model = Sequential::new(vec![
+            Dense::from_params(&g, 1, 2, Activation::Identity, &[1., 2., 0., 1.]),
+            Dense::from_params(&g, 2, 1, Activation::Tanh, &[0.5, -0.5, 0.]),
+        ]);
+        let x = g.constant(2.);
+        let y = model.forward(&[x])[0];
+        assert_approx_eq!(y.val(), (-1.5_f64).tanh());
+        let grads = y.grad().wrt(&model);
+        assert_eq!(grads.len(), 7);
+        let d = 1. - (-1.5_f64).tanh().powi(2);
+        assert_approx_eq!(grads[4], 2. * d);
+        assert_approx_eq!(grads[5], 5. * d);
+
+        let params = model
+            .params()
+            .iter()
+            .map(|p| p.val() * 2.)
+            .collect::<Vec<_>>();
+        g.clear();
+        let model = model.rebind(&g, &params);
+        assert_eq!(g.len(), 7);
+        assert_eq!(model.param_vals(), params);
+    }
+}
@@ -0,0 +1,175 @@
+//! Layer and batch normalization, recorded as blocks.
+//!
+//! Both standardize groups of entries to zero mean and unit variance, `(x - mean) / sqrt(var +
+//! eps)` with the biased variance. Recording a normalization as one block avoids the deep chain of
+//! nodes that composing it from `mean`, `variance` and `sqrt` would create, and the backward pass
+//! uses the compact formula
+//!
+//! `x_bar = (y_bar - mean(y_bar) - y * mean(y_bar * y)) / sqrt(var + eps)`,
+//!
+//! which is cheaper and more accurate than differentiating through the statistics.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let xs = tape.add_vars(&[1., 2., 3., 6.]);
+//! let ys = layer_norm(&xs, 0.);
+//! assert!(mean(&ys).val().abs() < 1e-12);
+//! assert!((variance(&ys).val() - 1.).abs() < 1e-12);
+//! // shifting every entry does not change the result
+//! assert!(ys[0].grad().wrt(&xs).iter().sum::<f64>().abs() < 1e-12);
+//! ```
+
+use crate::{error::assert_same_tape, Mat, Op, Tape, Var};
+
+/// Standardize `x`, returning the result and the reciprocal of the standard deviation.
+fn standardize(x: &[f64], eps: f64) -> (Vec<f64>, f64) {
+    let n = x.len() as f64;
+    let mean = x.iter().sum::<f64>() / n;
+    let var = x.iter().map(|x| (x - mean).powi(2)).sum::<f64>() / n;
+    let inv_std = 1. / (var + eps).sqrt();
+    (x.iter().map(|x| (x - mean) * inv_std).collect(), inv_std)
+}
+
+/// Map the adjoints `y_bar` of the standardized entries `y` to adjoints of the inputs.
+fn standardize_backward(y: &[f64], inv_std: f64, y_bar: &[f64]) -> Vec<f64> {
+    let n = y.len() as f64;
+    let mean_bar = y_bar.iter().sum::<f64>() / n;
+    let mean_dot = y.iter().zip(y_bar).map(|(y, g)| y * g).sum::<f64>() / n;
+    y.iter()
+        .zip(y_bar)
+        .map(|(y, g)| inv_std * (g - mean_bar - y * mean_dot))
+        .collect()
+}
+
+/// Record the outputs `vals` computed from `xs` as a block with the given backward pass, for the
+/// function `name`.
+fn record<'a>(
+    name: &'static str,
+    xs: &[Var<'a>],
+    vals: Vec<f64>,
+    backward: impl Fn(&[f64], &mut [f64]) + Send + Sync + 'static,
+) -> Vec<Var<'a>> {
+    let tape: &'a Tape = xs.first().expect("cannot normalize an empty slice").tape;
+    let inputs = xs
+        .iter()
+        .map(|x| {
+            assert_same_tape(name, tape, x.tape);
+            x.location
+        })
+        .collect();
+    let start = tape.add_block(Op::Normalize, &vals, inputs, backward);
+    vals.into_iter()
+        .enumerate()
+        .map(|(i, val)| Var {
+            val,
+            location: start + i,
+            tape,
+        })
+        .collect()
+}
+
+/// Normalize `xs` to zero mean and unit variance, `(x - mean) / sqrt(var + eps)`. The whole
+/// normalization is recorded as a single block. A learned scale and shift can be applied to the
+/// result with `elementwise`.
+///
+/// # Panics
+///
+/// Panics if `xs` is empty, `eps` is negative, or the variables are on different tapes.
+pub fn layer_norm<'a>(xs: &[Var<'a>], eps: f64) -> Vec<Var<'a>> {
+    assert!(eps >= 0., "eps must not be negative");
+    let x = xs.iter().map(|x| x.val).collect::<Vec<_>>();
+    let (y, inv_std) = standardize(&x, eps);
+    record("layer_norm", xs, y.clone(), move |out_bars, in_bars| {
+        in_bars.copy_from_slice(&standardize_backward(&y, inv_std, out_bars));
+    })
+}
+
+/// Normalize every column of `batch`, whose rows are the samples, to zero mean and unit variance
+/// over the batch, using the statistics of the batch itself as in training. The whole batch is
+/// recorded as a single block.
+///
+/// # Panics
+///
+/// Panics if `batch` is empty, `eps` is negative, or the variables are on different tapes.
+pub fn batch_norm<'a>(batch: &Mat<'a>, eps: f64) -> Mat<'a> {
+    assert!(eps >= 0., "eps must not be negative");
+    let (rows, cols) = batch.shape();
+    let x = batch.vals();
+    let column = move |x: &[f64], j: usize| (0..rows).map(|i| x[i * cols + j]).collect::<Vec<_>>();
+    let columns = (0..cols)
+        .map(|j| standardize(&column(&x, j), eps))
+        .collect::<Vec<_>>();
+    let mut vals = vec![0.; rows * cols];
+    for (j, (y, _)) in columns.iter().enumerate() {
+        for (i, y) in y.iter().enumerate() {
+            vals[i * cols + j] = *y;
+        }
+    }
+    let data = record(
+        "batch_norm",
+        batch.as_slice(),
+        vals,
+        move |out_bars, in_bars| {
+            for (j, (y, inv_std)) in columns.iter().enumerate() {
+                let x_bar = standardize_backward(y, *inv_std, &column(out_bars, j));
+                for (i, bar) in x_bar.into_iter().enumerate() {
+                    in_bars[i * cols + j] = bar;
+                }
+            }
+        },
+    );
+    Mat::new(rows, cols, data)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{gradcheck, mean, variance};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_layer_norm() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[0.5, -1., 2., 4., 0.]);
+        let ys = layer_norm(&xs, 0.);
+        assert_eq!(g.len(), 10);
+        assert!(mean(&ys).val().abs() < 1e-12);
+        assert_approx_eq!(variance(&ys).val(), 1.);
+
+        for eps in [0., 0.1] {
+            let check = gradcheck(
+                |x| {
+                    let y = layer_norm(x, eps);
+                    y[0] * 3. + y[1] * y[2] - y[4].powi(3)
+                },
+                &[0.5, -1., 2., 4., 0.],
+                1e-6,
+            );
+            assert!(check.passed(), "{}", check);
+        }
+    }
+
+    #[test]
+    fn test_batch_norm() {
+        let g = Tape::new();
+        let batch = Mat::from_vals(&g, 3, 2, &[1., 10., 2., 20., 6., 60.]);
+        let normed = batch_norm(&batch, 0.);
+        // the columns are proportional, so they normalize to the same values
+        for i in 0..3 {
+            assert_approx_eq!(normed.row(i)[0].val(), normed.row(i)[1].val());
+        }
+
+        let check = gradcheck(
+            |x| {
+                let y = batch_norm(&Mat::new(3, 2, x.to_vec()), 1e-3);
+                let y = y.as_slice();
+                y[0] * y[1] + y[2] * 2. - y[5].powi(2) + y[3] * y[4]
+            },
+            &[1., 10., 2., 25., 6., 60.],
+            1e-6,
+        );
+        assert!(check.passed(), "{}", check);
+    }
+}
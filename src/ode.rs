@@ -0,0 +1,304 @@
+//! Integrators for systems of ordinary differential equations `dy/dt = f(t, y)` whose initial
+//! conditions and parameters are variables.
+//!
+//! `rk4` and `dopri5` record every step of the integration on the tape, which is simple but uses
+//! memory proportional to the number of steps. `rk4_adjoint` records the whole integration as a
+//! single block instead, keeping only the state at each step, and computes gradients by stepping
+//! backwards through the discrete adjoint of the method.
+//!
+//! ```rust
+//! use reverse::*;
+//! use reverse::ode::{rk4, rk4_adjoint};
+//!
+//! let tape = Tape::new();
+//! let rate = tape.add_var(0.5);
+//! let y0 = tape.add_vars(&[2.]);
+//!
+//! // exponential decay, with the rate captured by the closure
+//! let y = rk4(|_, y| vec![-rate * y[0]], &y0, 0., 1., 100);
+//! assert!((y[0].val() - 2. * (-0.5_f64).exp()).abs() < 1e-9);
+//! let grads = y[0].grad();
+//! assert!((grads.wrt(&rate) + 2. * (-0.5_f64).exp()).abs() < 1e-9);
+//!
+//! // the same system with the rate passed as a parameter
+//! fn decay<'b>(_: f64, y: &[Var<'b>], params: &[Var<'b>]) -> Vec<Var<'b>> {
+//!     vec![-params[0] * y[0]]
+//! }
+//! let z = rk4_adjoint(decay, &y0, &[rate], 0., 1., 100);
+//! assert!((z[0].grad().wrt(&rate) - grads.wrt(&rate)).abs() < 1e-12);
+//! ```
+
+use crate::{error::assert_same_tape, vjp, Gradient, Op, Tape, Var};
+
+/// Gets `y + h * sum_j a_j k_j` for the stages `k_j` of a Runge-Kutta method.
+fn combine<'a>(y: &[Var<'a>], h: f64, stages: &[(f64, &[Var<'a>])]) -> Vec<Var<'a>> {
+    (0..y.len())
+        .map(|i| {
+            stages
+                .iter()
+                .filter(|(a, _)| *a != 0.)
+                .fold(y[i], |acc, (a, k)| acc + a * h * k[i])
+        })
+        .collect()
+}
+
+/// Take one step of the classic fourth-order Runge-Kutta method from `(t, y)`.
+fn rk4_step<'a>(
+    f: &impl Fn(f64, &[Var<'a>]) -> Vec<Var<'a>>,
+    t: f64,
+    h: f64,
+    y: &[Var<'a>],
+) -> Vec<Var<'a>> {
+    let k1 = f(t, y);
+    let k2 = f(t + h / 2., &combine(y, h, &[(0.5, &k1)]));
+    let k3 = f(t + h / 2., &combine(y, h, &[(0.5, &k2)]));
+    let k4 = f(t + h, &combine(y, h, &[(1., &k3)]));
+    combine(
+        y,
+        h,
+        &[
+            (1. / 6., &k1),
+            (1. / 3., &k2),
+            (1. / 3., &k3),
+            (1. / 6., &k4),
+        ],
+    )
+}
+
+/// Integrate `dy/dt = f(t, y)` from `y0` at time `t0` to time `t1` with `steps` equal steps of the
+/// classic fourth-order Runge-Kutta method. Returns the state at `t1`.
+///
+/// # Panics
+///
+/// Panics if `steps` is zero.
+pub fn rk4<'a, F>(f: F, y0: &[Var<'a>], t0: f64, t1: f64, steps: usize) -> Vec<Var<'a>>
+where
+    F: Fn(f64, &[Var<'a>]) -> Vec<Var<'a>>,
+{
+    assert!(steps > 0, "need at least one step");
+    let h = (t1 - t0) / steps as f64;
+    (0..steps).fold(y0.to_vec(), |y, i| rk4_step(&f, t0 + i as f64 * h, h, &y))
+}
+
+/// Nodes of the Dormand-Prince method.
+const DOPRI_C: [f64; 6] = [1. / 5., 3. / 10., 4. / 5., 8. / 9., 1., 1.];
+/// Coefficients of the Dormand-Prince method, where the last row gives the fifth-order solution.
+const DOPRI_A: [&[f64]; 6] = [
+    &[1. / 5.],
+    &[3. / 40., 9. / 40.],
+    &[44. / 45., -56. / 15., 32. / 9.],
+    &[
+        19372. / 6561.,
+        -25360. / 2187.,
+        64448. / 6561.,
+        -212. / 729.,
+    ],
+    &[
+        9017. / 3168.,
+        -355. / 33.,
+        46732. / 5247.,
+        49. / 176.,
+        -5103. / 18656.,
+    ],
+    &[
+        35. / 384.,
+        0.,
+        500. / 1113.,
+        125. / 192.,
+        -2187. / 6784.,
+        11. / 84.,
+    ],
+];
+/// Difference between the weights of the fifth and fourth-order solutions.
+const DOPRI_E: [f64; 7] = [
+    71. / 57600.,
+    0.,
+    -71. / 16695.,
+    71. / 1920.,
+    -17253. / 339200.,
+    22. / 525.,
+    -1. / 40.,
+];
+
+/// Integrate `dy/dt = f(t, y)` from `y0` at time `t0` to time `t1` with the adaptive
+/// Dormand-Prince method of order five, choosing steps to keep the estimated error of each step
+/// below `tol` (both relative and absolute). Returns the state at `t1`.
+///
+/// Rejected steps are rewound off the tape. The step sizes depend on the values of the states
+/// but are not differentiated.
+///
+/// # Panics
+///
+/// Panics if `tol` is not positive, `y0` is empty, or the integration needs more than a million
+/// steps.
+pub fn dopri5<'a, F>(f: F, y0: &[Var<'a>], t0: f64, t1: f64, tol: f64) -> Vec<Var<'a>>
+where
+    F: Fn(f64, &[Var<'a>]) -> Vec<Var<'a>>,
+{
+    assert!(tol > 0., "tolerance must be positive");
+    let tape = y0.first().expect("cannot integrate an empty state").tape;
+    let span = t1 - t0;
+    let mut y = y0.to_vec();
+    let mut t = t0;
+    let mut h = span / 100.;
+    let mut k1 = f(t, &y);
+    for _ in 0..1_000_000 {
+        if (t - t1) * span.signum() >= 0. {
+            return y;
+        }
+        // do not step past the end
+        if (t + h - t1) * span.signum() > 0. {
+            h = t1 - t;
+        }
+        let mark = tape.mark();
+        let mut stages = vec![k1.clone()];
+        let mut y_new = vec![];
+        for (c, a) in DOPRI_C.iter().zip(DOPRI_A) {
+            let weights = a.iter().copied().zip(stages.iter().map(Vec::as_slice));
+            // the last stage is evaluated at the fifth-order solution
+            y_new = combine(&y, h, &weights.collect::<Vec<_>>());
+            stages.push(f(t + c * h, &y_new));
+        }
+
+        let err = (0..y.len())
+            .map(|i| {
+                let e = h * DOPRI_E
+                    .iter()
+                    .zip(&stages)
+                    .map(|(e, k)| e * k[i].val)
+                    .sum::<f64>();
+                let scale = tol + tol * y[i].val.abs().max(y_new[i].val.abs());
+                (e / scale).powi(2)
+            })
+            .sum::<f64>()
+            / y.len() as f64;
+        let err = err.sqrt();
+        let factor = if err == 0. {
+            5.
+        } else {
+            (0.9 * err.powf(-0.2)).clamp(0.2, 5.)
+        };
+        if err <= 1. {
+            t += h;
+            y = y_new;
+            // the last stage was evaluated at the new state, so it starts the next step
+            k1 = stages.pop().unwrap();
+        } else {
+            tape.rewind_to(mark);
+        }
+        h *= factor;
+    }
+    panic!("integration did not finish within a million steps")
+}
+
+/// Integrate `dy/dt = f(t, y, params)` from `y0` at time `t0` to time `t1` with `steps` equal
+/// steps of the classic fourth-order Runge-Kutta method, recording the integration as a single
+/// block. Returns the state at `t1`.
+///
+/// Only the state at each step is stored. The backward pass steps back from `t1`, pulling the
+/// adjoint of the state back through each step by re-evaluating it on a scratch tape, so the
+/// gradients are those of the discrete solution, as with `rk4`. As `f` is kept for the backward
+/// pass, it cannot borrow anything and is usually a plain function.
+///
+/// # Panics
+///
+/// Panics if `steps` is zero, `y0` is empty, or the variables are on different tapes.
+pub fn rk4_adjoint<'a, F>(
+    f: F,
+    y0: &[Var<'a>],
+    params: &[Var<'a>],
+    t0: f64,
+    t1: f64,
+    steps: usize,
+) -> Vec<Var<'a>>
+where
+    F: for<'b> Fn(f64, &[Var<'b>], &[Var<'b>]) -> Vec<Var<'b>> + Send + Sync + 'static,
+{
+    assert!(steps > 0, "need at least one step");
+    let tape = y0.first().expect("cannot integrate an empty state").tape;
+    let mut inputs = vec![];
+    for x in y0.iter().chain(params) {
+        assert_same_tape("rk4_adjoint", tape, x.tape);
+        inputs.push(x.location);
+    }
+    let n = y0.len();
+    let h = (t1 - t0) / steps as f64;
+    let param_vals = params.iter().map(|p| p.val).collect::<Vec<_>>();
+
+    // the state at the start of every step, followed by the final state
+    let mut states = vec![y0.iter().map(|y| y.val).collect::<Vec<_>>()];
+    let scratch = Tape::new();
+    for i in 0..steps {
+        let y = scratch.add_vars(&states[i]);
+        let p = scratch.add_vars(&param_vals);
+        let next = rk4_step(&|t, y: &[Var]| f(t, y, &p), t0 + i as f64 * h, h, &y);
+        states.push(next.iter().map(|y| y.val).collect());
+        scratch.clear();
+    }
+
+    let vals = states.pop().unwrap();
+    let start = tape.add_block(Op::Ode, &vals, inputs, move |y_bar, input_bars| {
+        let scratch = Tape::new();
+        let mut y_bar = y_bar.to_vec();
+        let (y0_bar, p_bar) = input_bars.split_at_mut(n);
+        for (i, state) in states.iter().enumerate().rev() {
+            let y = scratch.add_vars(state);
+            let p = scratch.add_vars(&param_vals);
+            let next = rk4_step(&|t, y: &[Var]| f(t, y, &p), t0 + i as f64 * h, h, &y);
+            let grads = vjp(&next, &y_bar);
+            for (bar, grad) in p_bar.iter_mut().zip(grads.wrt(&p)) {
+                *bar += grad;
+            }
+            y_bar = grads.wrt(&y);
+            scratch.clear();
+        }
+        y0_bar.copy_from_slice(&y_bar);
+    });
+    (0..n)
+        .map(|i| Var {
+            val: vals[i],
+            location: start + i,
+            tape,
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    /// Damped oscillator with stiffness `params[0]` and damping `params[1]`.
+    fn oscillator<'b>(_: f64, y: &[Var<'b>], params: &[Var<'b>]) -> Vec<Var<'b>> {
+        vec![y[1], -params[0] * y[0] - params[1] * y[1]]
+    }
+
+    #[test]
+    fn test_ode() {
+        let g = Tape::new();
+        let params = g.add_vars(&[4., 0.3]);
+        let y0 = g.add_vars(&[1., 0.]);
+        let taped = rk4(|t, y| oscillator(t, y, &params), &y0, 0., 2., 200);
+        let adaptive = dopri5(|t, y| oscillator(t, y, &params), &y0, 0., 2., 1e-10);
+        let adjoint = rk4_adjoint(oscillator, &y0, &params, 0., 2., 200);
+
+        let wrt = [params[0], params[1], y0[0], y0[1]];
+        for i in 0..2 {
+            assert_approx_eq!(taped[i].val(), adjoint[i].val());
+            assert_approx_eq!(taped[i].val(), adaptive[i].val(), 1e-6);
+            let expected = taped[i].grad().wrt(&wrt);
+            for (grad, expected) in adjoint[i].grad().wrt(&wrt).iter().zip(&expected) {
+                assert_approx_eq!(*grad, *expected);
+            }
+            for (grad, expected) in adaptive[i].grad().wrt(&wrt).iter().zip(&expected) {
+                assert_approx_eq!(*grad, *expected, 1e-5);
+            }
+        }
+
+        // exponential decay has a closed form, also when integrating backwards in time
+        let rate = g.add_var(0.7);
+        let y = dopri5(|_, y| vec![-rate * y[0]], &y0[..1], 1., -1., 1e-10);
+        assert_approx_eq!(y[0].val(), 1.4_f64.exp(), 1e-8);
+        assert_approx_eq!(y[0].grad().wrt(&rate), 2. * 1.4_f64.exp(), 1e-8);
+    }
+}
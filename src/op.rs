@@ -0,0 +1,514 @@
+use crate::{
+    linalg::{cofactors, transpose, Lu},
+    losses, special,
+};
+use std::f64::consts::FRAC_2_SQRT_PI;
+
+/// Operation that produced a node on the tape, along with any constants it depends on.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Op {
+    /// Variable added with `Tape::add_var`.
+    Input,
+    /// Constant with no dependencies.
+    Const(f64),
+    Add,
+    AddConst(f64),
+    /// `c - x`.
+    ConstSub(f64),
+    Mul,
+    MulConst(f64),
+    /// `c / x`.
+    ConstDiv(f64),
+    Recip,
+    Sin,
+    Cos,
+    Tan,
+    Ln,
+    Log(f64),
+    Ln1p,
+    Asin,
+    Acos,
+    Atan,
+    Sinh,
+    Cosh,
+    Tanh,
+    Asinh,
+    Acosh,
+    Atanh,
+    Exp,
+    ExpM1,
+    Exp2,
+    Sqrt,
+    Abs,
+    Huber(f64),
+    SmoothAbs(f64),
+    Powi(i32),
+    Powf,
+    PowfConst(f64),
+    /// `c.powf(x)`.
+    ConstPowf(f64),
+    Atan2,
+    Atan2Const(f64),
+    /// `c.atan2(x)`.
+    ConstAtan2(f64),
+    Hypot,
+    HypotConst(f64),
+    LogAddExp,
+    LogAddExpConst(f64),
+    /// `x.copysign(y)`, where only the magnitude `x` receives a gradient.
+    Copysign,
+    CopysignConst(f64),
+    Floor,
+    Ceil,
+    Round,
+    Trunc,
+    Signum,
+    Fract,
+    Erf,
+    Erfc,
+    Lgamma,
+    Polygamma(u32),
+    BesselJ(i32),
+    BesselI0,
+    BesselI1,
+    NormCdf,
+    NormCdfInv,
+    Sinc,
+    LnBeta,
+    #[cfg(feature = "nn")]
+    Relu,
+    #[cfg(feature = "nn")]
+    Sigmoid,
+    Sum,
+    /// Sum with compensation for rounding errors, as recorded on compensated tapes.
+    CompensatedSum,
+    Mean,
+    Variance,
+    Std,
+    Dot,
+    LogSumExp,
+    MaxOf,
+    MinOf,
+    NormL1,
+    NormL2,
+    NormLp(f64),
+    /// Polynomial with the coefficients from the highest degree down, followed by the point.
+    Polyval,
+    /// Loss functions over predictions and targets interleaved in pairs.
+    Mse,
+    Mae,
+    BinaryCrossEntropy,
+    CrossEntropy,
+    Hinge,
+    Entropy,
+    KlDiv,
+    KlDivLogits,
+    /// Sum of products of this many factors each, as recorded by `einsum`.
+    Einsum(usize),
+    /// Bias plus a sum of products of pairs, as recorded by `nn::Dense` and by `Tape::fuse`.
+    Affine,
+    /// Sum of the operands times their recorded weights, which are constants and so are both the
+    /// coefficients and the partial derivatives, see `eval_operands`.
+    Linear,
+    /// Copy of a branch chosen by a condition that is not on the tape, as recorded by `select`
+    /// and `Piecewise`. It is never replayed, since the choice cannot be re-evaluated.
+    Select,
+    /// The second operand if the first is greater than the threshold, and the third otherwise.
+    SelectGt(f64),
+    LogDet,
+    Det,
+    Solve,
+    Inv,
+    Eigh,
+    Fft,
+    Ifft,
+    Ode,
+    Implicit,
+    SoftSort,
+    Normalize,
+    Attention,
+}
+
+impl Op {
+    /// Value and partial derivatives of a scalar operation applied to `x`, and to `y` for the
+    /// binary ones. Unary operations ignore `y` and have a zero second partial.
+    ///
+    /// # Panics
+    ///
+    /// Panics for leaves and for operations on more than two values, see `eval_nary`.
+    pub(crate) fn eval(self, x: f64, y: f64) -> (f64, f64, f64) {
+        let unary = |val: f64, deriv: f64| (val, deriv, 0.);
+        match self {
+            Op::Add => (x + y, 1., 1.),
+            Op::AddConst(c) => unary(x + c, 1.),
+            Op::ConstSub(c) => unary(c - x, -1.),
+            Op::Mul => (x * y, y, x),
+            Op::MulConst(c) => unary(x * c, c),
+            Op::ConstDiv(c) => unary(c / x, -1. / x),
+            Op::Recip => unary(x.recip(), -1. / (x.powi(2))),
+            Op::Sin => unary(x.sin(), x.cos()),
+            Op::Cos => unary(x.cos(), -x.sin()),
+            Op::Tan => unary(x.tan(), 1. / x.cos().powi(2)),
+            Op::Ln => unary(x.ln(), 1. / x),
+            Op::Log(base) => unary(x.log(base), 1. / (x * base.ln())),
+            Op::Ln1p => unary(x.ln_1p(), 1. / (1. + x)),
+            Op::Asin => unary(x.asin(), 1. / (1. - x.powi(2)).sqrt()),
+            Op::Acos => unary(x.acos(), -1. / (1. - x.powi(2)).sqrt()),
+            Op::Atan => unary(x.atan(), 1. / (1. + x.powi(2))),
+            Op::Sinh => unary(x.sinh(), x.cosh()),
+            Op::Cosh => unary(x.cosh(), x.sinh()),
+            Op::Tanh => unary(x.tanh(), 1. / (x.cosh().powi(2))),
+            Op::Asinh => unary(x.asinh(), 1. / (1. + x.powi(2)).sqrt()),
+            Op::Acosh => unary(x.acosh(), 1. / (x.powi(2) - 1.).sqrt()),
+            Op::Atanh => unary(x.atanh(), 1. / (1. - x.powi(2))),
+            Op::Exp => unary(x.exp(), x.exp()),
+            Op::ExpM1 => unary(x.exp_m1(), x.exp()),
+            Op::Exp2 => unary(x.exp2(), x.exp2() * 2_f64.ln()),
+            Op::Sqrt => unary(x.sqrt(), 1. / (2. * x.sqrt())),
+            Op::Abs => unary(x.abs(), if x == 0. { f64::NAN } else { x / x.abs() }),
+            Op::Huber(delta) if x.abs() <= delta => unary(0.5 * x * x, x),
+            Op::Huber(delta) => unary(delta * (x.abs() - 0.5 * delta), delta * x.signum()),
+            Op::SmoothAbs(eps) => {
+                let r = x.hypot(eps);
+                unary(r - eps, x / r)
+            }
+            Op::Select => unary(x, 1.),
+            Op::Powi(n) => unary(x.powi(n), n as f64 * x.powi(n - 1)),
+            Op::Powf => (x.powf(y), y * x.powf(y - 1.), x.powf(y) * x.ln()),
+            Op::PowfConst(c) => unary(x.powf(c), c * x.powf(c - 1.)),
+            Op::ConstPowf(c) => unary(c.powf(x), c.powf(x) * c.ln()),
+            Op::Atan2 => {
+                let denom = x.powi(2) + y.powi(2);
+                (x.atan2(y), y / denom, -x / denom)
+            }
+            Op::Atan2Const(c) => unary(x.atan2(c), c / (x.powi(2) + c.powi(2))),
+            Op::ConstAtan2(c) => unary(c.atan2(x), -c / (c.powi(2) + x.powi(2))),
+            Op::Hypot => {
+                let h = x.hypot(y);
+                (h, hypot_partial(x, h), hypot_partial(y, h))
+            }
+            Op::HypotConst(c) => {
+                let h = x.hypot(c);
+                unary(h, hypot_partial(x, h))
+            }
+            Op::LogAddExp => logaddexp(x, y),
+            Op::LogAddExpConst(c) => {
+                let (val, dx, _) = logaddexp(x, c);
+                unary(val, dx)
+            }
+            Op::Copysign => (x.copysign(y), x.signum() * y.signum(), 0.),
+            Op::CopysignConst(c) => unary(x.copysign(c), x.signum() * c.signum()),
+            // piecewise-constant functions have a zero derivative almost everywhere, and zero is
+            // also used at the jumps
+            Op::Floor => unary(x.floor(), 0.),
+            Op::Ceil => unary(x.ceil(), 0.),
+            Op::Round => unary(x.round(), 0.),
+            Op::Trunc => unary(x.trunc(), 0.),
+            Op::Signum => unary(x.signum(), 0.),
+            Op::Fract => unary(x.fract(), 1.),
+            Op::Erf => unary(special::erf(x), FRAC_2_SQRT_PI * (-x.powi(2)).exp()),
+            Op::Erfc => unary(special::erfc(x), -FRAC_2_SQRT_PI * (-x.powi(2)).exp()),
+            Op::Lgamma => unary(special::ln_gamma(x), special::digamma(x)),
+            Op::Polygamma(n) => unary(special::polygamma(n, x), special::polygamma(n + 1, x)),
+            Op::BesselJ(n) => unary(
+                special::bessel_jn(n, x),
+                (special::bessel_jn(n - 1, x) - special::bessel_jn(n + 1, x)) / 2.,
+            ),
+            Op::BesselI0 => unary(special::bessel_i(0, x), special::bessel_i(1, x)),
+            Op::BesselI1 => unary(
+                special::bessel_i(1, x),
+                (special::bessel_i(0, x) + special::bessel_i(2, x)) / 2.,
+            ),
+            Op::NormCdf => unary(special::norm_cdf(x), special::norm_pdf(x)),
+            Op::NormCdfInv => {
+                let val = special::norm_cdf_inv(x);
+                unary(val, special::norm_pdf(val).recip())
+            }
+            Op::Sinc => unary(special::sinc(x), special::sinc_deriv(x)),
+            Op::LnBeta => {
+                let digamma_xy = special::digamma(x + y);
+                (
+                    special::ln_gamma(x) + special::ln_gamma(y) - special::ln_gamma(x + y),
+                    special::digamma(x) - digamma_xy,
+                    special::digamma(y) - digamma_xy,
+                )
+            }
+            #[cfg(feature = "nn")]
+            Op::Relu if x > 0. => unary(x, 1.),
+            #[cfg(feature = "nn")]
+            Op::Relu => unary(0., 0.),
+            #[cfg(feature = "nn")]
+            Op::Sigmoid => {
+                let s = 1. / (1. + (-x).exp());
+                unary(s, s * (1. - s))
+            }
+            op => panic!("{:?} is not a scalar operation", op),
+        }
+    }
+
+    /// Value and partial derivatives of the operation on the values `xs` of the operands of a
+    /// node, whose recorded weights are `weights`. Only `Linear` reads the weights, and every
+    /// other operation is evaluated with `eval_nary`.
+    pub(crate) fn eval_operands(self, xs: &[f64], weights: &[f64]) -> (f64, Vec<f64>) {
+        match self {
+            Op::Linear => (
+                xs.iter().zip(weights).map(|(x, w)| x * w).sum(),
+                weights.to_vec(),
+            ),
+            op => op.eval_nary(xs),
+        }
+    }
+
+    /// Value and partial derivatives of an operation on any number of values `xs`. The fused
+    /// products `Dot`, `Einsum` and `Affine` take their factors interleaved, as recorded.
+    ///
+    /// # Panics
+    ///
+    /// Panics for scalar operations, for `Linear` and for operations recorded as blocks.
+    pub(crate) fn eval_nary(self, xs: &[f64]) -> (f64, Vec<f64>) {
+        let n = xs.len() as f64;
+        match self {
+            Op::Sum => (xs.iter().sum(), vec![1.; xs.len()]),
+            Op::CompensatedSum => {
+                let mut sum = Compensated::default();
+                xs.iter().for_each(|&x| sum.add(x));
+                (sum.total(), vec![1.; xs.len()])
+            }
+            Op::Mean => (moments(xs).0, vec![1. / n; xs.len()]),
+            Op::Variance => {
+                let (mean, m2) = moments(xs);
+                (m2 / n, xs.iter().map(|x| 2. * (x - mean) / n).collect())
+            }
+            Op::Std => {
+                let (mean, m2) = moments(xs);
+                let std = (m2 / n).sqrt();
+                let weights = xs
+                    .iter()
+                    .map(|x| {
+                        if std == 0. {
+                            0.
+                        } else {
+                            (x - mean) / (n * std)
+                        }
+                    })
+                    .collect();
+                (std, weights)
+            }
+            Op::LogSumExp => {
+                let max = xs.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+                if max.is_infinite() {
+                    let count = xs.iter().filter(|&&x| x == max).count() as f64;
+                    let weights = xs
+                        .iter()
+                        .map(|&x| if x == max { 1. / count } else { 0. })
+                        .collect();
+                    (max, weights)
+                } else {
+                    let val = max + xs.iter().map(|x| (x - max).exp()).sum::<f64>().ln();
+                    (val, xs.iter().map(|x| (x - val).exp()).collect())
+                }
+            }
+            Op::MaxOf | Op::MinOf => {
+                // the first extreme value wins ties, unless a NaN comes first
+                let mut best = 0;
+                for (i, &x) in xs.iter().enumerate().skip(1) {
+                    if xs[best].is_nan() {
+                        break;
+                    }
+                    let better = match self {
+                        Op::MaxOf => x > xs[best],
+                        _ => x < xs[best],
+                    };
+                    if better || x.is_nan() {
+                        best = i;
+                    }
+                }
+                let mut weights = vec![0.; xs.len()];
+                weights[best] = 1.;
+                (xs[best], weights)
+            }
+            Op::NormL1 => (
+                xs.iter().map(|x| x.abs()).sum(),
+                xs.iter().map(|&x| sign(x)).collect(),
+            ),
+            Op::NormL2 | Op::NormLp(_) => {
+                let p = match self {
+                    Op::NormLp(p) => p,
+                    _ => 2.,
+                };
+                // dividing by the largest magnitude keeps the powers from overflowing
+                let scale = xs.iter().fold(0., |max: f64, x| max.max(x.abs()));
+                if scale == 0. || scale.is_infinite() {
+                    // the gradient goes to the infinite entries, and is zero at the origin
+                    let weights = xs
+                        .iter()
+                        .map(|&x| if x.abs() == scale { sign(x) } else { 0. })
+                        .collect();
+                    (scale, weights)
+                } else {
+                    let sum = xs.iter().map(|x| (x.abs() / scale).powf(p)).sum::<f64>();
+                    let val = if p == 2. {
+                        scale * sum.sqrt()
+                    } else {
+                        scale * sum.powf(p.recip())
+                    };
+                    let weights = xs
+                        .iter()
+                        .map(|&x| {
+                            if x == 0. {
+                                0.
+                            } else {
+                                sign(x) * (x.abs() / val).powf(p - 1.)
+                            }
+                        })
+                        .collect();
+                    (val, weights)
+                }
+            }
+            Op::Mse
+            | Op::Mae
+            | Op::BinaryCrossEntropy
+            | Op::CrossEntropy
+            | Op::Hinge
+            | Op::Entropy
+            | Op::KlDiv
+            | Op::KlDivLogits => losses::eval(self, xs),
+            Op::Polyval => {
+                let (coeffs, x) = xs.split_at(xs.len() - 1);
+                let x = x[0];
+                // Horner's scheme for the polynomial and its derivative together
+                let (mut val, mut deriv) = (0., 0.);
+                for &c in coeffs {
+                    deriv = deriv * x + val;
+                    val = val * x + c;
+                }
+                let mut weights = vec![0.; xs.len()];
+                let mut power = 1.;
+                for weight in weights[..coeffs.len()].iter_mut().rev() {
+                    *weight = power;
+                    power *= x;
+                }
+                weights[coeffs.len()] = deriv;
+                (val, weights)
+            }
+            Op::Dot => Op::Einsum(2).eval_nary(xs),
+            Op::Einsum(factors) => {
+                let mut val = 0.;
+                let mut weights = Vec::with_capacity(xs.len());
+                for term in xs.chunks(factors) {
+                    val += term.iter().product::<f64>();
+                    for k in 0..term.len() {
+                        weights.push(
+                            term.iter()
+                                .enumerate()
+                                .filter(|&(l, _)| l != k)
+                                .map(|(_, x)| x)
+                                .product(),
+                        );
+                    }
+                }
+                (val, weights)
+            }
+            Op::SelectGt(threshold) if xs[0] > threshold => (xs[1], vec![0., 1., 0.]),
+            Op::SelectGt(_) => (xs[2], vec![0., 0., 1.]),
+            Op::Affine => {
+                let (val, mut weights) = Op::Dot.eval_nary(&xs[1..]);
+                weights.insert(0, 1.);
+                (xs[0] + val, weights)
+            }
+            Op::LogDet | Op::Det => {
+                let size = (xs.len() as f64).sqrt() as usize;
+                match Lu::new(xs, size) {
+                    Some(lu) => {
+                        let weights = transpose(&lu.inverse(), size, size);
+                        if self == Op::Det {
+                            let det = lu.det();
+                            (det, weights.into_iter().map(|w| det * w).collect())
+                        } else {
+                            (lu.ln_abs_det(), weights)
+                        }
+                    }
+                    // a singular matrix has no inverse, but its determinant still has the
+                    // cofactors as partial derivatives
+                    None if self == Op::Det => (0., cofactors(xs, size)),
+                    None => (
+                        f64::NEG_INFINITY,
+                        cofactors(xs, size).into_iter().map(|c| c / 0.).collect(),
+                    ),
+                }
+            }
+            op => panic!("{:?} is not an operation on a slice of values", op),
+        }
+    }
+}
+
+/// Sign of `x`, taken to be zero at zero so that norms have a zero subgradient there.
+fn sign(x: f64) -> f64 {
+    if x == 0. {
+        0.
+    } else {
+        x.signum()
+    }
+}
+
+/// Partial derivative of `hypot(x, y) = h` with respect to `x`, taken to be zero at the origin.
+fn hypot_partial(x: f64, h: f64) -> f64 {
+    if h == 0. {
+        0.
+    } else {
+        x / h
+    }
+}
+
+/// Value of `ln(exp(x) + exp(y))` and its partial derivatives, the softmax weights of `x` and `y`.
+/// The exponentials are shifted by the larger argument so that they cannot overflow, and the
+/// weights are split equally between infinite arguments.
+fn logaddexp(x: f64, y: f64) -> (f64, f64, f64) {
+    let max = x.max(y);
+    if max.is_infinite() {
+        let (dx, dy) = match (x == max, y == max) {
+            (true, true) => (0.5, 0.5),
+            (true, false) => (1., 0.),
+            _ => (0., 1.),
+        };
+        return (max, dx, dy);
+    }
+    let val = max + (-(x - y).abs()).exp().ln_1p();
+    (val, (x - val).exp(), (y - val).exp())
+}
+
+/// Mean and sum of squared deviations of `xs`, using Welford's algorithm.
+fn moments(xs: &[f64]) -> (f64, f64) {
+    let mut mean = 0.;
+    let mut m2 = 0.;
+    for (i, x) in xs.iter().enumerate() {
+        let delta = x - mean;
+        mean += delta / (i + 1) as f64;
+        m2 += delta * (x - mean);
+    }
+    (mean, m2)
+}
+
+/// Running sum that tracks the rounding error of each addition, using Neumaier's variant of Kahan
+/// summation.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Compensated {
+    sum: f64,
+    compensation: f64,
+}
+
+impl Compensated {
+    pub(crate) fn add(&mut self, x: f64) {
+        let sum = self.sum + x;
+        self.compensation += if self.sum.abs() >= x.abs() {
+            (self.sum - sum) + x
+        } else {
+            (x - sum) + self.sum
+        };
+        self.sum = sum;
+    }
+
+    pub(crate) fn total(self) -> f64 {
+        self.sum + self.compensation
+    }
+}
@@ -1,3 +1,6 @@
+// the assignment operators go through the by-reference binary operators
+#![allow(clippy::op_ref)]
+
 mod unary {
     use std::{iter::Sum, ops::Neg};
 
@@ -10,32 +13,29 @@ mod unary {
 
     impl<'a> Sum<Var<'a>> for Var<'a> {
         fn sum<I: Iterator<Item = Var<'a>>>(iter: I) -> Self {
-            iter.reduce(|a, b| a + b).unwrap()
+            crate::sum(&iter.collect::<Vec<_>>())
+        }
+    }
+
+    impl<'a: 'b, 'b> Sum<&'b Var<'a>> for Var<'a> {
+        fn sum<I: Iterator<Item = &'b Var<'a>>>(iter: I) -> Self {
+            iter.copied().sum()
         }
     }
 }
 
 mod add {
-    use crate::{Tape, Var};
+    use crate::{Op, Var};
     use std::ops::{Add, AddAssign};
 
     #[opimps::impl_ops(Add)]
     fn add<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
-        assert_eq!(self.tape as *const Tape, rhs.tape as *const Tape);
-        Self::Output {
-            val: self.val + rhs.val,
-            location: self.tape.add_node(self.location, rhs.location, 1., 1.),
-            tape: self.tape,
-        }
+        self.binary("add", rhs, Op::Add)
     }
 
     #[opimps::impl_ops_rprim(Add)]
     fn add<'a>(self: Var<'a>, rhs: f64) -> Var<'a> {
-        Self::Output {
-            val: self.val + rhs,
-            location: self.tape.add_node(self.location, self.location, 1., 0.),
-            tape: self.tape,
-        }
+        self.unary(Op::AddConst(rhs))
     }
 
     #[opimps::impl_ops_lprim(Add)]
@@ -55,21 +55,18 @@ mod add {
 }
 
 mod sub {
-    use crate::Var;
+    use crate::{error::assert_same_tape, Op, Var};
     use std::ops::{Neg, Sub, SubAssign};
 
     #[opimps::impl_ops(Sub)]
     fn sub<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
+        assert_same_tape("sub", self.tape, rhs.tape);
         self + rhs.neg()
     }
 
     #[opimps::impl_ops_lprim(Sub)]
     fn sub<'a>(self: f64, rhs: Var<'a>) -> Var<'a> {
-        Self::Output {
-            val: self - rhs.val,
-            location: rhs.tape.add_node(rhs.location, rhs.location, 0., -1.),
-            tape: rhs.tape,
-        }
+        rhs.unary(Op::ConstSub(self))
     }
 
     #[opimps::impl_ops_rprim(Sub)]
@@ -89,28 +86,17 @@ mod sub {
 }
 
 mod mul {
-    use crate::{Tape, Var};
+    use crate::{Op, Var};
     use std::ops::{Mul, MulAssign};
 
     #[opimps::impl_ops(Mul)]
     fn mul<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
-        assert_eq!(self.tape as *const Tape, rhs.tape as *const Tape);
-        Self::Output {
-            val: self.val * rhs.val,
-            location: self
-                .tape
-                .add_node(self.location, rhs.location, rhs.val, self.val),
-            tape: self.tape,
-        }
+        self.binary("mul", rhs, Op::Mul)
     }
 
     #[opimps::impl_ops_rprim(Mul)]
     fn mul<'a>(self: Var<'a>, rhs: f64) -> Var<'a> {
-        Self::Output {
-            val: self.val * rhs,
-            location: self.tape.add_node(self.location, self.location, rhs, 0.),
-            tape: self.tape,
-        }
+        self.unary(Op::MulConst(rhs))
     }
 
     #[opimps::impl_ops_lprim(Mul)]
@@ -130,11 +116,12 @@ mod mul {
 }
 
 mod div {
-    use crate::Var;
+    use crate::{error::assert_same_tape, Op, Var};
     use std::ops::{Div, DivAssign};
 
     #[opimps::impl_ops(Div)]
     fn div<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
+        assert_same_tape("div", self.tape, rhs.tape);
         self * rhs.recip()
     }
 
@@ -145,13 +132,7 @@ mod div {
 
     #[opimps::impl_ops_lprim(Div)]
     fn div<'a>(self: f64, rhs: Var<'a>) -> Var<'a> {
-        Self::Output {
-            val: self / rhs.val,
-            location: rhs
-                .tape
-                .add_node(rhs.location, rhs.location, 0., -1. / rhs.val),
-            tape: rhs.tape,
-        }
+        rhs.unary(Op::ConstDiv(self))
     }
 
     #[opimps::impl_ops_assign(DivAssign)]
@@ -166,49 +147,91 @@ mod div {
 }
 
 mod powf {
-    use crate::{Powf, Tape, Var};
+    use crate::{Op, Powf, Var};
 
     #[opimps::impl_ops(Powf)]
     fn powf<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
-        assert_eq!(self.tape as *const Tape, rhs.tape as *const Tape);
-
-        Self::Output {
-            val: self.val.powf(rhs.val),
-            location: self.tape.add_node(
-                self.location,
-                rhs.location,
-                rhs.val * f64::powf(self.val, rhs.val - 1.),
-                f64::powf(self.val, rhs.val) * f64::ln(self.val),
-            ),
-            tape: self.tape,
-        }
+        self.binary("powf", rhs, Op::Powf)
     }
 
     #[opimps::impl_ops_rprim(Powf)]
     fn powf<'a>(self: Var<'a>, rhs: f64) -> Var<'a> {
-        Self::Output {
-            val: f64::powf(self.val, rhs),
-            location: self.tape.add_node(
-                self.location,
-                self.location,
-                rhs * f64::powf(self.val, rhs - 1.),
-                0.,
-            ),
-            tape: self.tape,
-        }
+        self.unary(Op::PowfConst(rhs))
     }
 
     #[opimps::impl_ops_lprim(Powf)]
     fn powf<'a>(self: f64, rhs: Var<'a>) -> Var<'a> {
-        Self::Output {
-            val: f64::powf(self, rhs.val),
-            location: rhs.tape.add_node(
-                rhs.location,
-                rhs.location,
-                0.,
-                rhs.val * f64::powf(self, rhs.val - 1.),
-            ),
-            tape: rhs.tape,
-        }
+        rhs.unary(Op::ConstPowf(self))
+    }
+}
+
+mod atan2 {
+    use crate::{Atan2, Op, Var};
+
+    #[opimps::impl_ops(Atan2)]
+    fn atan2<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
+        self.binary("atan2", rhs, Op::Atan2)
+    }
+
+    #[opimps::impl_ops_rprim(Atan2)]
+    fn atan2<'a>(self: Var<'a>, rhs: f64) -> Var<'a> {
+        self.unary(Op::Atan2Const(rhs))
+    }
+
+    #[opimps::impl_ops_lprim(Atan2)]
+    fn atan2<'a>(self: f64, rhs: Var<'a>) -> Var<'a> {
+        rhs.unary(Op::ConstAtan2(self))
+    }
+}
+
+mod hypot {
+    use crate::{Hypot, Op, Var};
+
+    #[opimps::impl_ops(Hypot)]
+    fn hypot<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
+        self.binary("hypot", rhs, Op::Hypot)
+    }
+
+    #[opimps::impl_ops_rprim(Hypot)]
+    fn hypot<'a>(self: Var<'a>, rhs: f64) -> Var<'a> {
+        self.unary(Op::HypotConst(rhs))
+    }
+
+    #[opimps::impl_ops_lprim(Hypot)]
+    fn hypot<'a>(self: f64, rhs: Var<'a>) -> Var<'a> {
+        rhs.hypot(self)
+    }
+}
+
+mod logaddexp {
+    use crate::{LogAddExp, Op, Var};
+
+    #[opimps::impl_ops(LogAddExp)]
+    fn logaddexp<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
+        self.binary("logaddexp", rhs, Op::LogAddExp)
+    }
+
+    #[opimps::impl_ops_rprim(LogAddExp)]
+    fn logaddexp<'a>(self: Var<'a>, rhs: f64) -> Var<'a> {
+        self.unary(Op::LogAddExpConst(rhs))
+    }
+
+    #[opimps::impl_ops_lprim(LogAddExp)]
+    fn logaddexp<'a>(self: f64, rhs: Var<'a>) -> Var<'a> {
+        rhs.logaddexp(self)
+    }
+}
+
+mod copysign {
+    use crate::{Copysign, Op, Var};
+
+    #[opimps::impl_ops(Copysign)]
+    fn copysign<'a>(self: Var<'a>, rhs: Var<'a>) -> Var<'a> {
+        self.binary("copysign", rhs, Op::Copysign)
+    }
+
+    #[opimps::impl_ops_rprim(Copysign)]
+    fn copysign<'a>(self: Var<'a>, rhs: f64) -> Var<'a> {
+        self.unary(Op::CopysignConst(rhs))
     }
 }
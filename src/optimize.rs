@@ -0,0 +1,491 @@
+//! Passes that shrink a recorded tape by rewriting its nodes.
+//!
+//! Rewriting moves nodes to new locations, so the variables recorded before a pass must be
+//! translated with the returned `Remap` before they are used again.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2.]);
+//! let y = (x[0] * x[1]).sin() + (x[1] * x[0]).sin();
+//! let before = tape.len();
+//! let remap = tape.optimize();
+//! assert_eq!(tape.len(), before - 2);
+//! let (x, y) = (remap.vars(&x), remap.var(&y));
+//! assert_eq!(y.grad().wrt(&x), vec![4. * 2f64.cos(), 2. * 2f64.cos()]);
+//! ```
+
+use crate::{intern::Interner, Block, Node, Op, Span, Tape, Var};
+use std::collections::HashMap;
+
+/// Map from the locations of nodes before a pass over the tape to their locations after it,
+/// returned by `Tape::optimize`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Remap {
+    locations: Vec<usize>,
+    len: usize,
+}
+
+impl Remap {
+    /// Gets the new location of the node at `location`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the node was removed without a replacement, as `Tape::fuse` does.
+    pub fn location(&self, location: usize) -> usize {
+        let new = self.locations[location];
+        assert_ne!(
+            new, REMOVED,
+            "the node at location {} was removed",
+            location
+        );
+        new
+    }
+
+    /// Translate a variable recorded before the pass into one referring to its new node.
+    pub fn var<'a>(&self, var: &Var<'a>) -> Var<'a> {
+        Var {
+            location: self.location(var.location),
+            ..*var
+        }
+    }
+
+    /// Translate several variables, see `var`.
+    pub fn vars<'a>(&self, vars: &[Var<'a>]) -> Vec<Var<'a>> {
+        vars.iter().map(|var| self.var(var)).collect()
+    }
+
+    /// Gets the number of nodes removed by the pass.
+    pub fn removed(&self) -> usize {
+        self.locations.len() - self.len
+    }
+}
+
+/// Node of a tape being rewritten, with its dependencies at their locations before the rewrite.
+#[derive(Debug, Clone)]
+pub(crate) enum Rebuilt {
+    /// Scalar operation, or a leaf or block output depending on its own location.
+    Scalar(Op, Node),
+    /// Operation on the operands, as recorded with `add_nary_node`.
+    Nary(Op, Vec<(usize, f64)>),
+}
+
+/// What a rewrite does with a node.
+pub(crate) enum Action {
+    /// Record the node, which may differ from the original.
+    Keep(Rebuilt),
+    /// Replace the node with the earlier node at this location before the rewrite.
+    Merge(usize),
+    /// Remove the node, which nothing that is kept may depend on.
+    Drop,
+}
+
+/// Marker of a removed node in `Remap::locations`.
+const REMOVED: usize = usize::MAX;
+
+impl Tape {
+    /// Remove common subexpressions: nodes with the same operation on the same dependencies as
+    /// an earlier node are dropped, and their users rewired to the earlier node. Sums and
+    /// products are matched regardless of the order of their operands. Inputs, the outputs of
+    /// blocks and nodes with hooks are always kept.
+    ///
+    /// Returns where every node moved, which must be used to translate existing variables.
+    pub fn optimize(&self) -> Remap {
+        let mut seen = HashMap::new();
+        // location of the node that each node was merged into, if any
+        let mut canonical = vec![];
+        self.rewrite(|idx, node, unique| {
+            canonical.push(idx);
+            let mut node = match node {
+                // a hook belongs to one node, and blocks are not compared
+                _ if unique => return Action::Keep(node),
+                Rebuilt::Scalar(Op::Input, _) => return Action::Keep(node),
+                node => node,
+            };
+            let key = match &mut node {
+                Rebuilt::Scalar(Op::Const(val), _) => (format!("{:?}", Op::Const(*val)), vec![]),
+                Rebuilt::Scalar(op, n) => {
+                    let mut deps = n.dependencies().map(|dep| canonical[dep]);
+                    if matches!(op, Op::Add | Op::Mul) && deps[0] > deps[1] {
+                        deps.swap(0, 1);
+                    }
+                    (format!("{:?}", op), deps.to_vec())
+                }
+                // the weights of a linear combination are its coefficients
+                Rebuilt::Nary(Op::Linear, operands) => (
+                    format!("{:?}", operands.iter().map(|&(_, w)| w).collect::<Vec<_>>()),
+                    operands.iter().map(|&(dep, _)| canonical[dep]).collect(),
+                ),
+                Rebuilt::Nary(op, operands) => (
+                    format!("{:?}", op),
+                    operands.iter().map(|&(dep, _)| canonical[dep]).collect(),
+                ),
+            };
+            match seen.get(&key) {
+                Some(&existing) => {
+                    canonical[idx] = existing;
+                    Action::Merge(existing)
+                }
+                None => {
+                    seen.insert(key, idx);
+                    Action::Keep(node)
+                }
+            }
+        })
+    }
+
+    /// Fuse chains of operations into single nodes, keeping the nodes of the variables in `keep`
+    /// (typically the outputs) along with inputs, block outputs and nodes with hooks:
+    ///
+    /// - sums of products `a * x + b * y + c`, with at most one term that is not a product, become
+    ///   one fused product (with a bias), however they were grouped,
+    /// - repeated scaling `x * c1 * c2` and shifting `x + c1 + c2` become one scaling or shift,
+    ///   which rounds slightly differently than applying each constant in turn.
+    ///
+    /// An intermediate node is fused into its user only if nothing else uses it. Returns where
+    /// every node moved, which must be used to translate the variables in `keep`; other variables
+    /// may refer to removed nodes.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// let tape = Tape::new();
+    /// let x = tape.add_vars(&[1., 2., 3.]);
+    /// let y = x[0] * x[1] + x[1] * x[2] + (x[2] * 2. * 3. + 1.);
+    /// let remap = tape.fuse(&[y]);
+    /// assert_eq!(tape.len(), 3 + 3);
+    /// let (x, y) = (remap.vars(&x), remap.var(&y));
+    /// assert_eq!(y.grad().wrt(&x), vec![2., 4., 8.]);
+    /// assert_eq!(tape.replay(&[1., 2., 3.]).unwrap().wrt(&y), 27.);
+    /// ```
+    pub fn fuse(&self, keep: &[Var]) -> Remap {
+        let plan = self.plan_fusion(keep);
+        let mut plan = plan.into_iter();
+        self.rewrite(|_, node, _| plan.next().unwrap().unwrap_or(Action::Keep(node)))
+    }
+
+    /// Decide what `fuse` does with every node, where `None` keeps the node as it is.
+    fn plan_fusion(&self, keep: &[Var]) -> Vec<Option<Action>> {
+        let nodes = self.nodes.borrow();
+        let ops = self.ops.borrow();
+        let spans = self.spans.borrow();
+        let operands = self.operands.borrow();
+        let len = nodes.len();
+
+        // nodes as recorded, and how many times each is used
+        let mut spans = spans.iter().peekable();
+        let mut uses = vec![0; len];
+        let mut rebuilt = Vec::with_capacity(len);
+        for (idx, (&node, &op)) in nodes.iter().zip(ops.iter()).enumerate() {
+            match spans.next_if(|span| span.node == idx) {
+                Some(span) => {
+                    let deps = operands[span.start..span.end].to_vec();
+                    deps.iter().for_each(|&(dep, _)| uses[dep] += 1);
+                    rebuilt.push(Rebuilt::Nary(op, deps));
+                }
+                None => {
+                    let [x, y] = node.dependencies();
+                    if x != idx {
+                        uses[x] += 1;
+                    }
+                    if y != x {
+                        uses[y] += 1;
+                    }
+                    rebuilt.push(Rebuilt::Scalar(op, node));
+                }
+            }
+        }
+        let mut fixed = vec![false; len];
+        for block in self.blocks.borrow().iter() {
+            block.inputs.iter().for_each(|&input| uses[input] += 1);
+            fixed[block.start..block.end]
+                .iter_mut()
+                .for_each(|f| *f = true);
+        }
+        for hook in self.hooks.borrow().iter() {
+            fixed[hook.location] = true;
+        }
+        for var in keep {
+            fixed[var.location] = true;
+        }
+
+        let mut plan = (0..len).map(|_| None).collect::<Vec<_>>();
+        for idx in 0..len {
+            let absorbable = |dep: usize| uses[dep] == 1 && !fixed[dep];
+            let fused = match &rebuilt[idx] {
+                Rebuilt::Scalar(op @ (Op::MulConst(_) | Op::AddConst(_)), node) => {
+                    let x = node.dependencies()[0];
+                    match (op, &rebuilt[x]) {
+                        (Op::MulConst(c1), Rebuilt::Scalar(Op::MulConst(c2), inner))
+                            if absorbable(x) =>
+                        {
+                            let c = c1 * c2;
+                            let y = inner.dependencies()[0];
+                            Some((
+                                x,
+                                Rebuilt::Scalar(Op::MulConst(c), Node::new([c, 0.], [y, y])),
+                            ))
+                        }
+                        (Op::AddConst(c1), Rebuilt::Scalar(Op::AddConst(c2), inner))
+                            if absorbable(x) =>
+                        {
+                            let y = inner.dependencies()[0];
+                            let node = Node::new([1., 0.], [y, y]);
+                            Some((x, Rebuilt::Scalar(Op::AddConst(c1 + c2), node)))
+                        }
+                        _ => None,
+                    }
+                }
+                Rebuilt::Scalar(Op::Add, node) => {
+                    let deps = node.dependencies();
+                    fuse_sum(&rebuilt, deps, absorbable).map(|fused| {
+                        for dep in deps {
+                            if absorbable(dep) && product_terms(&rebuilt[dep]).is_some() {
+                                plan[dep] = Some(Action::Drop);
+                            }
+                        }
+                        (REMOVED, fused)
+                    })
+                }
+                _ => None,
+            };
+            if let Some((absorbed, fused)) = fused {
+                if absorbed != REMOVED {
+                    plan[absorbed] = Some(Action::Drop);
+                }
+                // later users see the fused node, so chains fuse all the way
+                rebuilt[idx] = fused.clone();
+                plan[idx] = Some(Action::Keep(fused));
+            }
+        }
+        plan
+    }
+
+    /// Rebuild the tape node by node. `f` gets the location of each node before the rewrite, the
+    /// node, and whether it must be kept as it is (as for block outputs and nodes with hooks).
+    pub(crate) fn rewrite(&self, mut f: impl FnMut(usize, Rebuilt, bool) -> Action) -> Remap {
+        let old_nodes = self.nodes.borrow_mut().take();
+        let old_ops = self.ops.borrow_mut().take();
+        let old_spans = self.spans.borrow_mut().take();
+        let old_operands = self.operands.borrow_mut().take();
+        let old_vals = self
+            .provenance
+            .as_ref()
+            .map(|vals| std::mem::take(&mut *vals.borrow_mut()));
+        let mut blocks = self.blocks.borrow_mut();
+        let mut hooks = self.hooks.borrow_mut();
+
+        let mut nodes = self.nodes.borrow_mut();
+        let mut ops = self.ops.borrow_mut();
+        let mut spans = self.spans.borrow_mut();
+        let mut operands = self.operands.borrow_mut();
+        let mut locations: Vec<usize> = Vec::with_capacity(old_nodes.len());
+        let mut old_spans = old_spans.iter().peekable();
+        let mut block_outputs = blocks
+            .iter()
+            .flat_map(|block| block.start..block.end)
+            .peekable();
+        let mut hooked = hooks.iter().map(|hook| hook.location).peekable();
+        for (idx, (&node, &op)) in old_nodes.iter().zip(&old_ops).enumerate() {
+            let len = nodes.len();
+            let mut unique = block_outputs.next_if_eq(&idx).is_some();
+            while hooked.next_if_eq(&idx).is_some() {
+                unique = true;
+            }
+            let rebuilt = match old_spans.next_if(|span| span.node == idx) {
+                Some(span) => Rebuilt::Nary(op, old_operands[span.start..span.end].to_vec()),
+                None => Rebuilt::Scalar(op, node),
+            };
+            let translate = |dep: usize| {
+                if dep == idx {
+                    return len;
+                }
+                let location = locations[dep];
+                assert_ne!(location, REMOVED, "node {} depends on a removed node", idx);
+                location
+            };
+            let location = match f(idx, rebuilt, unique) {
+                Action::Keep(Rebuilt::Scalar(op, node)) => {
+                    nodes.push(Node::new(node.weights, node.dependencies().map(translate)));
+                    ops.push(op);
+                    len
+                }
+                Action::Keep(Rebuilt::Nary(op, deps)) => {
+                    let deps = deps
+                        .into_iter()
+                        .map(|(dep, weight)| (translate(dep), weight))
+                        .collect::<Vec<_>>();
+                    let start = operands.len();
+                    operands.extend(deps);
+                    spans.push(Span {
+                        node: len,
+                        start,
+                        end: operands.len(),
+                    });
+                    nodes.push(Node::new([0., 0.], [len, len]));
+                    ops.push(op);
+                    len
+                }
+                Action::Merge(existing) => locations[existing],
+                Action::Drop => REMOVED,
+            };
+            locations.push(location);
+        }
+
+        for block in blocks.iter_mut() {
+            let start = locations[block.start];
+            *block = Block {
+                start,
+                end: start + (block.end - block.start),
+                inputs: block.inputs.iter().map(|&input| locations[input]).collect(),
+                backward: block.backward.clone(),
+            };
+        }
+        for hook in hooks.iter_mut() {
+            hook.location = locations[hook.location];
+        }
+        if let (Some(vals), Some(old_vals)) = (&self.provenance, old_vals) {
+            let mut vals = vals.borrow_mut();
+            vals.resize(nodes.len(), 0.);
+            for (&location, val) in locations.iter().zip(old_vals) {
+                if location != REMOVED {
+                    vals[location] = val;
+                }
+            }
+        }
+        if let Some(interner) = &self.interner {
+            // the interned locations are stale, so start over
+            *interner.borrow_mut() = Interner::default();
+        }
+        Remap {
+            locations,
+            len: nodes.len(),
+        }
+    }
+}
+
+/// Splits a node that `fuse` can merge into a sum into its bias, if any, and the pairs of factors
+/// of its products with their weights.
+#[allow(clippy::type_complexity)]
+fn product_terms(node: &Rebuilt) -> Option<(Option<(usize, f64)>, Vec<(usize, f64)>)> {
+    match node {
+        Rebuilt::Scalar(Op::Mul, n) => {
+            let [a, x] = n.dependencies();
+            Some((None, vec![(a, n.weights[0]), (x, n.weights[1])]))
+        }
+        Rebuilt::Nary(Op::Dot, operands) => Some((None, operands.clone())),
+        Rebuilt::Nary(Op::Affine, operands) => Some((Some(operands[0]), operands[1..].to_vec())),
+        _ => None,
+    }
+}
+
+/// Fuse the sum of the nodes at `deps` into one `Dot` or `Affine` node, absorbing the products
+/// among them that nothing else uses. Returns `None` if there is nothing to fuse or more than
+/// one term that is not a product.
+fn fuse_sum(
+    rebuilt: &[Rebuilt],
+    deps: [usize; 2],
+    absorbable: impl Fn(usize) -> bool,
+) -> Option<Rebuilt> {
+    if deps[0] == deps[1] {
+        return None;
+    }
+    let (mut biases, mut pairs) = (vec![], vec![]);
+    for dep in deps {
+        match product_terms(&rebuilt[dep]).filter(|_| absorbable(dep)) {
+            Some((bias, terms)) => {
+                biases.extend(bias);
+                pairs.extend(terms);
+            }
+            None => biases.push((dep, 1.)),
+        }
+    }
+    match biases.len() {
+        _ if pairs.is_empty() => None,
+        0 => Some(Rebuilt::Nary(Op::Dot, pairs)),
+        1 => {
+            biases.extend(pairs);
+            Some(Rebuilt::Nary(Op::Affine, biases))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dot, solve, sum, Gradient, Mat};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_optimize() {
+        fn f<'a>(x: &[Var<'a>]) -> Var<'a> {
+            let a = (x[0] * x[1]).exp() + dot(x, x);
+            let b = (x[1] * x[0]).exp() + dot(x, x);
+            let z = solve(&Mat::new(1, 1, vec![x[0] + 1.]), &[a]);
+            let c = sum(&[z[0], (x[0] + 1.).ln(), a * b]);
+            c.register_hook(|adjoint| adjoint * 2.);
+            c * (x[0] + 1.).ln()
+        }
+        let tape = Tape::with_provenance();
+        let x = tape.add_vars(&[0.5, -0.3]);
+        let y = f(&x);
+        let before = y.grad().wrt(&x);
+        let len = tape.len();
+        let remap = tape.optimize();
+        assert_eq!(remap.removed(), 7);
+        assert_eq!(tape.len(), len - 7);
+        let (x, y) = (remap.vars(&x), remap.var(&y));
+        let after = y.grad().wrt(&x);
+        assert_approx_eq!(before[0], after[0]);
+        assert_approx_eq!(before[1], after[1]);
+        assert_eq!(
+            tape.provenance.as_ref().unwrap().borrow()[y.location],
+            y.val
+        );
+
+        // a second pass finds nothing left to remove
+        assert_eq!(tape.optimize().removed(), 0);
+    }
+
+    #[test]
+    fn test_fuse() {
+        fn f<'a>(x: &[Var<'a>]) -> [Var<'a>; 2] {
+            let shared = x[0] * x[2];
+            let a = x[0] * x[1] + (x[1] * x[2] + x[3].sin()) + shared;
+            let b = (x[3] * 2. * -0.5 * 4. + 1. + 2.).exp() - shared * x[1];
+            [a * b, a]
+        }
+        let point = [0.5, -1.5, 2., 0.3];
+        let tape = Tape::new();
+        let x = tape.add_vars(&point);
+        let y = f(&x);
+        let (grads, len) = (y[0].grad().wrt(&x), tape.len());
+        let remap = tape.fuse(&y);
+        // two products and a sum fused into the sum above them, two scalings and a shift
+        assert_eq!(remap.removed(), 6);
+        assert_eq!(tape.len(), len - 6);
+        let (x, y) = (remap.vars(&x), remap.vars(&y));
+        for (fused, grad) in y[0].grad().wrt(&x).iter().zip(grads) {
+            assert_approx_eq!(*fused, grad);
+        }
+        assert_approx_eq!(y[1].grad().wrt(&x[2]), point[1] + point[0]);
+
+        let fresh = Tape::new();
+        let shifted = [1., 2., -0.5, 0.7];
+        let expected = f(&fresh.add_vars(&shifted))[0].val;
+        assert_approx_eq!(tape.replay(&shifted).unwrap().wrt(&y[0]), expected);
+    }
+
+    #[test]
+    #[should_panic(expected = "the node at location 2 was removed")]
+    fn test_removed() {
+        let tape = Tape::new();
+        let x = tape.add_vars(&[1., 2.]);
+        let z = x[0] * 2.;
+        let y = z * 3.;
+        let remap = tape.fuse(&[y]);
+        let _ = remap.var(&z);
+    }
+}
@@ -0,0 +1,713 @@
+//! Parsing formulas given as strings at runtime and recording them on a tape.
+//!
+//! Formulas use the usual infix syntax, with `^` (or `**`) for powers, unary minus, parentheses,
+//! the constants `pi` and `e`, and calls to the functions of `Var` by name, such as `sin(x)`,
+//! `ln_1p(x)`, `atan2(y, x)` or `log(x, 10)`. The functions `pow`, `max` and `min` are also
+//! available. Any other name is a variable. Parentheses, calls, negations and powers can be nested
+//! up to `Formula::MAX_DEPTH` levels deep.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let (y, vars) = tape.parse("x0^2 * sin(x1) + 3", &[("x0", 2.), ("x1", 0.5)]).unwrap();
+//! assert_eq!(y.val(), 4. * 0.5_f64.sin() + 3.);
+//! assert_eq!(y.grad().wrt(&vars), vec![4. * 0.5_f64.sin(), 4. * 0.5_f64.cos()]);
+//!
+//! // a formula can also be parsed once and evaluated many times
+//! let formula: Formula = "max(a, b) / (1 + a^2)".parse().unwrap();
+//! assert_eq!(formula.vars(), &["a", "b"]);
+//! assert_eq!(formula.eval(&[1., 3.]), 1.5);
+//! ```
+
+use crate::{error::assert_same_tape, Real, Tape, Var};
+use std::{
+    error::Error,
+    f64::consts::{E, PI},
+    fmt::{self, Display},
+    str::FromStr,
+};
+
+/// Error returned when parsing a formula.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ParseError {
+    /// The token starting at byte `position` does not fit where it appears.
+    Unexpected { position: usize, found: String },
+    /// The formula ended in the middle of an expression.
+    UnexpectedEnd,
+    /// The function called at byte `position` does not exist or takes a different number of
+    /// arguments.
+    UnknownFunction {
+        position: usize,
+        name: String,
+        args: usize,
+    },
+    /// The variable is used by the formula but was not given a value.
+    UnknownVariable { name: String },
+    /// The token at byte `position` is nested more than `Formula::MAX_DEPTH` levels deep.
+    TooDeep { position: usize },
+}
+
+impl Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Unexpected { position, found } => {
+                write!(f, "unexpected `{}` at position {}", found, position)
+            }
+            Self::UnexpectedEnd => write!(f, "formula ended unexpectedly"),
+            Self::UnknownFunction {
+                position,
+                name,
+                args,
+            } => write!(
+                f,
+                "no function `{}` with {} arguments at position {}",
+                name, args, position
+            ),
+            Self::UnknownVariable { name } => write!(f, "no value for variable `{}`", name),
+            Self::TooDeep { position } => write!(
+                f,
+                "formula is nested more than {} levels deep at position {}",
+                Formula::MAX_DEPTH,
+                position
+            ),
+        }
+    }
+}
+
+impl Error for ParseError {}
+
+/// Functions of one variable, named as the methods of `Var`.
+const UNARY: [&str; 34] = [
+    "sin", "cos", "tan", "ln", "log10", "log2", "ln_1p", "asin", "acos", "atan", "sinh", "cosh",
+    "tanh", "asinh", "acosh", "atanh", "exp", "exp_m1", "exp2", "sqrt", "cbrt", "abs", "recip",
+    "floor", "ceil", "round", "trunc", "signum", "fract", "erf", "erfc", "lgamma", "sinc",
+    "norm_cdf",
+];
+
+/// Functions of two variables.
+const BINARY: [&str; 5] = ["atan2", "hypot", "pow", "logaddexp", "log"];
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Num(f64),
+    Name(String),
+    /// One of `+ - * / ^ ( ) ,`, with `**` read as `^`.
+    Symbol(char),
+}
+
+/// Split `src` into tokens, each with the byte position where it starts.
+fn tokenize(src: &str) -> Result<Vec<(usize, Token)>, ParseError> {
+    let bytes = src.as_bytes();
+    let mut tokens = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let c = bytes[i];
+        let start = i;
+        if c.is_ascii_whitespace() {
+            i += 1;
+            continue;
+        }
+        if c.is_ascii_digit() || c == b'.' {
+            while i < bytes.len() && (bytes[i].is_ascii_digit() || bytes[i] == b'.') {
+                i += 1;
+            }
+            // an exponent, but not a variable named `e` following a number
+            if i < bytes.len() && (bytes[i] == b'e' || bytes[i] == b'E') {
+                let mut j = i + 1;
+                if j < bytes.len() && (bytes[j] == b'+' || bytes[j] == b'-') {
+                    j += 1;
+                }
+                if j < bytes.len() && bytes[j].is_ascii_digit() {
+                    i = j;
+                    while i < bytes.len() && bytes[i].is_ascii_digit() {
+                        i += 1;
+                    }
+                }
+            }
+            let text = &src[start..i];
+            let num = text.parse().map_err(|_| ParseError::Unexpected {
+                position: start,
+                found: text.to_string(),
+            })?;
+            tokens.push((start, Token::Num(num)));
+        } else if c.is_ascii_alphabetic() || c == b'_' {
+            while i < bytes.len() && (bytes[i].is_ascii_alphanumeric() || bytes[i] == b'_') {
+                i += 1;
+            }
+            tokens.push((start, Token::Name(src[start..i].to_string())));
+        } else if src[i..].starts_with("**") {
+            tokens.push((start, Token::Symbol('^')));
+            i += 2;
+        } else if b"+-*/^(),".contains(&c) {
+            tokens.push((start, Token::Symbol(c as char)));
+            i += 1;
+        } else {
+            let found = src[i..].chars().next().unwrap();
+            return Err(ParseError::Unexpected {
+                position: start,
+                found: found.to_string(),
+            });
+        }
+    }
+    Ok(tokens)
+}
+
+/// Parsed formula, as a tree.
+#[derive(Debug, Clone, PartialEq)]
+enum Expr {
+    Num(f64),
+    /// Variable with this index in `Formula::vars`.
+    Var(usize),
+    Neg(Box<Expr>),
+    /// Base raised to an exponent.
+    Power(Box<Expr>, Box<Expr>),
+    /// Expression followed by operands of `+ -` or of `* /`, applied from left to right. Keeping
+    /// the operands in a list rather than in nested `Binary` nodes means that a long sum does not
+    /// make the tree deep.
+    Chain(Box<Expr>, Vec<(char, Expr)>),
+    Call(&'static str, Vec<Expr>),
+}
+
+/// Recursive descent parser over tokens, collecting the names of variables as they appear.
+struct Parser {
+    tokens: Vec<(usize, Token)>,
+    next: usize,
+    vars: Vec<String>,
+    /// Levels of nesting around the next token.
+    depth: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.next).map(|(_, token)| token)
+    }
+
+    /// Consume the next token if it is the symbol `c`.
+    fn eat(&mut self, c: char) -> bool {
+        if self.peek() == Some(&Token::Symbol(c)) {
+            self.next += 1;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Parse with `parse` one level of nesting deeper, failing past `Formula::MAX_DEPTH`.
+    fn nested(
+        &mut self,
+        parse: impl FnOnce(&mut Self) -> Result<Expr, ParseError>,
+    ) -> Result<Expr, ParseError> {
+        if self.depth == Formula::MAX_DEPTH {
+            return Err(match self.tokens.get(self.next) {
+                Some(&(position, _)) => ParseError::TooDeep { position },
+                None => ParseError::UnexpectedEnd,
+            });
+        }
+        self.depth += 1;
+        let expr = parse(self);
+        self.depth -= 1;
+        expr
+    }
+
+    /// Gets an error for the next token, which does not fit.
+    fn unexpected(&self) -> ParseError {
+        match self.tokens.get(self.next) {
+            Some((position, token)) => ParseError::Unexpected {
+                position: *position,
+                found: match token {
+                    Token::Num(num) => num.to_string(),
+                    Token::Name(name) => name.clone(),
+                    Token::Symbol(c) => c.to_string(),
+                },
+            },
+            None => ParseError::UnexpectedEnd,
+        }
+    }
+
+    /// Parse operands with `operand`, separated by any of the left-associative operators `ops`.
+    fn chain(
+        &mut self,
+        ops: [char; 2],
+        operand: fn(&mut Self) -> Result<Expr, ParseError>,
+    ) -> Result<Expr, ParseError> {
+        let first = operand(self)?;
+        let mut rest = vec![];
+        while let Some(&Token::Symbol(c)) = self.peek() {
+            if !ops.contains(&c) {
+                break;
+            }
+            self.next += 1;
+            rest.push((c, operand(self)?));
+        }
+        Ok(if rest.is_empty() {
+            first
+        } else {
+            Expr::Chain(Box::new(first), rest)
+        })
+    }
+
+    /// Parse a sum or difference of terms.
+    fn expr(&mut self) -> Result<Expr, ParseError> {
+        self.chain(['+', '-'], Self::term)
+    }
+
+    /// Parse a product or quotient of factors.
+    fn term(&mut self) -> Result<Expr, ParseError> {
+        self.chain(['*', '/'], Self::factor)
+    }
+
+    /// Parse a negation or a power, which binds more tightly so that `-x^2` is `-(x^2)`. Powers
+    /// are right-associative.
+    fn factor(&mut self) -> Result<Expr, ParseError> {
+        if self.eat('-') {
+            return Ok(Expr::Neg(Box::new(self.nested(Self::factor)?)));
+        }
+        let base = self.atom()?;
+        if self.eat('^') {
+            let exponent = self.nested(Self::factor)?;
+            return Ok(Expr::Power(Box::new(base), Box::new(exponent)));
+        }
+        Ok(base)
+    }
+
+    fn atom(&mut self) -> Result<Expr, ParseError> {
+        let (position, token) = match self.tokens.get(self.next) {
+            Some(next) => next.clone(),
+            None => return Err(ParseError::UnexpectedEnd),
+        };
+        match token {
+            Token::Num(num) => {
+                self.next += 1;
+                Ok(Expr::Num(num))
+            }
+            Token::Symbol('(') => {
+                self.next += 1;
+                let expr = self.nested(Self::expr)?;
+                if !self.eat(')') {
+                    return Err(self.unexpected());
+                }
+                Ok(expr)
+            }
+            Token::Name(name) => {
+                self.next += 1;
+                if self.eat('(') {
+                    return self.call(position, name);
+                }
+                Ok(match name.as_str() {
+                    "pi" => Expr::Num(PI),
+                    "e" => Expr::Num(E),
+                    _ => {
+                        let index = match self.vars.iter().position(|var| *var == name) {
+                            Some(index) => index,
+                            None => {
+                                self.vars.push(name);
+                                self.vars.len() - 1
+                            }
+                        };
+                        Expr::Var(index)
+                    }
+                })
+            }
+            Token::Symbol(_) => Err(self.unexpected()),
+        }
+    }
+
+    /// Parse the arguments of a call to `name`, after the opening parenthesis.
+    fn call(&mut self, position: usize, name: String) -> Result<Expr, ParseError> {
+        let mut args = vec![];
+        if !self.eat(')') {
+            loop {
+                args.push(self.nested(Self::expr)?);
+                if self.eat(')') {
+                    break;
+                }
+                if !self.eat(',') {
+                    return Err(self.unexpected());
+                }
+            }
+        }
+        let known = match args.len() {
+            1 => UNARY
+                .iter()
+                .chain(&["log", "max", "min"])
+                .find(|&&f| f == name),
+            2 => BINARY.iter().chain(&["max", "min"]).find(|&&f| f == name),
+            _ => ["max", "min"].iter().find(|&&f| f == name),
+        };
+        match known {
+            Some(&function) if !args.is_empty() => Ok(Expr::Call(function, args)),
+            _ => Err(ParseError::UnknownFunction {
+                position,
+                name,
+                args: args.len(),
+            }),
+        }
+    }
+}
+
+/// Formula parsed from a string, which can be evaluated on numbers or recorded on a tape any
+/// number of times.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Formula {
+    expr: Expr,
+    vars: Vec<String>,
+}
+
+impl Formula {
+    /// Deepest nesting of parentheses, calls, negations and powers that `parse` accepts, so that
+    /// parsing and evaluating a formula cannot overflow the stack.
+    pub const MAX_DEPTH: usize = 256;
+
+    /// Parse the formula `src`.
+    pub fn parse(src: &str) -> Result<Self, ParseError> {
+        let mut parser = Parser {
+            tokens: tokenize(src)?,
+            next: 0,
+            vars: vec![],
+            depth: 0,
+        };
+        let expr = parser.expr()?;
+        if parser.next < parser.tokens.len() {
+            return Err(parser.unexpected());
+        }
+        Ok(Self {
+            expr,
+            vars: parser.vars,
+        })
+    }
+
+    /// Gets the names of the variables of the formula, in the order they first appear.
+    pub fn vars(&self) -> &[String] {
+        &self.vars
+    }
+
+    /// Evaluate the formula with `vals` as the values of its variables, in the order of `vars`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of values is wrong.
+    pub fn eval(&self, vals: &[f64]) -> f64 {
+        assert_eq!(vals.len(), self.vars.len(), "need one value per variable");
+        match eval(&self.expr, vals, &|c| c) {
+            Value::Num(val) | Value::Real(val) => val,
+        }
+    }
+
+    /// Record the formula on `tape` with `vars` as its variables, in the order of `vars`.
+    /// Constant subexpressions are computed while recording rather than recorded.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the number of variables is wrong or they are on other tapes.
+    pub fn record<'a>(&self, tape: &'a Tape, vars: &[Var<'a>]) -> Var<'a> {
+        assert_eq!(vars.len(), self.vars.len(), "need one variable per name");
+        match eval(&self.expr, vars, &|c| tape.constant(c)) {
+            Value::Num(val) => tape.constant(val),
+            Value::Real(var) => {
+                assert_same_tape("Formula::record", tape, var.tape);
+                var
+            }
+        }
+    }
+}
+
+impl FromStr for Formula {
+    type Err = ParseError;
+
+    fn from_str(src: &str) -> Result<Self, Self::Err> {
+        Self::parse(src)
+    }
+}
+
+impl Tape {
+    /// Parse the formula `src` and record it, adding an input variable for each of `vars`, a
+    /// name and a value, in their order. Returns the result and the new variables.
+    ///
+    /// Returns an error if the formula is malformed or uses a variable that is not in `vars`.
+    pub fn parse<'a>(
+        &'a self,
+        src: &str,
+        vars: &[(&str, f64)],
+    ) -> Result<(Var<'a>, Vec<Var<'a>>), ParseError> {
+        let formula = Formula::parse(src)?;
+        let inputs = vars
+            .iter()
+            .map(|&(_, val)| self.add_var(val))
+            .collect::<Vec<_>>();
+        let args = formula
+            .vars
+            .iter()
+            .map(|name| match vars.iter().position(|&(var, _)| var == name) {
+                Some(index) => Ok(inputs[index]),
+                None => Err(ParseError::UnknownVariable { name: name.clone() }),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok((formula.record(self, &args), inputs))
+    }
+}
+
+/// Value of a subexpression, which is kept as a number while it does not depend on a variable.
+#[derive(Debug, Clone, Copy)]
+enum Value<T> {
+    Num(f64),
+    Real(T),
+}
+
+/// Evaluate `expr` with `vars` as the values of the variables, using `lift` to turn numbers into
+/// values where an operation needs both operands as values.
+fn eval<T: Real>(expr: &Expr, vars: &[T], lift: &impl Fn(f64) -> T) -> Value<T> {
+    use Value::*;
+    let real = |value: Value<T>| match value {
+        Num(c) => lift(c),
+        Real(x) => x,
+    };
+    match expr {
+        Expr::Num(c) => Num(*c),
+        Expr::Var(index) => Real(vars[*index]),
+        Expr::Neg(x) => match eval(x, vars, lift) {
+            Num(c) => Num(-c),
+            Real(x) => Real(-x),
+        },
+        Expr::Power(x, y) => infix('^', eval(x, vars, lift), eval(y, vars, lift), lift),
+        Expr::Chain(first, rest) => rest.iter().fold(eval(first, vars, lift), |lhs, (op, y)| {
+            infix(*op, lhs, eval(y, vars, lift), lift)
+        }),
+        Expr::Call(name, args) => {
+            let args = args
+                .iter()
+                .map(|arg| eval(arg, vars, lift))
+                .collect::<Vec<_>>();
+            if args.iter().all(|arg| matches!(arg, Num(_))) {
+                let nums = args.iter().map(|&arg| real_f64(arg)).collect::<Vec<_>>();
+                return Num(call(name, &nums));
+            }
+            match (args.as_slice(), *name) {
+                (_, "max") | (_, "min") | ([_], _) => {
+                    Real(call(name, &args.into_iter().map(real).collect::<Vec<_>>()))
+                }
+                // a constant second argument is kept as a number
+                ([Real(x), Num(y)], _) => Real(binary_const(name, *x, *y)),
+                _ => Real(call(name, &args.into_iter().map(real).collect::<Vec<_>>())),
+            }
+        }
+    }
+}
+
+/// Apply the infix operator `op`, keeping the result a number if both operands are.
+fn infix<T: Real>(op: char, x: Value<T>, y: Value<T>, lift: &impl Fn(f64) -> T) -> Value<T> {
+    use Value::*;
+    match (x, y) {
+        (Num(x), Num(y)) => Num(arithmetic(op, x, y)),
+        (Real(x), Num(y)) => Real(arithmetic_const(op, x, y)),
+        (Num(x), Real(y)) => Real(match op {
+            '+' => y + x,
+            '*' => y * x,
+            '-' => -y + x,
+            '/' => y.recip() * x,
+            // `x^y` with a constant base is recorded as a general power
+            _ => lift(x).powf(y),
+        }),
+        (Real(x), Real(y)) => Real(arithmetic(op, x, y)),
+    }
+}
+
+/// Gets a value that is known to be a number.
+fn real_f64<T>(value: Value<T>) -> f64 {
+    match value {
+        Value::Num(c) => c,
+        Value::Real(_) => unreachable!("value depends on a variable"),
+    }
+}
+
+/// Apply the infix operator `op`.
+fn arithmetic<T: Real>(op: char, x: T, y: T) -> T {
+    match op {
+        '+' => x + y,
+        '-' => x - y,
+        '*' => x * y,
+        '/' => x / y,
+        _ => x.powf(y),
+    }
+}
+
+/// Apply the infix operator `op` with a constant right operand, using `powi` for integer powers.
+fn arithmetic_const<T: Real>(op: char, x: T, y: f64) -> T {
+    match op {
+        '+' => x + y,
+        '-' => x - y,
+        '*' => x * y,
+        '/' => x / y,
+        _ if y.fract() == 0. && y.abs() <= i32::MAX as f64 => x.powi(y as i32),
+        _ => x.powf(y),
+    }
+}
+
+/// Apply the function `name` to `args`, whose number was checked while parsing.
+fn call<T: Real>(name: &str, args: &[T]) -> T {
+    match (name, args) {
+        ("max", _) | ("min", _) => extremum(name, args),
+        (_, [x]) => unary(name, *x),
+        (_, [x, y]) => binary(name, *x, *y),
+        _ => unreachable!("checked while parsing"),
+    }
+}
+
+/// Gets the maximum or minimum of `args`, which is the first of them on ties, so that only it
+/// receives a gradient.
+fn extremum<T: Real>(name: &str, args: &[T]) -> T {
+    let mut best = args[0];
+    for &x in &args[1..] {
+        if (name == "max" && x > best) || (name == "min" && x < best) {
+            best = x;
+        }
+    }
+    best
+}
+
+fn unary<T: Real>(name: &str, x: T) -> T {
+    match name {
+        "sin" => x.sin(),
+        "cos" => x.cos(),
+        "tan" => x.tan(),
+        "ln" | "log" => x.ln(),
+        "log10" => x.log10(),
+        "log2" => x.log2(),
+        "ln_1p" => x.ln_1p(),
+        "asin" => x.asin(),
+        "acos" => x.acos(),
+        "atan" => x.atan(),
+        "sinh" => x.sinh(),
+        "cosh" => x.cosh(),
+        "tanh" => x.tanh(),
+        "asinh" => x.asinh(),
+        "acosh" => x.acosh(),
+        "atanh" => x.atanh(),
+        "exp" => x.exp(),
+        "exp_m1" => x.exp_m1(),
+        "exp2" => x.exp2(),
+        "sqrt" => x.sqrt(),
+        "cbrt" => x.cbrt(),
+        "abs" => x.abs(),
+        "recip" => x.recip(),
+        "floor" => x.floor(),
+        "ceil" => x.ceil(),
+        "round" => x.round(),
+        "trunc" => x.trunc(),
+        "signum" => x.signum(),
+        "fract" => x.fract(),
+        "erf" => x.erf(),
+        "erfc" => x.erfc(),
+        "lgamma" => x.lgamma(),
+        "sinc" => x.sinc(),
+        "norm_cdf" => x.norm_cdf(),
+        _ => unreachable!("checked while parsing"),
+    }
+}
+
+fn binary<T: Real>(name: &str, x: T, y: T) -> T {
+    match name {
+        "atan2" => x.atan2(y),
+        "hypot" => x.hypot(y),
+        "pow" => x.powf(y),
+        "logaddexp" => x.logaddexp(y),
+        // the logarithm of `x` to the base `y`
+        _ => x.ln() / y.ln(),
+    }
+}
+
+fn binary_const<T: Real>(name: &str, x: T, y: f64) -> T {
+    match name {
+        "atan2" => x.atan2(y),
+        "hypot" => x.hypot(y),
+        "pow" => arithmetic_const('^', x, y),
+        "logaddexp" => x.logaddexp(y),
+        _ => x.log(y),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Gradient, Hypot};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_parse() {
+        let f = Formula::parse("-x^2 + 2 * 3^2 - y / 4 * (1 - x) + hypot(x, 3) * -log(y, 2e0)")
+            .unwrap();
+        assert_eq!(f.vars(), &["x", "y"]);
+        let (x, y) = (1.5_f64, 0.25_f64);
+        let expected = -x.powi(2) + 18. - y / 4. * (1. - x) + x.hypot(3.) * -y.log2();
+        assert_approx_eq!(f.eval(&[x, y]), expected);
+
+        let g = Tape::new();
+        let vars = g.add_vars(&[x, y]);
+        let res = f.record(&g, &vars);
+        assert_approx_eq!(res.val(), expected);
+        let naive = -vars[0].powi(2) + 18. - vars[1] / 4. * (-vars[0] + 1.)
+            + vars[0].hypot(3.) * -vars[1].log2();
+        let (grads, expected) = (res.grad().wrt(&vars), naive.grad().wrt(&vars));
+        for (grad, expected) in grads.iter().zip(expected) {
+            assert_approx_eq!(*grad, expected);
+        }
+
+        // constant subexpressions are not recorded
+        let len = g.len();
+        let res = g.parse("2^x * max(1, x, pi) - e", &[("x", 2.)]).unwrap().0;
+        assert_eq!(g.len(), len + 7);
+        assert_approx_eq!(res.val(), 4. * PI - E);
+        let x = g.len() - 7;
+        assert_approx_eq!(res.grad()[x], 4. * 2_f64.ln() * PI);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = |src| Formula::parse(src).unwrap_err();
+        assert_eq!(
+            err("x + * 2"),
+            ParseError::Unexpected {
+                position: 4,
+                found: "*".to_string()
+            }
+        );
+        assert_eq!(err("(x + 1"), ParseError::UnexpectedEnd);
+        assert_eq!(
+            err("1 + sin(x, y)"),
+            ParseError::UnknownFunction {
+                position: 4,
+                name: "sin".to_string(),
+                args: 2
+            }
+        );
+        assert!(matches!(
+            err("x $ y"),
+            ParseError::Unexpected { position: 2, .. }
+        ));
+        let g = Tape::new();
+        assert_eq!(
+            g.parse("x * z", &[("x", 1.)]).unwrap_err(),
+            ParseError::UnknownVariable {
+                name: "z".to_string()
+            }
+        );
+
+        let nested = |depth| "(".repeat(depth) + "x" + &")".repeat(depth);
+        assert!(Formula::parse(&nested(Formula::MAX_DEPTH)).is_ok());
+        assert_eq!(
+            Formula::parse(&nested(100_000)),
+            Err(ParseError::TooDeep {
+                position: Formula::MAX_DEPTH + 1
+            })
+        );
+        // long sums and products are not nested
+        let sum = vec!["x"; 100_000].join(" - ");
+        assert_eq!(Formula::parse(&sum).unwrap().eval(&[1.]), -99_998.);
+        for src in ["-", "x^", "sin("] {
+            assert!(matches!(
+                Formula::parse(&src.repeat(100_000)),
+                Err(ParseError::TooDeep { .. })
+            ));
+        }
+    }
+}
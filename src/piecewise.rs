@@ -0,0 +1,226 @@
+//! Functions that choose between branches depending on a value, and functions defined piecewise.
+//!
+//! Branching on `val` by hand works, but only gives the right gradients if every branch is
+//! computed from variables on the tape. These functions make the choice explicit, and the
+//! gradient flows only through the chosen branch. `select_gt` records the comparison, so replaying
+//! or compiling the tape makes the choice again at the new point. Choices made outside the tape,
+//! by `select` and `Piecewise`, are recorded as such, and replaying or compiling a tape that holds
+//! one returns an error instead of silently keeping the branch taken when it was recorded.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_var(-2.);
+//! // a leaky ReLU
+//! let y = select_gt(x, 0., x, x * 0.1);
+//! assert_eq!(y.val(), -0.2);
+//! assert_eq!(y.grad().wrt(&x), 0.1);
+//! ```
+//!
+//! `Piecewise` builds a function from one closure for each interval between breakpoints, such as
+//! a schedule of marginal tax rates:
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tax = Piecewise::new(|x| x * 0.)
+//!     .then(10_000., |x| (x - 10_000.) * 0.2)
+//!     .then(50_000., |x| (x - 50_000.) * 0.4 + 8_000.);
+//!
+//! let tape = Tape::new();
+//! let income = tape.add_var(60_000.);
+//! let owed = tax.eval(income);
+//! assert_eq!(owed.val(), 12_000.);
+//! assert_eq!(owed.grad().wrt(&income), 0.4);
+//! ```
+
+use crate::{error::assert_same_tape, Op, Var};
+use std::fmt::{self, Debug};
+
+/// Function of one variable defined by a different closure on each interval.
+type Segment = Box<dyn for<'a> Fn(Var<'a>) -> Var<'a> + Send + Sync>;
+
+/// Choose `a` if `cond` holds and `b` otherwise. Both branches must be on the same tape, and the
+/// result is a copy of the chosen variable, so gradients only flow to that branch. Since `cond`
+/// is not on the tape, the tape can no longer be replayed or compiled; use `select_gt` to branch
+/// on a variable.
+///
+/// # Panics
+///
+/// Panics if the branches are on different tapes.
+pub fn select<'a>(cond: bool, a: Var<'a>, b: Var<'a>) -> Var<'a> {
+    assert_same_tape("select", a.tape, b.tape);
+    if cond {
+        a.unary(Op::Select)
+    } else {
+        b.unary(Op::Select)
+    }
+}
+
+/// Choose `a` if `x` is greater than `threshold` and `b` otherwise. No gradient flows to `x`
+/// through the comparison, as it is piecewise constant. The comparison is recorded, so replays
+/// and compiled programs choose again at each point.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let x = tape.add_var(-2.);
+/// let y = select_gt(x, 0., x * 3., x * 0.1);
+/// assert_eq!(y.val(), -0.2);
+/// let vals = tape.replay(&[5.]).unwrap();
+/// assert_eq!(vals.wrt(&y), 15.);
+/// assert_eq!(y.grad().wrt(&x), 3.);
+/// ```
+///
+/// # Panics
+///
+/// Panics if the variables are on different tapes.
+pub fn select_gt<'a>(x: Var<'a>, threshold: f64, a: Var<'a>, b: Var<'a>) -> Var<'a> {
+    assert_same_tape("select_gt", x.tape, a.tape);
+    assert_same_tape("select_gt", x.tape, b.tape);
+    let op = Op::SelectGt(threshold);
+    let (val, weights) = op.eval_nary(&[x.val, a.val, b.val]);
+    Var {
+        val,
+        location: x
+            .tape
+            .add_nary_node(op, val, &[x.location, a.location, b.location], &weights),
+        tape: x.tape,
+    }
+}
+
+/// Function built from one differentiable closure for each interval between increasing
+/// breakpoints. At a breakpoint itself the function and its gradient are those of the segment
+/// starting there, so the function is continuous from the right.
+///
+/// Only the segment containing the point is evaluated, so a tape holding a function with
+/// breakpoints cannot be replayed or compiled, as the other segments were never recorded.
+pub struct Piecewise {
+    /// Breakpoint at which each segment after the first starts.
+    breakpoints: Vec<f64>,
+    segments: Vec<Segment>,
+}
+
+impl Piecewise {
+    /// Create a function that is `segment` everywhere, until breakpoints are added with `then`.
+    pub fn new(segment: impl for<'a> Fn(Var<'a>) -> Var<'a> + Send + Sync + 'static) -> Self {
+        Self {
+            breakpoints: vec![],
+            segments: vec![Box::new(segment)],
+        }
+    }
+
+    /// Use `segment` from `breakpoint` onwards, up to the next breakpoint.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `breakpoint` is not greater than the previous breakpoint.
+    pub fn then(
+        mut self,
+        breakpoint: f64,
+        segment: impl for<'a> Fn(Var<'a>) -> Var<'a> + Send + Sync + 'static,
+    ) -> Self {
+        assert!(
+            self.breakpoints
+                .last()
+                .map_or(true, |&last| breakpoint > last),
+            "breakpoints must be increasing"
+        );
+        self.breakpoints.push(breakpoint);
+        self.segments.push(Box::new(segment));
+        self
+    }
+
+    /// Gets the breakpoints, in increasing order.
+    pub fn breakpoints(&self) -> &[f64] {
+        &self.breakpoints
+    }
+
+    /// Evaluate the function at `x`, using the segment whose interval contains it.
+    pub fn eval<'a>(&self, x: Var<'a>) -> Var<'a> {
+        let segment = self.breakpoints.partition_point(|&b| b <= x.val);
+        let y = (self.segments[segment])(x);
+        if self.breakpoints.is_empty() {
+            y
+        } else {
+            y.unary(Op::Select)
+        }
+    }
+}
+
+impl Debug for Piecewise {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Piecewise")
+            .field("breakpoints", &self.breakpoints)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Gradient, Tape};
+
+    #[test]
+    fn test_select() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2., 3.]);
+        let y = select(true, x[0] * x[1], x[2]) + select_gt(x[2], 3., x[0], x[1] * 4.);
+        assert_eq!(y.val(), 2. + 8.);
+        assert_eq!(y.grad().wrt(&x), vec![2., 1. + 4., 0.]);
+    }
+
+    #[test]
+    fn test_piecewise() {
+        let f = Piecewise::new(|x| -x)
+            .then(0., |x| x.powi(2))
+            .then(2., |x| x * 4. - 4.);
+        assert_eq!(f.breakpoints(), &[0., 2.]);
+        let g = Tape::new();
+        for &(x, val, grad) in &[(-1., 1., -1.), (0., 0., 0.), (1., 1., 2.), (2., 4., 4.)] {
+            let x = g.add_var(x);
+            let y = f.eval(x);
+            assert_eq!((y.val(), y.grad().wrt(&x)), (val, grad));
+        }
+    }
+
+    #[test]
+    fn test_replay_branches() {
+        let g = Tape::new();
+        let x = g.add_var(-2.);
+        let y = select_gt(x, 0., x * 3., x * 0.1);
+        let program = g.compile().unwrap();
+        for &(x0, val, grad) in &[(5., 15., 3.), (-1., -0.1, 0.1), (0., 0., 0.1)] {
+            assert_eq!(g.replay(&[x0]).unwrap().wrt(&y), val);
+            assert_eq!(y.grad().wrt(&x), grad);
+            assert_eq!(program.grad(&[x0], &y).wrt(&x), grad);
+        }
+
+        // choices made off the tape cannot be made again
+        let f = Piecewise::new(|x| x * 0.).then(0., |x| x * 2.);
+        let _ = f.eval(x);
+        let len = g.len();
+        assert!(g.replay(&[5.]).is_err());
+        assert!(g.compile().is_err());
+        assert_eq!(g.len(), len);
+        let g = Tape::new();
+        let x = g.add_var(1.);
+        let _ = select(true, x, x * 2.);
+        assert!(g.replay(&[2.]).is_err());
+    }
+
+    #[test]
+    #[should_panic(expected = "increasing")]
+    fn test_piecewise_unordered() {
+        let _ = Piecewise::new(|x| x).then(1., |x| x).then(1., |x| x);
+    }
+
+    #[test]
+    #[should_panic(expected = "different tapes")]
+    fn test_select_different_tapes() {
+        let (g1, g2) = (Tape::new(), Tape::new());
+        let _ = select(false, g1.add_var(1.), g2.add_var(2.));
+    }
+}
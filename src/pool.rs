@@ -0,0 +1,239 @@
+//! Evaluating a function at many independent points, on one tape or in parallel.
+//!
+//! A tape cannot be shared between threads, so `TapePool` gives each worker thread a tape of its
+//! own, which is cleared and reused for every item that worker handles.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! fn loss<'a>(params: &[Var<'a>], sample: &(f64, f64)) -> Var<'a> {
+//!     let (x, y) = *sample;
+//!     (params[0] * x + params[1] - y).powi(2)
+//! }
+//!
+//! let data = (0..100).map(|i| (i as f64, 2. * i as f64 + 1.)).collect::<Vec<_>>();
+//! let pool = TapePool::new(4);
+//! let (total, grads) = pool.grad_sum(&[2., 1.], &data, loss);
+//! assert_eq!(total, 0.);
+//! assert_eq!(grads, vec![0., 0.]);
+//! ```
+
+use crate::{Gradient, Tape, Var};
+use std::{cell::Cell, num::NonZeroUsize, panic, thread};
+
+thread_local! {
+    static TAPE: Tape = Tape::new();
+    static IN_USE: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` with this thread's tape, which is cleared first. The tape keeps its allocations between
+/// calls, so this is cheaper than creating a new tape each time.
+///
+/// # Panics
+///
+/// Panics if called from within `f`, as that would clear the tape while it is in use.
+pub fn with_tape<R>(f: impl FnOnce(&Tape) -> R) -> R {
+    IN_USE.with(|in_use| {
+        assert!(!in_use.replace(true), "the thread's tape is already in use");
+        // release the tape even if `f` panics
+        struct Release<'c>(&'c Cell<bool>);
+        impl Drop for Release<'_> {
+            fn drop(&mut self) {
+                self.0.set(false);
+            }
+        }
+        let _release = Release(in_use);
+        TAPE.with(|tape| {
+            tape.clear();
+            f(tape)
+        })
+    })
+}
+
+impl Tape {
+    /// Calculate the value and gradient of `f` at each point of `inputs`, in the same order. Each
+    /// evaluation is recorded after the tape's current contents and rewound afterwards, so the
+    /// tape's allocations are reused across the batch and variables already on it stay valid.
+    ///
+    /// ```rust
+    /// use reverse::*;
+    ///
+    /// fn f<'a>(x: &[Var<'a>]) -> Var<'a> {
+    ///     x[0] * x[1].exp()
+    /// }
+    ///
+    /// let tape = Tape::new();
+    /// let results = tape.map_batch(f, &[vec![1., 0.], vec![2., 1.]]);
+    /// assert_eq!(results[0], (1., vec![1., 1.]));
+    /// assert_eq!(results[1].1, vec![1_f64.exp(), 2. * 1_f64.exp()]);
+    /// assert!(tape.is_empty());
+    /// ```
+    pub fn map_batch<F>(&self, f: F, inputs: &[Vec<f64>]) -> Vec<(f64, Vec<f64>)>
+    where
+        F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+    {
+        let mark = self.mark();
+        inputs
+            .iter()
+            .map(|point| {
+                let vars = self.add_vars(point);
+                let res = f(&vars);
+                let grads = res.grad().wrt(&vars);
+                let val = res.val();
+                self.rewind_to(mark);
+                (val, grads)
+            })
+            .collect()
+    }
+}
+
+/// Splits work over a fixed number of threads, each with its own tape.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TapePool {
+    threads: usize,
+}
+
+impl TapePool {
+    /// Create a pool that uses up to `threads` threads.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `threads` is zero.
+    pub fn new(threads: usize) -> Self {
+        assert!(threads > 0, "need at least one thread");
+        Self { threads }
+    }
+
+    /// Gets the maximum number of threads used.
+    pub fn threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Apply `f` to each item on a cleared tape, returning the results in the same order as
+    /// `items`. Items are split into one contiguous chunk for each thread.
+    pub fn map<T, R, F>(&self, items: &[T], f: F) -> Vec<R>
+    where
+        T: Sync,
+        R: Send,
+        F: Fn(&Tape, &T) -> R + Sync,
+    {
+        if items.is_empty() {
+            return vec![];
+        }
+        let chunk = items.len().div_ceil(self.threads);
+        let f = &f;
+        thread::scope(|scope| {
+            let workers = items
+                .chunks(chunk)
+                .map(|items| {
+                    scope.spawn(move || {
+                        items
+                            .iter()
+                            .map(|item| with_tape(|tape| f(tape, item)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>();
+            workers
+                .into_iter()
+                .flat_map(|worker| {
+                    worker
+                        .join()
+                        .unwrap_or_else(|err| panic::resume_unwind(err))
+                })
+                .collect()
+        })
+    }
+
+    /// Calculate the value and gradient of `f(params, item)` with respect to `params` for each
+    /// item, in the same order as `items`.
+    pub fn grad_each<T, F>(&self, params: &[f64], items: &[T], f: F) -> Vec<(f64, Vec<f64>)>
+    where
+        T: Sync,
+        F: for<'a> Fn(&[Var<'a>], &T) -> Var<'a> + Sync,
+    {
+        self.map(items, |tape, item| {
+            let params = tape.add_vars(params);
+            let res = f(&params, item);
+            (res.val(), res.grad().wrt(&params))
+        })
+    }
+
+    /// Calculate the sum of `f(params, item)` over all items and its gradient with respect to
+    /// `params`, merging the gradients from each thread.
+    pub fn grad_sum<T, F>(&self, params: &[f64], items: &[T], f: F) -> (f64, Vec<f64>)
+    where
+        T: Sync,
+        F: for<'a> Fn(&[Var<'a>], &T) -> Var<'a> + Sync,
+    {
+        self.grad_each(params, items, f).into_iter().fold(
+            (0., vec![0.; params.len()]),
+            |(total, mut grads), (val, grad)| {
+                grads.iter_mut().zip(grad).for_each(|(g, x)| *g += x);
+                (total + val, grads)
+            },
+        )
+    }
+}
+
+/// Create a pool with one thread for each core, or a single thread if that cannot be determined.
+impl Default for TapePool {
+    fn default() -> Self {
+        Self::new(thread::available_parallelism().map_or(1, NonZeroUsize::get))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    fn loss<'a>(params: &[Var<'a>], x: &f64) -> Var<'a> {
+        (params[0] * *x).sin() * params[1]
+    }
+
+    #[test]
+    fn test_pool() {
+        let params = [0.5, 2.];
+        let items = (0..37).map(|i| i as f64 * 0.1).collect::<Vec<_>>();
+        let each = TapePool::new(3).grad_each(&params, &items, loss);
+        assert_eq!(each.len(), items.len());
+        for ((val, grad), x) in each.iter().zip(&items) {
+            assert_approx_eq!(*val, (0.5 * x).sin() * 2.);
+            assert_approx_eq!(grad[0], (0.5 * x).cos() * x * 2.);
+            assert_approx_eq!(grad[1], (0.5 * x).sin());
+        }
+
+        let (total, grads) = TapePool::default().grad_sum(&params, &items, loss);
+        let (serial, serial_grads) = TapePool::new(1).grad_sum(&params, &items, loss);
+        assert_approx_eq!(total, serial);
+        assert_approx_eq!(grads[0], serial_grads[0]);
+        assert_approx_eq!(grads[1], serial_grads[1]);
+        assert!(TapePool::new(8).map(&[] as &[f64], |_, x| *x).is_empty());
+    }
+
+    #[test]
+    fn test_map_batch() {
+        fn f<'a>(x: &[Var<'a>]) -> Var<'a> {
+            (x[0] * 0.5).sin() * x[1]
+        }
+        let g = Tape::new();
+        let a = g.add_var(3.);
+        let len = g.len();
+        let points = vec![vec![0.5, 2.], vec![-1., 0.25]];
+        let batch = g.map_batch(f, &points);
+        assert_eq!(g.len(), len);
+        for ((val, grads), point) in batch.iter().zip(&points) {
+            let x = g.add_vars(point);
+            let res = f(&x);
+            assert_eq!((*val, grads.clone()), (res.val(), res.grad().wrt(&x)));
+        }
+        assert_eq!((a * 2.).grad().wrt(&a), 2.);
+    }
+
+    #[test]
+    #[should_panic(expected = "already in use")]
+    fn test_nested_with_tape() {
+        with_tape(|_| with_tape(|tape| tape.len()));
+    }
+}
@@ -0,0 +1,209 @@
+//! Tracking down where NaNs and infinities come from, on tapes created with
+//! `Tape::with_provenance`.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::with_provenance();
+//! let x = tape.add_var(0.);
+//! let y = (x.sqrt() * 2.).sin();
+//! let grads = y.grad();
+//! let report = tape.find_non_finite(&grads).unwrap();
+//! // the value of `sqrt(0)` is fine, but its derivative is infinite
+//! assert_eq!(report.op, "Sqrt");
+//! assert!(matches!(report.kind, NonFiniteKind::Adjoint { .. }));
+//! ```
+
+use crate::Tape;
+use std::fmt::{self, Display};
+
+/// Node that introduced a NaN or infinity, as found by `Tape::find_non_finite`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct NonFinite {
+    /// Location of the node on the tape.
+    pub location: usize,
+    /// Name of the operation that produced the node.
+    pub op: String,
+    /// Value of the node.
+    pub value: f64,
+    pub kind: NonFiniteKind,
+}
+
+/// What a `NonFinite` node did wrong.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum NonFiniteKind {
+    /// The node computed a non-finite value from finite dependencies.
+    Value,
+    /// The node has a finite adjoint but propagated a non-finite contribution to the adjoint of
+    /// its dependency at location `input`, for example because the operation has an infinite
+    /// derivative.
+    Adjoint { input: usize, contribution: f64 },
+}
+
+impl Display for NonFinite {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "node {} ({} with value {}) ",
+            self.location, self.op, self.value
+        )?;
+        match self.kind {
+            NonFiniteKind::Value => write!(f, "has a non-finite value"),
+            NonFiniteKind::Adjoint {
+                input,
+                contribution,
+            } => write!(
+                f,
+                "propagated {} to the adjoint of node {}",
+                contribution, input
+            ),
+        }
+    }
+}
+
+impl Tape {
+    fn provenance_record(&self, location: usize) -> (String, f64) {
+        let provenance = self
+            .provenance
+            .as_ref()
+            .expect("tape does not record provenance, create it with `Tape::with_provenance`")
+            .borrow();
+        let op = self.ops.borrow()[location];
+        (format!("{:?}", op), provenance[location])
+    }
+
+    /// Finds the first node whose value is NaN or infinite. Its dependencies all have finite
+    /// values, so it is the node that introduced the non-finite value.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tape was not created with `Tape::with_provenance`.
+    pub fn non_finite_value(&self) -> Option<NonFinite> {
+        let location = self
+            .provenance
+            .as_ref()
+            .expect("tape does not record provenance, create it with `Tape::with_provenance`")
+            .borrow()
+            .iter()
+            .position(|val| !val.is_finite())?;
+        let (op, value) = self.provenance_record(location);
+        Some(NonFinite {
+            location,
+            op,
+            value,
+            kind: NonFiniteKind::Value,
+        })
+    }
+
+    /// Finds the node that introduced a NaN or infinity, either into the values on the tape or
+    /// into the gradients `grads` computed from it with `Var::grad`. Values are checked first,
+    /// since a non-finite value usually makes the gradients non-finite as well.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tape was not created with `Tape::with_provenance`.
+    pub fn find_non_finite(&self, grads: &[f64]) -> Option<NonFinite> {
+        if let Some(report) = self.non_finite_value() {
+            return Some(report);
+        }
+        // Every node above the last non-finite adjoint has a finite adjoint, so one of them must
+        // have made a non-finite contribution to it.
+        let input = grads.iter().rposition(|g| !g.is_finite())?;
+        let nodes = self.nodes.borrow();
+        let spans = self.spans.borrow();
+        let operands = self.operands.borrow();
+        let mut contributions = vec![];
+        for (location, node) in nodes.iter().enumerate().take(grads.len()).skip(input + 1) {
+            for (&dep, &weight) in node.dependencies().iter().zip(&node.weights) {
+                if dep == input {
+                    contributions.push((location, weight * grads[location]));
+                }
+            }
+        }
+        for span in spans.iter().filter(|span| span.node < grads.len()) {
+            for &(dep, weight) in &operands[span.start..span.end] {
+                if dep == input {
+                    contributions.push((span.node, weight * grads[span.node]));
+                }
+            }
+        }
+        // A block's backward pass is opaque, so it is blamed if nothing else is.
+        for block in self.blocks.borrow().iter() {
+            if block.inputs.contains(&input) {
+                contributions.push((block.start, f64::NAN));
+            }
+        }
+        // If every single contribution is finite, their sum overflowed, so blame the largest.
+        let (location, contribution) = contributions
+            .iter()
+            .copied()
+            .find(|(_, c)| !c.is_finite())
+            .or_else(|| {
+                contributions
+                    .iter()
+                    .copied()
+                    .max_by(|a, b| a.1.abs().total_cmp(&b.1.abs()))
+            })?;
+        let (op, value) = self.provenance_record(location);
+        Some(NonFinite {
+            location,
+            op,
+            value,
+            kind: NonFiniteKind::Adjoint {
+                input,
+                contribution: if contribution.is_nan() {
+                    grads[input]
+                } else {
+                    contribution
+                },
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_non_finite_value() {
+        let g = Tape::with_provenance();
+        let x = g.add_var(-1.);
+        let y = x.exp() + x.ln() * 2.;
+        let report = g.non_finite_value().unwrap();
+        assert_eq!(report.location, 2);
+        assert_eq!(report.op, "Ln");
+        assert!(report.value.is_nan());
+        assert_eq!(report.kind, NonFiniteKind::Value);
+        assert_eq!(g.find_non_finite(&y.grad()).unwrap().location, 2);
+
+        g.clear();
+        let x = g.add_var(2.);
+        let y = x.powi(2) / 4.;
+        assert_eq!(g.find_non_finite(&y.grad()), None);
+    }
+
+    #[test]
+    fn test_non_finite_adjoint() {
+        let g = Tape::with_provenance();
+        let x = g.add_var(0.);
+        let y = g.add_var(3.);
+        let z = (x.abs() + y) * y;
+        let grads = z.grad();
+        assert!(grads[0].is_nan());
+        let report = g.find_non_finite(&grads).unwrap();
+        assert_eq!(report.location, 2);
+        assert_eq!(report.op, "Abs");
+        assert!(matches!(
+            report.kind,
+            NonFiniteKind::Adjoint { input: 0, contribution } if contribution.is_nan()
+        ));
+        assert!(report.to_string().starts_with("node 2 (Abs with value 0)"));
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_requires_provenance() {
+        Tape::new().non_finite_value();
+    }
+}
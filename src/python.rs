@@ -0,0 +1,297 @@
+//! Python bindings with PyO3, enabled with the `python` feature.
+//!
+//! `Tape` and `Var` are Python classes forwarding to `handle::HandleTape`. A `Var` keeps its tape
+//! alive and supports the arithmetic operators with other variables and with numbers, which are
+//! treated as constants:
+//!
+//! ```python
+//! tape = Tape()
+//! x, y = tape.vars([2.0, 3.0])
+//! z = (x * y).sin() + 1 / x
+//! z.val                # sin(6) + 0.5
+//! z.grad([x, y])       # [3 cos 6 - 0.25, 2 cos 6]
+//! ```
+//!
+//! `register` adds the classes to a module, so an extension module built with maturin is defined
+//! in a crate depending on this one with
+//!
+//! ```rust
+//! use pyo3::prelude::*;
+//!
+//! #[pymodule]
+//! fn reverse_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+//!     reverse::python::register(m)
+//! }
+//! ```
+
+use crate::{handle::HandleTape, TapeMismatchError};
+use pyo3::{
+    exceptions::{PyTypeError, PyValueError},
+    prelude::*,
+};
+
+/// Add the `Tape` and `Var` classes to the module `m`.
+pub fn register(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add_class::<PyTape>()?;
+    m.add_class::<PyVar>()
+}
+
+/// Tape of variables for Python.
+#[pyclass(name = "Tape", unsendable)]
+#[derive(Debug, Default)]
+pub struct PyTape {
+    tape: HandleTape,
+}
+
+#[pymethods]
+impl PyTape {
+    #[new]
+    fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an input variable with value `val`.
+    fn var(slf: &Bound<'_, Self>, val: f64) -> PyVar {
+        let handle = slf.borrow_mut().tape.var(val);
+        PyVar::new(slf, handle)
+    }
+
+    /// Add an input variable for every value in `vals`.
+    fn vars(slf: &Bound<'_, Self>, vals: Vec<f64>) -> Vec<PyVar> {
+        vals.into_iter().map(|val| Self::var(slf, val)).collect()
+    }
+
+    /// Delete every variable, after which the existing ones must not be used.
+    fn clear(&mut self) {
+        self.tape.clear();
+    }
+
+    fn __len__(&self) -> usize {
+        self.tape.len()
+    }
+}
+
+/// Variable on a `Tape`, for Python.
+#[pyclass(name = "Var", unsendable)]
+#[derive(Debug)]
+pub struct PyVar {
+    tape: Py<PyTape>,
+    handle: usize,
+}
+
+/// Right-hand side of an arithmetic operator.
+#[derive(FromPyObject)]
+enum Operand<'py> {
+    Var(PyRef<'py, PyVar>),
+    Num(f64),
+}
+
+impl PyVar {
+    fn new(tape: &Bound<'_, PyTape>, handle: usize) -> Self {
+        Self {
+            tape: tape.clone().unbind(),
+            handle,
+        }
+    }
+
+    /// Error for combining `self` in the operation `op` with a variable on the tape `other`.
+    fn mismatch(&self, op: &'static str, other: &Py<PyTape>) -> PyErr {
+        let e = TapeMismatchError {
+            op,
+            expected: self.tape.as_ptr() as usize,
+            found: other.as_ptr() as usize,
+        };
+        PyValueError::new_err(e.to_string())
+    }
+
+    /// Gets the handle of `other` on the tape of `self` for the operation `op`, adding numbers as
+    /// constants.
+    fn operand(&self, py: Python<'_>, op: &'static str, other: Operand) -> PyResult<usize> {
+        match other {
+            Operand::Var(var) if var.tape.is(&self.tape) => Ok(var.handle),
+            Operand::Var(var) => Err(self.mismatch(op, &var.tape)),
+            Operand::Num(val) => Ok(self.tape.borrow_mut(py).tape.constant(val)),
+        }
+    }
+
+    /// Apply the binary function `name`, with `self` as the right-hand side if `reflected`.
+    fn binary(
+        &self,
+        py: Python<'_>,
+        name: &'static str,
+        other: Operand,
+        reflected: bool,
+    ) -> PyResult<Self> {
+        let other = self.operand(py, name, other)?;
+        let (x, y) = if reflected {
+            (other, self.handle)
+        } else {
+            (self.handle, other)
+        };
+        let handle = self.tape.borrow_mut(py).tape.binary(name, x, y);
+        Ok(Self {
+            tape: self.tape.clone_ref(py),
+            handle: handle.unwrap(),
+        })
+    }
+}
+
+#[pymethods]
+impl PyVar {
+    /// Gets the value of the variable.
+    #[getter]
+    fn val(&self, py: Python<'_>) -> f64 {
+        self.tape.borrow(py).tape.val(self.handle)
+    }
+
+    /// Calculate the gradient of this variable with respect to each of the variables `wrt`.
+    fn grad(&self, py: Python<'_>, wrt: Vec<PyRef<'_, PyVar>>) -> PyResult<Vec<f64>> {
+        if let Some(var) = wrt.iter().find(|var| !var.tape.is(&self.tape)) {
+            return Err(self.mismatch("grad", &var.tape));
+        }
+        let wrt = wrt.iter().map(|var| var.handle).collect::<Vec<_>>();
+        Ok(self.tape.borrow(py).tape.grad_wrt(self.handle, &wrt))
+    }
+
+    /// Apply the unary function `name`, for any name accepted by `HandleTape::unary`.
+    fn apply(&self, py: Python<'_>, name: &str) -> PyResult<Self> {
+        let handle = self.tape.borrow_mut(py).tape.unary(name, self.handle);
+        let handle =
+            handle.ok_or_else(|| PyTypeError::new_err(format!("unknown function {}", name)))?;
+        Ok(Self {
+            tape: self.tape.clone_ref(py),
+            handle,
+        })
+    }
+
+    fn sin(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "sin")
+    }
+
+    fn cos(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "cos")
+    }
+
+    fn tan(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "tan")
+    }
+
+    fn tanh(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "tanh")
+    }
+
+    fn exp(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "exp")
+    }
+
+    fn ln(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "ln")
+    }
+
+    fn sqrt(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "sqrt")
+    }
+
+    fn __abs__(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "abs")
+    }
+
+    fn __neg__(&self, py: Python<'_>) -> PyResult<Self> {
+        self.apply(py, "neg")
+    }
+
+    fn __add__(&self, py: Python<'_>, other: Operand) -> PyResult<Self> {
+        self.binary(py, "add", other, false)
+    }
+
+    fn __radd__(&self, py: Python<'_>, other: Operand) -> PyResult<Self> {
+        self.binary(py, "add", other, true)
+    }
+
+    fn __sub__(&self, py: Python<'_>, other: Operand) -> PyResult<Self> {
+        self.binary(py, "sub", other, false)
+    }
+
+    fn __rsub__(&self, py: Python<'_>, other: Operand) -> PyResult<Self> {
+        self.binary(py, "sub", other, true)
+    }
+
+    fn __mul__(&self, py: Python<'_>, other: Operand) -> PyResult<Self> {
+        self.binary(py, "mul", other, false)
+    }
+
+    fn __rmul__(&self, py: Python<'_>, other: Operand) -> PyResult<Self> {
+        self.binary(py, "mul", other, true)
+    }
+
+    fn __truediv__(&self, py: Python<'_>, other: Operand) -> PyResult<Self> {
+        self.binary(py, "div", other, false)
+    }
+
+    fn __rtruediv__(&self, py: Python<'_>, other: Operand) -> PyResult<Self> {
+        self.binary(py, "div", other, true)
+    }
+
+    fn __pow__(&self, py: Python<'_>, other: Operand, _modulo: Option<f64>) -> PyResult<Self> {
+        self.binary(py, "powf", other, false)
+    }
+
+    fn __rpow__(&self, py: Python<'_>, other: Operand, _modulo: Option<f64>) -> PyResult<Self> {
+        self.binary(py, "powf", other, true)
+    }
+
+    fn __float__(&self, py: Python<'_>) -> f64 {
+        self.val(py)
+    }
+
+    fn __repr__(&self, py: Python<'_>) -> String {
+        format!("Var({:?})", self.val(py))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use pyo3::{py_run, types::PyDict};
+
+    #[test]
+    fn test_python() {
+        Python::initialize();
+        Python::attach(|py| {
+            let module = PyModule::new(py, "reverse").unwrap();
+            register(&module).unwrap();
+            let locals = PyDict::new(py);
+            locals.set_item("reverse", module).unwrap();
+            py_run!(
+                py,
+                *locals,
+                r#"
+import math
+tape = reverse.Tape()
+x, y = tape.vars([2.0, 3.0])
+z = (x * y).sin() + 1 / x - 2 ** y + abs(-x)
+assert math.isclose(z.val, math.sin(6) + 0.5 - 8 + 2)
+gx, gy = z.grad([x, y])
+assert math.isclose(gx, 3 * math.cos(6) - 0.25 + 1)
+assert math.isclose(gy, 2 * math.cos(6) - 8 * math.log(2))
+assert float(x ** 2 - 1) == 3.0
+assert repr(x) == "Var(2.0)"
+assert math.isclose(x.apply("exp").ln().val, 2.0)
+assert len(tape) > 2
+
+try:
+    x.apply("nope")
+    assert False
+except TypeError:
+    pass
+other = reverse.Tape().var(1.0)
+try:
+    x + other
+    assert False
+except ValueError as e:
+    assert "different tapes cannot be combined in add" in str(e)
+"#
+            );
+        });
+    }
+}
@@ -0,0 +1,275 @@
+//! Writing functions once for both `f64` and `Var`.
+//!
+//! A function that is generic over `Real` can be evaluated on plain numbers, with no tape
+//! overhead, and differentiated by calling it on variables:
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! fn rosenbrock<T: Real>(x: T, y: T) -> T {
+//!     (-x + 1.).powi(2) + (y - x.powi(2)).powi(2) * 100.
+//! }
+//!
+//! assert_eq!(rosenbrock(1., 1.), 0.);
+//!
+//! let tape = Tape::new();
+//! let (x, y) = (tape.add_var(0.), tape.add_var(1.));
+//! let res = rosenbrock(x, y);
+//! assert_eq!(res.val(), rosenbrock(0., 1.));
+//! assert_eq!(res.grad().wrt(&[x, y]), vec![-2., 200.]);
+//! ```
+//!
+//! Constants can be combined with a `Real` on the right-hand side of an operator (`x * 2.`), but
+//! not on the left (`2. * x`), since the bound `f64: Mul<T>` cannot be implied by the trait.
+
+use crate::{Atan2, Copysign, Hypot, LogAddExp, Op, Powf, Var};
+use std::{
+    fmt::{Debug, Display},
+    iter::Sum,
+    ops::{Add, AddAssign, Div, DivAssign, Mul, MulAssign, Neg, Sub, SubAssign},
+};
+
+/// Scalar that behaves like an `f64`, implemented by `f64` and `Var`.
+pub trait Real:
+    Copy
+    + Debug
+    + Display
+    + PartialOrd
+    + PartialEq<f64>
+    + PartialOrd<f64>
+    + Neg<Output = Self>
+    + Add<Output = Self>
+    + Sub<Output = Self>
+    + Mul<Output = Self>
+    + Div<Output = Self>
+    + Add<f64, Output = Self>
+    + Sub<f64, Output = Self>
+    + Mul<f64, Output = Self>
+    + Div<f64, Output = Self>
+    + AddAssign
+    + SubAssign
+    + MulAssign
+    + DivAssign
+    + AddAssign<f64>
+    + SubAssign<f64>
+    + MulAssign<f64>
+    + DivAssign<f64>
+    + Sum
+    + Powf<Output = Self>
+    + Powf<f64, Output = Self>
+    + Atan2<Output = Self>
+    + Atan2<f64, Output = Self>
+    + Hypot<Output = Self>
+    + Hypot<f64, Output = Self>
+    + LogAddExp<Output = Self>
+    + LogAddExp<f64, Output = Self>
+    + Copysign<Output = Self>
+    + Copysign<f64, Output = Self>
+{
+    /// Gets the value as an `f64`.
+    fn val(&self) -> f64;
+
+    fn recip(self) -> Self;
+    fn sin(self) -> Self;
+    fn cos(self) -> Self;
+    fn sin_cos(self) -> (Self, Self);
+    fn tan(self) -> Self;
+    fn ln(self) -> Self;
+    fn log(self, base: f64) -> Self;
+    fn log10(self) -> Self;
+    fn log2(self) -> Self;
+    fn ln_1p(self) -> Self;
+    fn asin(self) -> Self;
+    fn acos(self) -> Self;
+    fn atan(self) -> Self;
+    fn sinh(self) -> Self;
+    fn cosh(self) -> Self;
+    fn tanh(self) -> Self;
+    fn asinh(self) -> Self;
+    fn acosh(self) -> Self;
+    fn atanh(self) -> Self;
+    fn exp(self) -> Self;
+    fn exp_m1(self) -> Self;
+    fn exp2(self) -> Self;
+    fn sqrt(self) -> Self;
+    fn cbrt(self) -> Self;
+    fn abs(self) -> Self;
+    fn powi(self, n: i32) -> Self;
+    fn floor(self) -> Self;
+    fn ceil(self) -> Self;
+    fn round(self) -> Self;
+    fn trunc(self) -> Self;
+    fn signum(self) -> Self;
+    fn fract(self) -> Self;
+    fn erf(self) -> Self;
+    fn sinc(self) -> Self;
+    fn erfc(self) -> Self;
+    fn lgamma(self) -> Self;
+    fn digamma(self) -> Self;
+    fn norm_cdf(self) -> Self;
+    fn norm_cdf_inv(self) -> Self;
+}
+
+/// Implements the methods of `Real` that share their name with an inherent method, which takes
+/// `self` by value or by reference.
+macro_rules! forward {
+    (val: $($name:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $name(self, $($arg: $ty),*) -> Self {
+                Self::$name(self, $($arg),*)
+            }
+        )*
+    };
+    (ref: $($name:ident($($arg:ident: $ty:ty),*);)*) => {
+        $(
+            fn $name(self, $($arg: $ty),*) -> Self {
+                Self::$name(&self, $($arg),*)
+            }
+        )*
+    };
+}
+
+impl Real for f64 {
+    fn val(&self) -> f64 {
+        *self
+    }
+
+    forward! {
+        val:
+        recip(); sin(); cos(); tan(); ln(); log(base: f64); log10(); log2(); ln_1p();
+        asin(); acos(); atan(); sinh(); cosh(); tanh(); asinh(); acosh(); atanh();
+        exp(); exp_m1(); exp2(); sqrt(); cbrt(); abs(); powi(n: i32);
+        floor(); ceil(); round(); trunc(); signum(); fract();
+    }
+
+    fn erf(self) -> Self {
+        Op::Erf.eval(self, 0.).0
+    }
+
+    fn erfc(self) -> Self {
+        Op::Erfc.eval(self, 0.).0
+    }
+
+    fn lgamma(self) -> Self {
+        Op::Lgamma.eval(self, 0.).0
+    }
+
+    fn digamma(self) -> Self {
+        Op::Polygamma(0).eval(self, 0.).0
+    }
+
+    fn norm_cdf(self) -> Self {
+        Op::NormCdf.eval(self, 0.).0
+    }
+
+    fn norm_cdf_inv(self) -> Self {
+        Op::NormCdfInv.eval(self, 0.).0
+    }
+
+    fn sinc(self) -> Self {
+        Op::Sinc.eval(self, 0.).0
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        f64::sin_cos(self)
+    }
+}
+
+impl<'a> Real for Var<'a> {
+    fn val(&self) -> f64 {
+        self.val
+    }
+
+    forward! {
+        val: exp2();
+    }
+
+    forward! {
+        ref:
+        recip(); sin(); cos(); tan(); ln(); log(base: f64); log10(); log2(); ln_1p();
+        asin(); acos(); atan(); sinh(); cosh(); tanh(); asinh(); acosh(); atanh();
+        exp(); exp_m1(); sqrt(); cbrt(); abs(); powi(n: i32);
+        floor(); ceil(); round(); trunc(); signum(); fract();
+        erf(); erfc(); lgamma(); digamma(); norm_cdf(); norm_cdf_inv(); sinc();
+    }
+
+    fn sin_cos(self) -> (Self, Self) {
+        Var::sin_cos(&self)
+    }
+}
+
+impl Powf for f64 {
+    type Output = f64;
+
+    fn powf(self, other: f64) -> f64 {
+        f64::powf(self, other)
+    }
+}
+
+impl Atan2 for f64 {
+    type Output = f64;
+
+    fn atan2(self, other: f64) -> f64 {
+        f64::atan2(self, other)
+    }
+}
+
+impl Hypot for f64 {
+    type Output = f64;
+
+    fn hypot(self, other: f64) -> f64 {
+        f64::hypot(self, other)
+    }
+}
+
+impl LogAddExp for f64 {
+    type Output = f64;
+
+    fn logaddexp(self, other: f64) -> f64 {
+        Op::LogAddExp.eval(self, other).0
+    }
+}
+
+impl Copysign for f64 {
+    type Output = f64;
+
+    fn copysign(self, sign: f64) -> f64 {
+        f64::copysign(self, sign)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Gradient, Tape};
+    use approx_eq::assert_approx_eq;
+
+    fn model<T: Real>(params: &[T], x: f64) -> T {
+        let z = params[0] * x + params[1];
+        let mut res = z.tanh() * params[2].exp() + Powf::powf(params[0], params[1].abs());
+        res += params.iter().copied().sum::<T>().erf();
+        res -= Atan2::atan2(params[1], params[2]) / x;
+        res
+    }
+
+    #[test]
+    fn test_real() {
+        let vals = [0.5, -1.5, 0.3];
+        let g = Tape::new();
+        let params = g.add_vars(&vals);
+        for &x in &[0.5, 2.] {
+            let res = model(&params, x);
+            assert_approx_eq!(res.val(), model(&vals, x));
+            let grads = res.grad().wrt(&params);
+            for (i, grad) in grads.iter().enumerate() {
+                let h = 1e-6;
+                let (mut hi, mut lo) = (vals, vals);
+                hi[i] += h;
+                lo[i] -= h;
+                let fd = (model(&hi, x) - model(&lo, x)) / (2. * h);
+                assert!((grad - fd).abs() < 1e-6, "{} vs {}", grad, fd);
+            }
+        }
+        assert_approx_eq!(Real::lgamma(5_f64), 24_f64.ln());
+    }
+}
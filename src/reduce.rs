@@ -0,0 +1,565 @@
+use crate::{error::assert_same_tape, Op, Tape, TapeMismatchError, Var};
+
+/// Returns the tape shared by every variable in `xs`, panicking if `xs` is empty or mixes tapes,
+/// which is reported as coming from `name`.
+fn tape_of<'a>(name: &'static str, xs: &[Var<'a>]) -> &'a Tape {
+    let tape = xs.first().expect("cannot reduce an empty slice").tape;
+    for x in xs {
+        assert_same_tape(name, tape, x.tape);
+    }
+    tape
+}
+
+/// Record the reduction `op` over `xs` as a single node, for the function `name`.
+fn reduction<'a>(name: &'static str, xs: &[Var<'a>], op: Op) -> Var<'a> {
+    let tape = tape_of(name, xs);
+    let vals = xs.iter().map(|x| x.val).collect::<Vec<_>>();
+    let (val, weights) = op.eval_nary(&vals);
+    let deps = xs.iter().map(|x| x.location).collect::<Vec<_>>();
+    Var {
+        val,
+        location: tape.add_nary_node(op, val, &deps, &weights),
+        tape,
+    }
+}
+
+/// Calculate the sum of `xs`, recorded as a single node. On tapes created with
+/// `Tape::with_compensation`, the sum is compensated for rounding errors.
+pub fn sum<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    if tape_of("sum", xs).compensated {
+        reduction("sum", xs, Op::CompensatedSum)
+    } else {
+        reduction("sum", xs, Op::Sum)
+    }
+}
+
+/// Calculate the mean of `xs`.
+pub fn mean<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    reduction("mean", xs, Op::Mean)
+}
+
+/// Calculate the population variance of `xs`, that is, the mean squared deviation from the mean.
+pub fn variance<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    reduction("variance", xs, Op::Variance)
+}
+
+/// Calculate the population standard deviation of `xs`. The gradient is taken to be zero when
+/// all values are equal.
+pub fn std<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    reduction("std", xs, Op::Std)
+}
+
+/// Record `sum(a * b)` over `pairs` as a single fused node on `tape`, for the function `name`.
+pub(crate) fn fused_dot<'a>(
+    name: &'static str,
+    tape: &'a Tape,
+    pairs: impl IntoIterator<Item = (Var<'a>, Var<'a>)>,
+) -> Var<'a> {
+    let mut vals = vec![];
+    let mut deps = vec![];
+    for (a, b) in pairs {
+        assert_same_tape(name, tape, a.tape);
+        assert_same_tape(name, tape, b.tape);
+        vals.extend([a.val, b.val]);
+        deps.extend([a.location, b.location]);
+    }
+    let (val, weights) = Op::Dot.eval_nary(&vals);
+    Var {
+        val,
+        location: tape.add_nary_node(Op::Dot, val, &deps, &weights),
+        tape,
+    }
+}
+
+/// Record the sum of `terms`, variables scaled by constant weights, as a single node, for the
+/// function `name`.
+fn linear<'a>(
+    name: &'static str,
+    tape: &'a Tape,
+    terms: impl IntoIterator<Item = (Var<'a>, f64)>,
+) -> Var<'a> {
+    let (mut deps, mut weights) = (vec![], vec![]);
+    let mut val = 0.;
+    for (x, weight) in terms {
+        assert_same_tape(name, tape, x.tape);
+        val += x.val * weight;
+        deps.push(x.location);
+        weights.push(weight);
+    }
+    Var {
+        val,
+        location: tape.add_nary_node(Op::Linear, val, &deps, &weights),
+        tape,
+    }
+}
+
+/// Calculate `sum(weights[i] * xs[i])` for constant weights, recorded as a single node rather
+/// than a scaling and an addition per entry.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let xs = tape.add_vars(&[1., 2., 3.]);
+/// let res = weighted_sum(&[0.5, -1., 2.], &xs);
+/// assert_eq!(res.val(), 4.5);
+/// assert_eq!(res.grad().wrt(&xs), vec![0.5, -1., 2.]);
+/// assert_eq!(tape.len(), 4);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `xs` is empty, the lengths differ, or the variables are on different tapes.
+pub fn weighted_sum<'a>(weights: &[f64], xs: &[Var<'a>]) -> Var<'a> {
+    assert_eq!(weights.len(), xs.len(), "need one weight for each variable");
+    let tape = tape_of("weighted_sum", xs);
+    linear(
+        "weighted_sum",
+        tape,
+        xs.iter().copied().zip(weights.iter().copied()),
+    )
+}
+
+/// Calculate `alpha * x + y` elementwise, recording each entry as a single node.
+///
+/// # Panics
+///
+/// Panics if `x` is empty, the lengths differ, or the variables are on different tapes.
+pub fn axpy<'a>(alpha: f64, x: &[Var<'a>], y: &[Var<'a>]) -> Vec<Var<'a>> {
+    assert_eq!(x.len(), y.len(), "axpy of slices with different lengths");
+    let tape = tape_of("axpy", x);
+    x.iter()
+        .zip(y)
+        .map(|(&x, &y)| linear("axpy", tape, [(x, alpha), (y, 1.)]))
+        .collect()
+}
+
+/// Calculate the dot product of `xs` and `ys`, recorded as a single fused node.
+pub fn dot<'a>(xs: &[Var<'a>], ys: &[Var<'a>]) -> Var<'a> {
+    assert_eq!(
+        xs.len(),
+        ys.len(),
+        "dot product of slices with different lengths"
+    );
+    fused_dot(
+        "dot",
+        tape_of("dot", xs),
+        xs.iter().copied().zip(ys.iter().copied()),
+    )
+}
+
+/// Calculate `ln(sum(exp(x)))` over `xs` without overflowing. The values are shifted by their
+/// maximum before exponentiating, and the gradient (the softmax of `xs`) is recorded in one go.
+pub fn logsumexp<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    reduction("logsumexp", xs, Op::LogSumExp)
+}
+
+/// Calculate the maximum of `xs`, recorded as a single node. The gradient is a subgradient that
+/// goes entirely to the maximum, or to the first maximum if several elements are tied. A NaN
+/// anywhere makes the result NaN, with the gradient going to the first NaN.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let xs = tape.add_vars(&[1., 3., -2., 3.]);
+/// let max = max_of(&xs);
+/// assert_eq!(max.val(), 3.);
+/// assert_eq!(max.grad().wrt(&xs), vec![0., 1., 0., 0.]);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `xs` is empty or the variables are on different tapes.
+pub fn max_of<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    reduction("max_of", xs, Op::MaxOf)
+}
+
+/// Calculate the minimum of `xs`, recorded as a single node, with the same rules for ties and NaNs
+/// as `max_of`.
+///
+/// # Panics
+///
+/// Panics if `xs` is empty or the variables are on different tapes.
+pub fn min_of<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    reduction("min_of", xs, Op::MinOf)
+}
+
+/// Calculate the L1 norm `sum(|x|)` of `xs`, recorded as a single node. The subgradient of a zero
+/// entry is taken to be zero.
+///
+/// # Panics
+///
+/// Panics if `xs` is empty or the variables are on different tapes.
+pub fn norm_l1<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    reduction("norm_l1", xs, Op::NormL1)
+}
+
+/// Calculate the Euclidean norm `sqrt(sum(x^2))` of `xs`, recorded as a single node. The entries
+/// are divided by the largest magnitude before squaring, so the norm neither overflows nor
+/// underflows when the squares would. The subgradient at the origin is taken to be zero.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let xs = tape.add_vars(&[3e200, -4e200]);
+/// let norm = norm_l2(&xs);
+/// assert!((norm.val() / 5e200 - 1.).abs() < 1e-15);
+/// let grads = norm.grad().wrt(&xs);
+/// assert!((grads[0] - 0.6).abs() < 1e-15 && (grads[1] + 0.8).abs() < 1e-15);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `xs` is empty or the variables are on different tapes.
+pub fn norm_l2<'a>(xs: &[Var<'a>]) -> Var<'a> {
+    reduction("norm_l2", xs, Op::NormL2)
+}
+
+/// Calculate the `p`-norm `sum(|x|^p)^(1 / p)` of `xs`, recorded as a single node and scaled like
+/// `norm_l2`. The subgradient of a zero entry is taken to be zero.
+///
+/// # Panics
+///
+/// Panics if `p` is not positive, or if `xs` is empty or the variables are on different tapes.
+pub fn norm_lp<'a>(xs: &[Var<'a>], p: f64) -> Var<'a> {
+    assert!(p > 0., "the order of a norm must be positive");
+    reduction("norm_lp", xs, Op::NormLp(p))
+}
+
+/// Running combination of `xs` with `f`, starting from the first entry.
+fn scan<'a>(xs: &[Var<'a>], f: impl Fn(Var<'a>, Var<'a>) -> Var<'a>) -> Vec<Var<'a>> {
+    let mut acc = None;
+    xs.iter()
+        .map(|&x| {
+            let next = acc.map_or(x, |acc| f(acc, x));
+            acc = Some(next);
+            next
+        })
+        .collect()
+}
+
+/// Calculate the running sums of `xs`, where entry `i` is the sum of `xs[..=i]`. Each sum adds
+/// one node to the previous one, so the tape grows linearly with the length of `xs`.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let xs = tape.add_vars(&[1., 2., 3.]);
+/// let sums = cumsum(&xs);
+/// assert_eq!(sums.iter().map(|s| s.val()).collect::<Vec<_>>(), vec![1., 3., 6.]);
+/// assert_eq!(sums[1].grad().wrt(&xs), vec![1., 1., 0.]);
+/// ```
+pub fn cumsum<'a>(xs: &[Var<'a>]) -> Vec<Var<'a>> {
+    scan(xs, |acc, x| acc + x)
+}
+
+/// Calculate the running products of `xs`, where entry `i` is the product of `xs[..=i]`. Each
+/// product multiplies the previous one by a single entry, so the tape grows linearly with the
+/// length of `xs`, and the gradients are exact even when entries are zero.
+pub fn cumprod<'a>(xs: &[Var<'a>]) -> Vec<Var<'a>> {
+    scan(xs, |acc, x| acc * x)
+}
+
+/// Evaluate the polynomial with coefficients `coeffs`, from the highest degree down, at `x` using
+/// Horner's scheme. The polynomial is recorded as a single node, with gradients with respect to
+/// both the coefficients and `x`.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let coeffs = tape.add_vars(&[2., -1., 3.]);
+/// let x = tape.add_var(2.);
+/// let y = polyval(&coeffs, x);
+/// assert_eq!(y.val(), 2. * 4. - 2. + 3.);
+/// let grads = y.grad();
+/// assert_eq!(grads.wrt(&coeffs), vec![4., 2., 1.]);
+/// assert_eq!(grads.wrt(&x), 2. * 2. * 2. - 1.);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `coeffs` is empty or the variables are on different tapes.
+pub fn polyval<'a>(coeffs: &[Var<'a>], x: Var<'a>) -> Var<'a> {
+    let tape = tape_of("polyval", coeffs);
+    assert_same_tape("polyval", tape, x.tape);
+    let mut xs = coeffs.to_vec();
+    xs.push(x);
+    reduction("polyval", &xs, Op::Polyval)
+}
+
+// Checked variants, returning an error rather than panicking when the variables are on different
+// tapes. They still panic for empty inputs and mismatched lengths, like the functions they check.
+
+/// Like `sum`, but returns an error if the variables are on different tapes.
+pub fn try_sum<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("sum", xs).map(|_| sum(xs))
+}
+
+/// Like `mean`, but returns an error if the variables are on different tapes.
+pub fn try_mean<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("mean", xs).map(|_| mean(xs))
+}
+
+/// Like `variance`, but returns an error if the variables are on different tapes.
+pub fn try_variance<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("variance", xs).map(|_| variance(xs))
+}
+
+/// Like `std`, but returns an error if the variables are on different tapes.
+pub fn try_std<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("std", xs).map(|_| std(xs))
+}
+
+/// Like `logsumexp`, but returns an error if the variables are on different tapes.
+pub fn try_logsumexp<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("logsumexp", xs).map(|_| logsumexp(xs))
+}
+
+/// Like `max_of`, but returns an error if the variables are on different tapes.
+pub fn try_max_of<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("max_of", xs).map(|_| max_of(xs))
+}
+
+/// Like `min_of`, but returns an error if the variables are on different tapes.
+pub fn try_min_of<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("min_of", xs).map(|_| min_of(xs))
+}
+
+/// Like `norm_l1`, but returns an error if the variables are on different tapes.
+pub fn try_norm_l1<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("norm_l1", xs).map(|_| norm_l1(xs))
+}
+
+/// Like `norm_l2`, but returns an error if the variables are on different tapes.
+pub fn try_norm_l2<'a>(xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("norm_l2", xs).map(|_| norm_l2(xs))
+}
+
+/// Like `norm_lp`, but returns an error if the variables are on different tapes.
+pub fn try_norm_lp<'a>(xs: &[Var<'a>], p: f64) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("norm_lp", xs).map(|_| norm_lp(xs, p))
+}
+
+/// Like `weighted_sum`, but returns an error if the variables are on different tapes.
+pub fn try_weighted_sum<'a>(weights: &[f64], xs: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("weighted_sum", xs).map(|_| weighted_sum(weights, xs))
+}
+
+/// Like `axpy`, but returns an error if the variables are on different tapes.
+pub fn try_axpy<'a>(
+    alpha: f64,
+    x: &[Var<'a>],
+    y: &[Var<'a>],
+) -> Result<Vec<Var<'a>>, TapeMismatchError> {
+    TapeMismatchError::check_all("axpy", x.iter().chain(y)).map(|_| axpy(alpha, x, y))
+}
+
+/// Like `dot`, but returns an error if the variables are on different tapes.
+pub fn try_dot<'a>(xs: &[Var<'a>], ys: &[Var<'a>]) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("dot", xs.iter().chain(ys)).map(|_| dot(xs, ys))
+}
+
+/// Like `polyval`, but returns an error if the variables are on different tapes.
+pub fn try_polyval<'a>(coeffs: &[Var<'a>], x: Var<'a>) -> Result<Var<'a>, TapeMismatchError> {
+    TapeMismatchError::check_all("polyval", coeffs.iter().chain([&x])).map(|_| polyval(coeffs, x))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::Gradient;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_sum() {
+        let g = Tape::new();
+        let xs = g.add_vars(&(0..100_000).map(f64::from).collect::<Vec<_>>());
+        let res = sum(&xs);
+        assert_eq!(res.val(), 4_999_950_000.);
+        assert_eq!(g.len(), xs.len() + 1);
+        assert!(res.grad().wrt(&xs).iter().all(|&grad| grad == 1.));
+
+        let res = xs[..3].iter().sum::<Var>() * 2.;
+        assert_eq!(res.val(), 6.);
+        assert_eq!(res.grad().wrt(&xs[..3]), vec![2.; 3]);
+    }
+
+    #[test]
+    fn test_moments() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[1e9 + 1., 1e9 + 2., 1e9 + 6.]);
+        let res = mean(&xs);
+        assert_approx_eq!(res.val(), 1e9 + 3.);
+        assert_approx_eq!(res.grad().wrt(&xs[1]), 1. / 3.);
+
+        let res = variance(&xs);
+        assert_approx_eq!(res.val(), 14. / 3.);
+        let grads = res.grad().wrt(&xs);
+        for (grad, dev) in grads.iter().zip([-2., -1., 3.]) {
+            assert_approx_eq!(*grad, 2. * dev / 3.);
+        }
+
+        let res = std(&xs);
+        assert_approx_eq!(res.val(), (14_f64 / 3.).sqrt());
+        let grads = res.grad().wrt(&xs);
+        for (grad, dev) in grads.iter().zip([-2., -1., 3.]) {
+            assert_approx_eq!(*grad, dev / (3. * res.val()));
+        }
+
+        let same = g.add_vars(&[2., 2.]);
+        assert_eq!(std(&same).grad().wrt(&same), vec![0., 0.]);
+    }
+
+    #[test]
+    fn test_norms() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[3., 0., -4.]);
+        let res = norm_l1(&xs);
+        assert_eq!(res.val(), 7.);
+        assert_eq!(res.grad().wrt(&xs), vec![1., 0., -1.]);
+
+        let res = norm_l2(&xs);
+        assert_eq!(res.val(), 5.);
+        assert_eq!(res.grad().wrt(&xs), vec![0.6, 0., -0.8]);
+        let tiny = g.add_vars(&[3e-200, 4e-200]);
+        assert_approx_eq!(norm_l2(&tiny).val() * 1e200, 5.);
+
+        let res = norm_lp(&xs, 3.);
+        let expected = 91_f64.cbrt();
+        assert_approx_eq!(res.val(), expected);
+        let grads = res.grad().wrt(&xs);
+        for (grad, x) in grads.iter().zip([3_f64, 0., -4.]) {
+            assert_approx_eq!(*grad, x.signum() * x.powi(2) / expected.powi(2));
+        }
+        assert_approx_eq!(norm_lp(&xs, 1.).val(), 7.);
+        assert_approx_eq!(norm_lp(&xs, 2.).val(), 5.);
+
+        let zeros = g.add_vars(&[0., 0.]);
+        for norm in [norm_l1(&zeros), norm_l2(&zeros), norm_lp(&zeros, 0.5)] {
+            assert_eq!(norm.val(), 0.);
+            assert_eq!(norm.grad().wrt(&zeros), vec![0., 0.]);
+        }
+    }
+
+    #[test]
+    fn test_cumulative() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[2., 0., 3., -1.]);
+        let len = g.len();
+        let sums = cumsum(&xs);
+        assert_eq!(g.len(), len + 3);
+        let vals = sums.iter().map(|s| s.val()).collect::<Vec<_>>();
+        assert_eq!(vals, vec![2., 2., 5., 4.]);
+        assert_eq!(sums[2].grad().wrt(&xs), vec![1., 1., 1., 0.]);
+
+        let products = cumprod(&xs);
+        let vals = products.iter().map(|p| p.val()).collect::<Vec<_>>();
+        assert_eq!(vals, vec![2., 0., 0., 0.]);
+        // the zero has a nonzero gradient, which dividing the products by entries would lose
+        assert_eq!(products[2].grad().wrt(&xs), vec![0., 6., 0., 0.]);
+        assert_eq!(products[0].grad().wrt(&xs), vec![1., 0., 0., 0.]);
+        assert!(cumsum(&[]).is_empty());
+    }
+
+    #[test]
+    fn test_linear() {
+        let g = Tape::with_provenance();
+        let xs = g.add_vars(&[1., -2., 0.5]);
+        let ys = g.add_vars(&[3., 1., 4.]);
+        let res = axpy(2., &xs, &ys);
+        assert_eq!(g.len(), 9);
+        let vals = res.iter().map(|r| r.val()).collect::<Vec<_>>();
+        assert_eq!(vals, vec![5., -3., 5.]);
+        let grads = res[1].grad();
+        assert_eq!(grads.wrt(&xs), vec![0., 2., 0.]);
+        assert_eq!(grads.wrt(&ys), vec![0., 1., 0.]);
+
+        let total = weighted_sum(&[1., -1., 3.], &res);
+        assert_eq!(total.val(), 23.);
+        assert_eq!(total.grad().wrt(&xs), vec![2., -2., 6.]);
+
+        // the weights are kept when the tape is evaluated again
+        let vals = g.replay(&[0., 0., 0., 1., 1., 1.]).unwrap();
+        assert_eq!(vals[total.location], 3.);
+        let program = g.compile().unwrap();
+        assert_eq!(program.eval(&[1., 1., 1., 0., 0., 0.])[total.location], 6.);
+        assert_eq!(total.grad_recorded(&[xs[0]])[0].val(), 2.);
+        assert_eq!(
+            total.expr_string(),
+            "1 * (2 * x0 + 1 * x3) + (-1) * (2 * x1 + 1 * x4) + 3 * (2 * x2 + 1 * x5)"
+        );
+    }
+
+    #[test]
+    fn test_dot() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[1., 2., 3.]);
+        let ys = g.add_vars(&[-4., 0.5, 2.]);
+        let res = dot(&xs, &ys);
+        assert_eq!(res.val(), 3.);
+        let grads = res.grad();
+        assert_eq!(grads.wrt(&xs), vec![-4., 0.5, 2.]);
+        assert_eq!(grads.wrt(&ys), vec![1., 2., 3.]);
+
+        let res = dot(&xs, &xs);
+        assert_eq!(res.grad().wrt(&xs), vec![2., 4., 6.]);
+    }
+
+    #[test]
+    fn test_logsumexp() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[1., 2., 3.]);
+        let res = logsumexp(&xs);
+        let total = 1_f64.exp() + 2_f64.exp() + 3_f64.exp();
+        assert_approx_eq!(res.val(), total.ln());
+        let grads = res.grad().wrt(&xs);
+        for (x, grad) in [1_f64, 2., 3.].iter().zip(grads) {
+            assert_approx_eq!(grad, x.exp() / total);
+        }
+
+        let big = g.add_vars(&[1000., 1000.]);
+        let res = logsumexp(&big);
+        assert_approx_eq!(res.val(), 1000. + 2_f64.ln());
+        for grad in res.grad().wrt(&big) {
+            assert_approx_eq!(grad, 0.5);
+        }
+    }
+
+    #[test]
+    fn test_extrema() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[2., -1., 5., -1., 5.]);
+        let (max, min) = (max_of(&xs), min_of(&xs));
+        assert_eq!((max.val(), min.val()), (5., -1.));
+        assert_eq!(max.grad().wrt(&xs), vec![0., 0., 1., 0., 0.]);
+        assert_eq!(min.grad().wrt(&xs), vec![0., 1., 0., 0., 0.]);
+        let margin = max_of(&[xs[0], xs[1] * -3.]) * 2.;
+        assert_eq!(margin.val(), 6.);
+        assert_eq!(margin.grad().wrt(&xs), vec![0., -6., 0., 0., 0.]);
+
+        let nan = g.add_vars(&[1., f64::NAN, 3.]);
+        let max = max_of(&nan);
+        assert!(max.val().is_nan());
+        assert_eq!(max.grad().wrt(&nan), vec![0., 1., 0.]);
+    }
+
+    #[test]
+    fn test_polyval() {
+        let g = Tape::new();
+        let c = g.add_vars(&[0.5, -2., 0., 1.5]);
+        let x = g.add_var(-1.5);
+        let y = polyval(&c, x);
+        let naive = c[0] * x.powi(3) + c[1] * x.powi(2) + c[2] * x + c[3];
+        assert_approx_eq!(y.val(), naive.val());
+        let (grads, expected) = (y.grad(), naive.grad());
+        for (grad, expected) in grads.wrt(&c).iter().zip(expected.wrt(&c)) {
+            assert_approx_eq!(*grad, expected);
+        }
+        assert_approx_eq!(grads.wrt(&x), expected.wrt(&x));
+        assert_eq!(polyval(&c[..1], x).grad().wrt(&x), 0.);
+    }
+}
@@ -0,0 +1,196 @@
+//! Re-evaluating a recorded tape at new input values, without running the code that recorded it.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_var(1.);
+//! let y = tape.add_var(2.);
+//! let z = x.sin() * y + y.powi(2);
+//!
+//! let vals = tape.replay(&[0., 3.]).unwrap();
+//! assert_eq!(vals.wrt(&z), 9.);
+//! let grads = z.grad();
+//! assert_eq!(grads.wrt(&x), 3.);
+//! assert_eq!(grads.wrt(&y), 6.);
+//! ```
+
+use crate::{Op, Tape};
+use std::{
+    error::Error,
+    fmt::{self, Display},
+};
+
+/// Error returned by `Tape::replay` and `Tape::compile`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ReplayError {
+    /// The number of input values does not match the number of variables on the tape.
+    InputCount { expected: usize, found: usize },
+    /// The node at `location` cannot be re-evaluated, either because its backward pass was
+    /// computed when it was recorded (as for `solve`), because it copies a branch chosen when it
+    /// was recorded (as for `select`), or because the operation is not supported for the kind of
+    /// values being evaluated.
+    Unsupported { location: usize, op: String },
+}
+
+impl Display for ReplayError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InputCount { expected, found } => write!(
+                f,
+                "tape has {} input variables but {} values were given",
+                expected, found
+            ),
+            Self::Unsupported { location, op } => {
+                write!(f, "node {} ({}) cannot be re-evaluated", location, op)
+            }
+        }
+    }
+}
+
+impl Error for ReplayError {}
+
+impl Tape {
+    /// Re-evaluate every node on the tape with `inputs` as the values of the variables added with
+    /// `add_var`, in the order they were added. The recorded partial derivatives are updated in
+    /// place, so `Var::grad` afterwards gives the gradients at the new point.
+    ///
+    /// Returns the new value of every node, which is read for a variable with `Gradient::wrt` as
+    /// for gradients. The `val` of existing variables is not changed.
+    ///
+    /// The tape is left untouched if an error is returned.
+    pub fn replay(&self, inputs: &[f64]) -> Result<Vec<f64>, ReplayError> {
+        let ops = self.ops.borrow();
+        check_replayable(&ops)?;
+        let expected = ops.iter().filter(|&&op| op == Op::Input).count();
+        if expected != inputs.len() {
+            return Err(ReplayError::InputCount {
+                expected,
+                found: inputs.len(),
+            });
+        }
+
+        let mut nodes = self.nodes.borrow_mut();
+        let mut operands = self.operands.borrow_mut();
+        let spans = self.spans.borrow();
+        let mut spans = spans.iter().peekable();
+        let mut inputs = inputs.iter();
+        let mut vals = Vec::with_capacity(ops.len());
+        for (idx, (&op, node)) in ops.iter().zip(nodes.iter_mut()).enumerate() {
+            let val = if let Some(span) = spans.next_if(|span| span.node == idx) {
+                let operands = &mut operands[span.start..span.end];
+                let (xs, recorded): (Vec<_>, Vec<_>) = operands
+                    .iter()
+                    .map(|&(dep, weight)| (vals[dep], weight))
+                    .unzip();
+                let (val, weights) = op.eval_operands(&xs, &recorded);
+                for ((_, weight), new) in operands.iter_mut().zip(weights) {
+                    *weight = new;
+                }
+                val
+            } else {
+                match op {
+                    Op::Input => *inputs.next().unwrap(),
+                    Op::Const(val) => val,
+                    op => {
+                        let [x, y] = node.dependencies();
+                        let (val, d_x, d_y) = op.eval(vals[x], vals[y]);
+                        node.weights = [d_x, d_y];
+                        val
+                    }
+                }
+            };
+            vals.push(val);
+        }
+        if let Some(provenance) = &self.provenance {
+            provenance.borrow_mut().copy_from_slice(&vals);
+        }
+        Ok(vals)
+    }
+}
+
+/// Checks that every operation in `ops` can be re-evaluated from the values of its
+/// dependencies.
+pub(crate) fn check_replayable(ops: &[Op]) -> Result<(), ReplayError> {
+    match ops.iter().position(|op| {
+        matches!(
+            op,
+            Op::Select
+                | Op::Solve
+                | Op::Inv
+                | Op::Eigh
+                | Op::Fft
+                | Op::Ifft
+                | Op::Ode
+                | Op::Implicit
+                | Op::SoftSort
+                | Op::Normalize
+                | Op::Attention
+        )
+    }) {
+        Some(location) => Err(ReplayError::Unsupported {
+            location,
+            op: format!("{:?}", ops[location]),
+        }),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{dot, logsumexp, solve, Gradient, Hypot, Mat, Var};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_replay() {
+        fn f<'a>(x: &[Var<'a>]) -> Var<'a> {
+            let sq = x.iter().map(|v| v.powi(2)).collect::<Vec<_>>();
+            (x[0] / x[1]).exp() + 2. * dot(x, &sq) - logsumexp(x) + x[2].hypot(1.)
+        }
+
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2., 3.]);
+        let y = f(&x);
+        let len = g.len();
+
+        for point in [[0.5, -1., 2.], [3., 0.25, -4.]] {
+            let vals = g.replay(&point).unwrap();
+            assert_eq!(g.len(), len);
+            let grads = y.grad().wrt(&x);
+
+            let fresh = Tape::new();
+            let x2 = fresh.add_vars(&point);
+            let y2 = f(&x2);
+            assert_approx_eq!(vals.wrt(&y), y2.val());
+            for (grad, expected) in grads.iter().zip(y2.grad().wrt(&x2)) {
+                assert_approx_eq!(*grad, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn test_replay_errors() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2.]);
+        let y = x[0] * x[1];
+        assert_eq!(
+            g.replay(&[1.]),
+            Err(ReplayError::InputCount {
+                expected: 2,
+                found: 1
+            })
+        );
+
+        let a = Mat::from_vals(&g, 1, 1, &[2.]);
+        let _ = solve(&a, &[y]);
+        assert_eq!(
+            g.replay(&[1., 2., 3.]),
+            Err(ReplayError::Unsupported {
+                location: 4,
+                op: "Solve".to_string()
+            })
+        );
+        assert_eq!(y.grad().wrt(&x), vec![2., 1.]);
+    }
+}
@@ -0,0 +1,562 @@
+//! Gradient-based Markov chain Monte Carlo, enabled with the `sample` feature.
+//!
+//! `hmc` and `nuts` draw samples from the distribution with log-density `f`, given as a closure
+//! over variables like the objectives of `optim::minimize`. Both simulate Hamiltonian dynamics with
+//! leapfrog steps that use the gradient of `f` from the tape, which is created once and cleared
+//! before every evaluation. The log-density only needs to be known up to a constant.
+//!
+//! During warmup the step size is adapted by dual averaging (Hoffman and Gelman, 2014) so that the
+//! average acceptance probability approaches `target_accept`. Warmup draws are not returned.
+//!
+//! ```rust
+//! use reverse::*;
+//! use reverse::sample::{nuts, NutsOptions};
+//!
+//! // normal distribution with mean 1 and standard deviation 2
+//! let chain = nuts(|x| -0.125 * (x[0] - 1.).powi(2), &[0.], NutsOptions::new(1000));
+//! assert_eq!(chain.samples.len(), 1000);
+//! assert!((chain.mean()[0] - 1.).abs() < 0.3);
+//! ```
+
+use crate::{error::assert_same_tape, rng::Rng, Tape, Var};
+
+/// Energy error beyond which a trajectory is considered divergent.
+const MAX_ENERGY_ERROR: f64 = 1000.;
+
+/// Options for `hmc`.
+#[derive(Debug, Clone)]
+pub struct HmcOptions {
+    /// Number of samples to return.
+    pub samples: usize,
+    /// Number of warmup iterations, used to adapt the step size, before the first sample.
+    pub warmup: usize,
+    /// Initial leapfrog step size, which is adapted during warmup.
+    pub step_size: f64,
+    /// Number of leapfrog steps in each trajectory.
+    pub leapfrog_steps: usize,
+    /// Average acceptance probability targeted by the step size adaptation.
+    pub target_accept: f64,
+    /// Seed of the random number generator.
+    pub seed: u64,
+}
+
+impl HmcOptions {
+    /// Options drawing `samples` samples after as many warmup iterations, with 10 leapfrog steps
+    /// per trajectory targeting an acceptance probability of 0.8.
+    pub fn new(samples: usize) -> Self {
+        Self {
+            samples,
+            warmup: samples,
+            step_size: 0.1,
+            leapfrog_steps: 10,
+            target_accept: 0.8,
+            seed: 0,
+        }
+    }
+}
+
+/// Options for `nuts`.
+#[derive(Debug, Clone)]
+pub struct NutsOptions {
+    /// Number of samples to return.
+    pub samples: usize,
+    /// Number of warmup iterations, used to adapt the step size, before the first sample.
+    pub warmup: usize,
+    /// Initial leapfrog step size, which is adapted during warmup.
+    pub step_size: f64,
+    /// Maximum depth of a trajectory tree, which then has at most `2^max_depth` leapfrog steps.
+    pub max_depth: usize,
+    /// Average acceptance probability targeted by the step size adaptation.
+    pub target_accept: f64,
+    /// Seed of the random number generator.
+    pub seed: u64,
+}
+
+impl NutsOptions {
+    /// Options drawing `samples` samples after as many warmup iterations, with trees of depth at
+    /// most 10 targeting an acceptance probability of 0.8.
+    pub fn new(samples: usize) -> Self {
+        Self {
+            samples,
+            warmup: samples,
+            step_size: 0.1,
+            max_depth: 10,
+            target_accept: 0.8,
+            seed: 0,
+        }
+    }
+}
+
+/// Result of `hmc` or `nuts`.
+#[derive(Debug, Clone)]
+pub struct Chain {
+    /// Samples drawn after warmup, in order.
+    pub samples: Vec<Vec<f64>>,
+    /// Log-density at every sample.
+    pub log_density: Vec<f64>,
+    /// Step size used after warmup.
+    pub step_size: f64,
+    /// Average acceptance probability after warmup.
+    pub acceptance: f64,
+    /// Number of trajectories after warmup whose energy error blew up, which suggests the step
+    /// size is too large for some region of the distribution.
+    pub divergences: usize,
+}
+
+impl Chain {
+    /// Mean of the samples in every dimension.
+    pub fn mean(&self) -> Vec<f64> {
+        let n = self.samples.len() as f64;
+        let dim = self.samples.first().map_or(0, |s| s.len());
+        (0..dim)
+            .map(|i| self.samples.iter().map(|s| s[i]).sum::<f64>() / n)
+            .collect()
+    }
+
+    /// Sample variance in every dimension.
+    pub fn variance(&self) -> Vec<f64> {
+        let n = self.samples.len() as f64;
+        self.mean()
+            .iter()
+            .enumerate()
+            .map(|(i, m)| self.samples.iter().map(|s| (s[i] - m).powi(2)).sum::<f64>() / (n - 1.))
+            .collect()
+    }
+}
+
+/// Standard normal sample, by the Box-Muller transform.
+fn normal(rng: &mut Rng) -> f64 {
+    let u = 1. - rng.uniform();
+    (-2. * u.ln()).sqrt() * (std::f64::consts::TAU * rng.uniform()).cos()
+}
+
+fn dot(a: &[f64], b: &[f64]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+/// Position with the log-density and its gradient there.
+#[derive(Debug, Clone)]
+struct Point {
+    x: Vec<f64>,
+    logp: f64,
+    grad: Vec<f64>,
+}
+
+/// Point in phase space, a position with a momentum.
+#[derive(Debug, Clone)]
+struct State {
+    point: Point,
+    p: Vec<f64>,
+}
+
+impl State {
+    /// Log of the unnormalized joint density, the negative of the Hamiltonian.
+    fn joint(&self) -> f64 {
+        self.point.logp - 0.5 * dot(&self.p, &self.p)
+    }
+}
+
+/// Log-density `f` together with the tape its gradients are computed on.
+struct Target<F> {
+    tape: Tape,
+    f: F,
+}
+
+impl<F> Target<F>
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    /// Evaluate the log-density and its gradient at `x`. A non-finite value is treated as
+    /// negative infinity, outside the support.
+    fn eval(&self, x: Vec<f64>) -> Point {
+        self.tape.clear();
+        let params = self.tape.add_vars(&x);
+        let res = (self.f)(&params);
+        assert_same_tape("Target::eval", &self.tape, res.tape);
+        let grad = res.grad().iter().take(x.len()).copied().collect();
+        let logp = if res.val.is_finite() {
+            res.val
+        } else {
+            f64::NEG_INFINITY
+        };
+        Point { x, logp, grad }
+    }
+
+    /// Take a leapfrog step of size `eps`, which is negative to integrate backwards in time.
+    fn leapfrog(&self, state: &State, eps: f64) -> State {
+        let half = state
+            .p
+            .iter()
+            .zip(&state.point.grad)
+            .map(|(p, g)| p + 0.5 * eps * g)
+            .collect::<Vec<_>>();
+        let x = state
+            .point
+            .x
+            .iter()
+            .zip(&half)
+            .map(|(x, p)| x + eps * p)
+            .collect();
+        let point = self.eval(x);
+        let p = half
+            .iter()
+            .zip(&point.grad)
+            .map(|(p, g)| p + 0.5 * eps * g)
+            .collect();
+        State { point, p }
+    }
+
+    /// Draw a momentum from the standard normal distribution.
+    fn momentum(&self, rng: &mut Rng, point: &Point) -> State {
+        State {
+            point: point.clone(),
+            p: point.x.iter().map(|_| normal(rng)).collect(),
+        }
+    }
+}
+
+/// Dual averaging adaptation of the step size.
+struct StepSize {
+    mu: f64,
+    target: f64,
+    log_eps: f64,
+    log_eps_bar: f64,
+    h_bar: f64,
+    iteration: f64,
+}
+
+impl StepSize {
+    fn new(eps: f64, target: f64) -> Self {
+        Self {
+            mu: (10. * eps).ln(),
+            target,
+            log_eps: eps.ln(),
+            log_eps_bar: 0.,
+            h_bar: 0.,
+            iteration: 0.,
+        }
+    }
+
+    fn current(&self) -> f64 {
+        self.log_eps.exp()
+    }
+
+    /// Update the step size after an iteration with acceptance probability `accept`.
+    fn update(&mut self, accept: f64) {
+        const GAMMA: f64 = 0.05;
+        const T0: f64 = 10.;
+        const KAPPA: f64 = 0.75;
+        self.iteration += 1.;
+        let m = self.iteration;
+        self.h_bar += (self.target - accept - self.h_bar) / (m + T0);
+        self.log_eps = self.mu - m.sqrt() / GAMMA * self.h_bar;
+        let weight = m.powf(-KAPPA);
+        self.log_eps_bar = weight * self.log_eps + (1. - weight) * self.log_eps_bar;
+    }
+
+    /// Step size to use after warmup.
+    fn adapted(&self) -> f64 {
+        if self.iteration == 0. {
+            self.current()
+        } else {
+            self.log_eps_bar.exp()
+        }
+    }
+}
+
+/// Check the options shared by the samplers, and evaluate the initial point.
+fn start<F>(f: F, x0: &[f64], step_size: f64, target_accept: f64) -> (Target<F>, Point)
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    assert!(step_size > 0., "step size must be positive");
+    assert!(
+        target_accept > 0. && target_accept < 1.,
+        "target acceptance probability must be in (0, 1)"
+    );
+    let target = Target {
+        tape: Tape::new(),
+        f,
+    };
+    let point = target.eval(x0.to_vec());
+    assert!(
+        point.logp.is_finite(),
+        "the log density must be finite at the initial point"
+    );
+    (target, point)
+}
+
+/// Run `iterations` of a sampler whose step from a point, with a step size, returns the next
+/// point, its acceptance probability and whether the trajectory diverged.
+fn run(
+    point: Point,
+    warmup: usize,
+    samples: usize,
+    mut step_size: StepSize,
+    mut step: impl FnMut(&Point, f64) -> (Point, f64, bool),
+) -> Chain {
+    let mut point = point;
+    for _ in 0..warmup {
+        let (next, accept, _) = step(&point, step_size.current());
+        point = next;
+        step_size.update(accept);
+    }
+    let eps = step_size.adapted();
+    let mut chain = Chain {
+        samples: Vec::with_capacity(samples),
+        log_density: Vec::with_capacity(samples),
+        step_size: eps,
+        acceptance: 0.,
+        divergences: 0,
+    };
+    for _ in 0..samples {
+        let (next, accept, divergent) = step(&point, eps);
+        point = next;
+        chain.acceptance += accept / samples as f64;
+        chain.divergences += divergent as usize;
+        chain.samples.push(point.x.clone());
+        chain.log_density.push(point.logp);
+    }
+    chain
+}
+
+/// Draw samples from the distribution with log-density `f` by Hamiltonian Monte Carlo, starting
+/// from `x0`. Every iteration simulates a trajectory of `leapfrog_steps` leapfrog steps from a
+/// fresh momentum, and accepts its end point with the Metropolis probability.
+///
+/// # Panics
+///
+/// Panics if the log-density is not finite at `x0`, or the options are invalid.
+pub fn hmc<F>(f: F, x0: &[f64], options: HmcOptions) -> Chain
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    assert!(
+        options.leapfrog_steps > 0,
+        "need at least one leapfrog step"
+    );
+    let (target, point) = start(f, x0, options.step_size, options.target_accept);
+    let mut rng = Rng::new(options.seed);
+    let step_size = StepSize::new(options.step_size, options.target_accept);
+    run(
+        point,
+        options.warmup,
+        options.samples,
+        step_size,
+        |point, eps| {
+            let initial = target.momentum(&mut rng, point);
+            let mut state = initial.clone();
+            for _ in 0..options.leapfrog_steps {
+                state = target.leapfrog(&state, eps);
+                if !state.point.logp.is_finite() {
+                    break;
+                }
+            }
+            let log_ratio = state.joint() - initial.joint();
+            let accept = if log_ratio.is_nan() {
+                0.
+            } else {
+                log_ratio.exp().min(1.)
+            };
+            let divergent = log_ratio.is_nan() || log_ratio <= -MAX_ENERGY_ERROR;
+            if rng.uniform() < accept {
+                (state.point, accept, divergent)
+            } else {
+                (point.clone(), accept, divergent)
+            }
+        },
+    )
+}
+
+/// Subtree of a NUTS trajectory.
+struct Tree {
+    minus: State,
+    plus: State,
+    proposal: Point,
+    /// Number of states in the slice.
+    n: usize,
+    /// Whether the subtree can be extended, having neither made a U-turn nor diverged.
+    ok: bool,
+    divergent: bool,
+    /// Sum of the acceptance probabilities of the states, and the number of states.
+    alpha: f64,
+    n_alpha: usize,
+}
+
+/// Whether the trajectory from `minus` to `plus` has not started to turn back on itself.
+fn no_u_turn(minus: &State, plus: &State) -> bool {
+    let dx = plus
+        .point
+        .x
+        .iter()
+        .zip(&minus.point.x)
+        .map(|(a, b)| a - b)
+        .collect::<Vec<_>>();
+    dot(&dx, &minus.p) >= 0. && dot(&dx, &plus.p) >= 0.
+}
+
+impl<F> Target<F>
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    /// Build a subtree of `2^depth` leapfrog steps from `state` in direction `dir`, for the slice
+    /// variable `log_u` and the initial joint log-density `joint0`.
+    #[allow(clippy::too_many_arguments)]
+    fn build_tree(
+        &self,
+        rng: &mut Rng,
+        state: &State,
+        log_u: f64,
+        dir: f64,
+        depth: usize,
+        eps: f64,
+        joint0: f64,
+    ) -> Tree {
+        if depth == 0 {
+            let next = self.leapfrog(state, dir * eps);
+            let joint = next.joint();
+            let divergent = joint.is_nan() || log_u >= MAX_ENERGY_ERROR + joint;
+            let log_ratio = joint - joint0;
+            return Tree {
+                minus: next.clone(),
+                plus: next.clone(),
+                proposal: next.point,
+                n: (log_u <= joint) as usize,
+                ok: !divergent,
+                divergent,
+                alpha: if log_ratio.is_nan() {
+                    0.
+                } else {
+                    log_ratio.exp().min(1.)
+                },
+                n_alpha: 1,
+            };
+        }
+        let mut tree = self.build_tree(rng, state, log_u, dir, depth - 1, eps, joint0);
+        if !tree.ok {
+            return tree;
+        }
+        let edge = if dir < 0. { &tree.minus } else { &tree.plus };
+        let other = self.build_tree(rng, edge, log_u, dir, depth - 1, eps, joint0);
+        if other.n > 0 && rng.uniform() < other.n as f64 / (tree.n + other.n) as f64 {
+            tree.proposal = other.proposal;
+        }
+        if dir < 0. {
+            tree.minus = other.minus;
+        } else {
+            tree.plus = other.plus;
+        }
+        tree.n += other.n;
+        tree.alpha += other.alpha;
+        tree.n_alpha += other.n_alpha;
+        tree.divergent = other.divergent;
+        tree.ok = other.ok && no_u_turn(&tree.minus, &tree.plus);
+        tree
+    }
+}
+
+/// Draw samples from the distribution with log-density `f` by the No-U-Turn Sampler (Hoffman and
+/// Gelman, 2014), starting from `x0`. Instead of a fixed number of leapfrog steps, every iteration
+/// doubles its trajectory, forwards or backwards in time, until it starts to turn back on itself,
+/// and picks the next sample among the visited states.
+///
+/// # Panics
+///
+/// Panics if the log-density is not finite at `x0`, or the options are invalid.
+pub fn nuts<F>(f: F, x0: &[f64], options: NutsOptions) -> Chain
+where
+    F: for<'a> Fn(&[Var<'a>]) -> Var<'a>,
+{
+    let (target, point) = start(f, x0, options.step_size, options.target_accept);
+    let mut rng = Rng::new(options.seed);
+    let step_size = StepSize::new(options.step_size, options.target_accept);
+    run(
+        point,
+        options.warmup,
+        options.samples,
+        step_size,
+        |point, eps| {
+            let initial = target.momentum(&mut rng, point);
+            let joint0 = initial.joint();
+            // slice variable, uniform on [0, exp(joint0)]
+            let log_u = joint0 + (1. - rng.uniform()).ln();
+            let (mut minus, mut plus) = (initial.clone(), initial);
+            let mut next = point.clone();
+            let (mut n, mut accept, mut divergent) = (1, 0., false);
+            for depth in 0..options.max_depth {
+                let dir = if rng.uniform() < 0.5 { -1. } else { 1. };
+                let edge = if dir < 0. { &minus } else { &plus };
+                let tree = target.build_tree(&mut rng, edge, log_u, dir, depth, eps, joint0);
+                if dir < 0. {
+                    minus = tree.minus;
+                } else {
+                    plus = tree.plus;
+                }
+                accept = tree.alpha / tree.n_alpha as f64;
+                divergent = tree.divergent;
+                if tree.ok && rng.uniform() < tree.n as f64 / n as f64 {
+                    next = tree.proposal;
+                }
+                n += tree.n;
+                if !tree.ok || !no_u_turn(&minus, &plus) {
+                    break;
+                }
+            }
+            (next, accept, divergent)
+        },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    /// Correlated normal distribution with means 1 and -2, standard deviations 1 and 0.5 and
+    /// correlation 0.6.
+    fn log_density<'a>(x: &[Var<'a>]) -> Var<'a> {
+        let a = x[0] - 1.;
+        let b = (x[1] + 2.) * 2.;
+        (a.powi(2) - a * b * 1.2 + b.powi(2)) * (-0.5 / 0.64)
+    }
+
+    fn check(chain: &Chain) {
+        let (mean, var) = (chain.mean(), chain.variance());
+        assert!((mean[0] - 1.).abs() < 0.15, "{:?}", mean);
+        assert!((mean[1] + 2.).abs() < 0.075, "{:?}", mean);
+        assert!((var[0] - 1.).abs() < 0.2, "{:?}", var);
+        assert!((var[1] - 0.25).abs() < 0.05, "{:?}", var);
+        assert!((chain.acceptance - 0.8).abs() < 0.1, "{}", chain.acceptance);
+        assert_eq!(chain.divergences, 0);
+    }
+
+    #[test]
+    fn test_hmc() {
+        let mut options = HmcOptions::new(2000);
+        options.leapfrog_steps = 5;
+        let chain = hmc(log_density, &[3., 0.], options);
+        assert_eq!(chain.samples.len(), 2000);
+        assert_approx_eq!(chain.log_density[7], log_density_at(&chain.samples[7]));
+        check(&chain);
+    }
+
+    #[test]
+    fn test_nuts() {
+        let chain = nuts(log_density, &[3., 0.], NutsOptions::new(2000));
+        check(&chain);
+        // the same seed gives the same chain
+        let again = nuts(log_density, &[3., 0.], NutsOptions::new(2000));
+        assert_eq!(chain.samples, again.samples);
+    }
+
+    fn log_density_at(x: &[f64]) -> f64 {
+        let tape = Tape::new();
+        log_density(&tape.add_vars(x)).val()
+    }
+
+    #[test]
+    #[should_panic(expected = "must be finite")]
+    fn test_outside_support() {
+        hmc(|x| x[0].ln(), &[-1.], HmcOptions::new(10));
+    }
+}
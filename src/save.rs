@@ -0,0 +1,619 @@
+//! Saving a recorded tape to bytes and loading it back, so that large recordings can be cached
+//! between runs.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2.]);
+//! let y = (x[0] * x[1]).sin() + x[1].ln();
+//! let mut bytes = vec![];
+//! tape.save(&mut bytes).unwrap();
+//!
+//! let loaded = Tape::load(&bytes[..]).unwrap();
+//! assert_eq!(loaded.len(), tape.len());
+//! let vals = loaded.replay(&[1., 2.]).unwrap();
+//! assert_eq!(vals.wrt(&y), y.val);
+//! ```
+//!
+//! Locations are kept, so a variable's location on the saved tape refers to the same node on the
+//! loaded one. The format is little-endian throughout:
+//!
+//! - the magic bytes `RVRS`, the format version as a `u32` (currently 1), and a `u8` of flags:
+//!   1 if values are recorded (`Tape::with_provenance`), 2 for compensation
+//!   (`Tape::with_compensation`), 4 for interning (`Tape::with_interning`),
+//! - the number of nodes as a `u64`, followed by each node as a `u8` operation tag, a `u64`
+//!   parameter (the bits of the `f64` constant of the operation, its integer parameter, or zero),
+//!   the two partial derivatives as `f64`s and the two dependency locations as `u64`s,
+//! - the number of n-ary nodes (sums, products, reductions and losses) as a `u64`, followed by
+//!   each as its location and number of operands as `u64`s, and every operand as its location
+//!   as a `u64` and its weight as an `f64`,
+//! - if values are recorded, the value of each node as an `f64`.
+//!
+//! The operation tags are listed in `encode`. Operations with a custom backward pass (such as
+//! `solve`) and hooks hold closures, so tapes with them cannot be saved.
+
+use crate::{intern::Interner, Node, Op, Span, Tape};
+use std::{
+    cell::RefCell,
+    convert::TryFrom,
+    io::{Error, ErrorKind, Read, Result, Write},
+};
+
+const MAGIC: &[u8; 4] = b"RVRS";
+const VERSION: u32 = 1;
+const PROVENANCE: u8 = 1;
+const COMPENSATED: u8 = 2;
+const INTERNING: u8 = 4;
+
+impl Tape {
+    /// Write the tape to `writer` in the format described in the `save` module. Wrap files in a
+    /// `BufWriter`, as the tape is written a few bytes at a time.
+    ///
+    /// Returns an error of kind `InvalidInput` if the tape holds an operation with a custom
+    /// backward pass or a hook.
+    pub fn save(&self, mut writer: impl Write) -> Result<()> {
+        if !self.blocks.borrow().is_empty() || !self.hooks.borrow().is_empty() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "tapes with custom backward passes or hooks cannot be saved",
+            ));
+        }
+        let flags = if self.provenance.is_some() {
+            PROVENANCE
+        } else {
+            0
+        } | if self.compensated { COMPENSATED } else { 0 }
+            | if self.interner.is_some() {
+                INTERNING
+            } else {
+                0
+            };
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        writer.write_all(&[flags])?;
+
+        let nodes = self.nodes.borrow();
+        writer.write_all(&(nodes.len() as u64).to_le_bytes())?;
+        for (node, &op) in nodes.iter().zip(self.ops.borrow().iter()) {
+            let (tag, param) = encode(op).expect("blocks are not saved");
+            writer.write_all(&[tag])?;
+            writer.write_all(&param.to_le_bytes())?;
+            for weight in node.weights {
+                writer.write_all(&weight.to_le_bytes())?;
+            }
+            for dep in node.dependencies() {
+                writer.write_all(&(dep as u64).to_le_bytes())?;
+            }
+        }
+
+        let spans = self.spans.borrow();
+        let operands = self.operands.borrow();
+        writer.write_all(&(spans.len() as u64).to_le_bytes())?;
+        for span in spans.iter() {
+            writer.write_all(&(span.node as u64).to_le_bytes())?;
+            writer.write_all(&((span.end - span.start) as u64).to_le_bytes())?;
+            for &(dep, weight) in &operands[span.start..span.end] {
+                writer.write_all(&(dep as u64).to_le_bytes())?;
+                writer.write_all(&weight.to_le_bytes())?;
+            }
+        }
+
+        if let Some(vals) = &self.provenance {
+            for val in vals.borrow().iter() {
+                writer.write_all(&val.to_le_bytes())?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Read a tape written by `save`. Wrap files in a `BufReader`, as the tape is read a few
+    /// bytes at a time.
+    ///
+    /// Returns an error of kind `InvalidData` if the bytes are not a valid tape.
+    pub fn load(mut reader: impl Read) -> Result<Tape> {
+        let mut magic = [0; 4];
+        reader.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(invalid("not a saved tape"));
+        }
+        if read_u32(&mut reader)? != VERSION {
+            return Err(invalid("unsupported version of the tape format"));
+        }
+        let mut flags = [0];
+        reader.read_exact(&mut flags)?;
+        let flags = flags[0];
+        let tape = Tape {
+            compensated: flags & COMPENSATED != 0,
+            interner: (flags & INTERNING != 0).then(|| RefCell::new(Interner::default())),
+            ..Tape::new()
+        };
+
+        let len = read_len(&mut reader)?;
+        {
+            let mut nodes = tape.nodes.borrow_mut();
+            let mut ops = tape.ops.borrow_mut();
+            for idx in 0..len {
+                let mut tag = [0];
+                reader.read_exact(&mut tag)?;
+                let op = decode(tag[0], read_u64(&mut reader)?)?;
+                let weights = [read_f64(&mut reader)?, read_f64(&mut reader)?];
+                let deps = [read_len(&mut reader)?, read_len(&mut reader)?];
+                // the backward pass relies on dependencies preceding their users
+                if deps.iter().any(|&dep| dep > idx) {
+                    return Err(invalid("node depends on a later node"));
+                }
+                nodes.push(Node::new(weights, deps));
+                ops.push(op);
+            }
+        }
+
+        let count = read_len(&mut reader)?;
+        {
+            let ops = tape.ops.borrow();
+            let mut spans = tape.spans.borrow_mut();
+            let mut operands = tape.operands.borrow_mut();
+            for _ in 0..count {
+                let node = read_len(&mut reader)?;
+                if node >= len || spans.last().is_some_and(|span: &Span| span.node >= node) {
+                    return Err(invalid("n-ary nodes are out of order"));
+                }
+                let start = operands.len();
+                for _ in 0..read_len(&mut reader)? {
+                    let dep = read_len(&mut reader)?;
+                    if dep >= node {
+                        return Err(invalid("node depends on a later node"));
+                    }
+                    operands.push((dep, read_f64(&mut reader)?));
+                }
+                spans.push(Span {
+                    node,
+                    start,
+                    end: operands.len(),
+                });
+            }
+            // every n-ary operation must have operands it can be evaluated on, and only those may
+            // have them
+            let mut spans = spans.iter().peekable();
+            for (idx, &op) in ops.iter().enumerate() {
+                match spans.next_if(|span| span.node == idx) {
+                    Some(span) if is_nary(op) => {
+                        if !valid_operands(op, span.end - span.start) {
+                            return Err(invalid("wrong number of operands for the operation"));
+                        }
+                    }
+                    None if !is_nary(op) => {}
+                    _ => return Err(invalid("operands do not match the operations")),
+                }
+            }
+        }
+
+        if flags & PROVENANCE != 0 {
+            let vals = (0..len)
+                .map(|_| read_f64(&mut reader))
+                .collect::<Result<Vec<_>>>()?;
+            return Ok(Tape {
+                provenance: Some(RefCell::new(vals)),
+                ..tape
+            });
+        }
+        Ok(tape)
+    }
+}
+
+fn invalid(message: &str) -> Error {
+    Error::new(ErrorKind::InvalidData, message)
+}
+
+fn read_u32(reader: &mut impl Read) -> Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f64(reader: &mut impl Read) -> Result<f64> {
+    read_u64(reader).map(f64::from_bits)
+}
+
+/// Read a location or count, which must fit in a `usize`.
+fn read_len(reader: &mut impl Read) -> Result<usize> {
+    int(read_u64(reader)?)
+}
+
+/// Convert a parameter to an integer type, failing if it does not fit.
+fn int<T: TryFrom<i64>>(param: u64) -> Result<T> {
+    T::try_from(param as i64).map_err(|_| invalid("integer out of range"))
+}
+
+/// Checks whether `op` takes its operands from a span rather than from the node's dependencies.
+fn is_nary(op: Op) -> bool {
+    matches!(
+        op,
+        Op::Sum
+            | Op::CompensatedSum
+            | Op::Mean
+            | Op::Variance
+            | Op::Std
+            | Op::Dot
+            | Op::LogSumExp
+            | Op::MaxOf
+            | Op::MinOf
+            | Op::NormL1
+            | Op::NormL2
+            | Op::NormLp(_)
+            | Op::Polyval
+            | Op::Mse
+            | Op::Mae
+            | Op::BinaryCrossEntropy
+            | Op::CrossEntropy
+            | Op::Hinge
+            | Op::Entropy
+            | Op::KlDiv
+            | Op::KlDivLogits
+            | Op::Einsum(_)
+            | Op::Affine
+            | Op::Linear
+            | Op::SelectGt(_)
+            | Op::LogDet
+            | Op::Det
+    )
+}
+
+/// Checks whether the n-ary operation `op` can be evaluated on `count` operands: products take
+/// whole terms, losses take pairs, determinants take square matrices and polynomials take at
+/// least a coefficient and the point.
+fn valid_operands(op: Op, count: usize) -> bool {
+    match op {
+        Op::Einsum(factors) => factors != 0 && count % factors == 0,
+        Op::Dot
+        | Op::Mse
+        | Op::Mae
+        | Op::BinaryCrossEntropy
+        | Op::CrossEntropy
+        | Op::Hinge
+        | Op::KlDiv
+        | Op::KlDivLogits => count % 2 == 0,
+        Op::Affine => count % 2 == 1,
+        Op::Polyval => count >= 2,
+        Op::MaxOf | Op::MinOf => count > 0,
+        Op::SelectGt(_) => count == 3,
+        Op::LogDet | Op::Det => {
+            let size = (count as f64).sqrt() as usize;
+            size * size == count
+        }
+        _ => true,
+    }
+}
+
+/// Tag and parameter of `op`, or `None` for operations recorded as blocks. The tags are:
+///
+/// 0 Input, 1 Const, 2 Add, 3 AddConst, 4 ConstSub, 5 Mul, 6 MulConst, 7 ConstDiv, 8 Recip, 9
+/// Sin, 10 Cos, 11 Tan, 12 Ln, 13 Log, 14 Ln1p, 15 Asin, 16 Acos, 17 Atan, 18 Sinh, 19 Cosh, 20
+/// Tanh, 21 Asinh, 22 Acosh, 23 Atanh, 24 Exp, 25 ExpM1, 26 Exp2, 27 Sqrt, 28 Abs, 29 Huber, 30
+/// SmoothAbs, 31 Powi, 32 Powf, 33 PowfConst, 34 ConstPowf, 35 Atan2, 36 Atan2Const, 37
+/// ConstAtan2, 38 Hypot, 39 HypotConst, 40 LogAddExp, 41 LogAddExpConst, 42 Copysign, 43
+/// CopysignConst, 44 Floor, 45 Ceil, 46 Round, 47 Trunc, 48 Signum, 49 Fract, 50 Erf, 51 Erfc,
+/// 52 Lgamma, 53 Polygamma, 54 BesselJ, 55 BesselI0, 56 BesselI1, 57 NormCdf, 58 NormCdfInv, 59
+/// Sinc, 60 LnBeta, 61 Relu, 62 Sigmoid, 63 Sum, 64 CompensatedSum, 65 Mean, 66 Variance, 67
+/// Std, 68 Dot, 69 LogSumExp, 70 MaxOf, 71 MinOf, 72 Polyval, 73 Mse, 74 Mae, 75
+/// BinaryCrossEntropy, 76 CrossEntropy, 77 Hinge, 78 Einsum, 79 Affine, 80 LogDet, 81 Det, 82
+/// NormL1, 83 NormL2, 84 NormLp, 85 Entropy, 86 KlDiv, 87 KlDivLogits, 88 Linear, 89 Select, 90
+/// SelectGt. Relu and Sigmoid need the `nn` feature to be loaded.
+fn encode(op: Op) -> Option<(u8, u64)> {
+    Some(match op {
+        Op::Input => (0, 0),
+        Op::Const(c) => (1, c.to_bits()),
+        Op::Add => (2, 0),
+        Op::AddConst(c) => (3, c.to_bits()),
+        Op::ConstSub(c) => (4, c.to_bits()),
+        Op::Mul => (5, 0),
+        Op::MulConst(c) => (6, c.to_bits()),
+        Op::ConstDiv(c) => (7, c.to_bits()),
+        Op::Recip => (8, 0),
+        Op::Sin => (9, 0),
+        Op::Cos => (10, 0),
+        Op::Tan => (11, 0),
+        Op::Ln => (12, 0),
+        Op::Log(c) => (13, c.to_bits()),
+        Op::Ln1p => (14, 0),
+        Op::Asin => (15, 0),
+        Op::Acos => (16, 0),
+        Op::Atan => (17, 0),
+        Op::Sinh => (18, 0),
+        Op::Cosh => (19, 0),
+        Op::Tanh => (20, 0),
+        Op::Asinh => (21, 0),
+        Op::Acosh => (22, 0),
+        Op::Atanh => (23, 0),
+        Op::Exp => (24, 0),
+        Op::ExpM1 => (25, 0),
+        Op::Exp2 => (26, 0),
+        Op::Sqrt => (27, 0),
+        Op::Abs => (28, 0),
+        Op::Huber(c) => (29, c.to_bits()),
+        Op::SmoothAbs(c) => (30, c.to_bits()),
+        Op::Powi(n) => (31, n as i64 as u64),
+        Op::Powf => (32, 0),
+        Op::PowfConst(c) => (33, c.to_bits()),
+        Op::ConstPowf(c) => (34, c.to_bits()),
+        Op::Atan2 => (35, 0),
+        Op::Atan2Const(c) => (36, c.to_bits()),
+        Op::ConstAtan2(c) => (37, c.to_bits()),
+        Op::Hypot => (38, 0),
+        Op::HypotConst(c) => (39, c.to_bits()),
+        Op::LogAddExp => (40, 0),
+        Op::LogAddExpConst(c) => (41, c.to_bits()),
+        Op::Copysign => (42, 0),
+        Op::CopysignConst(c) => (43, c.to_bits()),
+        Op::Floor => (44, 0),
+        Op::Ceil => (45, 0),
+        Op::Round => (46, 0),
+        Op::Trunc => (47, 0),
+        Op::Signum => (48, 0),
+        Op::Fract => (49, 0),
+        Op::Erf => (50, 0),
+        Op::Erfc => (51, 0),
+        Op::Lgamma => (52, 0),
+        Op::Polygamma(n) => (53, n as u64),
+        Op::BesselJ(n) => (54, n as i64 as u64),
+        Op::BesselI0 => (55, 0),
+        Op::BesselI1 => (56, 0),
+        Op::NormCdf => (57, 0),
+        Op::NormCdfInv => (58, 0),
+        Op::Sinc => (59, 0),
+        Op::LnBeta => (60, 0),
+        #[cfg(feature = "nn")]
+        Op::Relu => (61, 0),
+        #[cfg(feature = "nn")]
+        Op::Sigmoid => (62, 0),
+        Op::Sum => (63, 0),
+        Op::CompensatedSum => (64, 0),
+        Op::Mean => (65, 0),
+        Op::Variance => (66, 0),
+        Op::Std => (67, 0),
+        Op::Dot => (68, 0),
+        Op::LogSumExp => (69, 0),
+        Op::MaxOf => (70, 0),
+        Op::MinOf => (71, 0),
+        Op::Polyval => (72, 0),
+        Op::Mse => (73, 0),
+        Op::Mae => (74, 0),
+        Op::BinaryCrossEntropy => (75, 0),
+        Op::CrossEntropy => (76, 0),
+        Op::Hinge => (77, 0),
+        Op::Einsum(n) => (78, n as u64),
+        Op::Affine => (79, 0),
+        Op::LogDet => (80, 0),
+        Op::Det => (81, 0),
+        Op::NormL1 => (82, 0),
+        Op::NormL2 => (83, 0),
+        Op::NormLp(p) => (84, p.to_bits()),
+        Op::Entropy => (85, 0),
+        Op::KlDiv => (86, 0),
+        Op::KlDivLogits => (87, 0),
+        Op::Linear => (88, 0),
+        Op::Select => (89, 0),
+        Op::SelectGt(c) => (90, c.to_bits()),
+        _ => return None,
+    })
+}
+
+/// Operation with the tag `tag` and parameter `param`, see `encode`.
+fn decode(tag: u8, param: u64) -> Result<Op> {
+    Ok(match tag {
+        0 => Op::Input,
+        1 => Op::Const(f64::from_bits(param)),
+        2 => Op::Add,
+        3 => Op::AddConst(f64::from_bits(param)),
+        4 => Op::ConstSub(f64::from_bits(param)),
+        5 => Op::Mul,
+        6 => Op::MulConst(f64::from_bits(param)),
+        7 => Op::ConstDiv(f64::from_bits(param)),
+        8 => Op::Recip,
+        9 => Op::Sin,
+        10 => Op::Cos,
+        11 => Op::Tan,
+        12 => Op::Ln,
+        13 => Op::Log(f64::from_bits(param)),
+        14 => Op::Ln1p,
+        15 => Op::Asin,
+        16 => Op::Acos,
+        17 => Op::Atan,
+        18 => Op::Sinh,
+        19 => Op::Cosh,
+        20 => Op::Tanh,
+        21 => Op::Asinh,
+        22 => Op::Acosh,
+        23 => Op::Atanh,
+        24 => Op::Exp,
+        25 => Op::ExpM1,
+        26 => Op::Exp2,
+        27 => Op::Sqrt,
+        28 => Op::Abs,
+        29 => Op::Huber(f64::from_bits(param)),
+        30 => Op::SmoothAbs(f64::from_bits(param)),
+        31 => Op::Powi(int(param)?),
+        32 => Op::Powf,
+        33 => Op::PowfConst(f64::from_bits(param)),
+        34 => Op::ConstPowf(f64::from_bits(param)),
+        35 => Op::Atan2,
+        36 => Op::Atan2Const(f64::from_bits(param)),
+        37 => Op::ConstAtan2(f64::from_bits(param)),
+        38 => Op::Hypot,
+        39 => Op::HypotConst(f64::from_bits(param)),
+        40 => Op::LogAddExp,
+        41 => Op::LogAddExpConst(f64::from_bits(param)),
+        42 => Op::Copysign,
+        43 => Op::CopysignConst(f64::from_bits(param)),
+        44 => Op::Floor,
+        45 => Op::Ceil,
+        46 => Op::Round,
+        47 => Op::Trunc,
+        48 => Op::Signum,
+        49 => Op::Fract,
+        50 => Op::Erf,
+        51 => Op::Erfc,
+        52 => Op::Lgamma,
+        53 => Op::Polygamma(int(param)?),
+        54 => Op::BesselJ(int(param)?),
+        55 => Op::BesselI0,
+        56 => Op::BesselI1,
+        57 => Op::NormCdf,
+        58 => Op::NormCdfInv,
+        59 => Op::Sinc,
+        60 => Op::LnBeta,
+        #[cfg(feature = "nn")]
+        61 => Op::Relu,
+        #[cfg(feature = "nn")]
+        62 => Op::Sigmoid,
+        63 => Op::Sum,
+        64 => Op::CompensatedSum,
+        65 => Op::Mean,
+        66 => Op::Variance,
+        67 => Op::Std,
+        68 => Op::Dot,
+        69 => Op::LogSumExp,
+        70 => Op::MaxOf,
+        71 => Op::MinOf,
+        72 => Op::Polyval,
+        73 => Op::Mse,
+        74 => Op::Mae,
+        75 => Op::BinaryCrossEntropy,
+        76 => Op::CrossEntropy,
+        77 => Op::Hinge,
+        78 => Op::Einsum(int(param)?),
+        79 => Op::Affine,
+        80 => Op::LogDet,
+        81 => Op::Det,
+        82 => Op::NormL1,
+        83 => Op::NormL2,
+        84 => Op::NormLp(f64::from_bits(param)),
+        85 => Op::Entropy,
+        86 => Op::KlDiv,
+        87 => Op::KlDivLogits,
+        88 => Op::Linear,
+        89 => Op::Select,
+        90 => Op::SelectGt(f64::from_bits(param)),
+        _ => return Err(invalid("unknown operation tag")),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{losses, Atan2, Gradient, Var};
+
+    #[test]
+    fn test_round_trip() {
+        let g = Tape::with_provenance();
+        let x = g.add_vars(&[0.5, 2., -1.]);
+        let y =
+            (x[0] * x[1]).powi(3) + x[2].atan2(x[1]) - losses::mse(&x, &[x[1], x[0], x[2] * 0.5]);
+        let mut bytes = vec![];
+        g.save(&mut bytes).unwrap();
+
+        let loaded = Tape::load(&bytes[..]).unwrap();
+        assert_eq!(loaded.len(), g.len());
+        assert_eq!(loaded.ops.borrow()[..], g.ops.borrow()[..]);
+        assert_eq!(loaded.provenance, g.provenance);
+        let z = Var {
+            tape: &loaded,
+            location: y.location,
+            val: y.val,
+        };
+        assert_eq!(z.grad()[..], y.grad()[..]);
+        assert_eq!(loaded.replay(&[0.5, 2., -1.]).unwrap().wrt(&z), y.val);
+
+        let mut again = vec![];
+        loaded.save(&mut again).unwrap();
+        assert_eq!(again, bytes);
+    }
+
+    #[test]
+    fn test_invalid() {
+        let g = Tape::new();
+        let x = g.add_vars(&[1., 2.]);
+        let _ = crate::sum(&x).sin();
+        let mut bytes = vec![];
+        g.save(&mut bytes).unwrap();
+        assert!(Tape::load(&bytes[..]).is_ok());
+
+        let error = |bytes: &[u8]| Tape::load(bytes).unwrap_err().kind();
+        assert_eq!(error(&bytes[..bytes.len() - 1]), ErrorKind::UnexpectedEof);
+        assert_eq!(error(b"nope"), ErrorKind::InvalidData);
+        // the first dependency of the first node points past it
+        let mut later = bytes.clone();
+        later[17 + 25] = 1;
+        assert_eq!(error(&later), ErrorKind::InvalidData);
+        // unknown operation tag
+        let mut tag = bytes.clone();
+        tag[17] = 200;
+        assert_eq!(error(&tag), ErrorKind::InvalidData);
+
+        let _ = crate::inv(&crate::Mat::from_vals(&g, 1, 1, &[2.]));
+        assert_eq!(
+            g.save(&mut vec![]).unwrap_err().kind(),
+            ErrorKind::InvalidInput
+        );
+    }
+
+    #[test]
+    fn test_invalid_operands() {
+        // `count` inputs followed by the operation with tag `tag` and parameter `param` on them
+        let tape = |tag: u8, param: u64, count: u64| {
+            let mut bytes = MAGIC.to_vec();
+            bytes.extend(VERSION.to_le_bytes());
+            bytes.push(0);
+            bytes.extend((count + 1).to_le_bytes());
+            for (tag, param, dep) in (0..count).map(|i| (0, 0, i)).chain([(tag, param, 0)]) {
+                bytes.push(tag);
+                bytes.extend(param.to_le_bytes());
+                bytes.extend([0.; 2].iter().flat_map(|w: &f64| w.to_le_bytes()));
+                bytes.extend([dep; 2].iter().flat_map(|d| d.to_le_bytes()));
+            }
+            bytes.extend(1_u64.to_le_bytes());
+            bytes.extend(count.to_le_bytes());
+            bytes.extend(count.to_le_bytes());
+            for dep in 0..count {
+                bytes.extend(dep.to_le_bytes());
+                bytes.extend(1_f64.to_le_bytes());
+            }
+            bytes
+        };
+        let valid = |tag, param, count| match Tape::load(&tape(tag, param, count)[..]) {
+            Ok(loaded) => {
+                let _ = loaded.replay(&vec![1.; count as usize]).unwrap();
+                true
+            }
+            Err(e) => {
+                assert_eq!(e.kind(), ErrorKind::InvalidData);
+                false
+            }
+        };
+        // einsum with a factor of 2, 3 and 0
+        assert!(valid(78, 2, 4));
+        assert!(!valid(78, 3, 4));
+        assert!(!valid(78, 0, 2));
+        // polyval needs a coefficient and the point
+        assert!(valid(72, 0, 2));
+        assert!(!valid(72, 0, 1));
+        assert!(!valid(72, 0, 0));
+        // mse and dot on pairs, affine on an offset and pairs
+        assert!(valid(73, 0, 4));
+        assert!(!valid(73, 0, 3));
+        assert!(!valid(68, 0, 3));
+        assert!(valid(79, 0, 3));
+        assert!(!valid(79, 0, 0));
+        // determinants of square matrices
+        assert!(valid(81, 0, 4));
+        assert!(!valid(81, 0, 3));
+        assert!(!valid(80, 0, 5));
+        assert!(!valid(70, 0, 0));
+    }
+}
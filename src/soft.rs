@@ -0,0 +1,210 @@
+//! Differentiable relaxations of sorting and top-k selection.
+//!
+//! Both are built on the SoftSort relaxation (Prillo and Eisenschlos, 2020) of the permutation
+//! matrix that sorts `s` in decreasing order: row `i` is the softmax over `j` of
+//! `-|sort(s)_i - s_j| / temperature`, so it weights the entries by how close they are to the
+//! `i`th largest one. As the temperature goes to zero the rows approach the rows of the exact
+//! permutation, and for any positive temperature the result has useful gradients with respect to
+//! every entry.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let scores = tape.add_vars(&[0.2, 1.5, -0.3, 0.9]);
+//! let sorted = soft_sort(&scores, 0.01);
+//! assert!((sorted[0].val() - 1.5).abs() < 1e-9);
+//!
+//! // a soft indicator of the two largest scores, summing to two
+//! let mask = soft_top_k(&scores, 2, 0.1);
+//! assert!(mask[1].val() > 0.99 && mask[3].val() > 0.99 && mask[2].val() < 0.01);
+//! assert!((sum(&mask).val() - 2.).abs() < 1e-12);
+//! ```
+
+use crate::{error::assert_same_tape, Op, Tape, Var};
+
+/// Relaxed permutation matrix of `s`, with its first `rows` rows. Also returns the order that
+/// sorts `s` decreasingly, whose entry `i` is the column of the `i`th largest value.
+fn permutation(s: &[f64], rows: usize, temperature: f64) -> (Vec<Vec<f64>>, Vec<usize>) {
+    let mut order = (0..s.len()).collect::<Vec<_>>();
+    order.sort_by(|&a, &b| s[b].total_cmp(&s[a]));
+    let p = order[..rows]
+        .iter()
+        .map(|&i| {
+            let logits = s
+                .iter()
+                .map(|x| -(s[i] - x).abs() / temperature)
+                .collect::<Vec<_>>();
+            // the largest logit is zero, at the entry itself, so the exponentials cannot overflow
+            let exps = logits.iter().map(|l| l.exp()).collect::<Vec<_>>();
+            let total = exps.iter().sum::<f64>();
+            exps.into_iter().map(|e| e / total).collect()
+        })
+        .collect();
+    (p, order)
+}
+
+/// Map the adjoints `p_bar` of the rows of the relaxed permutation matrix `p` to adjoints of `s`,
+/// adding them to `s_bar`. Row `i` depends on `s` through the softmax of its logits, where the
+/// logit of column `j` depends on both `s_j` and the `i`th largest entry `s[order[i]]`.
+fn permutation_backward(
+    s: &[f64],
+    p: &[Vec<f64>],
+    order: &[usize],
+    p_bar: &[Vec<f64>],
+    temperature: f64,
+    s_bar: &mut [f64],
+) {
+    for ((row, row_bar), &i) in p.iter().zip(p_bar).zip(order) {
+        let mean = row.iter().zip(row_bar).map(|(p, g)| p * g).sum::<f64>();
+        for (j, (p, g)) in row.iter().zip(row_bar).enumerate() {
+            let logit_bar = p * (g - mean);
+            let diff = s[i] - s[j];
+            let slope = if diff == 0. {
+                0.
+            } else {
+                diff.signum() / temperature
+            };
+            s_bar[j] += logit_bar * slope;
+            s_bar[i] -= logit_bar * slope;
+        }
+    }
+}
+
+/// Record the outputs `vals` computed from `xs` as a block with the given backward pass, for the
+/// function `name`.
+fn record<'a>(
+    name: &'static str,
+    xs: &[Var<'a>],
+    vals: Vec<f64>,
+    backward: impl Fn(&[f64], &mut [f64]) + Send + Sync + 'static,
+) -> Vec<Var<'a>> {
+    let tape: &'a Tape = xs.first().expect("cannot sort an empty slice").tape;
+    let inputs = xs
+        .iter()
+        .map(|x| {
+            assert_same_tape(name, tape, x.tape);
+            x.location
+        })
+        .collect();
+    let start = tape.add_block(Op::SoftSort, &vals, inputs, backward);
+    vals.into_iter()
+        .enumerate()
+        .map(|(i, val)| Var {
+            val,
+            location: start + i,
+            tape,
+        })
+        .collect()
+}
+
+/// Sort `xs` in decreasing order, softly. Entry `i` of the result is the average of `xs` weighted
+/// by the softmax of `-|sort(xs)_i - xs_j| / temperature` over `j`, which is row `i` of a relaxed
+/// permutation matrix, and approaches the `i`th largest entry of `xs` as `temperature` goes to
+/// zero. The whole sort is recorded as a single block.
+///
+/// # Panics
+///
+/// Panics if `xs` is empty, the temperature is not positive, or the variables are on different
+/// tapes.
+pub fn soft_sort<'a>(xs: &[Var<'a>], temperature: f64) -> Vec<Var<'a>> {
+    assert!(temperature > 0., "temperature must be positive");
+    let s = xs.iter().map(|x| x.val).collect::<Vec<_>>();
+    let (p, order) = permutation(&s, s.len(), temperature);
+    let vals = p
+        .iter()
+        .map(|row| row.iter().zip(&s).map(|(p, x)| p * x).sum())
+        .collect();
+    record("soft_sort", xs, vals, move |out_bars, in_bars| {
+        in_bars.iter_mut().for_each(|bar| *bar = 0.);
+        // y_i = sum_j p_ij s_j, through both the weights and the values
+        let p_bar = out_bars
+            .iter()
+            .map(|&y_bar| s.iter().map(|x| y_bar * x).collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        for (row, &y_bar) in p.iter().zip(out_bars) {
+            for (bar, p) in in_bars.iter_mut().zip(row) {
+                *bar += y_bar * p;
+            }
+        }
+        permutation_backward(&s, &p, &order, &p_bar, temperature, in_bars);
+    })
+}
+
+/// Select the `k` largest entries of `xs`, softly. Returns a mask with one weight in `[0, 1]` for
+/// each entry, the sum of the first `k` rows of the relaxed permutation matrix of `soft_sort`, so
+/// the weights always sum to `k`. As `temperature` goes to zero the mask approaches one at the `k`
+/// largest entries and zero elsewhere. Multiplying the entries by the mask gives their soft top-k.
+///
+/// # Panics
+///
+/// Panics if `xs` is empty, `k` is larger than its length, the temperature is not positive, or the
+/// variables are on different tapes.
+pub fn soft_top_k<'a>(xs: &[Var<'a>], k: usize, temperature: f64) -> Vec<Var<'a>> {
+    assert!(temperature > 0., "temperature must be positive");
+    assert!(k <= xs.len(), "cannot select more entries than there are");
+    let s = xs.iter().map(|x| x.val).collect::<Vec<_>>();
+    let (p, order) = permutation(&s, k, temperature);
+    let vals = (0..s.len())
+        .map(|j| p.iter().map(|row| row[j]).sum())
+        .collect();
+    record("soft_top_k", xs, vals, move |out_bars, in_bars| {
+        in_bars.iter_mut().for_each(|bar| *bar = 0.);
+        // every selected row contributes to every mask entry
+        let p_bar = vec![out_bars.to_vec(); k];
+        permutation_backward(&s, &p, &order, &p_bar, temperature, in_bars);
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{gradcheck, sum, Gradient};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_soft_sort() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[0.3, -1., 2., 0.8]);
+        let sorted = soft_sort(&xs, 1e-3);
+        for (y, expected) in sorted.iter().zip([2., 0.8, 0.3, -1.]) {
+            assert_approx_eq!(y.val(), expected);
+        }
+        // at a low temperature the sorted values move with the entries they came from
+        assert_approx_eq!(sorted[1].grad().wrt(&xs[3]), 1.);
+
+        for temperature in [0.5, 2.] {
+            let check = gradcheck(
+                |x| {
+                    let y = soft_sort(x, temperature);
+                    y[0] * 3. + y[1] * y[2] - y[3].powi(2)
+                },
+                &[0.3, -1., 2., 0.8],
+                1e-6,
+            );
+            assert!(check.passed(), "{}", check);
+        }
+    }
+
+    #[test]
+    fn test_soft_top_k() {
+        let g = Tape::new();
+        let xs = g.add_vars(&[0.3, -1., 2., 0.8]);
+        let mask = soft_top_k(&xs, 2, 1e-2);
+        let vals = mask.iter().map(|m| m.val()).collect::<Vec<_>>();
+        for (val, expected) in vals.iter().zip([0., 0., 1., 1.]) {
+            assert!((val - expected).abs() < 1e-9);
+        }
+        assert_approx_eq!(sum(&soft_top_k(&xs, 3, 0.7)).val(), 3.);
+
+        let check = gradcheck(
+            |x| {
+                let mask = soft_top_k(x, 2, 0.5);
+                mask[0] * x[0] + mask[1] - mask[3].powi(3)
+            },
+            &[0.3, -1., 2., 0.8],
+            1e-6,
+        );
+        assert!(check.passed(), "{}", check);
+    }
+}
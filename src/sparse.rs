@@ -0,0 +1,229 @@
+//! Jacobians of functions with sparse dependency structure.
+//!
+//! The inputs each output depends on are found with a forward pass over the tape. Outputs that
+//! share no inputs are then grouped together (a greedy coloring of the rows), and each group is
+//! differentiated with a single backward pass seeded with all of its outputs at once. For
+//! functions where every output only depends on a few inputs, such as discretized differential
+//! equations, the number of passes stays small however many outputs there are.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2., 3., 4.]);
+//! // each output only depends on its neighbours
+//! let y = (1..3).map(|i| x[i - 1] - 2. * x[i] + x[i + 1].powi(2)).collect::<Vec<_>>();
+//! let jac = sparse_jacobian(&y, &x);
+//! assert_eq!(jac.sweeps(), 2);
+//! assert_eq!(jac.row(0), (&[0, 1, 2][..], &[1., -2., 6.][..]));
+//! assert_eq!(jac.get(1, 3), 8.);
+//! assert_eq!(jac.get(1, 0), 0.);
+//! ```
+
+use crate::{error::assert_same_tape, Tape, Var};
+
+/// Jacobian in compressed sparse row (CSR) form, as returned by `sparse_jacobian`. Entries that
+/// are structurally zero are not stored, while stored entries may still happen to be zero.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SparseJacobian {
+    rows: usize,
+    cols: usize,
+    /// Row `i` is stored at `row_ptr[i]..row_ptr[i + 1]` of `col_idx` and `values`.
+    row_ptr: Vec<usize>,
+    col_idx: Vec<usize>,
+    values: Vec<f64>,
+    sweeps: usize,
+}
+
+impl SparseJacobian {
+    /// Gets the number of rows (outputs) and columns (inputs).
+    pub fn shape(&self) -> (usize, usize) {
+        (self.rows, self.cols)
+    }
+
+    /// Gets the number of stored entries.
+    pub fn nnz(&self) -> usize {
+        self.values.len()
+    }
+
+    /// Gets the number of backward passes used to compute the Jacobian.
+    pub fn sweeps(&self) -> usize {
+        self.sweeps
+    }
+
+    /// Gets the row offsets, column indices and values of the CSR representation.
+    pub fn as_csr(&self) -> (&[usize], &[usize], &[f64]) {
+        (&self.row_ptr, &self.col_idx, &self.values)
+    }
+
+    /// Gets the column indices and values of the stored entries in row `i`.
+    pub fn row(&self, i: usize) -> (&[usize], &[f64]) {
+        let range = self.row_ptr[i]..self.row_ptr[i + 1];
+        (&self.col_idx[range.clone()], &self.values[range])
+    }
+
+    /// Gets the entry in row `i` and column `j`.
+    pub fn get(&self, i: usize, j: usize) -> f64 {
+        let (cols, vals) = self.row(i);
+        cols.binary_search(&j).map_or(0., |k| vals[k])
+    }
+
+    /// Expand into a dense row-major matrix.
+    pub fn to_dense(&self) -> Vec<Vec<f64>> {
+        (0..self.rows)
+            .map(|i| {
+                let mut row = vec![0.; self.cols];
+                let (cols, vals) = self.row(i);
+                for (&j, &val) in cols.iter().zip(vals) {
+                    row[j] = val;
+                }
+                row
+            })
+            .collect()
+    }
+}
+
+impl Tape {
+    /// For each of the first `len` nodes, the sorted indices of the `inputs` it depends on.
+    fn sparsity(&self, inputs: &[usize], len: usize) -> Vec<Vec<usize>> {
+        let mut pattern = vec![vec![]; len];
+        for (col, &input) in inputs.iter().enumerate() {
+            if input < len {
+                pattern[input].push(col);
+            }
+        }
+        let nodes = self.nodes.borrow();
+        let spans = self.spans.borrow();
+        let operands = self.operands.borrow();
+        let blocks = self.blocks.borrow();
+        let mut spans = spans.iter().peekable();
+        let mut blocks = blocks.iter().peekable();
+        for idx in 0..len {
+            let mut deps = nodes[idx].dependencies().to_vec();
+            if let Some(span) = spans.next_if(|span| span.node == idx) {
+                deps.extend(operands[span.start..span.end].iter().map(|&(dep, _)| dep));
+            }
+            if let Some(block) = blocks.peek() {
+                if block.start <= idx {
+                    deps.extend(&block.inputs);
+                    if idx + 1 == block.end {
+                        blocks.next();
+                    }
+                }
+            }
+            let mut cols = pattern[idx].clone();
+            for dep in deps.into_iter().filter(|&dep| dep != idx) {
+                cols.extend(&pattern[dep]);
+            }
+            cols.sort_unstable();
+            cols.dedup();
+            pattern[idx] = cols;
+        }
+        pattern
+    }
+}
+
+/// Calculate the Jacobian of `outputs` with respect to `inputs`, where row `i` holds the gradient
+/// of `outputs[i]`, exploiting sparsity to use as few backward passes as possible.
+///
+/// # Panics
+///
+/// Panics if `outputs` is empty or the variables do not share a tape.
+pub fn sparse_jacobian<'a>(outputs: &[Var<'a>], inputs: &[Var<'a>]) -> SparseJacobian {
+    let tape = outputs.first().expect("no outputs to differentiate").tape;
+    for v in outputs.iter().chain(inputs) {
+        assert_same_tape("sparse_jacobian", tape, v.tape);
+    }
+    let len = outputs.iter().map(|v| v.location).max().unwrap() + 1;
+    let input_locs = inputs.iter().map(|v| v.location).collect::<Vec<_>>();
+    let mut pattern = tape.sparsity(&input_locs, len);
+    let rows = outputs
+        .iter()
+        .map(|v| std::mem::take(&mut pattern[v.location]))
+        .collect::<Vec<_>>();
+
+    // greedy coloring: a row joins the first color that uses none of its columns
+    let mut colors: Vec<(Vec<usize>, Vec<bool>)> = vec![];
+    for (i, row) in rows.iter().enumerate() {
+        match colors
+            .iter_mut()
+            .find(|(_, used)| row.iter().all(|&j| !used[j]))
+        {
+            Some((members, used)) => {
+                members.push(i);
+                row.iter().for_each(|&j| used[j] = true);
+            }
+            None => {
+                let mut used = vec![false; inputs.len()];
+                row.iter().for_each(|&j| used[j] = true);
+                colors.push((vec![i], used));
+            }
+        }
+    }
+
+    let mut values = rows
+        .iter()
+        .map(|row| vec![0.; row.len()])
+        .collect::<Vec<_>>();
+    for (members, _) in &colors {
+        let mut derivs = vec![0.; tape.len()];
+        for &i in members {
+            derivs[outputs[i].location] += 1.;
+        }
+        tape.backward(&mut derivs);
+        for &i in members {
+            for (val, &j) in values[i].iter_mut().zip(&rows[i]) {
+                *val = derivs[input_locs[j]];
+            }
+        }
+    }
+
+    let mut row_ptr = vec![0];
+    for row in &rows {
+        row_ptr.push(row_ptr.last().unwrap() + row.len());
+    }
+    SparseJacobian {
+        rows: outputs.len(),
+        cols: inputs.len(),
+        row_ptr,
+        col_idx: rows.concat(),
+        values: values.concat(),
+        sweeps: colors.len(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{solve, sum, Gradient, Mat};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_sparse_jacobian() {
+        let g = Tape::new();
+        let n = 50;
+        let x = g.add_vars(&(0..n).map(|i| i as f64 / 10.).collect::<Vec<_>>());
+        let mut y = (1..n - 1)
+            .map(|i| x[i - 1].sin() * x[i] + (x[i + 1] * 0.5).exp())
+            .collect::<Vec<_>>();
+        y.push(sum(&x[..3]));
+        let jac = sparse_jacobian(&y, &x);
+        assert_eq!(jac.shape(), (n - 1, n));
+        assert_eq!(jac.nnz(), 3 * (n - 1));
+        assert!(jac.sweeps() <= 4, "used {} sweeps", jac.sweeps());
+        let dense = jac.to_dense();
+        for (row, out) in dense.iter().zip(&y) {
+            let grads = out.grad().wrt(&x);
+            for (entry, grad) in row.iter().zip(grads) {
+                assert_approx_eq!(*entry, grad);
+            }
+        }
+
+        // blocks depend on all of their inputs
+        let a = Mat::new(1, 1, vec![x[2]]);
+        let z = solve(&a, &[x[5]]);
+        let jac = sparse_jacobian(&z, &x);
+        assert_eq!(jac.row(0).0, &[2, 5]);
+        assert_approx_eq!(jac.get(0, 5), 1. / x[2].val());
+    }
+}
@@ -0,0 +1,548 @@
+use crate::{Op, Var};
+use std::f64::consts::{FRAC_2_SQRT_PI, PI};
+
+/// Below these magnitudes `erf` and `erfc` are computed from a series, above them from a continued
+/// fraction for `erfc`. The lower switch for `erfc` avoids cancellation in `1 - erf(x)`.
+const ERF_SWITCH: f64 = 3.;
+const ERFC_SWITCH: f64 = 1.;
+
+/// Series `erf(x) = 2/sqrt(pi) exp(-x^2) sum_n 2^n x^(2n+1) / (1 * 3 * ... * (2n+1))`, whose
+/// terms are all of the same sign.
+fn erf_series(x: f64) -> f64 {
+    let x2 = x * x;
+    let mut term = x;
+    let mut sum = x;
+    let mut n = 0.;
+    while term.abs() > sum.abs() * f64::EPSILON {
+        n += 1.;
+        term *= 2. * x2 / (2. * n + 1.);
+        sum += term;
+    }
+    FRAC_2_SQRT_PI * (-x2).exp() * sum
+}
+
+/// Continued fraction `erfc(x) = exp(-x^2) / sqrt(pi) / (x + (1/2) / (x + 1 / (x + (3/2) / ...)))`
+/// for positive `x`, evaluated with the modified Lentz method.
+fn erfc_cf(x: f64) -> f64 {
+    if x.is_infinite() {
+        return 0.;
+    }
+    let tiny = 1e-300;
+    let mut f = x;
+    let mut c = x;
+    let mut d = 0.;
+    for k in 1..500 {
+        let a = k as f64 / 2.;
+        d = x + a * d;
+        if d == 0. {
+            d = tiny;
+        }
+        c = x + a / c;
+        if c == 0. {
+            c = tiny;
+        }
+        d = d.recip();
+        let delta = c * d;
+        f *= delta;
+        if (delta - 1.).abs() < f64::EPSILON {
+            break;
+        }
+    }
+    (-x * x).exp() / PI.sqrt() / f
+}
+
+pub(crate) fn erf(x: f64) -> f64 {
+    if x.abs() < ERF_SWITCH {
+        erf_series(x)
+    } else {
+        (1. - erfc_cf(x.abs())).copysign(x)
+    }
+}
+
+pub(crate) fn erfc(x: f64) -> f64 {
+    if x.abs() < ERFC_SWITCH {
+        1. - erf_series(x)
+    } else if x > 0. {
+        erfc_cf(x)
+    } else {
+        2. - erfc_cf(-x)
+    }
+}
+
+/// Lanczos approximation coefficients for `g = 7` and nine terms.
+const LANCZOS_G: f64 = 7.;
+const LANCZOS: [f64; 9] = [
+    0.999_999_999_999_809_9,
+    676.520_368_121_885_1,
+    -1_259.139_216_722_402_8,
+    771.323_428_777_653_1,
+    -176.615_029_162_140_6,
+    12.507_343_278_686_905,
+    -0.138_571_095_265_720_12,
+    9.984_369_578_019_572e-6,
+    1.505_632_735_149_311_6e-7,
+];
+
+/// Bernoulli numbers `B_2, B_4, ..., B_14` used by the asymptotic expansions of the polygamma
+/// functions.
+const BERNOULLI: [f64; 7] = [
+    1. / 6.,
+    -1. / 30.,
+    1. / 42.,
+    -1. / 30.,
+    5. / 66.,
+    -691. / 2730.,
+    7. / 6.,
+];
+
+/// Arguments are shifted upwards with the recurrence relations until they reach this value,
+/// where the asymptotic expansions are accurate to machine precision.
+const ASYMPTOTIC_SWITCH: f64 = 10.;
+
+/// Natural logarithm of the absolute value of the gamma function.
+pub(crate) fn ln_gamma(x: f64) -> f64 {
+    if x < 0.5 {
+        (PI / (PI * x).sin().abs()).ln() - ln_gamma(1. - x)
+    } else {
+        let x = x - 1.;
+        let t = x + LANCZOS_G + 0.5;
+        let a = LANCZOS[1..]
+            .iter()
+            .enumerate()
+            .fold(LANCZOS[0], |a, (i, c)| a + c / (x + i as f64 + 1.));
+        0.5 * (2. * PI).ln() + (x + 0.5) * t.ln() - t + a.ln()
+    }
+}
+
+pub(crate) fn digamma(x: f64) -> f64 {
+    if x <= 0. && x == x.floor() {
+        return f64::NAN;
+    }
+    if x < 0. {
+        return digamma(1. - x) - PI / (PI * x).tan();
+    }
+    let mut x = x;
+    let mut result = 0.;
+    while x < ASYMPTOTIC_SWITCH {
+        result -= x.recip();
+        x += 1.;
+    }
+    let x2 = x.powi(-2);
+    let mut power = 1.;
+    let mut series = 0.;
+    for (k, b) in BERNOULLI.iter().enumerate() {
+        power *= x2;
+        series += b / (2. * (k as f64 + 1.)) * power;
+    }
+    result + x.ln() - 0.5 / x - series
+}
+
+fn factorial(n: u32) -> f64 {
+    (1..=n).map(f64::from).product()
+}
+
+/// The `n`th derivative of the digamma function.
+pub(crate) fn polygamma(n: u32, x: f64) -> f64 {
+    if n == 0 {
+        return digamma(x);
+    }
+    if x <= 0. && x == x.floor() {
+        return f64::NAN;
+    }
+    let sign = if n % 2 == 1 { 1. } else { -1. };
+    let nf = f64::from(n);
+    let n_factorial = factorial(n);
+    let mut x = x;
+    let mut result = 0.;
+    while x < ASYMPTOTIC_SWITCH + nf {
+        result += sign * n_factorial / x.powi(n as i32 + 1);
+        x += 1.;
+    }
+    // (-1)^(n+1) [(n-1)!/x^n + n!/(2 x^(n+1)) + sum_k B_2k (2k+n-1)! / ((2k)! x^(2k+n))]
+    let mut series =
+        factorial(n - 1) / x.powi(n as i32) + n_factorial / (2. * x.powi(n as i32 + 1));
+    // coef holds (2k+n-1)! / (2k)!, updated incrementally in k
+    let mut coef = factorial(n + 1) / 2.;
+    for (k, b) in BERNOULLI.iter().enumerate() {
+        let k = k as f64 + 1.;
+        if k > 1. {
+            coef *= (2. * k + nf - 2.) * (2. * k + nf - 1.) / ((2. * k - 1.) * (2. * k));
+        }
+        series += b * coef / x.powf(2. * k + nf);
+    }
+    result + sign * series
+}
+
+/// Power series `sum_k s^k (x/2)^(2k+n) / (k! (k+n)!)` shared by the Bessel functions of the
+/// first kind (`s = -1`) and the modified ones (`s = 1`).
+fn bessel_series(n: u32, x: f64, s: f64) -> f64 {
+    let half = x / 2.;
+    let mut term = half.powi(n as i32) / factorial(n);
+    let mut sum = term;
+    let mut k = 0.;
+    while term.abs() > sum.abs() * f64::EPSILON {
+        k += 1.;
+        term *= s * half * half / (k * (k + f64::from(n)));
+        sum += term;
+    }
+    sum
+}
+
+/// Terms `prod_j (4n^2 - (2j-1)^2) / (k! (8x)^k)` of the Hankel asymptotic expansions, stopping
+/// once they stop shrinking.
+fn hankel_terms(n: u32, x: f64) -> Vec<f64> {
+    let mu = 4. * f64::from(n).powi(2);
+    let mut terms = vec![1.];
+    let mut term: f64 = 1.;
+    for k in 1..60 {
+        let k = f64::from(k);
+        let next = term * (mu - (2. * k - 1.).powi(2)) / (k * 8. * x);
+        if next.abs() >= term.abs() || next == 0. {
+            break;
+        }
+        term = next;
+        terms.push(term);
+        if term.abs() < f64::EPSILON {
+            break;
+        }
+    }
+    terms
+}
+
+/// Bessel function of the first kind of integer order `n`.
+pub(crate) fn bessel_j(n: u32, x: f64) -> f64 {
+    let sign = if x < 0. && n % 2 == 1 { -1. } else { 1. };
+    let x = x.abs();
+    let nf = f64::from(n);
+    let val = if x <= 1. {
+        bessel_series(n, x, -1.)
+    } else if x >= 25. && x >= nf * nf {
+        let terms = hankel_terms(n, x);
+        let mut p = 0.;
+        let mut q = 0.;
+        for (k, t) in terms.iter().enumerate() {
+            let s = if (k / 2) % 2 == 0 { 1. } else { -1. };
+            if k % 2 == 0 {
+                p += s * t;
+            } else {
+                q += s * t;
+            }
+        }
+        let chi = x - (nf / 2. + 0.25) * PI;
+        (2. / (PI * x)).sqrt() * (p * chi.cos() - q * chi.sin())
+    } else {
+        // Miller's algorithm: recur downwards from far above `n` and `x`, then normalise with
+        // J_0 + 2 (J_2 + J_4 + ...) = 1.
+        let top = nf.max(x);
+        let start = 2 * ((top + 20. + (40. * top).sqrt()) as u32 / 2);
+        let mut next = 0.;
+        let mut current = 1e-30;
+        let mut norm = 0.;
+        let mut val = 0.;
+        for k in (1..=start).rev() {
+            let prev = 2. * f64::from(k) / x * current - next;
+            next = current;
+            current = prev;
+            if k - 1 == n {
+                val = current;
+            }
+            if (k - 1) % 2 == 0 {
+                norm += if k == 1 { current } else { 2. * current };
+            }
+            if current.abs() > 1e250 {
+                current *= 1e-250;
+                next *= 1e-250;
+                norm *= 1e-250;
+                val *= 1e-250;
+            }
+        }
+        val / norm
+    };
+    sign * val
+}
+
+/// Bessel function of the first kind of any integer order `n`, using `J_{-n} = (-1)^n J_n`.
+pub(crate) fn bessel_jn(n: i32, x: f64) -> f64 {
+    let val = bessel_j(n.unsigned_abs(), x);
+    if n < 0 && n % 2 != 0 {
+        -val
+    } else {
+        val
+    }
+}
+
+/// Modified Bessel function of the first kind of integer order `n`.
+pub(crate) fn bessel_i(n: u32, x: f64) -> f64 {
+    let sign = if x < 0. && n % 2 == 1 { -1. } else { 1. };
+    let x = x.abs();
+    let val = if x <= 30. {
+        bessel_series(n, x, 1.)
+    } else {
+        let series = hankel_terms(n, x)
+            .iter()
+            .enumerate()
+            .map(|(k, t)| if k % 2 == 0 { *t } else { -t })
+            .sum::<f64>();
+        x.exp() / (2. * PI * x).sqrt() * series
+    };
+    sign * val
+}
+
+pub(crate) fn norm_pdf(x: f64) -> f64 {
+    (-0.5 * x * x).exp() / (2. * PI).sqrt()
+}
+
+pub(crate) fn norm_cdf(x: f64) -> f64 {
+    0.5 * erfc(-x / 2_f64.sqrt())
+}
+
+/// Quantile function of the standard normal distribution. A rational approximation is refined
+/// with Halley's method, always working in the lower tail to avoid cancellation near one.
+pub(crate) fn norm_cdf_inv(p: f64) -> f64 {
+    if !(0. ..=1.).contains(&p) {
+        return f64::NAN;
+    }
+    let q = p.min(1. - p);
+    if q == 0. {
+        return if p == 0. {
+            f64::NEG_INFINITY
+        } else {
+            f64::INFINITY
+        };
+    }
+    // Abramowitz and Stegun 26.2.23
+    let t = (-2. * q.ln()).sqrt();
+    let mut z = -(t
+        - (2.515_517 + 0.802_853 * t + 0.010_328 * t * t)
+            / (1. + 1.432_788 * t + 0.189_269 * t * t + 0.001_308 * t * t * t));
+    for _ in 0..3 {
+        let u = (norm_cdf(z) - q) / norm_pdf(z);
+        z -= u / (1. + z * u / 2.);
+    }
+    if p < 0.5 {
+        z
+    } else {
+        -z
+    }
+}
+
+impl<'a> Var<'a> {
+    pub fn erf(&self) -> Self {
+        self.unary(Op::Erf)
+    }
+
+    pub fn erfc(&self) -> Self {
+        self.unary(Op::Erfc)
+    }
+
+    /// Natural logarithm of the absolute value of the gamma function.
+    pub fn lgamma(&self) -> Self {
+        self.unary(Op::Lgamma)
+    }
+
+    pub fn digamma(&self) -> Self {
+        self.polygamma(0)
+    }
+
+    /// The `n`th derivative of the digamma function.
+    pub fn polygamma(&self, n: u32) -> Self {
+        self.unary(Op::Polygamma(n))
+    }
+
+    pub fn bessel_j0(&self) -> Self {
+        self.bessel_jn(0)
+    }
+
+    pub fn bessel_j1(&self) -> Self {
+        self.bessel_jn(1)
+    }
+
+    /// Bessel function of the first kind of order `n`.
+    pub fn bessel_jn(&self, n: i32) -> Self {
+        self.unary(Op::BesselJ(n))
+    }
+
+    /// Modified Bessel function of the first kind of order zero.
+    pub fn bessel_i0(&self) -> Self {
+        self.unary(Op::BesselI0)
+    }
+
+    /// Modified Bessel function of the first kind of order one.
+    pub fn bessel_i1(&self) -> Self {
+        self.unary(Op::BesselI1)
+    }
+
+    /// Cumulative distribution function of the standard normal distribution.
+    pub fn norm_cdf(&self) -> Self {
+        self.unary(Op::NormCdf)
+    }
+
+    /// Inverse of `norm_cdf` (the probit function).
+    pub fn norm_cdf_inv(&self) -> Self {
+        self.unary(Op::NormCdfInv)
+    }
+
+    /// The unnormalized sinc function `sin(x) / x`, with the limit of one (and a zero derivative)
+    /// at zero.
+    pub fn sinc(&self) -> Self {
+        self.unary(Op::Sinc)
+    }
+}
+
+/// Below this magnitude `sinc` and its derivative are computed from their Taylor series, as the
+/// closed forms divide zero by zero at the origin and lose precision to cancellation near it.
+const SINC_SWITCH: f64 = 0.1;
+
+/// The unnormalized sinc function `sin(x) / x`, which is one at zero.
+pub(crate) fn sinc(x: f64) -> f64 {
+    if x.abs() < SINC_SWITCH {
+        let x2 = x * x;
+        1. - x2 / 6. * (1. - x2 / 20. * (1. - x2 / 42. * (1. - x2 / 72.)))
+    } else {
+        x.sin() / x
+    }
+}
+
+/// Derivative of `sinc`, `(x cos(x) - sin(x)) / x^2`, which is zero at zero.
+pub(crate) fn sinc_deriv(x: f64) -> f64 {
+    if x.abs() < SINC_SWITCH {
+        let x2 = x * x;
+        -x / 3. * (1. - x2 / 10. * (1. - x2 / 28. * (1. - x2 / 54. * (1. - x2 / 88.))))
+    } else {
+        (x * x.cos() - x.sin()) / (x * x)
+    }
+}
+
+/// Natural logarithm of the beta function, `ln B(a, b) = ln Γ(a) + ln Γ(b) - ln Γ(a + b)`.
+pub fn ln_beta<'a>(a: Var<'a>, b: Var<'a>) -> Var<'a> {
+    a.binary("ln_beta", b, Op::LnBeta)
+}
+
+/// The beta function `B(a, b)`, computed through `ln_beta`.
+pub fn beta<'a>(a: Var<'a>, b: Var<'a>) -> Var<'a> {
+    ln_beta(a, b).exp()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{Gradient, Tape};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_erf() {
+        assert_eq!(erf(0.), 0.);
+        assert_approx_eq!(erf(0.5), 0.5204998778130465, 1e-14);
+        assert_approx_eq!(erf(-2.9), -0.9999589021219005, 1e-14);
+        assert_approx_eq!(erf(3.5), 0.9999992569016276, 1e-14);
+        assert_approx_eq!(erfc(1.), 0.15729920705028513, 1e-14);
+        assert_approx_eq!(erfc(5.), 1.5374597944280351e-12, 1e-12);
+        assert_approx_eq!(erfc(-4.), 1.999999984582742, 1e-14);
+        assert_eq!(erf(f64::INFINITY), 1.);
+
+        let g = Tape::new();
+        let x = g.add_var(0.7);
+        let res = x.erf() - 2. * x.erfc();
+        assert_approx_eq!(res.val(), 3. * erf(0.7) - 2.);
+        assert_approx_eq!(res.grad().wrt(&x), 3. * FRAC_2_SQRT_PI * (-0.49_f64).exp());
+    }
+    #[test]
+    fn test_sinc() {
+        assert_eq!(sinc(0.), 1.);
+        assert_eq!(sinc_deriv(0.), 0.);
+        // the series and closed forms agree where they meet
+        for &x in &[SINC_SWITCH, -0.09, 2.5] {
+            assert_approx_eq!(sinc(x), x.sin() / x, 1e-15);
+            assert_approx_eq!(sinc_deriv(x), (x * x.cos() - x.sin()) / (x * x), 1e-13);
+        }
+        assert_approx_eq!(sinc_deriv(1e-4), -1e-4 / 3. + 1e-12 / 30., 1e-15);
+
+        let g = Tape::new();
+        let x = g.add_var(0.);
+        let res = (x * 2.).sinc() + x.powi(2);
+        assert_eq!(res.val(), 1.);
+        assert_eq!(res.grad().wrt(&x), 0.);
+    }
+
+    #[test]
+    fn test_gamma() {
+        assert_approx_eq!(ln_gamma(0.1), 2.252712651734206, 1e-14);
+        assert_approx_eq!(ln_gamma(-3.7), -1.3797399049658245, 1e-14);
+        assert_approx_eq!(ln_gamma(150.), 600.0094705553274, 1e-14);
+        assert_approx_eq!(digamma(1.), -0.5772156649015329, 1e-14);
+        assert_approx_eq!(digamma(-0.4), 0.9593807861068093, 1e-14);
+        assert_approx_eq!(polygamma(1, 0.5), PI.powi(2) / 2., 1e-14);
+        assert_approx_eq!(polygamma(2, 1.), -2.404113806319188, 1e-14);
+        assert_approx_eq!(polygamma(3, 7.9), 0.0048911780612039225, 1e-14);
+        assert!(digamma(-2.).is_nan());
+
+        let g = Tape::new();
+        let x = g.add_var(3.3);
+        let res = x.lgamma();
+        assert_approx_eq!(res.grad().wrt(&x), digamma(3.3));
+        let res = x.digamma() * x.polygamma(1);
+        assert_approx_eq!(
+            res.grad().wrt(&x),
+            polygamma(1, 3.3).powi(2) + digamma(3.3) * polygamma(2, 3.3)
+        );
+    }
+    #[test]
+    fn test_bessel() {
+        assert_approx_eq!(bessel_j(0, 2.5), -0.048383776468198, 1e-13);
+        assert_approx_eq!(bessel_j(1, -7.3), -0.08257043049325784, 1e-13);
+        assert_approx_eq!(bessel_j(5, 40.2), 0.11359627792432356, 1e-13);
+        assert_approx_eq!(bessel_i(0, 3.3), 6.242630465183028, 1e-13);
+        assert_approx_eq!(bessel_i(1, 45.), 2.0601334620815772e18, 1e-13);
+
+        let g = Tape::new();
+        let x = g.add_var(4.);
+        let res = x.bessel_jn(3);
+        assert_approx_eq!(res.val(), 0.43017147387562193);
+        assert_approx_eq!(
+            res.grad().wrt(&x),
+            (0.3641281458520728 - 0.2811290649613601) / 2.
+        );
+        let res = x.bessel_j0() + x.bessel_i0();
+        assert_approx_eq!(res.grad().wrt(&x), -bessel_j(1, 4.) + bessel_i(1, 4.));
+        let y = g.add_var(0.);
+        assert_approx_eq!(y.bessel_j1().grad().wrt(&y), 0.5);
+        assert_approx_eq!(y.bessel_i1().grad().wrt(&y), 0.5);
+    }
+    #[test]
+    fn test_beta() {
+        let g = Tape::new();
+        let a = g.add_var(2.5);
+        let b = g.add_var(0.7);
+        let res = ln_beta(a, b);
+        assert_approx_eq!(res.val(), -0.339_854_710_150_323_2, 1e-12);
+        let grads = res.grad();
+        assert_approx_eq!(grads.wrt(&a), digamma(2.5) - digamma(3.2));
+        assert_approx_eq!(grads.wrt(&b), digamma(0.7) - digamma(3.2));
+
+        let res = beta(a, b);
+        assert_approx_eq!(res.val(), (-0.339_854_710_150_323_2_f64).exp(), 1e-12);
+        assert_approx_eq!(
+            res.grad().wrt(&a),
+            res.val() * (digamma(2.5) - digamma(3.2))
+        );
+    }
+    #[test]
+    fn test_norm_cdf() {
+        assert_approx_eq!(norm_cdf(1.3), 0.9031995154143897, 1e-14);
+        assert_approx_eq!(norm_cdf(-9.), 1.1285884059538405e-19, 1e-12);
+        for &p in &[1e-300, 1e-10, 0.02, 0.3, 0.5, 0.975, 1. - 1e-12] {
+            assert_approx_eq!(norm_cdf(norm_cdf_inv(p)), p, 1e-12);
+        }
+        assert_approx_eq!(norm_cdf_inv(0.975), 1.959963984540054, 1e-14);
+
+        let g = Tape::new();
+        let x = g.add_var(0.4);
+        let res = x.norm_cdf();
+        assert_approx_eq!(res.grad().wrt(&x), norm_pdf(0.4));
+        let res = x.norm_cdf_inv();
+        assert_approx_eq!(res.grad().wrt(&x), 1. / norm_pdf(norm_cdf_inv(0.4)));
+        let res = x.norm_cdf().norm_cdf_inv();
+        assert_approx_eq!(res.val(), 0.4);
+        assert_approx_eq!(res.grad().wrt(&x), 1.);
+    }
+}
@@ -0,0 +1,334 @@
+//! Storage of the records of a tape that are indexed by location: its nodes, operations, spans
+//! and operands.
+//!
+//! A `Store` is a growable array of plain values. By default it is a `Vec`. With the `mmap`
+//! feature it can instead live in a temporary file mapped into memory, grown in chunks, so that a
+//! recording larger than the available RAM is paged out to disk by the operating system and read
+//! back on demand during the backward pass.
+
+use std::{
+    fmt, mem,
+    ops::{Deref, DerefMut},
+};
+
+/// Growable array of the records of a tape.
+pub(crate) enum Store<T> {
+    /// Records held in memory.
+    Memory(Vec<T>),
+    /// Records held in a memory-mapped file.
+    #[cfg(feature = "mmap")]
+    Mapped(mapped::Mapped<T>),
+}
+
+impl<T: Copy> Store<T> {
+    /// Create an empty store in memory.
+    pub(crate) fn new() -> Self {
+        Self::Memory(vec![])
+    }
+
+    /// Create an empty store in a new file in the directory `dir`, which is deleted when the store
+    /// is dropped.
+    #[cfg(feature = "mmap")]
+    pub(crate) fn mapped(dir: &std::path::Path) -> std::io::Result<Self> {
+        mapped::Mapped::new(dir).map(Self::Mapped)
+    }
+
+    pub(crate) fn push(&mut self, x: T) {
+        match self {
+            Self::Memory(v) => v.push(x),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(m) => m.push(x),
+        }
+    }
+
+    pub(crate) fn extend_from_slice(&mut self, xs: &[T]) {
+        match self {
+            Self::Memory(v) => v.extend_from_slice(xs),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(m) => m.extend_from_slice(xs),
+        }
+    }
+
+    pub(crate) fn truncate(&mut self, len: usize) {
+        match self {
+            Self::Memory(v) => v.truncate(len),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(m) => m.truncate(len),
+        }
+    }
+
+    /// Move the records out of `self`, leaving it empty but of the same kind.
+    pub(crate) fn take(&mut self) -> Self {
+        let empty = self.empty();
+        mem::replace(self, empty)
+    }
+
+    /// Create an empty store of the same kind as `self`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `self` is memory-mapped and a new file cannot be created next to its file.
+    fn empty(&self) -> Self {
+        match self {
+            Self::Memory(_) => Self::new(),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(m) => Self::Mapped(m.empty()),
+        }
+    }
+}
+
+impl<T> Deref for Store<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &[T] {
+        match self {
+            Self::Memory(v) => v,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(m) => m,
+        }
+    }
+}
+
+impl<T> DerefMut for Store<T> {
+    fn deref_mut(&mut self) -> &mut [T] {
+        match self {
+            Self::Memory(v) => v,
+            #[cfg(feature = "mmap")]
+            Self::Mapped(m) => m,
+        }
+    }
+}
+
+/// Copies the records into a new store of the same kind.
+impl<T: Copy> Clone for Store<T> {
+    fn clone(&self) -> Self {
+        let mut store = self.empty();
+        store.extend_from_slice(self);
+        store
+    }
+}
+
+impl<T: Copy> Extend<T> for Store<T> {
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        match self {
+            Self::Memory(v) => v.extend(iter),
+            #[cfg(feature = "mmap")]
+            Self::Mapped(m) => iter.into_iter().for_each(|x| m.push(x)),
+        }
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Store<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for Store<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        fmt::Debug::fmt(&**self, f)
+    }
+}
+
+#[cfg(feature = "mmap")]
+pub(crate) mod mapped {
+    use memmap2::MmapMut;
+    use std::{
+        fs::{self, File, OpenOptions},
+        io,
+        marker::PhantomData,
+        mem::{self, ManuallyDrop},
+        ops::{Deref, DerefMut},
+        path::{Path, PathBuf},
+        process, slice,
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    /// Number of records that a file has room for when it is created. Files are sparse, so the
+    /// room only takes up disk space once it is written.
+    const CHUNK: usize = 1 << 16;
+
+    /// Array of `Copy` values in a memory-mapped file, which doubles in size whenever it runs out
+    /// of room and is deleted when the array is dropped.
+    pub(crate) struct Mapped<T> {
+        path: PathBuf,
+        file: ManuallyDrop<File>,
+        map: ManuallyDrop<MmapMut>,
+        len: usize,
+        marker: PhantomData<T>,
+    }
+
+    impl<T: Copy> Mapped<T> {
+        /// Create an empty array in a new file in `dir`.
+        pub(crate) fn new(dir: &Path) -> io::Result<Self> {
+            let (path, file) = create(dir)?;
+            match map(&file, CHUNK * mem::size_of::<T>()) {
+                Ok(map) => Ok(Self {
+                    path,
+                    file: ManuallyDrop::new(file),
+                    map: ManuallyDrop::new(map),
+                    len: 0,
+                    marker: PhantomData,
+                }),
+                Err(e) => {
+                    drop(file);
+                    let _ = fs::remove_file(&path);
+                    Err(e)
+                }
+            }
+        }
+
+        /// Create an empty array in a new file in the same directory.
+        pub(crate) fn empty(&self) -> Self {
+            let dir = self
+                .path
+                .parent()
+                .expect("storage files are in a directory");
+            Self::new(dir).expect("could not create a storage file")
+        }
+
+        /// Number of values that fit in the file without growing it.
+        fn capacity(&self) -> usize {
+            self.map.len() / mem::size_of::<T>()
+        }
+
+        /// Grow the file to hold at least `capacity` values.
+        fn reserve(&mut self, capacity: usize) {
+            if capacity > self.capacity() {
+                let capacity = capacity.max(2 * self.capacity());
+                let map = map(&self.file, capacity * mem::size_of::<T>())
+                    .expect("could not grow a storage file");
+                *self.map = map;
+            }
+        }
+
+        pub(crate) fn push(&mut self, x: T) {
+            self.reserve(self.len + 1);
+            // SAFETY: the file has room for `len + 1` values, and the mapping is page-aligned.
+            unsafe { (self.map.as_mut_ptr() as *mut T).add(self.len).write(x) };
+            self.len += 1;
+        }
+
+        pub(crate) fn extend_from_slice(&mut self, xs: &[T]) {
+            self.reserve(self.len + xs.len());
+            // SAFETY: as in `push`, and `xs` cannot overlap the mapping, which is borrowed mutably.
+            unsafe {
+                let end = (self.map.as_mut_ptr() as *mut T).add(self.len);
+                end.copy_from_nonoverlapping(xs.as_ptr(), xs.len());
+            }
+            self.len += xs.len();
+        }
+
+        pub(crate) fn truncate(&mut self, len: usize) {
+            self.len = self.len.min(len);
+        }
+    }
+
+    impl<T> Drop for Mapped<T> {
+        fn drop(&mut self) {
+            // SAFETY: neither field is used again. The mapping and the file are closed first, as
+            // some platforms cannot delete a file that is still open.
+            unsafe {
+                ManuallyDrop::drop(&mut self.map);
+                ManuallyDrop::drop(&mut self.file);
+            }
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+
+    /// Create a file in `dir` with a name that no other store uses.
+    fn create(dir: &Path) -> io::Result<(PathBuf, File)> {
+        static COUNT: AtomicUsize = AtomicUsize::new(0);
+        loop {
+            let count = COUNT.fetch_add(1, Ordering::Relaxed);
+            let path = dir.join(format!(".reverse-{}-{}.tape", process::id(), count));
+            let file = OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create_new(true)
+                .open(&path);
+            match file {
+                Ok(file) => return Ok((path, file)),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Map `file` after resizing it to `bytes` bytes.
+    fn map(file: &File, bytes: usize) -> io::Result<MmapMut> {
+        file.set_len(bytes as u64)?;
+        // SAFETY: the file has a name private to this process, so nothing else modifies it.
+        unsafe { MmapMut::map_mut(file) }
+    }
+
+    impl<T> Deref for Mapped<T> {
+        type Target = [T];
+
+        fn deref(&self) -> &[T] {
+            // SAFETY: the first `len` values have been written, and `T: Copy` has no drop glue.
+            unsafe { slice::from_raw_parts(self.map.as_ptr() as *const T, self.len) }
+        }
+    }
+
+    impl<T> DerefMut for Mapped<T> {
+        fn deref_mut(&mut self) -> &mut [T] {
+            // SAFETY: as in `deref`.
+            unsafe { slice::from_raw_parts_mut(self.map.as_mut_ptr() as *mut T, self.len) }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "mmap"))]
+mod test {
+    use super::*;
+    use crate::{sum, Gradient, Tape};
+
+    #[test]
+    fn test_mapped_store() {
+        let mut store = Store::mapped(&std::env::temp_dir()).unwrap();
+        // enough to grow the file past its first chunk twice
+        let n = 300_000;
+        store.extend((0..n).map(|i| (i, i as f64)));
+        store.extend_from_slice(&[(n, 0.5)]);
+        assert_eq!(store.len(), n + 1);
+        assert_eq!(store[1234], (1234, 1234.));
+        store[0].1 = -1.;
+        store.truncate(10);
+        assert_eq!(store.iter().map(|x| x.1).sum::<f64>(), 44.);
+
+        let copy = store.clone();
+        let taken = store.take();
+        assert!(store.is_empty());
+        assert!(matches!(store, Store::Mapped(_)));
+        assert_eq!(&copy[..], &taken[..]);
+    }
+
+    #[test]
+    fn test_mapped_tape() {
+        let tape = Tape::with_mapped_storage(std::env::temp_dir()).unwrap();
+        let xs = tape.add_vars(&[1., 2., 3.]);
+        let mark = tape.mark();
+        let y = (0..1000).fold(sum(&xs), |y, _| y.sin() + xs[0] * xs[1]);
+        let g = Tape::new();
+        let vs = g.add_vars(&[1., 2., 3.]);
+        let z = (0..1000).fold(sum(&vs), |z, _| z.sin() + vs[0] * vs[1]);
+        assert_eq!(y.val(), z.val());
+        assert_eq!(y.grad().wrt(&xs), z.grad().wrt(&vs));
+
+        let copy = tape.clone();
+        assert_eq!(copy.len(), tape.len());
+        tape.rewind_to(mark);
+        assert_eq!(tape.len(), 3);
+
+        let y = xs[0] * xs[1] + xs[0] * xs[1];
+        let remap = tape.optimize();
+        let y = remap.var(&y);
+        let xs = remap.vars(&xs);
+        assert_eq!(y.grad().wrt(&xs), vec![4., 2., 0.]);
+        assert_eq!(tape.len(), 5);
+    }
+}
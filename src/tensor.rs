@@ -0,0 +1,269 @@
+//! Dense n-dimensional arrays of variables and `einsum`-style contractions over them.
+//!
+//! ```
+//! use reverse::*;
+//!
+//! let g = Tape::new();
+//! let a = Tensor::from_vals(&g, &[2, 2], &[1., 2., 3., 4.]);
+//! let x = Tensor::from_vals(&g, &[2], &[5., 6.]);
+//! let y = einsum("ij,j->i", &[&a, &x]);
+//! assert_eq!(y.vals(), vec![17., 39.]);
+//! assert_eq!(y[&[1]].grad().wrt(&a), vec![0., 0., 5., 6.]);
+//! ```
+
+use crate::{error::assert_same_tape, Grad, Gradient, Mat, Op, Tape, Var};
+use std::ops::Index;
+
+/// Dense tensor of differentiable variables, stored in row-major order.
+#[derive(Debug, Clone)]
+pub struct Tensor<'a> {
+    data: Vec<Var<'a>>,
+    shape: Vec<usize>,
+}
+
+impl<'a> Tensor<'a> {
+    /// Create a tensor with the given shape from variables given in row-major order.
+    pub fn new(shape: &[usize], data: Vec<Var<'a>>) -> Self {
+        assert_eq!(
+            data.len(),
+            shape.iter().product::<usize>(),
+            "data does not match the tensor shape"
+        );
+        Self {
+            data,
+            shape: shape.to_vec(),
+        }
+    }
+
+    /// Add the row-major values `vals` to `tape` as a tensor of variables with the given shape.
+    pub fn from_vals(tape: &'a Tape, shape: &[usize], vals: &[f64]) -> Self {
+        Self::new(shape, tape.add_vars(vals))
+    }
+
+    pub fn shape(&self) -> &[usize] {
+        &self.shape
+    }
+
+    /// Gets the number of dimensions.
+    pub fn ndim(&self) -> usize {
+        self.shape.len()
+    }
+
+    /// Gets the entries of the tensor in row-major order.
+    pub fn as_slice(&self) -> &[Var<'a>] {
+        &self.data
+    }
+
+    /// Gets the values of the entries in row-major order.
+    pub fn vals(&self) -> Vec<f64> {
+        self.data.iter().map(|v| v.val).collect()
+    }
+
+    /// Reinterpret the entries with a new shape holding the same number of entries.
+    pub fn reshape(self, shape: &[usize]) -> Self {
+        Self::new(shape, self.data)
+    }
+
+    /// Position of `index` in the row-major data.
+    fn offset(&self, index: &[usize]) -> usize {
+        assert_eq!(index.len(), self.ndim(), "wrong number of indices");
+        index.iter().zip(&self.shape).fold(0, |acc, (&i, &dim)| {
+            assert!(i < dim, "index out of bounds");
+            acc * dim + i
+        })
+    }
+}
+
+impl<'a> From<Mat<'a>> for Tensor<'a> {
+    fn from(m: Mat<'a>) -> Self {
+        let (rows, cols) = m.shape();
+        Self::new(&[rows, cols], m.as_slice().to_vec())
+    }
+}
+
+impl<'a> Index<&[usize]> for Tensor<'a> {
+    type Output = Var<'a>;
+
+    fn index(&self, index: &[usize]) -> &Self::Output {
+        &self.data[self.offset(index)]
+    }
+}
+
+impl<'a, const N: usize> Index<&[usize; N]> for Tensor<'a> {
+    type Output = Var<'a>;
+
+    fn index(&self, index: &[usize; N]) -> &Self::Output {
+        &self[&index[..]]
+    }
+}
+
+/// Calculate the gradient with respect to all entries of the tensor `t`, in row-major order.
+impl<'a> Gradient<&Tensor<'a>, Vec<f64>> for Vec<f64> {
+    fn wrt(&self, t: &Tensor<'a>) -> Vec<f64> {
+        self.wrt(t.as_slice())
+    }
+}
+
+/// Calculate the gradient with respect to all entries of the tensor `t`, in row-major order.
+impl<'a> Gradient<&Tensor<'a>, Vec<f64>> for Grad<'a> {
+    fn wrt(&self, t: &Tensor<'a>) -> Vec<f64> {
+        self.wrt(t.as_slice())
+    }
+}
+
+/// Contract `operands` according to the Einstein summation `spec`, such as `"ij,jk->ik"` for a
+/// matrix product or `"i,i->"` for a dot product. Each index is a single letter, and indices that
+/// do not appear in the output are summed over. Without `->`, the output holds the indices that
+/// appear exactly once, in alphabetical order.
+///
+/// Each entry of the result is recorded on the tape as a single fused node.
+///
+/// # Panics
+///
+/// Panics if `spec` is malformed, does not match the operands, or uses an index with inconsistent
+/// sizes, or if the operands do not share a tape.
+pub fn einsum<'a>(spec: &str, operands: &[&Tensor<'a>]) -> Tensor<'a> {
+    let spec = spec.replace(' ', "");
+    let (inputs, output) = match spec.split_once("->") {
+        Some((inputs, output)) => (inputs, output.chars().collect::<Vec<_>>()),
+        None => {
+            let mut once = spec
+                .chars()
+                .filter(|&c| c != ',' && spec.matches(c).count() == 1)
+                .collect::<Vec<_>>();
+            once.sort_unstable();
+            (&spec[..], once)
+        }
+    };
+    let inputs = inputs
+        .split(',')
+        .map(|term| term.chars().collect::<Vec<_>>())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        inputs.len(),
+        operands.len(),
+        "einsum spec does not match the number of operands"
+    );
+    let tape = operands
+        .iter()
+        .flat_map(|t| t.data.first())
+        .map(|v| v.tape)
+        .next()
+        .expect("einsum requires at least one non-empty operand");
+
+    // size of each index, with the output indices first
+    let mut labels = output.clone();
+    let mut sizes = vec![None; output.len()];
+    for (term, t) in inputs.iter().zip(operands) {
+        assert_eq!(term.len(), t.ndim(), "einsum term does not match operand");
+        for v in &t.data {
+            assert_same_tape("einsum", tape, v.tape);
+        }
+        for (&c, &dim) in term.iter().zip(&t.shape) {
+            assert!(c.is_ascii_alphabetic(), "invalid einsum index {:?}", c);
+            match labels.iter().position(|&l| l == c) {
+                Some(k) => {
+                    assert!(
+                        sizes[k].map_or(true, |size| size == dim),
+                        "inconsistent size for einsum index {:?}",
+                        c
+                    );
+                    sizes[k] = Some(dim);
+                }
+                None => {
+                    labels.push(c);
+                    sizes.push(Some(dim));
+                }
+            }
+        }
+    }
+    let sizes = sizes
+        .into_iter()
+        .zip(&labels)
+        .map(|(size, c)| size.unwrap_or_else(|| panic!("einsum output index {:?} is unused", c)))
+        .collect::<Vec<_>>();
+    let (out_shape, sum_shape) = sizes.split_at(output.len());
+    let positions = inputs
+        .iter()
+        .map(|term| {
+            term.iter()
+                .map(|c| labels.iter().position(|l| l == c).unwrap())
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    let out_len = out_shape.iter().product::<usize>();
+    let sum_len = sum_shape.iter().product::<usize>();
+    let mut index = vec![0; labels.len()];
+    let mut data = Vec::with_capacity(out_len);
+    for out in 0..out_len {
+        unravel(out, out_shape, &mut index[..output.len()]);
+        let mut vals = vec![];
+        let mut deps = vec![];
+        for s in 0..sum_len {
+            unravel(s, sum_shape, &mut index[output.len()..]);
+            let factors = operands
+                .iter()
+                .zip(&positions)
+                .map(|(t, pos)| {
+                    let offset = pos
+                        .iter()
+                        .zip(&t.shape)
+                        .fold(0, |acc, (&p, &dim)| acc * dim + index[p]);
+                    t.data[offset]
+                })
+                .collect::<Vec<_>>();
+            vals.extend(factors.iter().map(|v| v.val));
+            deps.extend(factors.iter().map(|v| v.location));
+        }
+        let op = Op::Einsum(operands.len());
+        let (val, weights) = op.eval_nary(&vals);
+        data.push(Var {
+            val,
+            location: tape.add_nary_node(op, val, &deps, &weights),
+            tape,
+        });
+    }
+    Tensor::new(out_shape, data)
+}
+
+/// Write the row-major multi-index of `flat` within `shape` into `index`.
+fn unravel(mut flat: usize, shape: &[usize], index: &mut [usize]) {
+    for (i, &dim) in index.iter_mut().zip(shape).rev() {
+        *i = flat % dim;
+        flat /= dim;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_einsum() {
+        let g = Tape::new();
+        let a = Tensor::from_vals(&g, &[2, 3], &[1., 2., 3., 4., 5., 6.]);
+        let b = Tensor::from_vals(&g, &[3, 2], &[-1., 0.5, 2., 1., 0., -3.]);
+        let c = einsum("ij,jk->ik", &[&a, &b]);
+        assert_eq!(c.shape(), &[2, 2]);
+        assert_eq!(c.vals(), vec![3., -6.5, 6., -11.]);
+        assert_eq!(g.len(), 12 + 4);
+        let grads = c[&[1, 0]].grad();
+        assert_eq!(grads.wrt(&a), vec![0., 0., 0., -1., 2., 0.]);
+        assert_eq!(grads.wrt(&b), vec![4., 0., 5., 0., 6., 0.]);
+
+        let at = einsum("ij->ji", &[&a]);
+        assert_eq!(at.vals(), vec![1., 4., 2., 5., 3., 6.]);
+        let trace = einsum("ij,ji", &[&a, &b]);
+        assert_eq!(trace.shape(), &[] as &[usize]);
+        assert_eq!(trace[&[]].val(), -8.);
+
+        let x = Tensor::from_vals(&g, &[2], &[2., 3.]);
+        let t = Tensor::from_vals(&g, &[2, 2, 2], &[1., 2., 3., 4., 5., 6., 7., 8.]);
+        let res = einsum("i,ijk,k->j", &[&x, &t, &x]);
+        assert_eq!(res.vals(), vec![100., 150.]);
+        let grads = res[&[0]].grad();
+        assert_eq!(grads.wrt(&x), vec![8. + 17., 28. + 22.]);
+        assert_eq!(grads.wrt(&t), vec![4., 6., 0., 0., 6., 9., 0., 0.]);
+    }
+}
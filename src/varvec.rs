@@ -0,0 +1,297 @@
+//! Vectors of variables with elementwise arithmetic.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let w = VarVec::from_vals(&tape, &[1., 2., 3.]);
+//! let x = VarVec::from_vals(&tape, &[0.5, -1., 2.]);
+//! let b = tape.add_var(0.1);
+//! let y = (&w * &x + b).sum() / 3.;
+//! let grads = y.grad();
+//! assert_eq!(grads.wrt(&w), vec![0.5 / 3., -1. / 3., 2. / 3.]);
+//! assert_eq!(grads.wrt(&b), 1.);
+//! ```
+
+use crate::{error::assert_same_tape, reduce, Grad, Gradient, Tape, Var};
+use std::{
+    iter::FromIterator,
+    ops::{Add, Deref, DerefMut, Div, Mul, Neg, Sub},
+};
+
+/// Vector of differentiable variables. Arithmetic between two vectors is elementwise and requires
+/// equal lengths, while arithmetic with a scalar, either a variable or an `f64`, applies it to
+/// every entry. It dereferences to a slice, for indexing and for the functions taking
+/// `&[Var]`.
+#[derive(Debug, Clone, Default)]
+pub struct VarVec<'a> {
+    data: Vec<Var<'a>>,
+}
+
+impl<'a> VarVec<'a> {
+    pub fn new(data: Vec<Var<'a>>) -> Self {
+        Self { data }
+    }
+
+    /// Add the values `vals` to `tape` as a vector of variables.
+    pub fn from_vals(tape: &'a Tape, vals: &[f64]) -> Self {
+        Self::new(tape.add_vars(vals))
+    }
+
+    /// Gets the values of the entries.
+    pub fn vals(&self) -> Vec<f64> {
+        self.data.iter().map(|v| v.val).collect()
+    }
+
+    pub fn into_vec(self) -> Vec<Var<'a>> {
+        self.data
+    }
+
+    /// Apply `f` to every entry.
+    pub fn map(&self, f: impl FnMut(Var<'a>) -> Var<'a>) -> Self {
+        self.data.iter().copied().map(f).collect()
+    }
+
+    /// Combine the entries of `self` and `other` pairwise with `f`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the lengths differ.
+    pub fn zip_with(
+        &self,
+        other: &[Var<'a>],
+        mut f: impl FnMut(Var<'a>, Var<'a>) -> Var<'a>,
+    ) -> Self {
+        assert_eq!(
+            self.len(),
+            other.len(),
+            "elementwise operation on vectors with different lengths"
+        );
+        self.data
+            .iter()
+            .zip(other)
+            .map(|(&a, &b)| {
+                assert_same_tape("VarVec::zip_with", a.tape, b.tape);
+                f(a, b)
+            })
+            .collect()
+    }
+
+    /// Calculate the sum of the entries, recorded as a single node.
+    pub fn sum(&self) -> Var<'a> {
+        reduce::sum(&self.data)
+    }
+
+    /// Calculate the dot product with `other`, recorded as a single node.
+    pub fn dot(&self, other: &[Var<'a>]) -> Var<'a> {
+        reduce::dot(&self.data, other)
+    }
+}
+
+impl<'a> Deref for VarVec<'a> {
+    type Target = [Var<'a>];
+
+    fn deref(&self) -> &[Var<'a>] {
+        &self.data
+    }
+}
+
+impl<'a> DerefMut for VarVec<'a> {
+    fn deref_mut(&mut self) -> &mut [Var<'a>] {
+        &mut self.data
+    }
+}
+
+impl<'a> From<Vec<Var<'a>>> for VarVec<'a> {
+    fn from(data: Vec<Var<'a>>) -> Self {
+        Self::new(data)
+    }
+}
+
+impl<'a> From<VarVec<'a>> for Vec<Var<'a>> {
+    fn from(v: VarVec<'a>) -> Self {
+        v.data
+    }
+}
+
+impl<'a> FromIterator<Var<'a>> for VarVec<'a> {
+    fn from_iter<I: IntoIterator<Item = Var<'a>>>(iter: I) -> Self {
+        Self::new(iter.into_iter().collect())
+    }
+}
+
+impl<'a> IntoIterator for VarVec<'a> {
+    type Item = Var<'a>;
+    type IntoIter = std::vec::IntoIter<Var<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.into_iter()
+    }
+}
+
+impl<'a, 'b> IntoIterator for &'b VarVec<'a> {
+    type Item = &'b Var<'a>;
+    type IntoIter = std::slice::Iter<'b, Var<'a>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
+#[opimps::impl_uni_ops(Neg)]
+fn neg<'a>(self: VarVec<'a>) -> VarVec<'a> {
+    self.map(|v| -v)
+}
+
+#[opimps::impl_ops(Add)]
+fn add<'a>(self: VarVec<'a>, rhs: VarVec<'a>) -> VarVec<'a> {
+    self.zip_with(&rhs[..], |a, b| a + b)
+}
+
+#[opimps::impl_ops_rprim(Add)]
+fn add<'a>(self: VarVec<'a>, rhs: Var<'a>) -> VarVec<'a> {
+    self.map(|v| v + rhs)
+}
+
+#[opimps::impl_ops_lprim(Add)]
+fn add<'a>(self: Var<'a>, rhs: VarVec<'a>) -> VarVec<'a> {
+    rhs + self
+}
+
+#[opimps::impl_ops_rprim(Add)]
+fn add<'a>(self: VarVec<'a>, rhs: f64) -> VarVec<'a> {
+    self.map(|v| v + rhs)
+}
+
+#[opimps::impl_ops_lprim(Add)]
+fn add<'a>(self: f64, rhs: VarVec<'a>) -> VarVec<'a> {
+    rhs + self
+}
+
+#[opimps::impl_ops(Sub)]
+fn sub<'a>(self: VarVec<'a>, rhs: VarVec<'a>) -> VarVec<'a> {
+    self.zip_with(&rhs[..], |a, b| a - b)
+}
+
+#[opimps::impl_ops_rprim(Sub)]
+fn sub<'a>(self: VarVec<'a>, rhs: Var<'a>) -> VarVec<'a> {
+    self.map(|v| v - rhs)
+}
+
+#[opimps::impl_ops_lprim(Sub)]
+fn sub<'a>(self: Var<'a>, rhs: VarVec<'a>) -> VarVec<'a> {
+    rhs.map(|v| self - v)
+}
+
+#[opimps::impl_ops_rprim(Sub)]
+fn sub<'a>(self: VarVec<'a>, rhs: f64) -> VarVec<'a> {
+    self.map(|v| v - rhs)
+}
+
+#[opimps::impl_ops_lprim(Sub)]
+fn sub<'a>(self: f64, rhs: VarVec<'a>) -> VarVec<'a> {
+    rhs.map(|v| self - v)
+}
+
+#[opimps::impl_ops(Mul)]
+fn mul<'a>(self: VarVec<'a>, rhs: VarVec<'a>) -> VarVec<'a> {
+    self.zip_with(&rhs[..], |a, b| a * b)
+}
+
+#[opimps::impl_ops_rprim(Mul)]
+fn mul<'a>(self: VarVec<'a>, rhs: Var<'a>) -> VarVec<'a> {
+    self.map(|v| v * rhs)
+}
+
+#[opimps::impl_ops_lprim(Mul)]
+fn mul<'a>(self: Var<'a>, rhs: VarVec<'a>) -> VarVec<'a> {
+    rhs * self
+}
+
+#[opimps::impl_ops_rprim(Mul)]
+fn mul<'a>(self: VarVec<'a>, rhs: f64) -> VarVec<'a> {
+    self.map(|v| v * rhs)
+}
+
+#[opimps::impl_ops_lprim(Mul)]
+fn mul<'a>(self: f64, rhs: VarVec<'a>) -> VarVec<'a> {
+    rhs * self
+}
+
+#[opimps::impl_ops(Div)]
+fn div<'a>(self: VarVec<'a>, rhs: VarVec<'a>) -> VarVec<'a> {
+    self.zip_with(&rhs[..], |a, b| a / b)
+}
+
+#[opimps::impl_ops_rprim(Div)]
+fn div<'a>(self: VarVec<'a>, rhs: Var<'a>) -> VarVec<'a> {
+    self.map(|v| v / rhs)
+}
+
+#[opimps::impl_ops_lprim(Div)]
+fn div<'a>(self: Var<'a>, rhs: VarVec<'a>) -> VarVec<'a> {
+    rhs.map(|v| self / v)
+}
+
+#[opimps::impl_ops_rprim(Div)]
+fn div<'a>(self: VarVec<'a>, rhs: f64) -> VarVec<'a> {
+    self.map(|v| v / rhs)
+}
+
+#[opimps::impl_ops_lprim(Div)]
+fn div<'a>(self: f64, rhs: VarVec<'a>) -> VarVec<'a> {
+    rhs.map(|v| self / v)
+}
+
+/// Calculate the gradient with respect to all entries of `v`, in the same order.
+impl<'a> Gradient<&VarVec<'a>, Vec<f64>> for Vec<f64> {
+    fn wrt(&self, v: &VarVec<'a>) -> Vec<f64> {
+        self.wrt(&v.data)
+    }
+}
+
+/// Calculate the gradient with respect to all entries of `v`, in the same order.
+impl<'a> Gradient<&VarVec<'a>, Vec<f64>> for Grad<'a> {
+    fn wrt(&self, v: &VarVec<'a>) -> Vec<f64> {
+        self.wrt(&v.data)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_arithmetic() {
+        let g = Tape::new();
+        let x = VarVec::from_vals(&g, &[1., 2., 4.]);
+        let y = VarVec::from_vals(&g, &[3., -1., 0.5]);
+        let s = g.add_var(2.);
+        assert_eq!((&x + &y).vals(), vec![4., 1., 4.5]);
+        assert_eq!((&x - &y).vals(), vec![-2., 3., 3.5]);
+        assert_eq!((&x * &y).vals(), vec![3., -2., 2.]);
+        assert_eq!((&x / 2.).vals(), vec![0.5, 1., 2.]);
+        assert_eq!((1. - &x).vals(), vec![0., -1., -3.]);
+        assert_eq!((s / &x).vals(), vec![2., 1., 0.5]);
+        assert_eq!((-&y).vals(), vec![-3., 1., -0.5]);
+
+        let z = ((&x * s - &y) / &x).sum() + x.dot(&y);
+        let grads = z.grad();
+        // d/dx (2x - y) / x = y / x^2, plus y from the dot product
+        for i in 0..3 {
+            assert_approx_eq!(grads.wrt(&x[i]), y[i].val / x[i].val.powi(2) + y[i].val);
+        }
+        assert_eq!(grads.wrt(&s), 3.);
+        assert_eq!(grads.wrt(&x).len(), 3);
+        assert_eq!(x.map(|v| v.exp()).len(), 3);
+        assert_eq!(x.iter().map(|v| v.val).sum::<f64>(), 7.);
+    }
+
+    #[test]
+    #[should_panic(expected = "different lengths")]
+    fn test_length_mismatch() {
+        let g = Tape::new();
+        let _ = VarVec::from_vals(&g, &[1., 2.]) + VarVec::from_vals(&g, &[1.]);
+    }
+}
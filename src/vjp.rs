@@ -0,0 +1,167 @@
+//! Vector-Jacobian and Jacobian-vector products over a recorded tape, with seeds chosen by the
+//! caller.
+//!
+//! ```rust
+//! use reverse::*;
+//!
+//! let tape = Tape::new();
+//! let x = tape.add_vars(&[1., 2.]);
+//! let y = [x[0] * x[1], x[0] + x[1].powi(2)];
+//!
+//! // cotangents are pulled back from the outputs to the inputs
+//! let pulled = vjp(&y, &[1., 10.]);
+//! assert_eq!(pulled.wrt(&x), vec![2. + 10., 1. + 10. * 4.]);
+//!
+//! // tangents are pushed forward from the inputs to the outputs
+//! let pushed = jvp(&x, &[1., -1.]);
+//! assert_eq!(pushed.wrt(&y), vec![2. - 1., 1. - 4.]);
+//! ```
+
+use crate::{error::assert_same_tape, Grad, Var};
+
+/// Calculate the vector-Jacobian product of `outputs` with the `cotangent` vector, in one backward
+/// pass. The result is the gradient of `sum_i cotangent[i] * outputs[i]` with respect to every node.
+///
+/// # Panics
+///
+/// Panics if `outputs` is empty, the lengths differ or the outputs do not share a tape.
+pub fn vjp<'a>(outputs: &[Var<'a>], cotangent: &[f64]) -> Grad<'a> {
+    assert_eq!(
+        outputs.len(),
+        cotangent.len(),
+        "need one cotangent for each output"
+    );
+    seeded(
+        "vjp",
+        outputs.iter().copied().zip(cotangent.iter().copied()),
+    )
+}
+
+/// Calculate the gradients of the weighted sum of `outputs`, where each output is paired with its
+/// weight, in one backward pass.
+///
+/// ```rust
+/// use reverse::*;
+///
+/// let tape = Tape::new();
+/// let x = tape.add_var(2.);
+/// let (loss, penalty) = (x.powi(2), x.abs());
+/// let grads = grad_weighted(&[(loss, 1.), (penalty, 0.1)]);
+/// assert_eq!(grads.wrt(&x), 4. + 0.1);
+/// ```
+///
+/// # Panics
+///
+/// Panics if `outputs` is empty or the outputs do not share a tape.
+pub fn grad_weighted<'a>(outputs: &[(Var<'a>, f64)]) -> Grad<'a> {
+    seeded("grad_weighted", outputs.iter().copied())
+}
+
+/// Run a backward pass seeded with each output's weight, for the function `name`.
+fn seeded<'a>(name: &'static str, mut outputs: impl Iterator<Item = (Var<'a>, f64)>) -> Grad<'a> {
+    let (first, weight) = outputs.next().expect("no outputs to differentiate");
+    let tape = first.tape;
+    let mut derivs = vec![0.; tape.len()];
+    derivs[first.location] = weight;
+    for (output, weight) in outputs {
+        assert_same_tape(name, tape, output.tape);
+        derivs[output.location] += weight;
+    }
+    tape.backward(&mut derivs);
+    Grad::new(tape, derivs)
+}
+
+/// Calculate the Jacobian-vector product of every node with the `tangent` vector of `inputs`, in
+/// one forward pass over the recorded derivatives. The result is the directional derivative of each
+/// node, indexed by location, and is read with `Gradient::wrt`. Variables that are not among
+/// `inputs` are held fixed.
+///
+/// # Panics
+///
+/// Panics if `inputs` is empty, the lengths differ or the inputs do not share a tape.
+pub fn jvp<'a>(inputs: &[Var<'a>], tangent: &[f64]) -> Vec<f64> {
+    assert_eq!(
+        inputs.len(),
+        tangent.len(),
+        "need one tangent for each input"
+    );
+    let tape = inputs.first().expect("no inputs to differentiate").tape;
+    let mut tangents = vec![0.; tape.len()];
+    for (input, &seed) in inputs.iter().zip(tangent) {
+        assert_same_tape("jvp", tape, input.tape);
+        tangents[input.location] += seed;
+    }
+
+    let nodes = tape.nodes.borrow();
+    let operands = tape.operands.borrow();
+    let spans = tape.spans.borrow();
+    let mut spans = spans.iter().peekable();
+    let blocks = tape.blocks.borrow();
+    let mut blocks = blocks.iter().peekable();
+    for (idx, node) in nodes.iter().enumerate() {
+        let mut dot = 0.;
+        for (&dep, &weight) in node.dependencies().iter().zip(&node.weights) {
+            if dep != idx {
+                dot += weight * tangents[dep];
+            }
+        }
+        if let Some(span) = spans.next_if(|span| span.node == idx) {
+            for &(dep, weight) in &operands[span.start..span.end] {
+                dot += weight * tangents[dep];
+            }
+        }
+        tangents[idx] += dot;
+        if let Some(block) = blocks.next_if(|block| block.start == idx) {
+            // a block only knows its transpose, so pull back one output at a time
+            let mut seed = vec![0.; block.end - block.start];
+            let mut row = vec![0.; block.inputs.len()];
+            for k in 0..seed.len() {
+                seed[k] = 1.;
+                row.iter_mut().for_each(|x| *x = 0.);
+                (block.backward)(&seed, &mut row);
+                seed[k] = 0.;
+                tangents[block.start + k] += row
+                    .iter()
+                    .zip(&block.inputs)
+                    .map(|(weight, &input)| weight * tangents[input])
+                    .sum::<f64>();
+            }
+        }
+    }
+    tangents
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::{jacobian, logsumexp, solve, Gradient, Mat, Tape};
+    use approx_eq::assert_approx_eq;
+
+    #[test]
+    fn test_vjp_jvp() {
+        let g = Tape::new();
+        let x = g.add_vars(&[0.5, -1., 2.]);
+        let m = Mat::new(2, 2, vec![x[0] + 2., x[1], x[2], x[0] * x[1] + 3.]);
+        let z = solve(&m, &[x[2].sin(), x[0]]);
+        let y = vec![z[0] * x[1], logsumexp(&x) + z[1], x[2].powi(3)];
+        let jac = jacobian(&y, &x);
+
+        let cotangent = [0.5, -2., 1.5];
+        let pulled = vjp(&y, &cotangent).wrt(&x);
+        for (j, grad) in pulled.iter().enumerate() {
+            let expected = (0..3).map(|i| cotangent[i] * jac.get(i, j)).sum::<f64>();
+            assert_approx_eq!(*grad, expected);
+        }
+
+        let tangent = [1., 0.25, -3.];
+        let pushed = jvp(&x, &tangent).wrt(&y);
+        for (i, dot) in pushed.iter().enumerate() {
+            let expected = (0..3).map(|j| jac.get(i, j) * tangent[j]).sum::<f64>();
+            assert_approx_eq!(*dot, expected);
+        }
+
+        let weighted = grad_weighted(&[(y[0], 0.5), (y[2], 1.5), (y[1], -2.)]);
+        assert_eq!(weighted.wrt(&x), pulled);
+        assert_eq!(y[2].grad_with_seed(-2.).wrt(&x[2]), -2. * 12.);
+    }
+}
@@ -0,0 +1,108 @@
+//! JavaScript bindings with `wasm-bindgen`, enabled with the `wasm` feature.
+//!
+//! `Tape` is exported to JavaScript as a class forwarding to `handle::HandleTape`, so variables
+//! are plain numbers on the JavaScript side. Operations are chosen by name, as for `HandleTape`,
+//! and return `undefined` for an unknown name:
+//!
+//! ```js
+//! const tape = new Tape();
+//! const x = tape.variable(2);
+//! const y = tape.variable(3);
+//! const z = tape.unary("sin", tape.binary("mul", x, y));
+//! tape.gradWrt(z, [x, y]); // Float64Array [3 cos 6, 2 cos 6]
+//! ```
+
+use crate::handle::HandleTape;
+use wasm_bindgen::prelude::wasm_bindgen;
+
+/// Tape of variables identified by numbers, for JavaScript.
+#[wasm_bindgen(js_name = Tape)]
+#[derive(Debug, Clone, Default)]
+pub struct WasmTape {
+    tape: HandleTape,
+}
+
+#[wasm_bindgen(js_class = Tape)]
+impl WasmTape {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add an input variable with value `val`.
+    #[wasm_bindgen(js_name = variable)]
+    pub fn var(&mut self, val: f64) -> usize {
+        self.tape.var(val)
+    }
+
+    /// Add a constant with value `val`.
+    pub fn constant(&mut self, val: f64) -> usize {
+        self.tape.constant(val)
+    }
+
+    /// Gets the value of the variable `handle`.
+    pub fn val(&self, handle: usize) -> f64 {
+        self.tape.val(handle)
+    }
+
+    /// Gets the number of nodes on the tape.
+    pub fn len(&self) -> usize {
+        self.tape.len()
+    }
+
+    /// Checks whether the tape is empty.
+    #[wasm_bindgen(js_name = isEmpty)]
+    pub fn is_empty(&self) -> bool {
+        self.tape.is_empty()
+    }
+
+    /// Delete every variable, invalidating all handles.
+    pub fn clear(&mut self) {
+        self.tape.clear();
+    }
+
+    /// Apply the unary function `name` to `x`, as with `HandleTape::unary`.
+    pub fn unary(&mut self, name: &str, x: usize) -> Option<usize> {
+        self.tape.unary(name, x)
+    }
+
+    /// Apply the binary function `name` to `x` and `y`, as with `HandleTape::binary`.
+    pub fn binary(&mut self, name: &str, x: usize, y: usize) -> Option<usize> {
+        self.tape.binary(name, x, y)
+    }
+
+    /// Calculate the gradient of `output` with respect to every node, indexed by handle.
+    pub fn grad(&self, output: usize) -> Vec<f64> {
+        self.tape.grad(output)
+    }
+
+    /// Calculate the gradient of `output` with respect to each of the variables `wrt`.
+    #[wasm_bindgen(js_name = gradWrt)]
+    pub fn grad_wrt(&self, output: usize, wrt: &[usize]) -> Vec<f64> {
+        self.tape.grad_wrt(output, wrt)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_wasm_tape() {
+        // the exported methods are ordinary functions outside of WebAssembly
+        let mut tape = WasmTape::new();
+        let x = tape.var(2.);
+        let y = tape.var(3.);
+        let xy = tape.binary("mul", x, y).unwrap();
+        let z = tape.unary("sin", xy).unwrap();
+        assert!(tape.unary("nope", z).is_none());
+        assert_eq!(tape.val(z), 6_f64.sin());
+        assert_eq!(
+            tape.grad_wrt(z, &[x, y]),
+            vec![3. * 6_f64.cos(), 2. * 6_f64.cos()]
+        );
+        assert_eq!(tape.len(), 4);
+        tape.clear();
+        assert!(tape.is_empty());
+    }
+}